@@ -0,0 +1,33 @@
+CREATE TABLE IF NOT EXISTS ledger_transactions (
+    id VARCHAR PRIMARY KEY,
+    owner_type VARCHAR NOT NULL,
+    owner_id VARCHAR NOT NULL,
+    action_type VARCHAR NOT NULL,
+    amount NUMERIC NOT NULL CHECK (amount > 0),
+    fee NUMERIC NOT NULL DEFAULT 0,
+    reference_id VARCHAR,
+    idempotent_key VARCHAR NOT NULL UNIQUE,
+    created_at TIMESTAMP NOT NULL DEFAULT NOW()
+);
+CREATE INDEX IF NOT EXISTS ledger_transactions_owner_idx ON ledger_transactions (owner_type, owner_id, created_at DESC);
+CREATE INDEX IF NOT EXISTS ledger_transactions_reference_idx ON ledger_transactions (reference_id);
+
+CREATE TABLE IF NOT EXISTS ledger_postings (
+    id BIGSERIAL PRIMARY KEY,
+    transaction_id VARCHAR NOT NULL REFERENCES ledger_transactions (id),
+    owner_type VARCHAR NOT NULL,
+    owner_id VARCHAR NOT NULL,
+    account VARCHAR NOT NULL,
+    amount NUMERIC NOT NULL
+);
+CREATE INDEX IF NOT EXISTS ledger_postings_transaction_idx ON ledger_postings (transaction_id);
+CREATE INDEX IF NOT EXISTS ledger_postings_owner_idx ON ledger_postings (owner_type, owner_id, account);
+
+CREATE TABLE IF NOT EXISTS ledger_balances (
+    owner_type VARCHAR NOT NULL,
+    owner_id VARCHAR NOT NULL,
+    account VARCHAR NOT NULL,
+    balance NUMERIC NOT NULL DEFAULT 0,
+    updated_at TIMESTAMP NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (owner_type, owner_id, account)
+);
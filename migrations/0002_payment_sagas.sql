@@ -0,0 +1,16 @@
+CREATE TABLE IF NOT EXISTS payment_sagas (
+    id VARCHAR PRIMARY KEY,
+    kind VARCHAR NOT NULL,
+    reference_id VARCHAR NOT NULL,
+    status VARCHAR NOT NULL,
+    completed_steps INT NOT NULL DEFAULT 0,
+    in_flight BOOLEAN NOT NULL DEFAULT FALSE,
+    context TEXT NOT NULL,
+    error TEXT,
+    locked_until TIMESTAMP,
+    created_at TIMESTAMP NOT NULL,
+    updated_at TIMESTAMP NOT NULL
+);
+CREATE INDEX IF NOT EXISTS payment_sagas_unfinished_idx ON payment_sagas (updated_at)
+    WHERE status IN ('running', 'compensating');
+CREATE INDEX IF NOT EXISTS payment_sagas_reference_idx ON payment_sagas (reference_id);
@@ -0,0 +1 @@
+CREATE INDEX IF NOT EXISTS payments_unpaid_deadline_idx ON payments (deadline) WHERE status = 'UNPAID';
@@ -0,0 +1,36 @@
+CREATE TABLE IF NOT EXISTS disputes (
+    id VARCHAR PRIMARY KEY,
+    payment_id VARCHAR NOT NULL,
+    merchant_id VARCHAR NOT NULL,
+    trader_id VARCHAR NOT NULL,
+    opened_by VARCHAR NOT NULL,
+    opener_id VARCHAR NOT NULL,
+    reason TEXT NOT NULL,
+    status VARCHAR NOT NULL,
+    payment_status_before VARCHAR NOT NULL,
+    resolution VARCHAR,
+    resolved_by VARCHAR,
+    resolution_comment TEXT,
+    respond_by TIMESTAMP NOT NULL,
+    resolve_by TIMESTAMP NOT NULL,
+    created_at TIMESTAMP NOT NULL,
+    updated_at TIMESTAMP,
+    resolved_at TIMESTAMP
+);
+CREATE UNIQUE INDEX IF NOT EXISTS disputes_one_active_per_payment ON disputes (payment_id) WHERE status <> 'RESOLVED';
+CREATE INDEX IF NOT EXISTS disputes_payment_idx ON disputes (payment_id);
+CREATE INDEX IF NOT EXISTS disputes_sla_idx ON disputes (resolve_by) WHERE status <> 'RESOLVED';
+
+CREATE TABLE IF NOT EXISTS dispute_evidence (
+    id VARCHAR PRIMARY KEY,
+    dispute_id VARCHAR NOT NULL REFERENCES disputes (id),
+    uploaded_by VARCHAR NOT NULL,
+    uploader_id VARCHAR NOT NULL,
+    file_name VARCHAR NOT NULL,
+    content_type VARCHAR NOT NULL,
+    size_bytes BIGINT NOT NULL,
+    storage_key VARCHAR NOT NULL,
+    sha256 VARCHAR NOT NULL,
+    created_at TIMESTAMP NOT NULL
+);
+CREATE INDEX IF NOT EXISTS dispute_evidence_dispute_idx ON dispute_evidence (dispute_id);
@@ -0,0 +1,3 @@
+ALTER TABLE payments ADD COLUMN IF NOT EXISTS original_fiat_amount NUMERIC;
+ALTER TABLE payments ADD COLUMN IF NOT EXISTS original_crypto_amount NUMERIC;
+ALTER TABLE payments ADD COLUMN IF NOT EXISTS original_trader_crypto_amount NUMERIC;
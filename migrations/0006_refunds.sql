@@ -0,0 +1,20 @@
+ALTER TABLE payments ADD COLUMN IF NOT EXISTS refunded_fiat_amount NUMERIC;
+
+CREATE TABLE IF NOT EXISTS refunds (
+    id VARCHAR PRIMARY KEY,
+    payment_id VARCHAR NOT NULL,
+    merchant_id VARCHAR NOT NULL,
+    trader_id VARCHAR NOT NULL,
+    kind VARCHAR NOT NULL,
+    status VARCHAR NOT NULL,
+    fiat_amount NUMERIC NOT NULL,
+    crypto_amount NUMERIC NOT NULL,
+    trader_crypto_amount NUMERIC NOT NULL,
+    reason TEXT NOT NULL,
+    initiator_id VARCHAR NOT NULL,
+    idempotent_key VARCHAR NOT NULL,
+    created_at TIMESTAMP NOT NULL,
+    completed_at TIMESTAMP
+);
+CREATE UNIQUE INDEX IF NOT EXISTS refunds_idempotent_key_idx ON refunds (payment_id, idempotent_key);
+CREATE INDEX IF NOT EXISTS refunds_merchant_idx ON refunds (merchant_id, created_at);
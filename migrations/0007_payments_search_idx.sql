@@ -0,0 +1,6 @@
+CREATE INDEX IF NOT EXISTS payments_merchant_created_idx ON payments (merchant_id, created_at DESC, id DESC);
+CREATE INDEX IF NOT EXISTS payments_trader_created_idx ON payments (trader_id, created_at DESC, id DESC);
+CREATE INDEX IF NOT EXISTS payments_created_idx ON payments (created_at DESC, id DESC);
+CREATE INDEX IF NOT EXISTS payments_external_id_prefix_idx ON payments (external_id text_pattern_ops);
+CREATE INDEX IF NOT EXISTS payments_holder_account_suffix_idx ON payments (reverse(holder_account) text_pattern_ops);
+CREATE INDEX IF NOT EXISTS payments_fiat_amount_idx ON payments (fiat_amount);
@@ -0,0 +1,27 @@
+CREATE TABLE IF NOT EXISTS settlement_reports (
+    id VARCHAR PRIMARY KEY,
+    merchant_id VARCHAR NOT NULL,
+    report_date DATE NOT NULL,
+    version INT NOT NULL,
+    report_json TEXT NOT NULL,
+    report_csv TEXT NOT NULL,
+    sha256 VARCHAR NOT NULL,
+    json_signature TEXT NOT NULL,
+    csv_signature TEXT NOT NULL,
+    key_id VARCHAR NOT NULL,
+    created_at TIMESTAMP NOT NULL,
+    UNIQUE (merchant_id, report_date, version)
+);
+
+-- сохраненный отчет не меняется и не удаляется, исправления - только новой версией
+CREATE OR REPLACE FUNCTION settlement_reports_immutable() RETURNS trigger AS $$
+BEGIN
+    RAISE EXCEPTION 'settlement_reports is append-only';
+END;
+$$ LANGUAGE plpgsql;
+
+DROP TRIGGER IF EXISTS settlement_reports_immutable ON settlement_reports;
+CREATE TRIGGER settlement_reports_immutable BEFORE UPDATE OR DELETE ON settlement_reports
+    FOR EACH ROW EXECUTE FUNCTION settlement_reports_immutable();
+
+CREATE INDEX IF NOT EXISTS ledger_transactions_created_idx ON ledger_transactions (owner_type, created_at);
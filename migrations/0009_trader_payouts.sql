@@ -0,0 +1,34 @@
+CREATE TABLE IF NOT EXISTS payout_batches (
+    id VARCHAR PRIMARY KEY,
+    trader_id VARCHAR NOT NULL,
+    status VARCHAR NOT NULL,
+    amount NUMERIC NOT NULL CHECK (amount > 0),
+    payments_count BIGINT NOT NULL,
+    until TIMESTAMP NOT NULL,
+    idempotent_key VARCHAR NOT NULL,
+    created_by VARCHAR NOT NULL,
+    created_at TIMESTAMP NOT NULL,
+    completed_at TIMESTAMP
+);
+CREATE UNIQUE INDEX IF NOT EXISTS payout_batches_idempotent_key_idx ON payout_batches (trader_id, idempotent_key);
+CREATE INDEX IF NOT EXISTS payout_batches_pending_idx ON payout_batches (created_at) WHERE status = 'PENDING';
+
+CREATE TABLE IF NOT EXISTS payout_items (
+    batch_id VARCHAR NOT NULL REFERENCES payout_batches (id),
+    payment_id VARCHAR NOT NULL,
+    amount NUMERIC NOT NULL,
+    PRIMARY KEY (batch_id, payment_id)
+);
+CREATE INDEX IF NOT EXISTS payout_items_payment_idx ON payout_items (payment_id);
+
+CREATE TABLE IF NOT EXISTS payout_events (
+    id BIGSERIAL PRIMARY KEY,
+    batch_id VARCHAR NOT NULL REFERENCES payout_batches (id),
+    kind VARCHAR NOT NULL,
+    actor_id VARCHAR NOT NULL,
+    details TEXT,
+    created_at TIMESTAMP NOT NULL
+);
+CREATE INDEX IF NOT EXISTS payout_events_batch_idx ON payout_events (batch_id);
+
+CREATE INDEX IF NOT EXISTS payments_trader_earnings_idx ON payments (trader_id, created_at) WHERE status IN ('COMPLETED', 'FROZEN');
@@ -0,0 +1,14 @@
+CREATE TABLE IF NOT EXISTS block_events (
+    id VARCHAR PRIMARY KEY,
+    target VARCHAR NOT NULL,
+    target_id VARCHAR NOT NULL,
+    action VARCHAR NOT NULL,
+    reason TEXT NOT NULL,
+    actor_id VARCHAR NOT NULL,
+    actor_role VARCHAR NOT NULL,
+    expires_at TIMESTAMP,
+    created_at TIMESTAMP NOT NULL
+);
+CREATE INDEX IF NOT EXISTS block_events_target_idx ON block_events (target, target_id, created_at DESC, id DESC);
+CREATE INDEX IF NOT EXISTS block_events_expires_idx ON block_events (expires_at, target, target_id, created_at, id)
+    WHERE expires_at IS NOT NULL AND action = 'BLOCK';
@@ -0,0 +1,31 @@
+CREATE TABLE IF NOT EXISTS audit_log (
+    seq BIGSERIAL PRIMARY KEY,
+    id VARCHAR NOT NULL UNIQUE,
+    actor_id VARCHAR NOT NULL,
+    actor_role VARCHAR NOT NULL,
+    subject_id VARCHAR,
+    subject_role VARCHAR,
+    method VARCHAR NOT NULL,
+    route VARCHAR NOT NULL,
+    path TEXT NOT NULL,
+    status INT NOT NULL,
+    outcome VARCHAR NOT NULL,
+    request_hash VARCHAR NOT NULL,
+    created_at TIMESTAMP NOT NULL,
+    prev_hash VARCHAR NOT NULL,
+    hash VARCHAR NOT NULL UNIQUE
+);
+
+-- журнал только дополняется, подмена записей в обход триггера выявляется по цепочке хэшей
+CREATE OR REPLACE FUNCTION audit_log_immutable() RETURNS trigger AS $$
+BEGIN
+    RAISE EXCEPTION 'audit_log is append-only';
+END;
+$$ LANGUAGE plpgsql;
+
+DROP TRIGGER IF EXISTS audit_log_immutable ON audit_log;
+CREATE TRIGGER audit_log_immutable BEFORE UPDATE OR DELETE ON audit_log
+    FOR EACH ROW EXECUTE FUNCTION audit_log_immutable();
+
+CREATE INDEX IF NOT EXISTS audit_log_actor_idx ON audit_log (actor_id, created_at);
+CREATE INDEX IF NOT EXISTS audit_log_subject_idx ON audit_log (subject_id, created_at) WHERE subject_id IS NOT NULL;
@@ -0,0 +1,9 @@
+-- лимиты запросов, отличные от лимитов по умолчанию. route_group '*' - на все группы маршрутов субъекта
+CREATE TABLE IF NOT EXISTS rate_limits (
+    subject_id VARCHAR NOT NULL,
+    route_group VARCHAR NOT NULL,
+    "limit" INT NOT NULL CHECK ("limit" > 0),
+    period_ms BIGINT NOT NULL CHECK (period_ms > 0),
+    updated_at TIMESTAMP NOT NULL,
+    PRIMARY KEY (subject_id, route_group)
+);
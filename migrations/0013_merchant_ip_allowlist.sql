@@ -0,0 +1,2 @@
+-- диапазоны CIDR, с которых мерчант может вызывать API. пустой массив - без ограничений
+ALTER TABLE merchants ADD COLUMN IF NOT EXISTS ip_allowlist TEXT[] NOT NULL DEFAULT '{}';
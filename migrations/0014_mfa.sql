@@ -0,0 +1,18 @@
+CREATE TABLE IF NOT EXISTS mfa_secrets (
+    user_id VARCHAR PRIMARY KEY,
+    role VARCHAR NOT NULL,
+    -- base32, TOTP с HMAC-SHA1
+    secret VARCHAR NOT NULL,
+    -- последний принятый шаг TOTP, повторно код не принимается
+    last_step BIGINT,
+    -- NULL - подключение не подтверждено кодом
+    confirmed_at TIMESTAMP,
+    created_at TIMESTAMP NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS mfa_recovery_codes (
+    user_id VARCHAR NOT NULL,
+    code_hash VARCHAR NOT NULL,
+    used_at TIMESTAMP,
+    PRIMARY KEY (user_id, code_hash)
+);
@@ -0,0 +1,9 @@
+-- снятие заморозок по отмененным по таймауту платежам, выполняется вне транзакции отмены
+CREATE TABLE IF NOT EXISTS payment_expiry_jobs (
+    payment_id VARCHAR PRIMARY KEY,
+    attempts INT NOT NULL DEFAULT 0,
+    next_attempt_at TIMESTAMP NOT NULL,
+    last_error TEXT,
+    created_at TIMESTAMP NOT NULL DEFAULT NOW()
+);
+CREATE INDEX IF NOT EXISTS payment_expiry_jobs_next_attempt_idx ON payment_expiry_jobs (next_attempt_at);
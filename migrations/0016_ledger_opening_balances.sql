@@ -0,0 +1,4 @@
+-- начальный остаток (OPENING) пишется и при нулевом балансе, как отметка о посеве
+ALTER TABLE ledger_transactions DROP CONSTRAINT IF EXISTS ledger_transactions_amount_check;
+ALTER TABLE ledger_transactions ADD CONSTRAINT ledger_transactions_amount_check
+    CHECK (amount > 0 OR (action_type = 'OPENING' AND amount = 0));
@@ -7,4 +7,5 @@ fn main() {
     tonic_build::compile_protos("src/proto/bank.proto").unwrap();
     tonic_build::compile_protos("src/proto/payment.proto").unwrap();
     tonic_build::compile_protos("src/proto/exchange.proto").unwrap();
+    tonic_build::compile_protos("src/proto/error_details.proto").unwrap();
 }
\ No newline at end of file
@@ -0,0 +1,118 @@
+use std::collections::HashMap;
+use prost::Message;
+use tonic::{Code, Status};
+use crate::errors::LibError;
+use crate::rpc_proto::{ErrorInfo, Status as RpcStatus};
+
+pub const ERROR_DOMAIN: &str = "bankirpay";
+const ERROR_INFO_TYPE_URL: &str = "type.googleapis.com/google.rpc.ErrorInfo";
+
+impl LibError {
+    // коды совпадают с прежним сопоставлением в services::status_to_err, чтобы старые клиенты
+    // продолжали понимать ответы. сообщение - Display, его тоже разбирают старые клиенты
+    pub fn grpc_code(&self) -> Code {
+        match self {
+            LibError::NotFound | LibError::TraderNotFound | LibError::MerchantNotFound
+            | LibError::NoAvailableRequisites => Code::NotFound,
+            LibError::InsufficientFunds | LibError::InvalidAmount | LibError::InvalidRequest(_)
+            | LibError::PayloadTooLarge => Code::InvalidArgument,
+            LibError::Conflict => Code::Cancelled,
+            LibError::Forbidden => Code::PermissionDenied,
+            LibError::Unauthorized | LibError::MfaRequired => Code::Unauthenticated,
+            LibError::TooManyRequests(_) => Code::ResourceExhausted,
+            LibError::InternalError => Code::Internal,
+        }
+    }
+
+    fn from_error_info(info: &ErrorInfo) -> Option<Self> {
+        let error = match info.reason.as_str() {
+            "TRADER_NOT_FOUND" => LibError::TraderNotFound,
+            "FORBIDDEN" => LibError::Forbidden,
+            "UNAUTHORIZED" => LibError::Unauthorized,
+            "INTERNAL_ERROR" => LibError::InternalError,
+            "MERCHANT_NOT_FOUND" => LibError::MerchantNotFound,
+            "NOT_FOUND" => LibError::NotFound,
+            "NO_AVAILABLE_REQUISITES" => LibError::NoAvailableRequisites,
+            "INSUFFICIENT_FUNDS" => LibError::InsufficientFunds,
+            "INVALID_AMOUNT" => LibError::InvalidAmount,
+            "CONFLICT" => LibError::Conflict,
+            "TOO_MANY_REQUESTS" => LibError::TooManyRequests(
+                info.metadata.get("retry_after").and_then(|v| v.parse().ok()).unwrap_or(1)),
+            "MFA_REQUIRED" => LibError::MfaRequired,
+            "PAYLOAD_TOO_LARGE" => LibError::PayloadTooLarge,
+            "INVALID_REQUEST" => LibError::InvalidRequest(
+                info.metadata.get("fields").and_then(|v| serde_json::from_str(v).ok()).unwrap_or_default()),
+            _ => return None,
+        };
+        Some(error)
+    }
+
+    fn error_info(&self) -> ErrorInfo {
+        let mut metadata = HashMap::new();
+        match self {
+            LibError::TooManyRequests(retry_after) => {
+                metadata.insert("retry_after".to_string(), retry_after.to_string());
+            }
+            LibError::InvalidRequest(fields) => {
+                metadata.insert("fields".to_string(), serde_json::to_string(fields).unwrap_or_default());
+            }
+            _ => (),
+        }
+        ErrorInfo { reason: self.code().to_string(), domain: ERROR_DOMAIN.to_string(), metadata }
+    }
+}
+
+// для gRPC-серверов на этой библиотеке: причина уходит в ErrorInfo в grpc-status-details-bin
+impl From<LibError> for Status {
+    fn from(err: LibError) -> Self {
+        let code = err.grpc_code();
+        let message = err.to_string();
+        let details = RpcStatus {
+            code: code as i32,
+            message: message.clone(),
+            details: vec![prost_types::Any {
+                type_url: ERROR_INFO_TYPE_URL.to_string(),
+                value: err.error_info().encode_to_vec(),
+            }],
+        };
+        Status::with_details(code, message, details.encode_to_vec().into())
+    }
+}
+
+// ErrorInfo из ответа, если его прислал сервер на этой библиотеке
+pub fn decode_status(status: &Status) -> Option<LibError> {
+    let details = RpcStatus::decode(status.details()).ok()?;
+    details.details.iter()
+        .filter(|any| any.type_url == ERROR_INFO_TYPE_URL)
+        .filter_map(|any| ErrorInfo::decode(any.value.as_slice()).ok())
+        .find(|info| info.domain == ERROR_DOMAIN)
+        .and_then(|info| LibError::from_error_info(&info))
+}
+
+// сервис жив и ответил лимитом этого клиента: такой ответ не повторяется и не размыкает цепь
+pub fn is_rate_limited(status: &Status) -> bool {
+    status.code() == Code::ResourceExhausted && matches!(decode_status(status), Some(LibError::TooManyRequests(_)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn status_round_trip() {
+        let errors = vec![
+            LibError::TraderNotFound, LibError::Forbidden, LibError::Unauthorized, LibError::InternalError,
+            LibError::MerchantNotFound, LibError::NotFound, LibError::NoAvailableRequisites,
+            LibError::InsufficientFunds, LibError::InvalidAmount, LibError::Conflict, LibError::TooManyRequests(7),
+            LibError::MfaRequired, LibError::invalid_field("amount", "POSITIVE", "must be positive"),
+            LibError::PayloadTooLarge,
+        ];
+        for err in errors {
+            let status = Status::from(err.clone());
+            assert_eq!(decode_status(&status).as_ref(), Some(&err));
+        }
+        assert_eq!(decode_status(&Status::not_found("no available requisites")), None);
+        assert!(is_rate_limited(&Status::from(LibError::TooManyRequests(1))));
+        assert!(!is_rate_limited(&Status::resource_exhausted("bulkhead is full")));
+    }
+}
@@ -1,95 +1,229 @@
-use axum::body::Body;
+use std::error::Error;
+use axum::http::{HeaderValue, StatusCode};
 use axum::response::{IntoResponse, Response};
-#[derive(Debug, PartialOrd, PartialEq, Eq)]
+use axum::Json;
+use serde::{Deserialize, Serialize};
+use tracing::error;
+
+pub mod grpc;
+
+tokio::task_local! {
+    // id запроса, выставляется correlation_id_middleware и попадает в тело ошибки
+    pub static CORRELATION_ID: String;
+}
+
+pub const CORRELATION_ID_HEADER: &str = "X-Correlation-ID";
+
+// ошибка в конкретном поле запроса
+#[derive(Deserialize, Serialize, Debug, Clone, PartialOrd, PartialEq, Eq)]
+pub struct FieldError {
+    pub field: String,
+    pub code: String,
+    pub message: String,
+}
+
+#[derive(Debug, Clone, thiserror::Error, PartialOrd, PartialEq, Eq)]
 pub enum LibError {
+    #[error("Trader not found")]
     TraderNotFound,
+    #[error("Forbidden")]
     Forbidden,
+    #[error("Unauthorized")]
     Unauthorized,
+    #[error("Internal Error")]
     InternalError,
+    #[error("Merchant not found")]
     MerchantNotFound,
+    #[error("Not Found")]
     NotFound,
+    #[error("No Available Requisites")]
     NoAvailableRequisites,
+    #[error("Insufficient Funds")]
     InsufficientFunds,
+    #[error("Invalid Amount")]
     InvalidAmount,
-    Conflict
+    #[error("Conflict")]
+    Conflict,
+    // через сколько секунд можно повторить запрос
+    #[error("Too Many Requests")]
+    TooManyRequests(u64),
+    // маршрут требует недавнего прохождения второго фактора
+    #[error("MFA Required")]
+    MfaRequired,
+    #[error("Invalid Request")]
+    InvalidRequest(Vec<FieldError>),
+    #[error("Payload Too Large")]
+    PayloadTooLarge,
 }
 
-impl IntoResponse for LibError {
-    fn into_response(self) -> Response {
+impl LibError {
+    pub fn invalid_field(field: &str, code: &str, message: &str) -> Self {
+        LibError::InvalidRequest(vec![FieldError {
+            field: field.to_string(),
+            code: code.to_string(),
+            message: message.to_string(),
+        }])
+    }
+
+    // код для клиентов, не меняется при переименовании вариантов
+    pub fn code(&self) -> &'static str {
+        match self {
+            LibError::TraderNotFound => "TRADER_NOT_FOUND",
+            LibError::Forbidden => "FORBIDDEN",
+            LibError::Unauthorized => "UNAUTHORIZED",
+            LibError::InternalError => "INTERNAL_ERROR",
+            LibError::MerchantNotFound => "MERCHANT_NOT_FOUND",
+            LibError::NotFound => "NOT_FOUND",
+            LibError::NoAvailableRequisites => "NO_AVAILABLE_REQUISITES",
+            LibError::InsufficientFunds => "INSUFFICIENT_FUNDS",
+            LibError::InvalidAmount => "INVALID_AMOUNT",
+            LibError::Conflict => "CONFLICT",
+            LibError::TooManyRequests(_) => "TOO_MANY_REQUESTS",
+            LibError::MfaRequired => "MFA_REQUIRED",
+            LibError::InvalidRequest(_) => "INVALID_REQUEST",
+            LibError::PayloadTooLarge => "PAYLOAD_TOO_LARGE",
+        }
+    }
+
+    // 400 - InvalidAmount, InsufficientFunds, InvalidRequest
+    // 401 - Unauthorized, MfaRequired
+    // 403 - Forbidden
+    // 404 - NotFound, TraderNotFound, MerchantNotFound
+    // 409 - Conflict
+    // 413 - PayloadTooLarge
+    // 429 - TooManyRequests, с заголовком Retry-After
+    // 500 - InternalError
+    // 503 - NoAvailableRequisites: сейчас нет свободных реквизитов, запрос можно повторить
+    pub fn status(&self) -> StatusCode {
         match self {
-            LibError::Unauthorized => {
-                Response::builder()
-                    .status(401)
-                    .header("Content-Type", "application/json")
-                    .body(Body::from("{\"error\":401, \"message\":\"Unauthorized\"}"))
-                    .unwrap()
-            }
-            LibError::InternalError => {
-                Response::builder()
-                    .status(500)
-                    .header("Content-Type", "application/json")
-                    .body(Body::from("{\"error\":500, \"message\":\"Internal Error\"}"))
-                    .unwrap()
-            }
-            LibError::NotFound => {
-                Response::builder()
-                    .status(404)
-                    .header("Content-Type", "application/json")
-                    .body(Body::from("{\"error\":404, \"message\":\"Not Found\"}"))
-                    .unwrap()
-            }
-
-            LibError::TraderNotFound => {
-                Response::builder()
-                    .status(404)
-                    .header("Content-Type", "application/json")
-                    .body(Body::from("{\"error\":404, \"message\":\"Trader not found\"}"))
-                    .unwrap()
-            }
-            LibError::Forbidden => {
-                Response::builder()
-                    .status(403)
-                    .header("Content-Type", "application/json")
-                    .body(Body::from("{\"error\":403, \"message\":\"Forbidden\"}"))
-                    .unwrap()
-            }
-
-            LibError::MerchantNotFound => {
-                Response::builder()
-                .status(404)
-                .header("Content-Type", "application/json")
-                .body(Body::from("{\"error\":404, \"message\":\"Merchant not found\"}"))
-                .unwrap()
-            }
-            LibError::NoAvailableRequisites => {
-                Response::builder()
-                .status(500)
-                .header("Content-Type", "application/json")
-                .body(Body::from("{\"error\":500, \"message\":\"No Available Requisites\"}"))
-                .unwrap()
-            }
-            LibError::InsufficientFunds => {
-                Response::builder()
-                .status(400)
-                .header("Content-Type", "application/json")
-                .body(Body::from("{\"error\":400,\"message\":\"Insufficient Funds\"}"))
-                .unwrap()
-            }
-            LibError::InvalidAmount => {
-                Response::builder()
-                .status(400)
-                .header("Content-Type", "application/json")
-                .body(Body::from("{\"error\":400, \"message\":\"Invalid Amount\"}"))
-                .unwrap()
-            }
-            LibError::Conflict => {
-                Response::builder()
-                .status(409)
-                .header("Content-Type", "application/json")
-                .body(Body::from("{\"error\":409, \"message\":\"Conflict\"}"))
-                .unwrap()
-            }
+            LibError::InvalidAmount | LibError::InsufficientFunds | LibError::InvalidRequest(_) => StatusCode::BAD_REQUEST,
+            LibError::Unauthorized | LibError::MfaRequired => StatusCode::UNAUTHORIZED,
+            LibError::Forbidden => StatusCode::FORBIDDEN,
+            LibError::NotFound | LibError::TraderNotFound | LibError::MerchantNotFound => StatusCode::NOT_FOUND,
+            LibError::Conflict => StatusCode::CONFLICT,
+            LibError::PayloadTooLarge => StatusCode::PAYLOAD_TOO_LARGE,
+            LibError::TooManyRequests(_) => StatusCode::TOO_MANY_REQUESTS,
+            LibError::InternalError => StatusCode::INTERNAL_SERVER_ERROR,
+            LibError::NoAvailableRequisites => StatusCode::SERVICE_UNAVAILABLE,
+        }
+    }
+
+    fn details(&self) -> Option<serde_json::Value> {
+        match self {
+            LibError::InvalidRequest(fields) => Some(serde_json::json!({ "fields": fields })),
+            LibError::TooManyRequests(retry_after) => Some(serde_json::json!({ "retry_after": retry_after })),
+            _ => None,
+        }
+    }
+}
+
+// тело ответа с ошибкой. error - HTTP статус, оставлен для старых клиентов
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct ErrorBody {
+    pub error: u16,
+    pub code: String,
+    pub message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub details: Option<serde_json::Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub correlation_id: Option<String>,
+}
+
+// LibError с подробностями для клиента и причиной для логов. причина наружу не отдается
+#[derive(Debug, thiserror::Error)]
+#[error("{kind}")]
+pub struct ApiError {
+    pub kind: LibError,
+    pub details: Option<serde_json::Value>,
+    #[source]
+    pub source: Option<Box<dyn Error + Send + Sync>>,
+}
+
+impl ApiError {
+    pub fn with_details(mut self, details: serde_json::Value) -> Self {
+        self.details = Some(details);
+        self
+    }
+
+    pub fn with_source(mut self, source: impl Error + Send + Sync + 'static) -> Self {
+        self.source = Some(Box::new(source));
+        self
+    }
+
+    pub fn body(&self) -> ErrorBody {
+        ErrorBody {
+            error: self.kind.status().as_u16(),
+            code: self.kind.code().to_string(),
+            message: self.kind.to_string(),
+            details: self.details.clone().or_else(|| self.kind.details()),
+            correlation_id: CORRELATION_ID.try_with(|id| id.clone()).ok(),
+        }
+    }
+}
+
+impl From<LibError> for ApiError {
+    fn from(kind: LibError) -> Self {
+        Self { kind, details: None, source: None }
+    }
+}
+
+// причины через source() до корня, для логов
+fn source_chain(err: &dyn Error) -> String {
+    let mut chain = err.to_string();
+    let mut source = err.source();
+    while let Some(err) = source {
+        chain.push_str(": ");
+        chain.push_str(&err.to_string());
+        source = err.source();
+    }
+    chain
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        let body = self.body();
+        if self.source.is_some() {
+            error!(code=body.code, correlation_id=body.correlation_id, err=source_chain(&self), "Request failed");
         }
+        let mut response = (self.kind.status(), Json(body)).into_response();
+        if let LibError::TooManyRequests(retry_after) = self.kind {
+            response.headers_mut().insert("Retry-After", HeaderValue::from(retry_after));
+        }
+        response
+    }
+}
+
+impl IntoResponse for LibError {
+    fn into_response(self) -> Response {
+        ApiError::from(self).into_response()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use http_body_util::BodyExt;
+    use super::*;
+
+    async fn read_body(response: Response) -> ErrorBody {
+        serde_json::from_slice(&response.into_body().collect().await.unwrap().to_bytes()).unwrap()
     }
 
-}
\ No newline at end of file
+    #[tokio::test]
+    async fn error_body() {
+        let response = LibError::invalid_field("limit", "POSITIVE", "must be positive").into_response();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        let body = read_body(response).await;
+        assert_eq!(body.code, "INVALID_REQUEST");
+        assert_eq!(body.details.unwrap()["fields"][0]["field"], "limit");
+        assert_eq!(body.correlation_id, None);
+
+        let response = CORRELATION_ID.scope("req-1".to_string(), async {
+            LibError::TooManyRequests(3).into_response()
+        }).await;
+        assert_eq!(response.headers()["Retry-After"], "3");
+        let body = read_body(response).await;
+        assert_eq!((body.error, body.correlation_id.as_deref()), (429, Some("req-1")));
+
+        assert_eq!(LibError::NoAvailableRequisites.status(), StatusCode::SERVICE_UNAVAILABLE);
+    }
+}
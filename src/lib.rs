@@ -5,6 +5,7 @@ pub mod errors;
 pub mod repository;
 pub mod use_case;
 pub mod services;
+pub mod retry;
 
 pub mod device_proto {
     tonic::include_proto!("device");
@@ -29,6 +30,10 @@ pub mod exchange_proto {
     tonic::include_proto!("exchange");
 }
 
+pub mod rpc_proto {
+    tonic::include_proto!("google.rpc");
+}
+
 pub fn add(left: u64, right: u64) -> u64 {
     left + right
 }
@@ -0,0 +1,121 @@
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use axum::body::{Body, Bytes};
+use axum::extract::MatchedPath;
+use axum::http::Request;
+use axum::response::{IntoResponse, Response};
+use http_body_util::LengthLimitError;
+use tower_layer::Layer;
+use tower_service::Service;
+use tracing::error;
+use crate::errors::{ApiError, LibError};
+use crate::models::audit::{sha256_hex, AuditRecord};
+use crate::models::{Claims, WorkerPool};
+use crate::use_case;
+
+// как DefaultBodyLimit в axum: тело читается здесь целиком до экстракторов, и без своего
+// ограничения слой принял бы тело любого размера
+pub const AUDIT_BODY_LIMIT: usize = 2 * 1024 * 1024;
+
+// пишет в audit_log запросы админов и имперсонированных пользователей.
+// claims берутся из extensions, поэтому слой ставится под middleware авторизации:
+// router.layer(AuditLayer::new(..)).layer(from_fn_with_state(.., only_merchant_middleware))
+#[derive(Clone)]
+pub struct AuditLayer {
+    pool: deadpool_postgres::Pool,
+    workers: WorkerPool,
+}
+
+impl AuditLayer {
+    pub fn new(pool: deadpool_postgres::Pool, workers: WorkerPool) -> Self {
+        Self { pool, workers }
+    }
+}
+
+impl<S> Layer<S> for AuditLayer {
+    type Service = AuditService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        AuditService { inner, pool: self.pool.clone(), workers: self.workers.clone() }
+    }
+}
+
+#[derive(Clone)]
+pub struct AuditService<S> {
+    inner: S,
+    pool: deadpool_postgres::Pool,
+    workers: WorkerPool,
+}
+
+impl<S> Service<Request<Body>> for AuditService<S>
+where
+    S: Service<Request<Body>, Response = Response> + Clone + Send + 'static,
+    S::Future: Send + 'static,
+{
+    type Response = Response;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Response, S::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request<Body>) -> Self::Future {
+        let record = req.extensions().get::<Arc<Claims>>().and_then(|claims| AuditRecord::from_claims(claims));
+        let Some(mut record) = record else {
+            return Box::pin(self.inner.call(req));
+        };
+        // poll_ready был вызван у self.inner, он и уходит в future
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+        let (pool, workers) = (self.pool.clone(), self.workers.clone());
+        Box::pin(async move {
+            let (parts, body) = req.into_parts();
+            let body_bytes = match read_body(body, AUDIT_BODY_LIMIT).await {
+                Ok(bytes) => bytes,
+                Err(e) => return Ok(e.into_response()),
+            };
+            let path = parts.uri.path_and_query().map(|p| p.as_str()).unwrap_or(parts.uri.path()).to_string();
+            record.method = parts.method.to_string();
+            record.route = parts.extensions.get::<MatchedPath>()
+                .map(|p| p.as_str().to_string())
+                .unwrap_or_else(|| parts.uri.path().to_string());
+            let mut raw = format!("{}\n{}\n", record.method, path).into_bytes();
+            raw.extend_from_slice(&body_bytes);
+            record.request_hash = sha256_hex(&raw);
+            record.path = path;
+
+            let response = inner.call(Request::from_parts(parts, body_bytes.into())).await?;
+            record.set_status(response.status().as_u16());
+            workers.execute(move || async move {
+                if let Err(e) = use_case::audit::append_audit_record(&pool, record.clone()).await {
+                    error!(id=record.id, actor_id=record.actor_id, route=record.route, err=format!("{:?}", e),
+                        "Error write audit record");
+                }
+            }).await;
+            Ok(response)
+        })
+    }
+}
+
+async fn read_body(body: Body, limit: usize) -> Result<Bytes, ApiError> {
+    axum::body::to_bytes(body, limit).await.map_err(|e| {
+        let too_large = std::error::Error::source(&e).is_some_and(|source| source.is::<LengthLimitError>());
+        let kind = if too_large { LibError::PayloadTooLarge } else { LibError::InternalError };
+        ApiError::from(kind).with_source(e)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::StatusCode;
+    use super::*;
+
+    #[tokio::test]
+    async fn body_over_limit_is_rejected() {
+        assert_eq!(read_body(Body::from("12345"), 5).await.unwrap(), Bytes::from("12345"));
+        let response = read_body(Body::from("123456"), 5).await.unwrap_err().into_response();
+        assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+    }
+}
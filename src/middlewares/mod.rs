@@ -1,21 +1,32 @@
 use std::env;
+use std::net::SocketAddr;
+use std::str::FromStr;
 use std::sync::Arc;
 use axum::body::Body;
-use axum::extract::State;
-use axum::http::{Method, Request};
+use axum::extract::{ConnectInfo, State};
+use axum::http::{HeaderValue, Method, Request};
 use axum::middleware::Next;
 use axum::response::{IntoResponse, Response};
 use chrono::{DateTime, Utc};
 use jsonwebtoken::{decode, DecodingKey, Validation};
 use once_cell::sync::Lazy;
-use tracing::error;
-use crate::errors::LibError;
+use tracing::{error, warn};
+use crate::errors::{LibError, CORRELATION_ID, CORRELATION_ID_HEADER};
 use crate::{models, use_case};
 use crate::models::Claims;
+use crate::models::ip_allowlist::{client_ip, IpAllowlist};
+use crate::models::mfa::MFA_MAX_AGE_SECS;
 use http_body_util::BodyExt;
+pub mod audit;
+pub mod rate_limit;
 pub static SECRET: Lazy<String> = Lazy::new(|| {
     env::var("JWT_SECRET").expect("JWT_SECRET не задан")
 });
+// прокси перед сервисом, которым можно верить в X-Forwarded-For, через запятую
+pub static TRUSTED_PROXIES: Lazy<IpAllowlist> = Lazy::new(|| {
+    env::var("TRUSTED_PROXIES").map(|proxies| IpAllowlist::from_str(&proxies).expect("TRUSTED_PROXIES задан неверно"))
+        .unwrap_or_default()
+});
 pub async fn only_trader_middleware (
     State(state): State<Arc<models::AuthState>>,
     mut req: Request<Body>,
@@ -99,6 +110,21 @@ pub async fn merchant_api_middleware(
         None => return LibError::Unauthorized.into_response(),
     };
 
+    // 1.1. Адрес клиента должен входить в allowlist мерчанта, проверяется до подписи.
+    // адрес соединения есть только при into_make_service_with_connect_info
+    let ip = parts.extensions.get::<ConnectInfo<SocketAddr>>().map(|ConnectInfo(peer)| {
+        client_ip(peer.ip(), headers.get("X-Forwarded-For").and_then(|h| h.to_str().ok()), &TRUSTED_PROXIES)
+    });
+    match use_case::merchant::check_ip_allowed(state.clone(), merchant_id, ip).await {
+        Ok(true) => (),
+        Ok(false) => {
+            warn!(merchant_id=merchant_id, ip=?ip, "Merchant API call from IP outside allowlist");
+            return LibError::Forbidden.into_response();
+        }
+        Err(LibError::MerchantNotFound) => return LibError::Unauthorized.into_response(),
+        Err(e) => return e.into_response(),
+    }
+
     // 2. Получаем timestamp и проверяем формат
     let (timestamp, timestamp_str) = match headers.get("X-Timestamp").and_then(|h| h.to_str().ok()) {
         Some(ts) => match DateTime::parse_from_rfc3339(ts) {
@@ -160,6 +186,7 @@ pub async fn merchant_api_middleware(
                 role: "merchant".to_string(),
                 exp: 0,
                 impersonated_by: None,
+                mfa_at: None,
             }));
             next.run(req).await
         }
@@ -225,6 +252,40 @@ pub async fn for_all_users_middleware (
     next.run(req).await
 }
 
+// id запроса для тела ошибок: X-Correlation-ID клиента или новый. ставится самым внешним слоем,
+// иначе ошибки внешних middleware уйдут без id
+pub async fn correlation_id_middleware(
+    req: Request<Body>,
+    next: Next,
+) -> Response {
+    let id = req.headers().get(CORRELATION_ID_HEADER).and_then(|h| h.to_str().ok())
+        .filter(|id| !id.is_empty() && id.len() <= 128)
+        .map(|id| id.to_string())
+        .unwrap_or_else(|| uuid::Uuid::now_v7().to_string());
+    let mut response = CORRELATION_ID.scope(id.clone(), next.run(req)).await;
+    if let Ok(value) = HeaderValue::from_str(&id) {
+        response.headers_mut().insert(CORRELATION_ID_HEADER, value);
+    }
+    response
+}
+
+// ставится под middleware авторизации на чувствительные маршруты: вывод средств, изменение реквизитов.
+// токен с mfa_at выдает use_case::mfa::verify
+pub async fn require_mfa_middleware(
+    req: Request<Body>,
+    next: Next,
+) -> Response {
+    let claims = match req.extensions().get::<Arc<Claims>>() {
+        Some(claims) => claims,
+        None => return LibError::Unauthorized.into_response(),
+    };
+    let now = Utc::now().timestamp() as usize;
+    match claims.mfa_at {
+        Some(mfa_at) if now.saturating_sub(mfa_at) <= MFA_MAX_AGE_SECS => next.run(req).await,
+        _ => LibError::MfaRequired.into_response(),
+    }
+}
+
 fn verify_jwt(token: &str) -> Result<models::Claims, LibError> {
     let token_data = decode::<Claims>(token, &DecodingKey::from_secret(SECRET.as_bytes()), &Validation::default()).map_err(|e| {
         error!("error verify jwt, {}", e);
@@ -0,0 +1,134 @@
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::net::SocketAddr;
+use axum::body::Body;
+use axum::extract::ConnectInfo;
+use axum::http::Request;
+use axum::response::{IntoResponse, Response};
+use tower_layer::Layer;
+use tower_service::Service;
+use crate::middlewares::TRUSTED_PROXIES;
+use crate::models::{AuthState, Claims};
+use crate::models::ip_allowlist::client_ip;
+use crate::models::rate_limit::RateLimit;
+use crate::use_case;
+
+// чей лимит расходует запрос
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RateLimitSubject {
+    // Claims.sub, слой ставится под middleware авторизации
+    Authenticated,
+    // адрес клиента, слой ставится над авторизацией и отсекает лишние запросы до проверки подписи
+    ClientIp,
+}
+
+impl RateLimitSubject {
+    // заголовкам до авторизации не верим: по X-Merchant-ID можно исчерпать лимит чужого мерчанта
+    fn subject_id(self, req: &Request<Body>) -> Option<String> {
+        match self {
+            RateLimitSubject::Authenticated => req.extensions().get::<Arc<Claims>>().map(|claims| claims.sub.clone()),
+            RateLimitSubject::ClientIp => req.extensions().get::<ConnectInfo<SocketAddr>>().map(|ConnectInfo(peer)| {
+                let forwarded_for = req.headers().get("X-Forwarded-For").and_then(|h| h.to_str().ok());
+                format!("ip:{}", client_ip(peer.ip(), forwarded_for, &TRUSTED_PROXIES))
+            }),
+        }
+    }
+}
+
+// ограничивает запросы субъекта к группе маршрутов. для merchant_api слой per_ip ставится над
+// merchant_api_middleware, а new - под ним, чтобы лимит мерчанта расходовали только подписанные запросы
+#[derive(Clone)]
+pub struct RateLimitLayer {
+    state: Arc<AuthState>,
+    route_group: &'static str,
+    default: RateLimit,
+    subject: RateLimitSubject,
+}
+
+impl RateLimitLayer {
+    pub fn new(state: Arc<AuthState>, route_group: &'static str, default: RateLimit) -> Self {
+        Self { state, route_group, default, subject: RateLimitSubject::Authenticated }
+    }
+
+    pub fn per_ip(state: Arc<AuthState>, route_group: &'static str, default: RateLimit) -> Self {
+        Self { state, route_group, default, subject: RateLimitSubject::ClientIp }
+    }
+}
+
+impl<S> Layer<S> for RateLimitLayer {
+    type Service = RateLimitService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        RateLimitService { inner, layer: self.clone() }
+    }
+}
+
+#[derive(Clone)]
+pub struct RateLimitService<S> {
+    inner: S,
+    layer: RateLimitLayer,
+}
+
+impl<S> Service<Request<Body>> for RateLimitService<S>
+where
+    S: Service<Request<Body>, Response = Response> + Clone + Send + 'static,
+    S::Future: Send + 'static,
+{
+    type Response = Response;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Response, S::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request<Body>) -> Self::Future {
+        // без Claims запрос отклонит авторизация, без адреса соединения (сервис запущен без
+        // into_make_service_with_connect_info) лимит по IP не считается
+        let Some(subject_id) = self.layer.subject.subject_id(&req) else {
+            return Box::pin(self.inner.call(req));
+        };
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+        let layer = self.layer.clone();
+        Box::pin(async move {
+            let (state, route_group) = (&layer.state, layer.route_group);
+            let checked = match layer.subject {
+                RateLimitSubject::Authenticated =>
+                    use_case::rate_limit::check_rate_limit(state, &subject_id, route_group, layer.default).await,
+                // адреса не настраиваются, лимит слоя действует без обращения к БД
+                RateLimitSubject::ClientIp =>
+                    use_case::rate_limit::check_fixed_rate_limit(state, &subject_id, route_group, layer.default).await,
+            };
+            if let Err(e) = checked {
+                return Ok(e.into_response());
+            }
+            inner.call(req).await
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn subject_is_authenticated_or_peer() {
+        let mut req = Request::builder().header("X-Merchant-ID", "m1").header("X-Forwarded-For", "6.6.6.6")
+            .body(Body::empty()).unwrap();
+        assert_eq!(RateLimitSubject::Authenticated.subject_id(&req), None);
+        assert_eq!(RateLimitSubject::ClientIp.subject_id(&req), None);
+
+        req.extensions_mut().insert(ConnectInfo(SocketAddr::from(([1, 2, 3, 4], 443))));
+        assert_eq!(RateLimitSubject::ClientIp.subject_id(&req), Some("ip:1.2.3.4".to_string()));
+        req.extensions_mut().insert(Arc::new(Claims {
+            sub: "m2".to_string(),
+            role: "merchant".to_string(),
+            exp: 0,
+            impersonated_by: None,
+            mfa_at: None,
+        }));
+        assert_eq!(RateLimitSubject::Authenticated.subject_id(&req), Some("m2".to_string()));
+    }
+}
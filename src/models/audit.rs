@@ -0,0 +1,204 @@
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+use chrono::{NaiveDateTime, SubsecRound, Utc};
+use rsa::sha2::{Digest, Sha256};
+use serde::{Deserialize, Serialize};
+use crate::models::Claims;
+
+// prev_hash первой записи журнала
+pub const AUDIT_GENESIS_HASH: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+
+// в claims имперсонированного пользователя нет роли того, кто за ним стоит, имперсонировать может только админ
+pub const IMPERSONATOR_ROLE: &str = "admin";
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum AuditOutcome {
+    Success,
+    Denied,
+    Failed,
+}
+
+impl AuditOutcome {
+    pub fn from_status(status: u16) -> Self {
+        match status {
+            200..=399 => AuditOutcome::Success,
+            401 | 403 => AuditOutcome::Denied,
+            _ => AuditOutcome::Failed,
+        }
+    }
+}
+
+impl Display for AuditOutcome {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AuditOutcome::Success => f.write_str("SUCCESS"),
+            AuditOutcome::Denied => f.write_str("DENIED"),
+            AuditOutcome::Failed => f.write_str("FAILED"),
+        }
+    }
+}
+
+impl FromStr for AuditOutcome {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "SUCCESS" => Ok(AuditOutcome::Success),
+            "DENIED" => Ok(AuditOutcome::Denied),
+            "FAILED" => Ok(AuditOutcome::Failed),
+            _ => Err(format!("unknown audit outcome {}", s)),
+        }
+    }
+}
+
+// действие админа или действие от имени имперсонированного пользователя
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct AuditRecord {
+    pub id: String,
+    pub actor_id: String,
+    pub actor_role: String,
+    // пользователь, от имени которого действовал actor, None - действовал от себя
+    pub subject_id: Option<String>,
+    pub subject_role: Option<String>,
+    pub method: String,
+    pub route: String,
+    pub path: String,
+    pub status: i32,
+    pub outcome: AuditOutcome,
+    // sha256 метода, пути с query и тела запроса
+    pub request_hash: String,
+    pub created_at: NaiveDateTime,
+}
+
+impl AuditRecord {
+    // None - запрос не требует аудита
+    pub fn from_claims(claims: &Claims) -> Option<Self> {
+        let (actor_id, actor_role, subject_id, subject_role) = match claims.impersonated_by.as_ref() {
+            Some(admin_id) => (admin_id.clone(), IMPERSONATOR_ROLE.to_string(),
+                               Some(claims.sub.clone()), Some(claims.role.clone())),
+            None if claims.role.to_lowercase() == "admin" => (claims.sub.clone(), claims.role.clone(), None, None),
+            None => return None,
+        };
+        Some(Self {
+            id: uuid::Uuid::now_v7().to_string(),
+            actor_id,
+            actor_role,
+            subject_id,
+            subject_role,
+            method: String::new(),
+            route: String::new(),
+            path: String::new(),
+            status: 0,
+            outcome: AuditOutcome::Failed,
+            request_hash: String::new(),
+            // TIMESTAMP в БД хранит микросекунды, хэш должен сойтись после чтения
+            created_at: Utc::now().naive_utc().trunc_subsecs(6),
+        })
+    }
+
+    pub fn set_status(&mut self, status: u16) {
+        self.status = status as i32;
+        self.outcome = AuditOutcome::from_status(status);
+    }
+
+    // хэш записи вместе с хэшем предыдущей, изменение или удаление любой записи ломает цепочку дальше
+    pub fn chain_hash(&self, prev_hash: &str) -> String {
+        let line = [
+            prev_hash,
+            self.id.as_str(),
+            self.actor_id.as_str(),
+            self.actor_role.as_str(),
+            self.subject_id.as_deref().unwrap_or(""),
+            self.subject_role.as_deref().unwrap_or(""),
+            self.method.as_str(),
+            self.route.as_str(),
+            self.path.as_str(),
+            &self.status.to_string(),
+            &self.outcome.to_string(),
+            self.request_hash.as_str(),
+            &self.created_at.format("%Y-%m-%dT%H:%M:%S%.6f").to_string(),
+        ].join("\n");
+        sha256_hex(line.as_bytes())
+    }
+}
+
+pub fn sha256_hex(data: &[u8]) -> String {
+    Sha256::digest(data).iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+// сохраненная запись журнала
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct AuditEntry {
+    pub seq: i64,
+    pub record: AuditRecord,
+    pub prev_hash: String,
+    pub hash: String,
+}
+
+impl AuditEntry {
+    pub fn is_valid(&self, prev_hash: &str) -> bool {
+        self.prev_hash == prev_hash && self.record.chain_hash(prev_hash) == self.hash
+    }
+}
+
+impl From<&tokio_postgres::Row> for AuditEntry {
+    fn from(row: &tokio_postgres::Row) -> Self {
+        Self {
+            seq: row.get("seq"),
+            record: AuditRecord {
+                id: row.get("id"),
+                actor_id: row.get("actor_id"),
+                actor_role: row.get("actor_role"),
+                subject_id: row.get("subject_id"),
+                subject_role: row.get("subject_role"),
+                method: row.get("method"),
+                route: row.get("route"),
+                path: row.get("path"),
+                status: row.get("status"),
+                outcome: AuditOutcome::from_str(row.get("outcome")).unwrap(),
+                request_hash: row.get("request_hash"),
+                created_at: row.get("created_at"),
+            },
+            prev_hash: row.get("prev_hash"),
+            hash: row.get("hash"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn chain_detects_tampering() {
+        let claims = Claims {
+            sub: "m1".to_string(),
+            role: "merchant".to_string(),
+            exp: 0,
+            impersonated_by: Some("a1".to_string()),
+            mfa_at: None,
+        };
+        let mut first = AuditRecord::from_claims(&claims).unwrap();
+        assert_eq!(first.actor_id, "a1");
+        assert_eq!(first.subject_id.as_deref(), Some("m1"));
+        first.set_status(200);
+        let second = AuditRecord::from_claims(&claims).unwrap();
+
+        let first = AuditEntry { seq: 1, hash: first.chain_hash(AUDIT_GENESIS_HASH),
+            prev_hash: AUDIT_GENESIS_HASH.to_string(), record: first };
+        let second = AuditEntry { seq: 2, hash: second.chain_hash(&first.hash),
+            prev_hash: first.hash.clone(), record: second };
+        assert!(first.is_valid(AUDIT_GENESIS_HASH));
+        assert!(second.is_valid(&first.hash));
+
+        let mut tampered = first.clone();
+        tampered.record.status = 500;
+        assert!(!tampered.is_valid(AUDIT_GENESIS_HASH));
+        // вместо удаленной первой записи
+        assert!(!second.is_valid(AUDIT_GENESIS_HASH));
+
+        let trader = Claims { sub: "t1".to_string(), role: "trader".to_string(), exp: 0, impersonated_by: None,
+            mfa_at: None };
+        assert!(AuditRecord::from_claims(&trader).is_none());
+    }
+}
@@ -0,0 +1,172 @@
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
+use crate::errors::LibError;
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum BlockTarget {
+    Trader,
+    Merchant,
+}
+
+impl Display for BlockTarget {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BlockTarget::Trader => f.write_str("trader"),
+            BlockTarget::Merchant => f.write_str("merchant"),
+        }
+    }
+}
+
+impl FromStr for BlockTarget {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "trader" => Ok(BlockTarget::Trader),
+            "merchant" => Ok(BlockTarget::Merchant),
+            _ => Err(format!("unknown block target {}", s)),
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum BlockAction {
+    Block,
+    Unblock,
+}
+
+impl Display for BlockAction {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BlockAction::Block => f.write_str("BLOCK"),
+            BlockAction::Unblock => f.write_str("UNBLOCK"),
+        }
+    }
+}
+
+impl FromStr for BlockAction {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "BLOCK" => Ok(BlockAction::Block),
+            "UNBLOCK" => Ok(BlockAction::Unblock),
+            _ => Err(format!("unknown block action {}", s)),
+        }
+    }
+}
+
+// актор снятия временной блокировки по истечении срока
+pub const SYSTEM_ACTOR: &str = "system";
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct BlockRequest {
+    pub reason: String,
+    // None - бессрочно, для UNBLOCK игнорируется
+    pub expires_at: Option<NaiveDateTime>,
+}
+
+impl BlockRequest {
+    // причина попадает в историю блокировок, поэтому обязательна.
+    // срок в прошлом снял бы блокировку при следующем запуске expire_temporary_blocks
+    pub fn validate(&self, now: NaiveDateTime) -> Result<(), LibError> {
+        if self.reason.trim().is_empty() {
+            return Err(LibError::invalid_field("reason", "REQUIRED", "must not be blank"));
+        }
+        if self.expires_at.is_some_and(|expires_at| expires_at <= now) {
+            return Err(LibError::invalid_field("expires_at", "FUTURE", "must be in the future"));
+        }
+        Ok(())
+    }
+}
+
+// запись истории блокировок, только добавляется
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct BlockEvent {
+    pub id: String,
+    pub target: BlockTarget,
+    pub target_id: String,
+    pub action: BlockAction,
+    pub reason: String,
+    pub actor_id: String,
+    pub actor_role: String,
+    pub expires_at: Option<NaiveDateTime>,
+    pub created_at: NaiveDateTime,
+}
+
+impl BlockEvent {
+    // временную блокировку можно снять, только пока она последнее событие цели и срок истек
+    pub fn is_expired(&self, latest: Option<&BlockEvent>, now: NaiveDateTime) -> bool {
+        self.action == BlockAction::Block
+            && self.expires_at.is_some_and(|expires_at| expires_at <= now)
+            && latest.is_some_and(|latest| latest.id == self.id)
+    }
+}
+
+impl From<&tokio_postgres::Row> for BlockEvent {
+    fn from(row: &tokio_postgres::Row) -> Self {
+        Self {
+            id: row.get("id"),
+            target: BlockTarget::from_str(row.get("target")).unwrap(),
+            target_id: row.get("target_id"),
+            action: BlockAction::from_str(row.get("action")).unwrap(),
+            reason: row.get("reason"),
+            actor_id: row.get("actor_id"),
+            actor_role: row.get("actor_role"),
+            expires_at: row.get("expires_at"),
+            created_at: row.get("created_at"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(id: &str, action: BlockAction, expires_at: Option<NaiveDateTime>) -> BlockEvent {
+        BlockEvent {
+            id: id.to_string(),
+            target: BlockTarget::Trader,
+            target_id: "t1".to_string(),
+            action,
+            reason: String::new(),
+            actor_id: "admin".to_string(),
+            actor_role: "admin".to_string(),
+            expires_at,
+            created_at: NaiveDateTime::default(),
+        }
+    }
+
+    #[test]
+    fn expiry_requires_latest_block() {
+        let now = NaiveDateTime::default() + chrono::Duration::hours(2);
+        let temporary = event("b1", BlockAction::Block, Some(now - chrono::Duration::hours(1)));
+        assert!(temporary.is_expired(Some(&temporary), now));
+        assert!(!temporary.is_expired(Some(&temporary), now - chrono::Duration::hours(2)));
+        // после выборки админ снял блокировку или выдал новую - старый срок ее не снимает
+        assert!(!temporary.is_expired(Some(&event("u1", BlockAction::Unblock, None)), now));
+        assert!(!temporary.is_expired(Some(&event("b2", BlockAction::Block, None)), now));
+        assert!(!temporary.is_expired(None, now));
+        let permanent = event("b3", BlockAction::Block, None);
+        assert!(!permanent.is_expired(Some(&permanent), now));
+    }
+
+    #[test]
+    fn block_request_expiry_in_future() {
+        let now = NaiveDateTime::default() + chrono::Duration::hours(2);
+        let request = |expires_at| BlockRequest { reason: "fraud".to_string(), expires_at };
+        assert!(request(None).validate(now).is_ok());
+        assert!(request(Some(now + chrono::Duration::minutes(1))).validate(now).is_ok());
+        assert!(matches!(request(Some(now)).validate(now), Err(LibError::InvalidRequest(_))));
+        assert!(matches!(request(Some(now - chrono::Duration::hours(1))).validate(now), Err(LibError::InvalidRequest(_))));
+    }
+
+    #[test]
+    fn block_request_requires_reason() {
+        let request = BlockRequest { reason: " \t".to_string(), expires_at: None };
+        assert_eq!(request.validate(NaiveDateTime::default()),
+                   Err(LibError::invalid_field("reason", "REQUIRED", "must not be blank")));
+    }
+}
@@ -0,0 +1,146 @@
+use std::fmt::{Display, Formatter};
+use std::net::IpAddr;
+use std::str::FromStr;
+use serde::{Deserialize, Serialize};
+
+// диапазон адресов в нотации CIDR, адрес без префикса - один хост
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IpNet {
+    addr: IpAddr,
+    prefix: u8,
+}
+
+impl IpNet {
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        // IPv4 за IPv6-сокетом приходит как ::ffff:a.b.c.d
+        match (self.addr, ip.to_canonical()) {
+            (IpAddr::V4(net), IpAddr::V4(ip)) => mask_eq(&net.octets(), &ip.octets(), self.prefix),
+            (IpAddr::V6(net), IpAddr::V6(ip)) => mask_eq(&net.octets(), &ip.octets(), self.prefix),
+            _ => false,
+        }
+    }
+}
+
+fn mask_eq(net: &[u8], ip: &[u8], prefix: u8) -> bool {
+    let (bytes, bits) = ((prefix / 8) as usize, prefix % 8);
+    if net[..bytes] != ip[..bytes] {
+        return false;
+    }
+    bits == 0 || (net[bytes] ^ ip[bytes]) & (0xff << (8 - bits)) == 0
+}
+
+impl Display for IpNet {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}/{}", self.addr, self.prefix)
+    }
+}
+
+impl FromStr for IpNet {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (addr, prefix) = match s.trim().split_once('/') {
+            Some((addr, prefix)) => (addr, Some(prefix)),
+            None => (s.trim(), None),
+        };
+        let addr = IpAddr::from_str(addr).map_err(|_| format!("invalid CIDR {}", s))?.to_canonical();
+        let max = if addr.is_ipv4() { 32 } else { 128 };
+        let prefix = match prefix {
+            Some(prefix) => prefix.parse::<u8>().ok().filter(|p| *p <= max).ok_or(format!("invalid CIDR {}", s))?,
+            None => max,
+        };
+        Ok(Self { addr, prefix })
+    }
+}
+
+// пустой список - ограничений нет
+#[derive(Deserialize, Serialize, Debug, Clone, Default, PartialEq, Eq)]
+#[serde(try_from = "Vec<String>", into = "Vec<String>")]
+pub struct IpAllowlist(Vec<IpNet>);
+
+impl IpAllowlist {
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        self.0.iter().any(|net| net.contains(ip))
+    }
+
+    pub fn allows(&self, ip: IpAddr) -> bool {
+        self.is_empty() || self.contains(ip)
+    }
+}
+
+impl TryFrom<Vec<String>> for IpAllowlist {
+    type Error = String;
+    fn try_from(value: Vec<String>) -> Result<Self, Self::Error> {
+        value.iter().map(|net| IpNet::from_str(net)).collect::<Result<Vec<_>, _>>().map(IpAllowlist)
+    }
+}
+
+impl From<IpAllowlist> for Vec<String> {
+    fn from(value: IpAllowlist) -> Self {
+        value.0.iter().map(|net| net.to_string()).collect()
+    }
+}
+
+// формат значения в кэше и переменных окружения: диапазоны через запятую
+impl Display for IpAllowlist {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&Vec::<String>::from(self.clone()).join(","))
+    }
+}
+
+impl FromStr for IpAllowlist {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.split(',').filter(|net| !net.trim().is_empty()).map(IpNet::from_str)
+            .collect::<Result<Vec<_>, _>>().map(IpAllowlist)
+    }
+}
+
+// адрес клиента: если соединение пришло от доверенного прокси, X-Forwarded-For читается справа
+// налево до первого адреса не из доверенных. левее него адреса подставляет сам клиент, им верить нельзя
+pub fn client_ip(peer: IpAddr, forwarded_for: Option<&str>, trusted_proxies: &IpAllowlist) -> IpAddr {
+    let mut ip = peer.to_canonical();
+    if !trusted_proxies.contains(ip) {
+        return ip;
+    }
+    for hop in forwarded_for.unwrap_or("").rsplit(',') {
+        match IpAddr::from_str(hop.trim()) {
+            Ok(hop) => {
+                ip = hop.to_canonical();
+                if !trusted_proxies.contains(ip) {
+                    break;
+                }
+            }
+            // мусор в заголовке - дальше цепочке не верим
+            Err(_) => break,
+        }
+    }
+    ip
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn allowlist_and_forwarded_for() {
+        let allowlist = IpAllowlist::from_str("10.1.0.0/16, 192.168.5.7, 2001:db8::/32").unwrap();
+        assert!(allowlist.allows("10.1.200.3".parse().unwrap()));
+        assert!(!allowlist.allows("10.2.0.1".parse().unwrap()));
+        assert!(allowlist.allows("::ffff:192.168.5.7".parse().unwrap()));
+        assert!(allowlist.allows("2001:db8:1::1".parse().unwrap()));
+        assert!(IpAllowlist::default().allows("8.8.8.8".parse().unwrap()));
+        assert!(IpNet::from_str("10.0.0.0/33").is_err());
+        assert_eq!(IpAllowlist::from_str(&allowlist.to_string()), Ok(allowlist));
+
+        let proxies = IpAllowlist::from_str("10.0.0.0/8").unwrap();
+        let proxy = "10.0.0.2".parse().unwrap();
+        let client = "1.2.3.4".parse::<IpAddr>().unwrap();
+        assert_eq!(client_ip(proxy, Some("6.6.6.6, 1.2.3.4, 10.0.0.5"), &proxies), client);
+        assert_eq!(client_ip(client, Some("6.6.6.6"), &proxies), client);
+        assert_eq!(client_ip(proxy, None, &proxies), proxy);
+    }
+}
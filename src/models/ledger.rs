@@ -0,0 +1,408 @@
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+use chrono::NaiveDateTime;
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use crate::errors::LibError;
+use crate::{merchant_proto, trader_proto};
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum LedgerOwner {
+    Trader,
+    Merchant,
+}
+
+impl Display for LedgerOwner {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LedgerOwner::Trader => f.write_str("trader"),
+            LedgerOwner::Merchant => f.write_str("merchant"),
+        }
+    }
+}
+
+impl FromStr for LedgerOwner {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "trader" => Ok(LedgerOwner::Trader),
+            "merchant" => Ok(LedgerOwner::Merchant),
+            _ => Err(format!("unknown ledger owner {}", s)),
+        }
+    }
+}
+
+// External - контрсчет для денег, которые приходят извне или уходят наружу
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum LedgerAccount {
+    Main,
+    Frozen,
+    Fees,
+    External,
+}
+
+impl LedgerAccount {
+    pub fn allows_negative(&self) -> bool {
+        matches!(self, LedgerAccount::External)
+    }
+}
+
+impl Display for LedgerAccount {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LedgerAccount::Main => f.write_str("main"),
+            LedgerAccount::Frozen => f.write_str("frozen"),
+            LedgerAccount::Fees => f.write_str("fees"),
+            LedgerAccount::External => f.write_str("external"),
+        }
+    }
+}
+
+impl FromStr for LedgerAccount {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "main" => Ok(LedgerAccount::Main),
+            "frozen" => Ok(LedgerAccount::Frozen),
+            "fees" => Ok(LedgerAccount::Fees),
+            "external" => Ok(LedgerAccount::External),
+            _ => Err(format!("unknown ledger account {}", s)),
+        }
+    }
+}
+
+// общий тип для trader_proto::BalanceActionType и merchant_proto::BalanceActionType,
+// у них разная нумерация WITHDRAW_*
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum BalanceAction {
+    FrozeSoft,
+    FrozeHard,
+    Unfroze,
+    WithdrawFrozen,
+    WithdrawMain,
+    Deposit,
+    // начальный остаток, есть только в леджере и в сервисы не отправляется
+    Opening,
+}
+
+impl BalanceAction {
+    // действие, которое отменяет это. начальный остаток не отменяется, его исправляют новым посевом
+    pub fn reversal(&self) -> Self {
+        match self {
+            BalanceAction::FrozeSoft | BalanceAction::FrozeHard => BalanceAction::Unfroze,
+            BalanceAction::Unfroze => BalanceAction::FrozeHard,
+            BalanceAction::WithdrawFrozen | BalanceAction::WithdrawMain => BalanceAction::Deposit,
+            BalanceAction::Deposit => BalanceAction::WithdrawMain,
+            BalanceAction::Opening => BalanceAction::Opening,
+        }
+    }
+}
+
+impl Display for BalanceAction {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BalanceAction::FrozeSoft => f.write_str("FROZE_SOFT"),
+            BalanceAction::FrozeHard => f.write_str("FROZE_HARD"),
+            BalanceAction::Unfroze => f.write_str("UNFROZE"),
+            BalanceAction::WithdrawFrozen => f.write_str("WITHDRAW_FROZEN"),
+            BalanceAction::WithdrawMain => f.write_str("WITHDRAW_MAIN"),
+            BalanceAction::Deposit => f.write_str("DEPOSIT"),
+            BalanceAction::Opening => f.write_str("OPENING"),
+        }
+    }
+}
+
+impl FromStr for BalanceAction {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "FROZE_SOFT" => Ok(BalanceAction::FrozeSoft),
+            "FROZE_HARD" => Ok(BalanceAction::FrozeHard),
+            "UNFROZE" => Ok(BalanceAction::Unfroze),
+            "WITHDRAW_FROZEN" | "WITHDRAW_FROZE" => Ok(BalanceAction::WithdrawFrozen),
+            "WITHDRAW_MAIN" => Ok(BalanceAction::WithdrawMain),
+            "DEPOSIT" => Ok(BalanceAction::Deposit),
+            "OPENING" => Ok(BalanceAction::Opening),
+            _ => Err(format!("unknown balance action {}", s)),
+        }
+    }
+}
+
+impl From<trader_proto::BalanceActionType> for BalanceAction {
+    fn from(value: trader_proto::BalanceActionType) -> Self {
+        match value {
+            trader_proto::BalanceActionType::FrozeSoft => BalanceAction::FrozeSoft,
+            trader_proto::BalanceActionType::FrozeHard => BalanceAction::FrozeHard,
+            trader_proto::BalanceActionType::Unfroze => BalanceAction::Unfroze,
+            trader_proto::BalanceActionType::WithdrawFrozen => BalanceAction::WithdrawFrozen,
+            trader_proto::BalanceActionType::WithdrawMain => BalanceAction::WithdrawMain,
+            trader_proto::BalanceActionType::Deposit => BalanceAction::Deposit,
+        }
+    }
+}
+
+impl From<merchant_proto::BalanceActionType> for BalanceAction {
+    fn from(value: merchant_proto::BalanceActionType) -> Self {
+        match value {
+            merchant_proto::BalanceActionType::FrozeSoft => BalanceAction::FrozeSoft,
+            merchant_proto::BalanceActionType::FrozeHard => BalanceAction::FrozeHard,
+            merchant_proto::BalanceActionType::Unfroze => BalanceAction::Unfroze,
+            merchant_proto::BalanceActionType::WithdrawFroze => BalanceAction::WithdrawFrozen,
+            merchant_proto::BalanceActionType::WithdrawMain => BalanceAction::WithdrawMain,
+            merchant_proto::BalanceActionType::Deposit => BalanceAction::Deposit,
+        }
+    }
+}
+
+impl TryFrom<BalanceAction> for trader_proto::BalanceActionType {
+    type Error = LibError;
+    fn try_from(value: BalanceAction) -> Result<Self, Self::Error> {
+        match value {
+            BalanceAction::FrozeSoft => Ok(trader_proto::BalanceActionType::FrozeSoft),
+            BalanceAction::FrozeHard => Ok(trader_proto::BalanceActionType::FrozeHard),
+            BalanceAction::Unfroze => Ok(trader_proto::BalanceActionType::Unfroze),
+            BalanceAction::WithdrawFrozen => Ok(trader_proto::BalanceActionType::WithdrawFrozen),
+            BalanceAction::WithdrawMain => Ok(trader_proto::BalanceActionType::WithdrawMain),
+            BalanceAction::Deposit => Ok(trader_proto::BalanceActionType::Deposit),
+            BalanceAction::Opening => Err(LibError::InvalidAmount),
+        }
+    }
+}
+
+impl TryFrom<BalanceAction> for merchant_proto::BalanceActionType {
+    type Error = LibError;
+    fn try_from(value: BalanceAction) -> Result<Self, Self::Error> {
+        match value {
+            BalanceAction::FrozeSoft => Ok(merchant_proto::BalanceActionType::FrozeSoft),
+            BalanceAction::FrozeHard => Ok(merchant_proto::BalanceActionType::FrozeHard),
+            BalanceAction::Unfroze => Ok(merchant_proto::BalanceActionType::Unfroze),
+            BalanceAction::WithdrawFrozen => Ok(merchant_proto::BalanceActionType::WithdrawFroze),
+            BalanceAction::WithdrawMain => Ok(merchant_proto::BalanceActionType::WithdrawMain),
+            BalanceAction::Deposit => Ok(merchant_proto::BalanceActionType::Deposit),
+            BalanceAction::Opening => Err(LibError::InvalidAmount),
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct LedgerPosting {
+    pub account: LedgerAccount,
+    pub amount: Decimal,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct LedgerEntry {
+    pub owner_type: LedgerOwner,
+    pub owner_id: String,
+    pub action: BalanceAction,
+    pub amount: Decimal,
+    // часть amount, которая уходит в комиссии (только для DEPOSIT и WITHDRAW_*)
+    pub fee: Decimal,
+    pub reference_id: Option<String>,
+    pub idempotent_key: String,
+}
+
+impl LedgerEntry {
+    // reference_id - платеж, возврат или пачка выплат, по которым проводится действие
+    pub fn new(owner_type: LedgerOwner, owner_id: String, action: BalanceAction, amount: Decimal,
+               reference_id: String, idempotent_key: String) -> Self {
+        Self {
+            owner_type,
+            owner_id,
+            action,
+            amount,
+            fee: Decimal::ZERO,
+            reference_id: Some(reference_id),
+            idempotent_key,
+        }
+    }
+
+    pub fn trader(trader_id: String, amount: Decimal, action: trader_proto::BalanceActionType,
+                  reference_id: String, idempotent_key: String) -> Self {
+        Self::new(LedgerOwner::Trader, trader_id, action.into(), amount, reference_id, idempotent_key)
+    }
+
+    pub fn merchant(merchant_id: String, amount: Decimal, action: merchant_proto::BalanceActionType,
+                    reference_id: String, idempotent_key: String) -> Self {
+        Self::new(LedgerOwner::Merchant, merchant_id, action.into(), amount, reference_id, idempotent_key)
+    }
+
+    // проводки по действию, сумма всегда равна нулю
+    pub fn postings(&self) -> Result<Vec<LedgerPosting>, LibError> {
+        if self.amount <= Decimal::ZERO || self.fee < Decimal::ZERO || self.fee > self.amount {
+            return Err(LibError::InvalidAmount);
+        }
+        let amount = self.amount;
+        let net = self.amount - self.fee;
+        let posting = |account, amount| LedgerPosting { account, amount };
+        let mut postings = match self.action {
+            BalanceAction::FrozeSoft | BalanceAction::FrozeHard | BalanceAction::Unfroze
+                if !self.fee.is_zero() => return Err(LibError::InvalidAmount),
+            // начальный остаток проводится через LedgerOpening
+            BalanceAction::Opening => return Err(LibError::InvalidAmount),
+            BalanceAction::FrozeSoft | BalanceAction::FrozeHard => vec![
+                posting(LedgerAccount::Main, -amount),
+                posting(LedgerAccount::Frozen, amount),
+            ],
+            BalanceAction::Unfroze => vec![
+                posting(LedgerAccount::Frozen, -amount),
+                posting(LedgerAccount::Main, amount),
+            ],
+            BalanceAction::WithdrawFrozen => vec![
+                posting(LedgerAccount::Frozen, -amount),
+                posting(LedgerAccount::External, net),
+            ],
+            BalanceAction::WithdrawMain => vec![
+                posting(LedgerAccount::Main, -amount),
+                posting(LedgerAccount::External, net),
+            ],
+            BalanceAction::Deposit => vec![
+                posting(LedgerAccount::External, -amount),
+                posting(LedgerAccount::Main, net),
+            ],
+        };
+        if !self.fee.is_zero() {
+            postings.push(posting(LedgerAccount::Fees, self.fee));
+        }
+        Ok(postings)
+    }
+}
+
+// остаток, который был на балансе в сервисе до появления леджера. без него сумма проводок
+// не равна балансу, поэтому отчеты и сверки остатков требуют его наличия
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct LedgerOpening {
+    pub owner_type: LedgerOwner,
+    pub owner_id: String,
+    pub main: Decimal,
+    pub frozen: Decimal,
+}
+
+impl LedgerOpening {
+    pub fn idempotent_key(owner_type: LedgerOwner, owner_id: &str) -> String {
+        format!("opening:{}:{}", owner_type, owner_id)
+    }
+
+    // сумма в ledger_transactions, для нулевого остатка запись тоже нужна как отметка о посеве
+    pub fn amount(&self) -> Decimal {
+        self.main.abs() + self.frozen.abs()
+    }
+
+    // External -> Main и External -> Frozen, нулевые проводки не пишутся
+    pub fn postings(&self) -> Vec<LedgerPosting> {
+        [
+            (LedgerAccount::External, -(self.main + self.frozen)),
+            (LedgerAccount::Main, self.main),
+            (LedgerAccount::Frozen, self.frozen),
+        ].into_iter()
+            .filter(|(_, amount)| !amount.is_zero())
+            .map(|(account, amount)| LedgerPosting { account, amount })
+            .collect()
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct LedgerTransaction {
+    pub id: String,
+    pub owner_type: LedgerOwner,
+    pub owner_id: String,
+    pub action: BalanceAction,
+    pub amount: Decimal,
+    pub fee: Decimal,
+    pub reference_id: Option<String>,
+    pub idempotent_key: String,
+    pub created_at: NaiveDateTime,
+}
+
+impl From<&tokio_postgres::Row> for LedgerTransaction {
+    fn from(row: &tokio_postgres::Row) -> Self {
+        Self {
+            id: row.get("id"),
+            owner_type: LedgerOwner::from_str(row.get("owner_type")).unwrap(),
+            owner_id: row.get("owner_id"),
+            action: BalanceAction::from_str(row.get("action_type")).unwrap(),
+            amount: row.get("amount"),
+            fee: row.get("fee"),
+            reference_id: row.get("reference_id"),
+            idempotent_key: row.get("idempotent_key"),
+            created_at: row.get("created_at"),
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Default, Clone, PartialEq, Eq)]
+pub struct LedgerBalances {
+    pub main: Decimal,
+    pub frozen: Decimal,
+    pub fees: Decimal,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct BalanceMismatch {
+    pub owner_type: LedgerOwner,
+    pub owner_id: String,
+    pub account: LedgerAccount,
+    pub recorded: Decimal,
+    pub computed: Decimal,
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_decimal::dec;
+    use super::*;
+
+    fn entry(action: BalanceAction, amount: Decimal, fee: Decimal) -> LedgerEntry {
+        LedgerEntry {
+            owner_type: LedgerOwner::Trader,
+            owner_id: "t1".to_string(),
+            action,
+            amount,
+            fee,
+            reference_id: None,
+            idempotent_key: "k".to_string(),
+        }
+    }
+
+    #[test]
+    fn postings_are_balanced() {
+        let actions = [
+            BalanceAction::FrozeSoft,
+            BalanceAction::FrozeHard,
+            BalanceAction::Unfroze,
+            BalanceAction::WithdrawFrozen,
+            BalanceAction::WithdrawMain,
+            BalanceAction::Deposit,
+        ];
+        for action in actions {
+            let postings = entry(action, dec!(100.5), Decimal::ZERO).postings().unwrap();
+            assert_eq!(postings.iter().map(|p| p.amount).sum::<Decimal>(), Decimal::ZERO, "{}", action);
+        }
+        let postings = entry(BalanceAction::WithdrawFrozen, dec!(100), dec!(2.5)).postings().unwrap();
+        assert_eq!(postings.iter().map(|p| p.amount).sum::<Decimal>(), Decimal::ZERO);
+        assert!(postings.contains(&LedgerPosting { account: LedgerAccount::Fees, amount: dec!(2.5) }));
+    }
+
+    #[test]
+    fn postings_reject_invalid_amounts() {
+        assert_eq!(entry(BalanceAction::Deposit, Decimal::ZERO, Decimal::ZERO).postings(), Err(LibError::InvalidAmount));
+        assert_eq!(entry(BalanceAction::Deposit, dec!(1), dec!(2)).postings(), Err(LibError::InvalidAmount));
+        assert_eq!(entry(BalanceAction::FrozeHard, dec!(10), dec!(1)).postings(), Err(LibError::InvalidAmount));
+        assert_eq!(entry(BalanceAction::Opening, dec!(10), Decimal::ZERO).postings(), Err(LibError::InvalidAmount));
+    }
+
+    #[test]
+    fn opening_postings_are_balanced() {
+        let opening = LedgerOpening { owner_type: LedgerOwner::Merchant, owner_id: "m1".to_string(),
+            main: dec!(150), frozen: dec!(-20) };
+        let postings = opening.postings();
+        assert_eq!(postings.iter().map(|p| p.amount).sum::<Decimal>(), Decimal::ZERO);
+        assert!(postings.contains(&LedgerPosting { account: LedgerAccount::External, amount: dec!(-130) }));
+        assert_eq!(opening.amount(), dec!(170));
+
+        let empty = LedgerOpening { main: Decimal::ZERO, frozen: Decimal::ZERO, ..opening };
+        assert!(empty.postings().is_empty());
+    }
+}
@@ -0,0 +1,183 @@
+use chrono::NaiveDateTime;
+use rand::RngCore;
+use rand::rngs::OsRng;
+use hmac::{Hmac, Mac};
+use sha1::Sha1;
+use serde::{Deserialize, Serialize};
+use crate::models::audit::sha256_hex;
+
+pub const TOTP_DIGITS: u32 = 6;
+pub const TOTP_PERIOD: i64 = 30;
+// сколько шагов до и после текущего принимается из-за расхождения часов
+pub const TOTP_DRIFT_STEPS: i64 = 1;
+pub const TOTP_SECRET_LEN: usize = 20;
+pub const MFA_ISSUER: &str = "BankirPay";
+pub const RECOVERY_CODES_COUNT: usize = 10;
+// сколько секунд после прохождения второго фактора открыты чувствительные маршруты
+pub const MFA_MAX_AGE_SECS: usize = 5 * 60;
+// роли, для которых доступен второй фактор
+pub const MFA_ROLES: [&str; 2] = ["trader", "admin"];
+
+const BASE32_ALPHABET: &[u8; 32] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+
+pub fn base32_encode(data: &[u8]) -> String {
+    let mut out = String::with_capacity(data.len().div_ceil(5) * 8);
+    let (mut buffer, mut bits) = (0u32, 0u32);
+    for byte in data {
+        buffer = (buffer << 8) | *byte as u32;
+        bits += 8;
+        while bits >= 5 {
+            bits -= 5;
+            out.push(BASE32_ALPHABET[((buffer >> bits) & 0x1f) as usize] as char);
+        }
+    }
+    if bits > 0 {
+        out.push(BASE32_ALPHABET[((buffer << (5 - bits)) & 0x1f) as usize] as char);
+    }
+    out
+}
+
+pub fn base32_decode(value: &str) -> Option<Vec<u8>> {
+    let mut out = Vec::with_capacity(value.len() * 5 / 8);
+    let (mut buffer, mut bits) = (0u32, 0u32);
+    for c in value.trim_end_matches('=').bytes() {
+        let index = BASE32_ALPHABET.iter().position(|a| *a == c.to_ascii_uppercase())? as u32;
+        buffer = (buffer << 5) | index;
+        bits += 5;
+        if bits >= 8 {
+            bits -= 8;
+            out.push((buffer >> bits) as u8);
+        }
+    }
+    Some(out)
+}
+
+pub fn generate_totp_secret() -> String {
+    let mut secret = [0u8; TOTP_SECRET_LEN];
+    OsRng.fill_bytes(&mut secret);
+    base32_encode(&secret)
+}
+
+// RFC 6238 с HMAC-SHA1: другой алгоритм большинство приложений-аутентификаторов игнорирует
+pub fn totp(secret: &[u8], step: i64) -> u32 {
+    let hash = hmac_sha1(secret, &step.to_be_bytes());
+    let offset = (hash[hash.len() - 1] & 0x0f) as usize;
+    let code = u32::from_be_bytes([hash[offset], hash[offset + 1], hash[offset + 2], hash[offset + 3]]) & 0x7fff_ffff;
+    code % 10u32.pow(TOTP_DIGITS)
+}
+
+fn hmac_sha1(key: &[u8], message: &[u8]) -> [u8; 20] {
+    // HMAC принимает ключ любой длины
+    let mut mac = Hmac::<Sha1>::new_from_slice(key).expect("HMAC accepts keys of any length");
+    mac.update(message);
+    mac.finalize().into_bytes().into()
+}
+
+// возвращает шаг, которому соответствует код. шаги не больше last_step уже использованы,
+// иначе перехваченный код можно предъявить повторно
+pub fn verify_totp(secret: &[u8], code: &str, unix_time: i64, last_step: Option<i64>) -> Option<i64> {
+    if code.len() != TOTP_DIGITS as usize || !code.bytes().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    let code: u32 = code.parse().ok()?;
+    let current = unix_time / TOTP_PERIOD;
+    (current - TOTP_DRIFT_STEPS..=current + TOTP_DRIFT_STEPS)
+        .filter(|step| last_step.is_none_or(|last| *step > last))
+        .find(|step| totp(secret, *step) == code)
+}
+
+pub fn otpauth_uri(account: &str, secret: &str) -> String {
+    format!("otpauth://totp/{issuer}:{account}?secret={secret}&issuer={issuer}&algorithm=SHA1&digits={TOTP_DIGITS}&period={TOTP_PERIOD}",
+            issuer = MFA_ISSUER, account = url_escape(account), secret = secret)
+}
+
+fn url_escape(value: &str) -> String {
+    value.bytes().map(|b| match b {
+        b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => (b as char).to_string(),
+        _ => format!("%{:02X}", b),
+    }).collect()
+}
+
+// код вида XXXXX-XXXXX, показывается пользователю один раз, в БД хранится только хэш
+pub fn generate_recovery_codes() -> Vec<String> {
+    (0..RECOVERY_CODES_COUNT).map(|_| {
+        let mut raw = [0u8; 7];
+        OsRng.fill_bytes(&mut raw);
+        let code = base32_encode(&raw);
+        format!("{}-{}", &code[..5], &code[5..10])
+    }).collect()
+}
+
+pub fn hash_recovery_code(code: &str) -> String {
+    let normalized: String = code.chars().filter(|c| c.is_ascii_alphanumeric()).map(|c| c.to_ascii_uppercase()).collect();
+    sha256_hex(normalized.as_bytes())
+}
+
+// ответ на подключение второго фактора, до подтверждения кодом он не действует
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct MfaEnrollment {
+    pub secret: String,
+    pub otpauth_uri: String,
+    pub recovery_codes: Vec<String>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct MfaVerifyRequest {
+    // TOTP или код восстановления
+    pub code: String,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct MfaToken {
+    pub token: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct MfaSecret {
+    pub user_id: String,
+    pub role: String,
+    pub secret: String,
+    pub last_step: Option<i64>,
+    pub confirmed_at: Option<NaiveDateTime>,
+    pub created_at: NaiveDateTime,
+}
+
+impl From<&tokio_postgres::Row> for MfaSecret {
+    fn from(row: &tokio_postgres::Row) -> Self {
+        Self {
+            user_id: row.get("user_id"),
+            role: row.get("role"),
+            secret: row.get("secret"),
+            last_step: row.get("last_step"),
+            confirmed_at: row.get("confirmed_at"),
+            created_at: row.get("created_at"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn totp_rfc6238_sha1() {
+        // RFC 2202, тест 2
+        assert_eq!(hmac_sha1(b"Jefe", b"what do ya want for nothing?").map(|b| format!("{:02x}", b)).concat(),
+                   "effcdf6ae5eb2fa2d27416d5f184df9c259a7c79");
+        let secret = b"12345678901234567890";
+        // RFC 6238, приложение B: 94287082 и 07081804 при 8 знаках
+        assert_eq!(totp(secret, 59 / TOTP_PERIOD), 287082);
+        assert_eq!(totp(secret, 1111111109 / TOTP_PERIOD), 81804);
+        assert_eq!(verify_totp(secret, "287082", 59, None), Some(1));
+        // соседний шаг в пределах расхождения часов
+        assert_eq!(verify_totp(secret, "287082", 89, None), Some(1));
+        assert_eq!(verify_totp(secret, "287082", 59, Some(1)), None);
+        assert_eq!(verify_totp(secret, "287082", 150, None), None);
+        assert!(otpauth_uri("t1@bankir", "ABC").contains("&algorithm=SHA1&"));
+
+        let encoded = base32_encode(secret);
+        assert_eq!(base32_decode(&encoded).unwrap(), secret);
+        assert_eq!(base32_encode(b"foobar"), "MZXW6YTBOI");
+        assert_eq!(hash_recovery_code("abcde-fghij"), hash_recovery_code("ABCDEFGHIJ"));
+    }
+}
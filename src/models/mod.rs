@@ -4,6 +4,15 @@ use std::pin::Pin;
 use std::sync::Arc;
 use tokio::sync::Semaphore;
 pub mod payments;
+pub mod ledger;
+pub mod reconciliation;
+pub mod settlement;
+pub mod block;
+pub mod saga;
+pub mod audit;
+pub mod rate_limit;
+pub mod ip_allowlist;
+pub mod mfa;
 #[derive(Clone)]
 pub struct AuthState {
     pub pool: deadpool_postgres::Pool,
@@ -17,6 +26,9 @@ pub struct Claims {
     pub exp: usize,
     #[serde(skip_serializing_if="Option::is_none")]
     pub impersonated_by: Option<String>,
+    // unix-время последнего прохождения второго фактора
+    #[serde(default, skip_serializing_if="Option::is_none")]
+    pub mfa_at: Option<usize>,
 }
 
 
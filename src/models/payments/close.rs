@@ -0,0 +1,153 @@
+use std::collections::HashMap;
+use rust_decimal::Decimal;
+use rust_decimal::dec;
+use serde::{Deserialize, Serialize};
+use crate::errors::LibError;
+use crate::models::payments::payment::{FullPayment, PaymentStatuses};
+use crate::models::reconciliation::{expected_balance_actions, ExpectedBalanceAction};
+
+// допустимые отклонения фактической суммы от fiat_amount, в процентах
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct AmountTolerance {
+    // отклонение, которое считается оплатой точной суммы
+    pub exact_percent: Decimal,
+    // минимальная доля от fiat_amount для частичного закрытия
+    pub min_percent: Decimal,
+    // максимальная доля от fiat_amount для переплаты
+    pub max_percent: Decimal,
+}
+
+impl Default for AmountTolerance {
+    fn default() -> Self {
+        Self { exact_percent: dec!(0.5), min_percent: dec!(10), max_percent: dec!(150) }
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Default)]
+pub struct ClosePolicy {
+    pub default: AmountTolerance,
+    // ключ - FullPayment.method
+    pub per_method: HashMap<String, AmountTolerance>,
+}
+
+impl ClosePolicy {
+    pub fn tolerance(&self, method: &str) -> &AmountTolerance {
+        self.per_method.get(method).unwrap_or(&self.default)
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum CloseKind {
+    Exact,
+    Partial,
+    Over,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct ClosePlan {
+    pub kind: CloseKind,
+    // платеж с пересчитанными суммами и статусом COMPLETED
+    pub payment: FullPayment,
+    pub balance_actions: Vec<ExpectedBalanceAction>,
+}
+
+const CRYPTO_DP: u32 = 6;
+const FIAT_DP: u32 = 2;
+
+// считает закрытие платежа на фактически оплаченную сумму.
+// None или сумма в пределах exact_percent - закрытие на исходную сумму
+pub fn plan_close(payment: &FullPayment, actual_amount: Option<Decimal>, policy: &ClosePolicy) -> Result<ClosePlan, LibError> {
+    // замороженный платеж закрывается только решением спора
+    if payment.status.is_final() || payment.status == PaymentStatuses::Frozen || payment.fiat_amount <= Decimal::ZERO {
+        return Err(LibError::Conflict);
+    }
+    let tolerance = policy.tolerance(&payment.method);
+    let mut closed = payment.clone();
+    closed.status = PaymentStatuses::Completed;
+
+    let kind = match actual_amount {
+        None => CloseKind::Exact,
+        Some(actual) if actual <= Decimal::ZERO => return Err(LibError::InvalidAmount),
+        Some(actual) => {
+            let percent = actual / payment.fiat_amount * Decimal::ONE_HUNDRED;
+            if (percent - Decimal::ONE_HUNDRED).abs() <= tolerance.exact_percent {
+                CloseKind::Exact
+            } else if percent < tolerance.min_percent || percent > tolerance.max_percent {
+                return Err(LibError::InvalidAmount);
+            } else {
+                rescale(&mut closed, actual);
+                if actual < payment.fiat_amount { CloseKind::Partial } else { CloseKind::Over }
+            }
+        }
+    };
+    let balance_actions = expected_balance_actions(&closed);
+    Ok(ClosePlan { kind, payment: closed, balance_actions })
+}
+
+// пересчитывает суммы, комиссии и earnings пропорционально фактической сумме
+fn rescale(payment: &mut FullPayment, actual: Decimal) {
+    let ratio = actual / payment.fiat_amount;
+    let crypto = |amount: Decimal| (amount * ratio).round_dp(CRYPTO_DP);
+    let fiat = |amount: Decimal| (amount * ratio).round_dp(FIAT_DP);
+    payment.original_fiat_amount.get_or_insert(payment.fiat_amount);
+    payment.original_crypto_amount.get_or_insert(payment.crypto_amount);
+    payment.original_trader_crypto_amount.get_or_insert(payment.trader_crypto_amount);
+    payment.fiat_amount = actual;
+    payment.crypto_amount = crypto(payment.crypto_amount);
+    payment.trader_crypto_amount = crypto(payment.trader_crypto_amount);
+    payment.crypto_fee = crypto(payment.crypto_fee);
+    payment.trader_crypto_fee = crypto(payment.trader_crypto_fee);
+    payment.fiat_fee = fiat(payment.fiat_fee);
+    payment.trader_fiat_fee = fiat(payment.trader_fiat_fee);
+    payment.earnings = crypto(payment.earnings);
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::models::ledger::{BalanceAction, LedgerOwner};
+    use crate::models::payments::payment::PaymentSides;
+    use super::*;
+
+    fn payment() -> FullPayment {
+        FullPayment {
+            id: "p1".to_string(),
+            trader_id: "t1".to_string(),
+            merchant_id: "m1".to_string(),
+            status: PaymentStatuses::Unpaid,
+            payment_side: PaymentSides::Buy,
+            method: "card".to_string(),
+            fiat_amount: dec!(1000),
+            crypto_amount: dec!(10),
+            trader_crypto_amount: dec!(10.2),
+            crypto_fee: dec!(0.3),
+            earnings: dec!(0.1),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn partial_close_rescales_and_unfreezes_rest() {
+        let plan = plan_close(&payment(), Some(dec!(500)), &ClosePolicy::default()).unwrap();
+        assert_eq!(plan.kind, CloseKind::Partial);
+        assert_eq!(plan.payment.fiat_amount, dec!(500));
+        assert_eq!(plan.payment.crypto_amount, dec!(5));
+        assert_eq!(plan.payment.original_fiat_amount, Some(dec!(1000)));
+        let trader = plan.balance_actions.iter()
+            .filter(|a| a.owner_type == LedgerOwner::Trader)
+            .map(|a| (a.action, a.amount))
+            .collect::<Vec<_>>();
+        assert_eq!(trader, vec![(BalanceAction::WithdrawFrozen, dec!(5.1)), (BalanceAction::Unfroze, dec!(5.1))]);
+    }
+
+    #[test]
+    fn tolerance_rules() {
+        let policy = ClosePolicy::default();
+        assert_eq!(plan_close(&payment(), Some(dec!(1003)), &policy).unwrap().kind, CloseKind::Exact);
+        assert_eq!(plan_close(&payment(), Some(dec!(1200)), &policy).unwrap().kind, CloseKind::Over);
+        assert!(plan_close(&payment(), Some(dec!(50)), &policy).is_err());
+        assert!(plan_close(&payment(), Some(dec!(2000)), &policy).is_err());
+        let frozen = FullPayment { status: PaymentStatuses::Frozen, ..payment() };
+        assert!(matches!(plan_close(&frozen, None, &policy), Err(LibError::Conflict)));
+    }
+}
@@ -0,0 +1,141 @@
+use base64::Engine;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use chrono::{DateTime, NaiveDateTime};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use crate::models::payments::merchant::MerchantPayment;
+use crate::models::payments::payment::FullPayment;
+use crate::models::payments::trader::TraderPayment;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CursorDirection {
+    // более старые платежи
+    Next,
+    // более новые платежи
+    Prev,
+}
+
+// позиция в выдаче ORDER BY created_at DESC, id DESC, клиенту отдается непрозрачной строкой
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PaymentCursor {
+    pub created_at: NaiveDateTime,
+    pub id: String,
+    pub direction: CursorDirection,
+}
+
+impl PaymentCursor {
+    pub fn encode(&self) -> String {
+        let direction = match self.direction {
+            CursorDirection::Next => 'n',
+            CursorDirection::Prev => 'p',
+        };
+        let raw = format!("{}|{}|{}", direction, self.created_at.and_utc().timestamp_micros(), self.id);
+        URL_SAFE_NO_PAD.encode(raw)
+    }
+
+    pub fn decode(token: &str) -> Result<Self, String> {
+        let raw = URL_SAFE_NO_PAD.decode(token).map_err(|_| format!("invalid cursor {}", token))?;
+        let raw = String::from_utf8(raw).map_err(|_| format!("invalid cursor {}", token))?;
+        let mut parts = raw.splitn(3, '|');
+        let direction = match parts.next() {
+            Some("n") => CursorDirection::Next,
+            Some("p") => CursorDirection::Prev,
+            _ => return Err(format!("invalid cursor {}", token)),
+        };
+        let created_at = parts.next()
+            .and_then(|micros| micros.parse::<i64>().ok())
+            .and_then(DateTime::from_timestamp_micros)
+            .ok_or_else(|| format!("invalid cursor {}", token))?
+            .naive_utc();
+        let id = parts.next().filter(|id| !id.is_empty()).ok_or_else(|| format!("invalid cursor {}", token))?;
+        Ok(Self { created_at, id: id.to_string(), direction })
+    }
+}
+
+impl Serialize for PaymentCursor {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.encode())
+    }
+}
+
+impl<'de> Deserialize<'de> for PaymentCursor {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let token = String::deserialize(deserializer)?;
+        PaymentCursor::decode(&token).map_err(serde::de::Error::custom)
+    }
+}
+
+// ключ сортировки выдачи
+pub trait CursorKey {
+    fn cursor_key(&self) -> (NaiveDateTime, &str);
+}
+
+impl CursorKey for FullPayment {
+    fn cursor_key(&self) -> (NaiveDateTime, &str) {
+        (self.created_at, self.id.as_str())
+    }
+}
+
+impl CursorKey for MerchantPayment {
+    fn cursor_key(&self) -> (NaiveDateTime, &str) {
+        (self.created_at, self.id.as_str())
+    }
+}
+
+impl CursorKey for TraderPayment {
+    fn cursor_key(&self) -> (NaiveDateTime, &str) {
+        (self.created_at, self.id.as_str())
+    }
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct PaymentsPage<T> {
+    pub items: Vec<T>,
+    pub next_cursor: Option<PaymentCursor>,
+    pub prev_cursor: Option<PaymentCursor>,
+}
+
+impl<T: CursorKey> PaymentsPage<T> {
+    // rows - результат запроса из GetPaymentsRequest::to_sql.
+    // с курсором запрашивается limit + 1 строка, лишняя означает что дальше есть данные.
+    // в режиме page/limit курсоры тоже отдаются, чтобы клиент мог перейти на них
+    pub fn new(mut rows: Vec<T>, limit: usize, cursor: Option<&PaymentCursor>, page: u32) -> Self {
+        let (has_more, has_before) = match cursor {
+            Some(cursor) => {
+                let has_more = rows.len() > limit;
+                rows.truncate(limit);
+                match cursor.direction {
+                    CursorDirection::Next => (has_more, true),
+                    CursorDirection::Prev => {
+                        // назад выбирается по возрастанию, разворачиваем к общему порядку
+                        rows.reverse();
+                        (true, has_more)
+                    }
+                }
+            }
+            None => (rows.len() >= limit, page > 1),
+        };
+        let make = |item: Option<&T>, direction| item.map(|item| {
+            let (created_at, id) = item.cursor_key();
+            PaymentCursor { created_at, id: id.to_string(), direction }
+        });
+        let next_cursor = if has_more { make(rows.last(), CursorDirection::Next) } else { None };
+        let prev_cursor = if has_before { make(rows.first(), CursorDirection::Prev) } else { None };
+        Self { items: rows, next_cursor, prev_cursor }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cursor_roundtrip() {
+        let cursor = PaymentCursor {
+            created_at: DateTime::from_timestamp_micros(1_700_000_000_123_456).unwrap().naive_utc(),
+            id: "0196a1b2-c3d4|x".to_string(),
+            direction: CursorDirection::Prev,
+        };
+        assert_eq!(PaymentCursor::decode(&cursor.encode()).unwrap(), cursor);
+        assert!(PaymentCursor::decode("bm90LWEtY3Vyc29y").is_err());
+    }
+}
@@ -0,0 +1,302 @@
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+use chrono::{Duration, NaiveDateTime};
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use crate::models::ledger::{BalanceAction, LedgerOwner};
+use crate::models::payments::cursor::PaymentCursor;
+use crate::models::payments::payment::{FullPayment, PaymentSides, PaymentStatuses, PaymentStatusesSlim};
+use crate::models::reconciliation::{expected_balance_actions, ExpectedBalanceAction};
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum DisputeOpener {
+    Merchant,
+    Trader,
+    Customer,
+}
+
+impl Display for DisputeOpener {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DisputeOpener::Merchant => f.write_str("merchant"),
+            DisputeOpener::Trader => f.write_str("trader"),
+            DisputeOpener::Customer => f.write_str("customer"),
+        }
+    }
+}
+
+impl FromStr for DisputeOpener {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "merchant" => Ok(DisputeOpener::Merchant),
+            "trader" => Ok(DisputeOpener::Trader),
+            "customer" => Ok(DisputeOpener::Customer),
+            _ => Err(format!("unknown dispute opener {}", s)),
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum DisputeStatus {
+    // ждем ответа второй стороны
+    Open,
+    // стороны ответили, решение за админом
+    UnderReview,
+    Resolved,
+}
+
+impl Display for DisputeStatus {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DisputeStatus::Open => f.write_str("OPEN"),
+            DisputeStatus::UnderReview => f.write_str("UNDER_REVIEW"),
+            DisputeStatus::Resolved => f.write_str("RESOLVED"),
+        }
+    }
+}
+
+impl FromStr for DisputeStatus {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "OPEN" => Ok(DisputeStatus::Open),
+            "UNDER_REVIEW" => Ok(DisputeStatus::UnderReview),
+            "RESOLVED" => Ok(DisputeStatus::Resolved),
+            _ => Err(format!("unknown dispute status {}", s)),
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum DisputeResolution {
+    // оплата подтверждена, платеж завершается
+    Completed,
+    // оплаты не было, платеж отменяется админом
+    Cancelled,
+    // спор необоснован, платеж возвращается в статус до заморозки
+    Rejected,
+}
+
+impl Display for DisputeResolution {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DisputeResolution::Completed => f.write_str("COMPLETED"),
+            DisputeResolution::Cancelled => f.write_str("CANCELLED"),
+            DisputeResolution::Rejected => f.write_str("REJECTED"),
+        }
+    }
+}
+
+impl FromStr for DisputeResolution {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "COMPLETED" => Ok(DisputeResolution::Completed),
+            "CANCELLED" => Ok(DisputeResolution::Cancelled),
+            "REJECTED" => Ok(DisputeResolution::Rejected),
+            _ => Err(format!("unknown dispute resolution {}", s)),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct DisputeSla {
+    pub respond_within: Duration,
+    pub resolve_within: Duration,
+}
+
+impl Default for DisputeSla {
+    fn default() -> Self {
+        Self { respond_within: Duration::hours(24), resolve_within: Duration::hours(72) }
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct Dispute {
+    pub id: String,
+    pub payment_id: String,
+    pub merchant_id: String,
+    pub trader_id: String,
+    pub opened_by: DisputeOpener,
+    pub opener_id: String,
+    pub reason: String,
+    pub status: DisputeStatus,
+    // статус платежа до заморозки, нужен для REJECTED
+    pub payment_status_before: PaymentStatuses,
+    pub resolution: Option<DisputeResolution>,
+    pub resolved_by: Option<String>,
+    pub resolution_comment: Option<String>,
+    pub respond_by: NaiveDateTime,
+    pub resolve_by: NaiveDateTime,
+    pub created_at: NaiveDateTime,
+    pub updated_at: Option<NaiveDateTime>,
+    pub resolved_at: Option<NaiveDateTime>,
+}
+
+// то же правило, что Dispute::is_overdue, для фильтров в SQL
+pub const OVERDUE_CONDITION: &str = "status<>'RESOLVED' AND (resolve_by<NOW() OR (status='OPEN' AND respond_by<NOW()))";
+
+impl Dispute {
+    pub fn is_overdue(&self, now: NaiveDateTime) -> bool {
+        match self.status {
+            DisputeStatus::Open => now > self.respond_by || now > self.resolve_by,
+            DisputeStatus::UnderReview => now > self.resolve_by,
+            DisputeStatus::Resolved => false,
+        }
+    }
+}
+
+impl From<&tokio_postgres::Row> for Dispute {
+    fn from(row: &tokio_postgres::Row) -> Self {
+        Self {
+            id: row.get("id"),
+            payment_id: row.get("payment_id"),
+            merchant_id: row.get("merchant_id"),
+            trader_id: row.get("trader_id"),
+            opened_by: DisputeOpener::from_str(row.get("opened_by")).unwrap(),
+            opener_id: row.get("opener_id"),
+            reason: row.get("reason"),
+            status: DisputeStatus::from_str(row.get("status")).unwrap(),
+            payment_status_before: PaymentStatuses::from_str(row.get("payment_status_before")).unwrap(),
+            resolution: row.get::<_, Option<&str>>("resolution").map(|r| DisputeResolution::from_str(r).unwrap()),
+            resolved_by: row.get("resolved_by"),
+            resolution_comment: row.get("resolution_comment"),
+            respond_by: row.get("respond_by"),
+            resolve_by: row.get("resolve_by"),
+            created_at: row.get("created_at"),
+            updated_at: row.get("updated_at"),
+            resolved_at: row.get("resolved_at"),
+        }
+    }
+}
+
+// метаданные вложения, сам файл лежит во внешнем хранилище по storage_key
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct DisputeEvidence {
+    pub id: String,
+    pub dispute_id: String,
+    pub uploaded_by: DisputeOpener,
+    pub uploader_id: String,
+    pub file_name: String,
+    pub content_type: String,
+    pub size_bytes: i64,
+    pub storage_key: String,
+    pub sha256: String,
+    pub created_at: NaiveDateTime,
+}
+
+impl From<&tokio_postgres::Row> for DisputeEvidence {
+    fn from(row: &tokio_postgres::Row) -> Self {
+        Self {
+            id: row.get("id"),
+            dispute_id: row.get("dispute_id"),
+            uploaded_by: DisputeOpener::from_str(row.get("uploaded_by")).unwrap(),
+            uploader_id: row.get("uploader_id"),
+            file_name: row.get("file_name"),
+            content_type: row.get("content_type"),
+            size_bytes: row.get("size_bytes"),
+            storage_key: row.get("storage_key"),
+            sha256: row.get("sha256"),
+            created_at: row.get("created_at"),
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct OpenDisputeRequest {
+    pub payment_id: String,
+    pub opened_by: DisputeOpener,
+    pub reason: String,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct NewDisputeEvidence {
+    pub file_name: String,
+    pub content_type: String,
+    pub size_bytes: i64,
+    pub storage_key: String,
+    pub sha256: String,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct ResolveDisputeRequest {
+    pub resolution: DisputeResolution,
+    pub comment: Option<String>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct DisputeOutcome {
+    pub payment_status: PaymentStatuses,
+    pub balance_actions: Vec<ExpectedBalanceAction>,
+}
+
+// во что превращается решение по спору: новый статус платежа и движения балансов
+pub fn dispute_outcome(dispute: &Dispute, payment: &FullPayment, resolution: DisputeResolution) -> DisputeOutcome {
+    // после отмены заморозки уже сняты, списываем с основного баланса
+    let unfrozen = dispute.payment_status_before.is_cancelled();
+    match resolution {
+        DisputeResolution::Completed => {
+            let mut completed = payment.clone();
+            completed.status = PaymentStatuses::Completed;
+            let balance_actions = expected_balance_actions(&completed).into_iter()
+                .filter(|action| !(unfrozen && action.action == BalanceAction::Unfroze))
+                .map(|mut action| {
+                    if unfrozen && action.action == BalanceAction::WithdrawFrozen {
+                        action.action = BalanceAction::WithdrawMain;
+                    }
+                    action
+                }).collect();
+            DisputeOutcome { payment_status: PaymentStatuses::Completed, balance_actions }
+        }
+        DisputeResolution::Cancelled => {
+            let mut balance_actions = Vec::new();
+            if !unfrozen {
+                balance_actions.push(unfroze(payment, LedgerOwner::Trader, payment.trader_crypto_amount));
+                if payment.payment_side == PaymentSides::Sell {
+                    balance_actions.push(unfroze(payment, LedgerOwner::Merchant, payment.crypto_amount));
+                }
+            }
+            let payment_status = if unfrozen {
+                dispute.payment_status_before.clone()
+            } else {
+                PaymentStatuses::CancelledByAdmin
+            };
+            DisputeOutcome { payment_status, balance_actions }
+        }
+        DisputeResolution::Rejected => DisputeOutcome {
+            payment_status: dispute.payment_status_before.clone(),
+            balance_actions: Vec::new(),
+        },
+    }
+}
+
+fn unfroze(payment: &FullPayment, owner_type: LedgerOwner, amount: Decimal) -> ExpectedBalanceAction {
+    ExpectedBalanceAction {
+        payment_id: payment.id.clone(),
+        owner_type,
+        owner_id: match owner_type {
+            LedgerOwner::Trader => payment.trader_id.clone(),
+            LedgerOwner::Merchant => payment.merchant_id.clone(),
+        },
+        action: BalanceAction::Unfroze,
+        amount,
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct GetDisputedPaymentsAdmin {
+    pub dispute_statuses: Option<Vec<DisputeStatus>>,
+    pub opened_by: Option<DisputeOpener>,
+    pub merchant_id: Option<String>,
+    pub trader_id: Option<String>,
+    pub statuses: Option<Vec<PaymentStatusesSlim>>,
+    // только споры с нарушенным SLA
+    pub overdue: Option<bool>,
+    pub limit: Option<u32>,
+    pub page: Option<u32>,
+    pub cursor: Option<PaymentCursor>,
+}
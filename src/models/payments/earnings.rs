@@ -0,0 +1,114 @@
+use chrono::NaiveDateTime;
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use tokio_postgres::types::Type;
+use crate::models::payments::filter::{SqlFilter, SqlQuery};
+use crate::models::payments::stats::StatsGranularity;
+
+// заработок по таким платежам не выплачивается, пока заморозка или спор не сняты
+pub const HELD_CONDITION: &str = "(status='FROZEN' OR EXISTS (SELECT 1 FROM disputes d \
+    WHERE d.payment_id=payments.id AND d.status<>'RESOLVED'))";
+
+#[derive(Deserialize, Serialize, Debug, Clone, Default)]
+pub struct EarningsRequest {
+    pub from: Option<NaiveDateTime>,
+    pub to: Option<NaiveDateTime>,
+    pub granularity: Option<StatsGranularity>,
+    #[serde(default)]
+    pub by_method: bool,
+    #[serde(default)]
+    pub by_bank: bool,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct EarningsRow {
+    pub period: Option<NaiveDateTime>,
+    pub method: Option<String>,
+    pub bank_id: Option<String>,
+    pub bank_name: Option<String>,
+    // завершенные платежи без удержания
+    pub payments_count: i64,
+    pub crypto_earnings: Decimal,
+    pub fiat_earnings: Decimal,
+    // замороженные и спорные платежи
+    pub held_count: i64,
+    pub held_crypto_earnings: Decimal,
+    pub held_fiat_earnings: Decimal,
+}
+
+impl From<&tokio_postgres::Row> for EarningsRow {
+    fn from(row: &tokio_postgres::Row) -> Self {
+        Self {
+            period: row.get("period"),
+            method: row.get("method"),
+            bank_id: row.get("bank_id"),
+            bank_name: row.get("bank_name"),
+            payments_count: row.get("payments_count"),
+            crypto_earnings: row.get("crypto_earnings"),
+            fiat_earnings: row.get("fiat_earnings"),
+            held_count: row.get("held_count"),
+            held_crypto_earnings: row.get("held_crypto_earnings"),
+            held_fiat_earnings: row.get("held_fiat_earnings"),
+        }
+    }
+}
+
+impl EarningsRequest {
+    // сумма trader_crypto_fee по завершенным и удержанным платежам трейдера
+    pub fn to_sql(&self, trader_id: &str) -> SqlQuery {
+        let mut filter = SqlFilter::new();
+        filter.eq("trader_id", trader_id.to_string(), Type::VARCHAR)
+            .raw("status IN ('COMPLETED', 'FROZEN')")
+            .ge_opt("created_at", self.from.as_ref(), Type::TIMESTAMP)
+            .le_opt("created_at", self.to.as_ref(), Type::TIMESTAMP);
+        let period = match self.granularity {
+            Some(StatsGranularity::Hour) => "date_trunc('hour', created_at)",
+            Some(StatsGranularity::Day) => "date_trunc('day', created_at)",
+            None => "NULL::timestamp",
+        };
+        let method = if self.by_method { "method" } else { "NULL::varchar" };
+        let (bank_id, bank_name) = if self.by_bank { ("bank_id", "MAX(bank_name)") } else { ("NULL::varchar", "NULL::varchar") };
+
+        let mut group_by = Vec::with_capacity(3);
+        for (index, grouped) in [self.granularity.is_some(), self.by_method, self.by_bank].iter().enumerate() {
+            if *grouped {
+                group_by.push((index + 1).to_string());
+            }
+        }
+        let tail = if group_by.is_empty() {
+            String::new()
+        } else {
+            format!(" GROUP BY {0} ORDER BY {0}", group_by.join(", "))
+        };
+
+        let released = format!("status='COMPLETED' AND NOT {}", HELD_CONDITION);
+        let sum = |column: &str, condition: &str, alias: &str|
+            format!("COALESCE(SUM({}) FILTER (WHERE {}), 0) AS {}", column, condition, alias);
+        let aggregates = [
+            format!("COUNT(*) FILTER (WHERE {}) AS payments_count", released),
+            sum("trader_crypto_fee", &released, "crypto_earnings"),
+            sum("trader_fiat_fee", &released, "fiat_earnings"),
+            format!("COUNT(*) FILTER (WHERE {}) AS held_count", HELD_CONDITION),
+            sum("trader_crypto_fee", HELD_CONDITION, "held_crypto_earnings"),
+            sum("trader_fiat_fee", HELD_CONDITION, "held_fiat_earnings"),
+        ];
+        let mut query = filter.build(tail.as_str());
+        query.sql = format!("SELECT {} AS period, {} AS method, {} AS bank_id, {} AS bank_name, {} FROM payments{}",
+                            period, method, bank_id, bank_name, aggregates.join(", "), query.sql);
+        query
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn earnings_grouped_by_bank() {
+        let request = EarningsRequest { by_bank: true, ..Default::default() };
+        let query = request.to_sql("t1");
+        assert!(query.sql.starts_with("SELECT NULL::timestamp AS period, NULL::varchar AS method, bank_id AS bank_id"));
+        assert!(query.sql.ends_with(" FROM payments WHERE trader_id=$1 AND status IN ('COMPLETED', 'FROZEN') GROUP BY 3 ORDER BY 3"));
+        assert_eq!(query.params.len(), 1);
+    }
+}
@@ -0,0 +1,56 @@
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
+use crate::retry::RetryPolicy;
+
+// снятие заморозок по платежу, отмененному по таймауту
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct ExpiryJob {
+    pub payment_id: String,
+    // попыток, включая текущую
+    pub attempts: i32,
+    pub next_attempt_at: NaiveDateTime,
+    pub last_error: Option<String>,
+}
+
+impl From<&tokio_postgres::Row> for ExpiryJob {
+    fn from(row: &tokio_postgres::Row) -> Self {
+        Self {
+            payment_id: row.get("payment_id"),
+            attempts: row.get("attempts"),
+            next_attempt_at: row.get("next_attempt_at"),
+            last_error: row.get("last_error"),
+        }
+    }
+}
+
+impl ExpiryJob {
+    // после ошибки задание уходит в конец очереди, пауза растет с числом попыток
+    pub fn reschedule(&mut self, now: NaiveDateTime, error: String, backoff: &RetryPolicy) {
+        let delay = backoff.backoff(self.attempts.max(1) as u32);
+        self.next_attempt_at = now + chrono::Duration::from_std(delay).unwrap_or(chrono::Duration::MAX);
+        self.last_error = Some(error);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+    use super::*;
+
+    #[test]
+    fn failed_jobs_back_off() {
+        let now = NaiveDateTime::default();
+        let backoff = RetryPolicy::default().with_backoff(Duration::from_secs(1), Duration::from_secs(60), 2.0);
+        let mut job = ExpiryJob { payment_id: "p1".to_string(), attempts: 1, next_attempt_at: now, last_error: None };
+        job.reschedule(now, "unavailable".to_string(), &backoff);
+        assert_eq!(job.next_attempt_at, now + chrono::Duration::seconds(1));
+        assert_eq!(job.last_error.as_deref(), Some("unavailable"));
+
+        job.attempts = 4;
+        job.reschedule(now, "unavailable".to_string(), &backoff);
+        assert_eq!(job.next_attempt_at, now + chrono::Duration::seconds(8));
+        job.attempts = 30;
+        job.reschedule(now, "unavailable".to_string(), &backoff);
+        assert_eq!(job.next_attempt_at, now + chrono::Duration::seconds(60));
+    }
+}
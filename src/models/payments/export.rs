@@ -0,0 +1,164 @@
+use chrono::NaiveDateTime;
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use crate::models::payments::merchant::MerchantPayment;
+use crate::models::payments::payment::{FullPayment, ToSQL};
+use crate::models::payments::trader::TraderPayment;
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ExportFormat {
+    Csv,
+    // требует фичу xlsx
+    Xlsx,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ExportCell {
+    Text(String),
+    Number(Decimal),
+    Empty,
+}
+
+impl ExportCell {
+    fn date(value: NaiveDateTime) -> Self {
+        ExportCell::Text(value.format("%Y-%m-%d %H:%M:%S").to_string())
+    }
+
+    fn optional_date(value: Option<NaiveDateTime>) -> Self {
+        value.map(Self::date).unwrap_or(ExportCell::Empty)
+    }
+
+    fn optional_number(value: Option<Decimal>) -> Self {
+        value.map(ExportCell::Number).unwrap_or(ExportCell::Empty)
+    }
+
+    pub fn to_text(&self) -> String {
+        match self {
+            ExportCell::Text(text) => text.clone(),
+            ExportCell::Number(number) => number.to_string(),
+            ExportCell::Empty => String::new(),
+        }
+    }
+}
+
+// строка выгрузки, набор колонок повторяет поля проекции, лишнего роль не увидит
+pub trait ExportRow: ToSQL + From<tokio_postgres::Row> {
+    fn headers() -> &'static [&'static str];
+    fn cells(&self) -> Vec<ExportCell>;
+}
+
+impl ExportRow for MerchantPayment {
+    fn headers() -> &'static [&'static str] {
+        &["id", "external_id", "client_id", "status", "payment_side", "currency", "target_amount", "fiat_amount",
+            "crypto_amount", "fee_type", "margin", "exchange_rate", "fiat_fee", "crypto_fee", "holder_name",
+            "holder_account", "bank_name", "method", "created_at", "updated_at", "deadline", "original_fiat_amount",
+            "refunded_fiat_amount"]
+    }
+
+    fn cells(&self) -> Vec<ExportCell> {
+        vec![
+            ExportCell::Text(self.id.clone()),
+            ExportCell::Text(self.external_id.clone()),
+            self.client_id.clone().map(ExportCell::Text).unwrap_or(ExportCell::Empty),
+            ExportCell::Text(self.status.to_string()),
+            ExportCell::Text(self.payment_side.to_string()),
+            ExportCell::Text(self.currency.clone()),
+            ExportCell::Number(self.target_amount),
+            ExportCell::Number(self.fiat_amount),
+            ExportCell::Number(self.crypto_amount),
+            ExportCell::Text(self.fee_type.to_string()),
+            ExportCell::Number(self.margin),
+            ExportCell::Number(self.exchange_rate),
+            ExportCell::Number(self.fiat_fee),
+            ExportCell::Number(self.crypto_fee),
+            ExportCell::Text(self.holder_name.clone()),
+            ExportCell::Text(self.holder_account.clone()),
+            ExportCell::Text(self.bank_name.clone()),
+            ExportCell::Text(self.method.clone()),
+            ExportCell::date(self.created_at),
+            ExportCell::optional_date(self.updated_at),
+            ExportCell::date(self.deadline),
+            ExportCell::optional_number(self.original_fiat_amount),
+            ExportCell::optional_number(self.refunded_fiat_amount),
+        ]
+    }
+}
+
+impl ExportRow for TraderPayment {
+    fn headers() -> &'static [&'static str] {
+        &["id", "requisite_id", "holder_account", "bank_id", "bank_name", "method", "status", "payment_side",
+            "currency", "margin", "exchange_rate", "fiat_amount", "crypto_amount", "crypto_earnings", "fiat_earnings",
+            "created_at", "updated_at", "deadline"]
+    }
+
+    fn cells(&self) -> Vec<ExportCell> {
+        vec![
+            ExportCell::Text(self.id.clone()),
+            ExportCell::Text(self.requisite_id.clone()),
+            ExportCell::Text(self.holder_account.clone()),
+            ExportCell::Text(self.bank_id.clone()),
+            ExportCell::Text(self.bank_name.clone()),
+            ExportCell::Text(self.method.clone()),
+            ExportCell::Text(self.status.to_string()),
+            ExportCell::Text(self.payment_side.to_string()),
+            ExportCell::Text(self.currency.clone()),
+            ExportCell::Number(self.margin),
+            ExportCell::Number(self.exchange_rate),
+            ExportCell::Number(self.fiat_amount),
+            ExportCell::Number(self.crypto_amount),
+            ExportCell::Number(self.crypto_earnings),
+            ExportCell::Number(self.fiat_earnings),
+            ExportCell::date(self.created_at),
+            ExportCell::optional_date(self.updated_at),
+            ExportCell::date(self.deadline),
+        ]
+    }
+}
+
+impl ExportRow for FullPayment {
+    fn headers() -> &'static [&'static str] {
+        &["id", "external_id", "merchant_id", "trader_id", "requisite_id", "bank_id", "status", "payment_side",
+            "currency", "target_amount", "fiat_amount", "crypto_amount", "trader_crypto_amount", "exchange_rate",
+            "fee_type", "crypto_fee", "fiat_fee", "trader_crypto_fee", "trader_fiat_fee", "holder_name",
+            "holder_account", "bank_name", "method", "margin", "trader_margin", "earnings", "created_at",
+            "updated_at", "deadline", "close_by", "original_fiat_amount", "refunded_fiat_amount"]
+    }
+
+    fn cells(&self) -> Vec<ExportCell> {
+        vec![
+            ExportCell::Text(self.id.clone()),
+            ExportCell::Text(self.external_id.clone()),
+            ExportCell::Text(self.merchant_id.clone()),
+            ExportCell::Text(self.trader_id.clone()),
+            ExportCell::Text(self.requisite_id.clone()),
+            ExportCell::Text(self.bank_id.clone()),
+            ExportCell::Text(self.status.to_string()),
+            ExportCell::Text(self.payment_side.to_string()),
+            ExportCell::Text(self.currency.clone()),
+            ExportCell::Number(self.target_amount),
+            ExportCell::Number(self.fiat_amount),
+            ExportCell::Number(self.crypto_amount),
+            ExportCell::Number(self.trader_crypto_amount),
+            ExportCell::Number(self.exchange_rate),
+            ExportCell::Text(self.fee_type.to_string()),
+            ExportCell::Number(self.crypto_fee),
+            ExportCell::Number(self.fiat_fee),
+            ExportCell::Number(self.trader_crypto_fee),
+            ExportCell::Number(self.trader_fiat_fee),
+            ExportCell::Text(self.holder_name.clone()),
+            ExportCell::Text(self.holder_account.clone()),
+            ExportCell::Text(self.bank_name.clone()),
+            ExportCell::Text(self.method.clone()),
+            ExportCell::Number(self.margin),
+            ExportCell::Number(self.trader_margin),
+            ExportCell::Number(self.earnings),
+            ExportCell::date(self.created_at),
+            ExportCell::optional_date(self.updated_at),
+            ExportCell::date(self.deadline),
+            self.close_by.clone().map(ExportCell::Text).unwrap_or(ExportCell::Empty),
+            ExportCell::optional_number(self.original_fiat_amount),
+            ExportCell::optional_number(self.refunded_fiat_amount),
+        ]
+    }
+}
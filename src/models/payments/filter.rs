@@ -0,0 +1,144 @@
+use tokio_postgres::types::{ToSql, Type};
+use crate::models::payments::cursor::{CursorDirection, PaymentCursor};
+
+type SqlParam = (Box<dyn ToSql + Sync + Send>, Type);
+
+// готовый запрос: SQL условия и параметры в порядке $1..$n
+#[derive(Debug)]
+pub struct SqlQuery {
+    pub sql: String,
+    pub params: Vec<SqlParam>,
+}
+
+impl SqlQuery {
+    // параметры в виде, который принимает query_typed
+    pub fn params(&self) -> Vec<(&(dyn ToSql + Sync), Type)> {
+        self.params.iter().map(|(value, ty)| (value.as_ref() as &(dyn ToSql + Sync), ty.clone())).collect()
+    }
+}
+
+// условия WHERE, значения всегда уходят параметрами, номера $n считаются сами
+#[derive(Debug, Default)]
+pub struct SqlFilter {
+    conditions: Vec<String>,
+    params: Vec<SqlParam>,
+}
+
+impl SqlFilter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // регистрирует значение и возвращает его плейсхолдер, для выражений вне WHERE
+    pub fn bind<V: ToSql + Sync + Send + 'static>(&mut self, value: V, ty: Type) -> String {
+        self.params.push((Box::new(value), ty));
+        format!("${}", self.params.len())
+    }
+
+    fn compare<V: ToSql + Sync + Send + 'static>(&mut self, column: &str, operator: &str, value: V, ty: Type) -> &mut Self {
+        let param = self.bind(value, ty);
+        self.conditions.push(format!("{}{}{}", column, operator, param));
+        self
+    }
+
+    pub fn eq<V: ToSql + Sync + Send + 'static>(&mut self, column: &str, value: V, ty: Type) -> &mut Self {
+        self.compare(column, "=", value, ty)
+    }
+
+    pub fn eq_opt<V: ToSql + Sync + Send + Clone + 'static>(&mut self, column: &str, value: Option<&V>, ty: Type) -> &mut Self {
+        match value {
+            Some(value) => self.eq(column, value.clone(), ty),
+            None => self,
+        }
+    }
+
+    pub fn ge_opt<V: ToSql + Sync + Send + Clone + 'static>(&mut self, column: &str, value: Option<&V>, ty: Type) -> &mut Self {
+        match value {
+            Some(value) => self.compare(column, ">=", value.clone(), ty),
+            None => self,
+        }
+    }
+
+    pub fn le_opt<V: ToSql + Sync + Send + Clone + 'static>(&mut self, column: &str, value: Option<&V>, ty: Type) -> &mut Self {
+        match value {
+            Some(value) => self.compare(column, "<=", value.clone(), ty),
+            None => self,
+        }
+    }
+
+    // column = ANY($n) с массивом text[]
+    pub fn any_of(&mut self, column: &str, values: Option<Vec<String>>) -> &mut Self {
+        match values {
+            Some(values) => {
+                let param = self.bind(values, Type::TEXT_ARRAY);
+                self.conditions.push(format!("{}=ANY({})", column, param));
+                self
+            }
+            None => self,
+        }
+    }
+
+    // поиск по началу строки, работает по индексу с text_pattern_ops
+    pub fn prefix_opt(&mut self, column: &str, prefix: Option<&String>) -> &mut Self {
+        match prefix {
+            Some(prefix) => self.compare(column, " LIKE ", format!("{}%", escape_like(prefix)), Type::TEXT),
+            None => self,
+        }
+    }
+
+    // поиск по концу строки через индекс по reverse(column)
+    pub fn suffix_opt(&mut self, column: &str, suffix: Option<&String>) -> &mut Self {
+        match suffix {
+            Some(suffix) => {
+                let reversed = escape_like(&suffix.chars().rev().collect::<String>());
+                self.compare(format!("reverse({})", column).as_str(), " LIKE ", format!("{}%", reversed), Type::TEXT)
+            }
+            None => self,
+        }
+    }
+
+    // условие без значений, только для констант из кода
+    pub fn raw(&mut self, condition: &'static str) -> &mut Self {
+        self.conditions.push(condition.to_string());
+        self
+    }
+
+    // column IN (select WHERE ...), условия подзапроса нумеруются вместе с внешними
+    pub fn in_subquery(&mut self, column: &str, select: &str, build: impl FnOnce(&mut SqlFilter)) -> &mut Self {
+        let mut inner = SqlFilter { conditions: Vec::new(), params: std::mem::take(&mut self.params) };
+        build(&mut inner);
+        self.params = inner.params;
+        self.conditions.push(format!("{} IN ({}{})", column, select, where_clause(&inner.conditions)));
+        self
+    }
+
+    // keyset-пагинация по (created_at, id)
+    pub fn cursor(&mut self, cursor: Option<&PaymentCursor>) -> &mut Self {
+        let Some(cursor) = cursor else { return self };
+        let operator = match cursor.direction {
+            CursorDirection::Next => "<",
+            CursorDirection::Prev => ">",
+        };
+        let created_at = self.bind(cursor.created_at, Type::TIMESTAMP);
+        let id = self.bind(cursor.id.clone(), Type::VARCHAR);
+        self.conditions.push(format!("(created_at, id){}({}, {})", operator, created_at, id));
+        self
+    }
+
+    // " WHERE ..." + хвост запроса (сортировка, лимит)
+    pub fn build(self, tail: &str) -> SqlQuery {
+        SqlQuery { sql: format!("{}{}", where_clause(&self.conditions), tail), params: self.params }
+    }
+}
+
+fn where_clause(conditions: &[String]) -> String {
+    if conditions.is_empty() {
+        String::new()
+    } else {
+        format!(" WHERE {}", conditions.join(" AND "))
+    }
+}
+
+fn escape_like(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('%', "\\%").replace('_', "\\_")
+}
@@ -2,9 +2,11 @@ use std::str::FromStr;
 use chrono::NaiveDateTime;
 use rust_decimal::Decimal;
 use serde::{Deserialize, Serialize};
-use crate::models::payments::payment::{FeeTypes, FullPayment, PaymentSides, PaymentStatuses, PaymentStatusesSlim, ToSQL};
+use crate::models::payments::cursor::PaymentCursor;
+use crate::models::payments::payment::{FeeTypes, FullPayment, PaymentSearch, PaymentSides, PaymentStatuses, PaymentStatusesSlim, ToSQL};
 use crate::models::payments::payment_proto;
 use crate::models::payments::payment_proto::from_timestamp_to_chrono;
+use crate::models::payments::refund::MerchantRefund;
 
 #[derive(Deserialize, Serialize, Debug, Clone)]
 pub struct MerchantPayment {
@@ -30,6 +32,11 @@ pub struct MerchantPayment {
     pub created_at: NaiveDateTime,
     pub updated_at: Option<NaiveDateTime>,
     pub deadline: NaiveDateTime,
+    pub original_fiat_amount: Option<Decimal>,
+    pub refunded_fiat_amount: Option<Decimal>,
+    // возврат, о котором уведомляется мерчант, в выборках из БД не заполняется
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub refund: Option<MerchantRefund>,
 }
 
 impl ToSQL for MerchantPayment {
@@ -37,7 +44,7 @@ impl ToSQL for MerchantPayment {
         String::from("SELECT id, external_id, merchant_id, client_id, status,
         payment_side, currency, target_amount, fiat_amount, crypto_amount,
         fee_type, margin, exchange_rate, fiat_fee, crypto_fee, holder_name, holder_account,
-        bank_name, method, created_at, updated_at, deadline
+        bank_name, method, created_at, updated_at, deadline, original_fiat_amount, refunded_fiat_amount
         FROM payments")
     }
 }
@@ -67,6 +74,9 @@ impl From<tokio_postgres::Row> for MerchantPayment {
             created_at: row.get("created_at"),
             updated_at: row.get("updated_at"),
             deadline: row.get("deadline"),
+            original_fiat_amount: row.get("original_fiat_amount"),
+            refunded_fiat_amount: row.get("refunded_fiat_amount"),
+            refund: None,
         }
     }
 }
@@ -96,6 +106,9 @@ impl From<FullPayment> for MerchantPayment {
             created_at: payment.created_at,
             updated_at: payment.updated_at,
             deadline: payment.deadline,
+            original_fiat_amount: payment.original_fiat_amount,
+            refunded_fiat_amount: payment.refunded_fiat_amount,
+            refund: None,
         }
     }
 }
@@ -126,6 +139,9 @@ impl From<payment_proto::PaymentProto> for MerchantPayment {
             created_at: from_timestamp_to_chrono(payment_proto.created_at.unwrap()),
             updated_at: payment_proto.updated_at.map(from_timestamp_to_chrono),
             deadline: from_timestamp_to_chrono(payment_proto.deadline.unwrap()),
+            original_fiat_amount: payment_proto.original_fiat_amount.map(|a| Decimal::from_str(a.as_str()).unwrap()),
+            refunded_fiat_amount: payment_proto.refunded_fiat_amount.map(|a| Decimal::from_str(a.as_str()).unwrap()),
+            refund: None,
         }
     }
 }
@@ -139,6 +155,10 @@ pub struct GetMerchantPayments {
     pub payment_side: Option<PaymentSides>,
     pub from: Option<NaiveDateTime>,
     pub to: Option<NaiveDateTime>,
+    #[serde(flatten)]
+    pub search: PaymentSearch,
     pub limit: Option<u32>,
     pub page: Option<u32>,
+    // при наличии курсора page игнорируется
+    pub cursor: Option<PaymentCursor>,
 }
\ No newline at end of file
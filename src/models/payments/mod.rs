@@ -2,6 +2,16 @@ pub mod payment;
 pub mod trader;
 pub mod merchant;
 pub mod requests;
+pub mod dispute;
+pub mod close;
+pub mod refund;
+pub mod cursor;
+pub mod filter;
+pub mod stats;
+pub mod export;
+pub mod earnings;
+pub mod payout;
+pub mod expiry;
 
 
 pub mod payment_proto {
@@ -45,6 +55,10 @@ pub mod payment_proto {
                 last_four: value.last_four,
                 card_last_four: value.card_last_four,
                 close_by: value.close_by,
+                original_fiat_amount: value.original_fiat_amount.map(|a| a.to_string()),
+                original_crypto_amount: value.original_crypto_amount.map(|a| a.to_string()),
+                original_trader_crypto_amount: value.original_trader_crypto_amount.map(|a| a.to_string()),
+                refunded_fiat_amount: value.refunded_fiat_amount.map(|a| a.to_string()),
             }
         }
     }
@@ -3,6 +3,7 @@ use std::str::FromStr;
 use chrono::NaiveDateTime;
 use rust_decimal::Decimal;
 use serde::{Deserialize, Serialize};
+use crate::models::payments::cursor::PaymentCursor;
 use crate::models::payments::payment_proto;
 use crate::models::payments::payment_proto::from_timestamp_to_chrono;
 
@@ -77,13 +78,14 @@ pub enum PaymentStatusesSlim {
 }
 
 impl PaymentStatusesSlim{
-    pub fn get_statuses_for_sql_query(&self) -> String {
+    // полные статусы, которые входят в группу
+    pub fn statuses(&self) -> Vec<PaymentStatuses> {
         match self {
-            PaymentStatusesSlim::Completed => String::from("'COMPLETED'"),
-            PaymentStatusesSlim::Processing => String::from("'PROCESSING', 'PAID'"),
-            PaymentStatusesSlim::Frozen => String::from("'FROZEN'"),
-            PaymentStatusesSlim::Cancelled => String::from("'CANCELLED_BY_TIMEOUT', 'CANCELLED_BY_ADMIN', \
-            'CANCELLED_BY_TRADER', 'CANCELLED_BY_MERCHANT', 'CANCELLED_BY_CUSTOMER'")
+            PaymentStatusesSlim::Completed => vec![PaymentStatuses::Completed],
+            PaymentStatusesSlim::Processing => vec![PaymentStatuses::Processing, PaymentStatuses::Paid],
+            PaymentStatusesSlim::Frozen => vec![PaymentStatuses::Frozen],
+            PaymentStatusesSlim::Cancelled => vec![PaymentStatuses::CancelledByTimeout, PaymentStatuses::CancelledByAdmin,
+                PaymentStatuses::CancelledByTrader, PaymentStatuses::CancelledByMerchant, PaymentStatuses::CancelledByCustomer],
         }
     }
 }
@@ -225,6 +227,12 @@ pub struct FullPayment {
     pub last_four : String,
     pub card_last_four: String,
     pub close_by: Option<String>,
+    // суммы до частичного закрытия или переплаты, None если закрыт на исходную сумму
+    pub original_fiat_amount: Option<Decimal>,
+    pub original_crypto_amount: Option<Decimal>,
+    pub original_trader_crypto_amount: Option<Decimal>,
+    // сумма возвратов и чарджбэков в фиате, None если возвратов не было
+    pub refunded_fiat_amount: Option<Decimal>,
 }
 
 
@@ -270,6 +278,10 @@ impl From<tokio_postgres::Row> for FullPayment {
             last_four: row.get("last_four"),
             card_last_four: row.get("card_last_four"),
             close_by: row.get("close_by"),
+            original_fiat_amount: row.get("original_fiat_amount"),
+            original_crypto_amount: row.get("original_crypto_amount"),
+            original_trader_crypto_amount: row.get("original_trader_crypto_amount"),
+            refunded_fiat_amount: row.get("refunded_fiat_amount"),
         }
     }
 }
@@ -311,6 +323,10 @@ impl From<&tokio_postgres::Row> for FullPayment {
             last_four: row.get("last_four"),
             card_last_four: row.get("card_last_four"),
             close_by: row.get("close_by"),
+            original_fiat_amount: row.get("original_fiat_amount"),
+            original_crypto_amount: row.get("original_crypto_amount"),
+            original_trader_crypto_amount: row.get("original_trader_crypto_amount"),
+            refunded_fiat_amount: row.get("refunded_fiat_amount"),
         }
     }
 }
@@ -350,6 +366,10 @@ impl From<payment_proto::PaymentProto> for FullPayment {
             last_four: value.last_four,
             card_last_four: value.card_last_four,
             close_by: value.close_by,
+            original_fiat_amount: value.original_fiat_amount.map(|a| Decimal::from_str(a.as_str()).unwrap()),
+            original_crypto_amount: value.original_crypto_amount.map(|a| Decimal::from_str(a.as_str()).unwrap()),
+            original_trader_crypto_amount: value.original_trader_crypto_amount.map(|a| Decimal::from_str(a.as_str()).unwrap()),
+            refunded_fiat_amount: value.refunded_fiat_amount.map(|a| Decimal::from_str(a.as_str()).unwrap()),
         }
     }
 }
@@ -376,8 +396,26 @@ pub struct GetPaymentRequestAdmin {
     pub merchant_id: Option<String>,
     pub trader_id: Option<String>,
     pub statuses: Option<Vec<PaymentStatusesSlim>>,
+    pub from: Option<NaiveDateTime>,
+    pub to: Option<NaiveDateTime>,
+    #[serde(flatten)]
+    pub search: PaymentSearch,
     pub limit: Option<u32>,
     pub page: Option<u32>,
+    pub cursor: Option<PaymentCursor>,
+}
+
+// общие фильтры поиска для мерчанта, трейдера и админа
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct PaymentSearch {
+    // границы fiat_amount включительно
+    pub min_amount: Option<Decimal>,
+    pub max_amount: Option<Decimal>,
+    pub methods: Option<Vec<String>>,
+    pub currencies: Option<Vec<String>>,
+    pub bank_name: Option<String>,
+    pub holder_account_suffix: Option<String>,
+    pub external_id_prefix: Option<String>,
 }
 
 
@@ -0,0 +1,280 @@
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+use chrono::NaiveDateTime;
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+use crate::errors::LibError;
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum PayoutStatus {
+    // платежи закреплены за пачкой, начисление трейдеру еще не подтверждено
+    Pending,
+    Completed,
+    // пачка отменена, ее платежи снова доступны для выплаты
+    Failed,
+}
+
+impl Display for PayoutStatus {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PayoutStatus::Pending => f.write_str("PENDING"),
+            PayoutStatus::Completed => f.write_str("COMPLETED"),
+            PayoutStatus::Failed => f.write_str("FAILED"),
+        }
+    }
+}
+
+impl FromStr for PayoutStatus {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "PENDING" => Ok(PayoutStatus::Pending),
+            "COMPLETED" => Ok(PayoutStatus::Completed),
+            "FAILED" => Ok(PayoutStatus::Failed),
+            _ => Err(format!("unknown payout status {}", s)),
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum PayoutEventKind {
+    Created,
+    // вызов change_balance, повторяется до успеха
+    BalanceRequested,
+    BalanceError,
+    Completed,
+    Failed,
+}
+
+impl Display for PayoutEventKind {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PayoutEventKind::Created => f.write_str("CREATED"),
+            PayoutEventKind::BalanceRequested => f.write_str("BALANCE_REQUESTED"),
+            PayoutEventKind::BalanceError => f.write_str("BALANCE_ERROR"),
+            PayoutEventKind::Completed => f.write_str("COMPLETED"),
+            PayoutEventKind::Failed => f.write_str("FAILED"),
+        }
+    }
+}
+
+impl FromStr for PayoutEventKind {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "CREATED" => Ok(PayoutEventKind::Created),
+            "BALANCE_REQUESTED" => Ok(PayoutEventKind::BalanceRequested),
+            "BALANCE_ERROR" => Ok(PayoutEventKind::BalanceError),
+            "COMPLETED" => Ok(PayoutEventKind::Completed),
+            "FAILED" => Ok(PayoutEventKind::Failed),
+            _ => Err(format!("unknown payout event {}", s)),
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct PayoutBatch {
+    pub id: String,
+    pub trader_id: String,
+    pub status: PayoutStatus,
+    // сумма trader_crypto_fee платежей пачки
+    pub amount: Decimal,
+    pub payments_count: i64,
+    // в пачку попадают платежи, созданные раньше until
+    pub until: NaiveDateTime,
+    pub idempotent_key: String,
+    pub created_by: String,
+    pub created_at: NaiveDateTime,
+    pub completed_at: Option<NaiveDateTime>,
+}
+
+// что делать с пачкой при повторе create_payout или resume_payout
+#[derive(Debug)]
+pub enum PayoutStep {
+    // завершенная или отмененная пачка возвращается как есть
+    Done(PayoutBatch),
+    // PENDING доводится начислением с тем же ключом
+    Settle(PayoutBatch),
+}
+
+impl PayoutBatch {
+    // пустая пачка не создается
+    pub fn new(actor_id: &str, request: NewPayoutRequest, items: &[PayoutItem], now: NaiveDateTime)
+        -> Result<Self, LibError>
+    {
+        if items.is_empty() {
+            return Err(LibError::NotFound);
+        }
+        Ok(Self {
+            id: Uuid::now_v7().to_string(),
+            trader_id: request.trader_id,
+            status: PayoutStatus::Pending,
+            amount: items.iter().map(|item| item.amount).sum::<Decimal>(),
+            payments_count: items.len() as i64,
+            until: request.until,
+            idempotent_key: request.idempotent_key,
+            created_by: actor_id.to_string(),
+            created_at: now,
+            completed_at: None,
+        })
+    }
+
+    // ключ начисления в trader-сервисе, одинаковый при каждом повторе
+    pub fn balance_key(&self) -> String {
+        format!("payout:{}", self.id)
+    }
+
+    pub fn next_step(self) -> PayoutStep {
+        match self.status {
+            PayoutStatus::Pending => PayoutStep::Settle(self),
+            _ => PayoutStep::Done(self),
+        }
+    }
+
+    // отмена возможна, только пока начисление не запрашивалось: после запроса оно могло пройти,
+    // и такую пачку можно только довести через resume_payout
+    pub fn cancel(&mut self, balance_requested: bool, now: NaiveDateTime) -> Result<(), LibError> {
+        if self.status != PayoutStatus::Pending || balance_requested {
+            return Err(LibError::Conflict);
+        }
+        self.status = PayoutStatus::Failed;
+        self.completed_at = Some(now);
+        Ok(())
+    }
+
+    // начисление запрашивается только по PENDING: отмененную пачку больше нельзя начислить
+    pub fn request_balance(&self) -> Result<(), LibError> {
+        if self.status != PayoutStatus::Pending {
+            return Err(LibError::Conflict);
+        }
+        Ok(())
+    }
+
+    pub fn complete(&mut self, now: NaiveDateTime) {
+        self.status = PayoutStatus::Completed;
+        self.completed_at = Some(now);
+    }
+}
+
+impl From<&tokio_postgres::Row> for PayoutBatch {
+    fn from(row: &tokio_postgres::Row) -> Self {
+        Self {
+            id: row.get("id"),
+            trader_id: row.get("trader_id"),
+            status: PayoutStatus::from_str(row.get("status")).unwrap(),
+            amount: row.get("amount"),
+            payments_count: row.get("payments_count"),
+            until: row.get("until"),
+            idempotent_key: row.get("idempotent_key"),
+            created_by: row.get("created_by"),
+            created_at: row.get("created_at"),
+            completed_at: row.get("completed_at"),
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct PayoutItem {
+    pub payment_id: String,
+    pub amount: Decimal,
+}
+
+impl From<&tokio_postgres::Row> for PayoutItem {
+    fn from(row: &tokio_postgres::Row) -> Self {
+        Self {
+            payment_id: row.get("payment_id"),
+            amount: row.get("amount"),
+        }
+    }
+}
+
+// журнал действий с пачкой, только добавляется
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct PayoutEvent {
+    pub batch_id: String,
+    pub kind: PayoutEventKind,
+    pub actor_id: String,
+    pub details: Option<String>,
+    pub created_at: NaiveDateTime,
+}
+
+impl From<&tokio_postgres::Row> for PayoutEvent {
+    fn from(row: &tokio_postgres::Row) -> Self {
+        Self {
+            batch_id: row.get("batch_id"),
+            kind: PayoutEventKind::from_str(row.get("kind")).unwrap(),
+            actor_id: row.get("actor_id"),
+            details: row.get("details"),
+            created_at: row.get("created_at"),
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct NewPayoutRequest {
+    pub trader_id: String,
+    pub until: NaiveDateTime,
+    pub idempotent_key: String,
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_decimal::dec;
+    use super::*;
+
+    fn batch() -> PayoutBatch {
+        let request = NewPayoutRequest {
+            trader_id: "t1".to_string(),
+            until: NaiveDateTime::default(),
+            idempotent_key: "k1".to_string(),
+        };
+        let items = [
+            PayoutItem { payment_id: "p1".to_string(), amount: dec!(1.5) },
+            PayoutItem { payment_id: "p2".to_string(), amount: dec!(0.25) },
+        ];
+        PayoutBatch::new("admin", request, &items, NaiveDateTime::default()).unwrap()
+    }
+
+    #[test]
+    fn batch_sums_payable_payments() {
+        let batch = batch();
+        assert_eq!((batch.status, batch.amount, batch.payments_count), (PayoutStatus::Pending, dec!(1.75), 2));
+        assert_eq!(batch.balance_key(), format!("payout:{}", batch.id));
+
+        let request = NewPayoutRequest { trader_id: "t1".to_string(), until: NaiveDateTime::default(), idempotent_key: "k2".to_string() };
+        assert!(matches!(PayoutBatch::new("admin", request, &[], NaiveDateTime::default()), Err(LibError::NotFound)));
+    }
+
+    #[test]
+    fn replay_settles_only_pending_batch() {
+        let pending = batch();
+        let key = pending.balance_key();
+        // resume_payout и повтор create_payout с тем же ключом доводят начисление тем же ключом
+        match pending.clone().next_step() {
+            PayoutStep::Settle(batch) => assert_eq!(batch.balance_key(), key),
+            step => panic!("unexpected {:?}", step),
+        }
+        let mut completed = pending;
+        completed.complete(NaiveDateTime::default());
+        assert!(matches!(completed.next_step(), PayoutStep::Done(batch) if batch.status == PayoutStatus::Completed));
+    }
+
+    #[test]
+    fn cancel_only_before_balance_request() {
+        let now = NaiveDateTime::default();
+        let mut requested = batch();
+        assert!(matches!(requested.cancel(true, now), Err(LibError::Conflict)));
+        assert_eq!(requested.status, PayoutStatus::Pending);
+
+        let mut created = batch();
+        created.cancel(false, now).unwrap();
+        assert_eq!(created.status, PayoutStatus::Failed);
+        assert!(matches!(created.cancel(false, now), Err(LibError::Conflict)));
+        assert!(matches!(created.request_balance(), Err(LibError::Conflict)));
+        assert!(matches!(created.next_step(), PayoutStep::Done(_)));
+        batch().request_balance().unwrap();
+    }
+}
@@ -0,0 +1,269 @@
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+use chrono::NaiveDateTime;
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use crate::errors::LibError;
+use crate::models::ledger::{BalanceAction, LedgerOwner};
+use crate::models::payments::payment::{FullPayment, PaymentSides, PaymentStatuses};
+use crate::models::reconciliation::ExpectedBalanceAction;
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum RefundKind {
+    // возврат по инициативе мерчанта, только BUY
+    Refund,
+    // отзыв оплаты банком клиента, оформляет админ, только SELL
+    Chargeback,
+}
+
+impl Display for RefundKind {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RefundKind::Refund => f.write_str("REFUND"),
+            RefundKind::Chargeback => f.write_str("CHARGEBACK"),
+        }
+    }
+}
+
+impl FromStr for RefundKind {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "REFUND" => Ok(RefundKind::Refund),
+            "CHARGEBACK" => Ok(RefundKind::Chargeback),
+            _ => Err(format!("unknown refund kind {}", s)),
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum RefundStatus {
+    // создан, движения балансов еще не проведены
+    Pending,
+    Completed,
+    Failed,
+}
+
+impl Display for RefundStatus {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RefundStatus::Pending => f.write_str("PENDING"),
+            RefundStatus::Completed => f.write_str("COMPLETED"),
+            RefundStatus::Failed => f.write_str("FAILED"),
+        }
+    }
+}
+
+impl FromStr for RefundStatus {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "PENDING" => Ok(RefundStatus::Pending),
+            "COMPLETED" => Ok(RefundStatus::Completed),
+            "FAILED" => Ok(RefundStatus::Failed),
+            _ => Err(format!("unknown refund status {}", s)),
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct Refund {
+    pub id: String,
+    pub payment_id: String,
+    pub merchant_id: String,
+    pub trader_id: String,
+    pub kind: RefundKind,
+    pub status: RefundStatus,
+    pub fiat_amount: Decimal,
+    // доли crypto_amount и trader_crypto_amount платежа пропорционально fiat_amount
+    pub crypto_amount: Decimal,
+    pub trader_crypto_amount: Decimal,
+    pub reason: String,
+    pub initiator_id: String,
+    pub idempotent_key: String,
+    pub created_at: NaiveDateTime,
+    pub completed_at: Option<NaiveDateTime>,
+}
+
+impl From<&tokio_postgres::Row> for Refund {
+    fn from(row: &tokio_postgres::Row) -> Self {
+        Self {
+            id: row.get("id"),
+            payment_id: row.get("payment_id"),
+            merchant_id: row.get("merchant_id"),
+            trader_id: row.get("trader_id"),
+            kind: RefundKind::from_str(row.get("kind")).unwrap(),
+            status: RefundStatus::from_str(row.get("status")).unwrap(),
+            fiat_amount: row.get("fiat_amount"),
+            crypto_amount: row.get("crypto_amount"),
+            trader_crypto_amount: row.get("trader_crypto_amount"),
+            reason: row.get("reason"),
+            initiator_id: row.get("initiator_id"),
+            idempotent_key: row.get("idempotent_key"),
+            created_at: row.get("created_at"),
+            completed_at: row.get("completed_at"),
+        }
+    }
+}
+
+impl Refund {
+    // обратные движения по стороне платежа: на BUY мерчант возвращает полученное, трейдеру
+    // возвращается списанное; на SELL трейдер возвращает зачисленное, мерчанту возвращается списанное
+    pub fn balance_actions(&self, side: PaymentSides) -> Vec<ExpectedBalanceAction> {
+        let (payer, payer_id, payer_amount, receiver, receiver_id, receiver_amount) = match side {
+            PaymentSides::Buy => (LedgerOwner::Merchant, &self.merchant_id, self.crypto_amount,
+                                  LedgerOwner::Trader, &self.trader_id, self.trader_crypto_amount),
+            PaymentSides::Sell => (LedgerOwner::Trader, &self.trader_id, self.trader_crypto_amount,
+                                   LedgerOwner::Merchant, &self.merchant_id, self.crypto_amount),
+        };
+        vec![
+            ExpectedBalanceAction {
+                payment_id: self.payment_id.clone(),
+                owner_type: payer,
+                owner_id: payer_id.clone(),
+                action: BalanceAction::WithdrawMain,
+                amount: payer_amount,
+            },
+            ExpectedBalanceAction {
+                payment_id: self.payment_id.clone(),
+                owner_type: receiver,
+                owner_id: receiver_id.clone(),
+                action: BalanceAction::Deposit,
+                amount: receiver_amount,
+            },
+        ]
+    }
+}
+
+// возврат в ответах и вебхуках мерчанту, без трейдера, его суммы и инициатора
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct MerchantRefund {
+    pub id: String,
+    pub kind: RefundKind,
+    pub status: RefundStatus,
+    pub fiat_amount: Decimal,
+    pub crypto_amount: Decimal,
+    pub created_at: NaiveDateTime,
+    pub completed_at: Option<NaiveDateTime>,
+}
+
+impl From<&Refund> for MerchantRefund {
+    fn from(refund: &Refund) -> Self {
+        Self {
+            id: refund.id.clone(),
+            kind: refund.kind,
+            status: refund.status,
+            fiat_amount: refund.fiat_amount,
+            crypto_amount: refund.crypto_amount,
+            created_at: refund.created_at,
+            completed_at: refund.completed_at,
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct NewRefundRequest {
+    pub payment_id: String,
+    pub kind: RefundKind,
+    // None - возврат всей оставшейся суммы
+    pub fiat_amount: Option<Decimal>,
+    pub reason: String,
+    // повтор запроса с тем же ключом возвращает уже созданный возврат
+    pub idempotent_key: String,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RefundAmounts {
+    pub fiat_amount: Decimal,
+    pub crypto_amount: Decimal,
+    pub trader_crypto_amount: Decimal,
+}
+
+const CRYPTO_DP: u32 = 6;
+
+// проверяет возврат и считает его суммы, сумма всех возвратов не больше суммы платежа
+pub fn prepare_refund(payment: &FullPayment, request: &NewRefundRequest) -> Result<RefundAmounts, LibError> {
+    if payment.status != PaymentStatuses::Completed {
+        return Err(LibError::Conflict);
+    }
+    let side_allowed = match request.kind {
+        RefundKind::Refund => payment.payment_side == PaymentSides::Buy,
+        RefundKind::Chargeback => payment.payment_side == PaymentSides::Sell,
+    };
+    if !side_allowed {
+        return Err(LibError::Conflict);
+    }
+    let available = payment.fiat_amount - payment.refunded_fiat_amount.unwrap_or_default();
+    let fiat_amount = request.fiat_amount.unwrap_or(available);
+    if fiat_amount <= Decimal::ZERO || fiat_amount > available {
+        return Err(LibError::InvalidAmount);
+    }
+    let ratio = fiat_amount / payment.fiat_amount;
+    Ok(RefundAmounts {
+        fiat_amount,
+        crypto_amount: (payment.crypto_amount * ratio).round_dp(CRYPTO_DP),
+        trader_crypto_amount: (payment.trader_crypto_amount * ratio).round_dp(CRYPTO_DP),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_decimal::dec;
+    use super::*;
+
+    #[test]
+    fn refunds_do_not_exceed_payment_amount() {
+        let payment = FullPayment {
+            status: PaymentStatuses::Completed,
+            payment_side: PaymentSides::Buy,
+            fiat_amount: dec!(1000),
+            crypto_amount: dec!(10),
+            trader_crypto_amount: dec!(10.2),
+            refunded_fiat_amount: Some(dec!(400)),
+            ..Default::default()
+        };
+        let request = |kind, fiat_amount| NewRefundRequest {
+            payment_id: String::new(), kind, fiat_amount, reason: String::new(), idempotent_key: String::new(),
+        };
+        let amounts = prepare_refund(&payment, &request(RefundKind::Refund, None)).unwrap();
+        assert_eq!((amounts.fiat_amount, amounts.crypto_amount, amounts.trader_crypto_amount), (dec!(600), dec!(6), dec!(6.12)));
+        assert!(prepare_refund(&payment, &request(RefundKind::Refund, Some(dec!(601)))).is_err());
+        assert!(prepare_refund(&payment, &request(RefundKind::Chargeback, Some(dec!(100)))).is_err());
+    }
+
+    #[test]
+    fn chargeback_moves_funds_back_to_merchant() {
+        let refund = Refund {
+            id: "r1".to_string(),
+            payment_id: "p1".to_string(),
+            merchant_id: "m1".to_string(),
+            trader_id: "t1".to_string(),
+            kind: RefundKind::Chargeback,
+            status: RefundStatus::Pending,
+            fiat_amount: dec!(500),
+            crypto_amount: dec!(5),
+            trader_crypto_amount: dec!(5.1),
+            reason: String::new(),
+            initiator_id: "admin".to_string(),
+            idempotent_key: "k1".to_string(),
+            created_at: NaiveDateTime::default(),
+            completed_at: None,
+        };
+        let actions = |side| refund.balance_actions(side).into_iter()
+            .map(|a| (a.owner_type, a.owner_id, a.action, a.amount))
+            .collect::<Vec<_>>();
+        assert_eq!(actions(PaymentSides::Sell), vec![
+            (LedgerOwner::Trader, "t1".to_string(), BalanceAction::WithdrawMain, dec!(5.1)),
+            (LedgerOwner::Merchant, "m1".to_string(), BalanceAction::Deposit, dec!(5)),
+        ]);
+        assert_eq!(actions(PaymentSides::Buy), vec![
+            (LedgerOwner::Merchant, "m1".to_string(), BalanceAction::WithdrawMain, dec!(5)),
+            (LedgerOwner::Trader, "t1".to_string(), BalanceAction::Deposit, dec!(5.1)),
+        ]);
+
+        let view = serde_json::to_value(MerchantRefund::from(&refund)).unwrap();
+        assert!(view.get("trader_id").is_none() && view.get("trader_crypto_amount").is_none() && view.get("initiator_id").is_none());
+    }
+}
@@ -1,6 +1,9 @@
-use tokio_postgres::types::{ToSql, Type};
+use tokio_postgres::types::Type;
+use crate::models::payments::cursor::{CursorDirection, CursorKey, PaymentCursor, PaymentsPage};
+use crate::models::payments::dispute::{GetDisputedPaymentsAdmin, OVERDUE_CONDITION};
+use crate::models::payments::filter::{SqlFilter, SqlQuery};
 use crate::models::payments::merchant::GetMerchantPayments;
-use crate::models::payments::payment::{GetPaymentRequestAdmin, PaymentSides};
+use crate::models::payments::payment::{GetPaymentRequestAdmin, PaymentSearch, PaymentStatusesSlim};
 use crate::models::payments::trader::GetPaymentsTrader;
 
 
@@ -10,156 +13,132 @@ pub enum GetPaymentsRequest {
     Merchant((String, Option<GetMerchantPayments>)),
     Trader((String, Option<GetPaymentsTrader>)),
     Admin(Option<GetPaymentRequestAdmin>),
-    
+    AdminDisputes(Option<GetDisputedPaymentsAdmin>),
+}
+
+// группы статусов раскрываются в полный список для status=ANY($n)
+fn status_values(statuses: Option<&Vec<PaymentStatusesSlim>>) -> Option<Vec<String>> {
+    statuses.map(|statuses| statuses.iter()
+        .flat_map(|status| status.statuses())
+        .map(|status| status.to_string())
+        .collect())
+}
+
+// сумма - диапазоном, строки - точным совпадением, префиксом или суффиксом, чтобы работали индексы
+fn search_filters<'a>(filter: &'a mut SqlFilter, search: &PaymentSearch) -> &'a mut SqlFilter {
+    filter.ge_opt("fiat_amount", search.min_amount.as_ref(), Type::NUMERIC)
+        .le_opt("fiat_amount", search.max_amount.as_ref(), Type::NUMERIC)
+        .any_of("method", search.methods.clone())
+        .any_of("currency", search.currencies.clone())
+        .eq_opt("bank_name", search.bank_name.as_ref(), Type::VARCHAR)
+        .suffix_opt("holder_account", search.holder_account_suffix.as_ref())
+        .prefix_opt("external_id", search.external_id_prefix.as_ref())
 }
 
 impl GetPaymentsRequest {
-    // делает SQL строку из условий и массив из параметров
-    pub fn to_sql(&self) -> (String, Vec<(&(dyn ToSql + Sync), Type)>) {
-        let mut from_exists = false;
-        let mut to_exists = false;
-        match self {
-            GetPaymentsRequest::Merchant((merchant_id, request)) => {
-                let mut query = String::from(" WHERE merchant_id=$1");
-                let mut query_conditions = Vec::<String>::with_capacity(5);
-                let mut query_params: Vec<(&(dyn ToSql + Sync), Type)> = Vec::with_capacity(10);
-                query_params.push((merchant_id, Type::VARCHAR));
-                let mut param_index = 2;
-                if let Some(request) = request {
-                    if let Some(id) = request.id.as_ref() {
-                        query_conditions.push(format!("id=${}", param_index));
-                        query_params.push((id, Type::VARCHAR));
-                        param_index += 1;
-                    }
-                    if let Some(side) = request.payment_side.as_ref() {
-                        query_conditions.push(format!("payment_side=${}", param_index));
-                        match side {
-                            PaymentSides::Buy => query_params.push((&"BUY", Type::VARCHAR)),
-                            PaymentSides::Sell => query_params.push((&"SELL", Type::VARCHAR)),
-                        }
-                        param_index += 1;
-                    }
-                    if let Some(from) = request.from.as_ref() {
-                        query_params.push((from, Type::TIMESTAMP));
-                        query_conditions.push(format!("created_at>=${}", param_index));
-                        param_index += 1;
-                        from_exists = true;
-                    }
-                    if let Some(to) = request.to.as_ref() {
-                        query_params.push((to, Type::TIMESTAMP));
-                        query_conditions.push(format!("created_at<=${}", param_index));
-                        param_index += 1;
-                        to_exists = true;
-                    }
-                    if let Some(statuses) = request.status.as_ref() {
-                        query_conditions.push(format!("status IN ({})", statuses.iter().map(|status| status.get_statuses_for_sql_query())
-                            .collect::<Vec<_>>().join(", ")));
-                    }
-                    if !query_conditions.is_empty() {
-                        query.push_str(" AND ");
-                    }
-                    query.push_str(query_conditions.join(" AND ").as_str());
-                    if from_exists && to_exists {
-                        if (request.to.unwrap() - request.from.unwrap()).num_days() > 30 {
-                            let limit = request.limit.unwrap_or(100).min(100);
-                            let offset = (request.page.unwrap_or(1) - 1) * limit;
-                            query.push_str(format!(" ORDER BY created_at DESC LIMIT {} OFFSET {}", limit, offset).as_str());
-                        }else {
-                            query.push_str(" ORDER BY created_at DESC");
-                        }
-                    }else {
-                        let limit = request.limit.unwrap_or(100).min(100);
-                        let offset = (request.page.unwrap_or(1) - 1) * limit;
-                        query.push_str(format!(" ORDER BY created_at DESC LIMIT {} OFFSET {}", limit, offset).as_str());
-                    }
+    // делает SQL строку условий с сортировкой и лимитом и параметры к ней
+    pub fn to_sql(&self) -> SqlQuery {
+        let unfiltered = matches!(self, GetPaymentsRequest::Merchant((_, None))
+            | GetPaymentsRequest::Trader((_, None)) | GetPaymentsRequest::Admin(None));
+        let tail = match self.pagination() {
+            _ if unfiltered => String::new(),
+            (Some(limit), page, cursor) => order_and_limit(cursor, limit, page),
+            // мерчант за период до 30 дней получает всю выдачу
+            (None, _, _) => " ORDER BY created_at DESC, id DESC".to_string(),
+        };
+        self.filter().build(tail.as_str())
+    }
 
-                }
+    // только условия выборки, без сортировки и лимита
+    pub(crate) fn filter(&self) -> SqlFilter {
+        let mut filter = self.scope();
+        filter.cursor(self.pagination().2);
+        filter
+    }
 
-                (query, query_params)
-            },
+    // условия выборки без курсора страницы, для агрегатов по всей выдаче
+    pub(crate) fn scope(&self) -> SqlFilter {
+        let mut filter = SqlFilter::new();
+        match self {
+            GetPaymentsRequest::Merchant((merchant_id, request)) => {
+                filter.eq("merchant_id", merchant_id.clone(), Type::VARCHAR);
+                let Some(request) = request else { return filter };
+                filter.eq_opt("id", request.id.as_ref(), Type::VARCHAR)
+                    .eq_opt("client_id", request.client_id.as_ref(), Type::VARCHAR)
+                    .eq_opt("payment_side", request.payment_side.map(|side| side.to_string()).as_ref(), Type::VARCHAR)
+                    .ge_opt("created_at", request.from.as_ref(), Type::TIMESTAMP)
+                    .le_opt("created_at", request.to.as_ref(), Type::TIMESTAMP)
+                    .any_of("status", status_values(request.status.as_ref()));
+                search_filters(&mut filter, &request.search);
+            }
             GetPaymentsRequest::Trader((trader_id, request)) => {
-                let mut query = String::from(" WHERE trader_id=$1");
-                let mut query_conditions = Vec::<String>::with_capacity(5);
-                let mut query_params: Vec<(&(dyn ToSql + Sync), Type)> = Vec::with_capacity(10);
-                query_params.push((trader_id, Type::VARCHAR));
-                let mut param_index = 2;
-                if let Some(request) = request {
-                    if let Some(bank_id) = request.bank_id.as_ref() {
-                        query_conditions.push(format!("bank_id=${}", param_index));
-                        query_params.push((bank_id, Type::VARCHAR));
-                        param_index += 1;
-                    }
-                    if let Some(id) = request.id.as_ref() {
-                        query_conditions.push(format!("id=${}", param_index));
-                        query_params.push((id, Type::VARCHAR));
-                        param_index += 1;
-                    }
-                    if let Some(states) = request.status.as_ref() {
-                        query_conditions.push(format!("status IN ({})", states.iter().map(|status| status.get_statuses_for_sql_query())
-                            .collect::<Vec<_>>().join(", ")));
-                    }
-                    let limit = request.limit.unwrap_or(50).min(50);
-                    let offset = (request.page.unwrap_or(1) - 1) * limit;
-                    if !query_conditions.is_empty() {
-                        query.push_str(" AND ");
-                    }
-                    query.push_str(query_conditions.join(" AND ").as_str());
-                    query.push_str(format!(" ORDER BY created_at DESC LIMIT {} OFFSET {}", limit, offset).as_str());
-                }
-
-                (query, query_params)
+                filter.eq("trader_id", trader_id.clone(), Type::VARCHAR);
+                let Some(request) = request else { return filter };
+                filter.eq_opt("bank_id", request.bank_id.as_ref(), Type::VARCHAR)
+                    .eq_opt("id", request.id.as_ref(), Type::VARCHAR)
+                    .ge_opt("created_at", request.from.as_ref(), Type::TIMESTAMP)
+                    .le_opt("created_at", request.to.as_ref(), Type::TIMESTAMP)
+                    .any_of("status", status_values(request.status.as_ref()));
+                search_filters(&mut filter, &request.search);
             }
             GetPaymentsRequest::Admin(request) => {
-                let mut query_conditions = Vec::<String>::with_capacity(5);
-                let mut query_params: Vec<(&(dyn ToSql + Sync), Type)> = Vec::with_capacity(10);
-                let mut query = String::new();
-                let mut param_index = 1;
-                if let Some(request) = request {
-                    if let Some(trader_id) = request.trader_id.as_ref() {
-                        query_conditions.push(format!("trader_id=${}", param_index));
-                        query_params.push((trader_id, Type::VARCHAR));
-                        param_index += 1;
-                    }
-                    if let Some(bank_id) = request.bank_id.as_ref() {
-                        query_conditions.push(format!("bank_id=${}", param_index));
-                        query_params.push((bank_id, Type::VARCHAR));
-                        param_index += 1;
-                    }
-                    if let Some(id) = request.id.as_ref() {
-                        query_conditions.push(format!("id=${}", param_index));
-                        query_params.push((id, Type::VARCHAR));
-                        param_index += 1;
-                    }
-                    if let Some(merchant_id) = request.merchant_id.as_ref() {
-                        query_conditions.push(format!("merchant_id=${}", param_index));
-                        query_params.push((merchant_id, Type::VARCHAR));
-                    }
-                    if let Some(client_id) = request.client_id.as_ref() {
-                        query_conditions.push(format!("client_id=${}", param_index));
-                        query_params.push((client_id, Type::VARCHAR));
-                        param_index += 1;
-                    }
-                    if let Some(requisite_id) = request.requisite_id.as_ref() {
-                        query_conditions.push(format!("requisite_id=${}", param_index));
-                        query_params.push((requisite_id, Type::VARCHAR));
-                        param_index += 1;
-                    }
-                    if let Some(states) = request.statuses.as_ref() {
-                        query_conditions.push(format!("status IN ({})", states.iter().map(|status| status.get_statuses_for_sql_query())
-                            .collect::<Vec<_>>().join(", ")));
-                    }
-                    let limit = request.limit.unwrap_or(50).min(50);
-                    let offset = (request.page.unwrap_or(1) - 1) * limit;
-                    if !query_conditions.is_empty() {
-                        query.push_str(" WHERE ");
+                let Some(request) = request else { return filter };
+                filter.eq_opt("trader_id", request.trader_id.as_ref(), Type::VARCHAR)
+                    .eq_opt("bank_id", request.bank_id.as_ref(), Type::VARCHAR)
+                    .eq_opt("id", request.id.as_ref(), Type::VARCHAR)
+                    .eq_opt("merchant_id", request.merchant_id.as_ref(), Type::VARCHAR)
+                    .eq_opt("client_id", request.client_id.as_ref(), Type::VARCHAR)
+                    .eq_opt("external_id", request.external_id.as_ref(), Type::VARCHAR)
+                    .eq_opt("requisite_id", request.requisite_id.as_ref(), Type::VARCHAR)
+                    .ge_opt("created_at", request.from.as_ref(), Type::TIMESTAMP)
+                    .le_opt("created_at", request.to.as_ref(), Type::TIMESTAMP)
+                    .any_of("status", status_values(request.statuses.as_ref()));
+                search_filters(&mut filter, &request.search);
+            }
+            GetPaymentsRequest::AdminDisputes(request) => {
+                let request = request.clone().unwrap_or_default();
+                filter.in_subquery("id", "SELECT payment_id FROM disputes", |disputes| {
+                    disputes.any_of("status", request.dispute_statuses.as_ref()
+                        .map(|statuses| statuses.iter().map(|status| status.to_string()).collect()))
+                        .eq_opt("opened_by", request.opened_by.map(|opener| opener.to_string()).as_ref(), Type::VARCHAR);
+                    if request.overdue.unwrap_or(false) {
+                        disputes.raw(OVERDUE_CONDITION);
                     }
-                    query.push_str(query_conditions.join(" AND ").as_str());
-                    query.push_str(format!(" ORDER BY created_at DESC LIMIT {} OFFSET {}", limit, offset).as_str());
-                }
-
-                (query, query_params)
+                });
+                filter.eq_opt("trader_id", request.trader_id.as_ref(), Type::VARCHAR)
+                    .eq_opt("merchant_id", request.merchant_id.as_ref(), Type::VARCHAR)
+                    .any_of("status", status_values(request.statuses.as_ref()));
             }
         }
+        filter
+    }
+    // курсор и параметры страницы запроса: (limit, page, cursor), limit None - выдача без лимита
+    fn pagination(&self) -> (Option<u32>, u32, Option<&PaymentCursor>) {
+        match self {
+            GetPaymentsRequest::Merchant((_, Some(request))) => {
+                let unlimited = request.cursor.is_none() && match (request.from, request.to) {
+                    (Some(from), Some(to)) => (to - from).num_days() <= 30,
+                    _ => false,
+                };
+                let limit = if unlimited { None } else { Some(request.limit.unwrap_or(100).min(100)) };
+                (limit, request.page.unwrap_or(1), request.cursor.as_ref())
+            }
+            GetPaymentsRequest::Trader((_, Some(request))) =>
+                (Some(request.limit.unwrap_or(50).min(50)), request.page.unwrap_or(1), request.cursor.as_ref()),
+            GetPaymentsRequest::Admin(Some(request)) =>
+                (Some(request.limit.unwrap_or(50).min(50)), request.page.unwrap_or(1), request.cursor.as_ref()),
+            GetPaymentsRequest::AdminDisputes(Some(request)) =>
+                (Some(request.limit.unwrap_or(50).min(50)), request.page.unwrap_or(1), request.cursor.as_ref()),
+            GetPaymentsRequest::AdminDisputes(None) => (Some(50), 1, None),
+            _ => (None, 1, None),
+        }
+    }
+    // собирает страницу из строк, полученных по to_sql, вместе с курсорами соседних страниц
+    pub fn to_page<T: CursorKey>(&self, rows: Vec<T>) -> PaymentsPage<T> {
+        match self.pagination() {
+            (Some(limit), page, cursor) => PaymentsPage::new(rows, limit as usize, cursor, page),
+            (None, _, _) => PaymentsPage { items: rows, next_cursor: None, prev_cursor: None },
+        }
     }
     pub fn single_query<'a>(&'a self, mut sql_index: Option<&mut u32>) -> (String, Option<&'a str>) {
         // создает SQL строку условий без фильтров и с id юзера если оно есть 
@@ -204,8 +183,127 @@ impl GetPaymentsRequest {
             GetPaymentsRequest::Admin(req) => { 
                 req.unwrap().limit.unwrap_or(50).min(50) as usize
             },
+            GetPaymentsRequest::AdminDisputes(req) => {
+                req.and_then(|r| r.limit).unwrap_or(50).min(50) as usize
+            },
           
         }
     }
     
-}
\ No newline at end of file
+}
+
+// с курсором берется на строку больше лимита, чтобы понять есть ли следующая страница
+fn order_and_limit(cursor: Option<&PaymentCursor>, limit: u32, page: u32) -> String {
+    match cursor {
+        Some(PaymentCursor { direction: CursorDirection::Prev, .. }) =>
+            format!(" ORDER BY created_at ASC, id ASC LIMIT {}", limit + 1),
+        Some(_) => format!(" ORDER BY created_at DESC, id DESC LIMIT {}", limit + 1),
+        None => format!(" ORDER BY created_at DESC, id DESC LIMIT {} OFFSET {}", limit, (page.max(1) - 1) * limit),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::NaiveDate;
+    use crate::models::payments::dispute::{DisputeOpener, DisputeStatus};
+    use crate::models::payments::payment::PaymentSides;
+    use super::*;
+
+    fn params(query: &SqlQuery) -> Vec<String> {
+        query.params.iter().map(|(value, ty)| format!("{:?}:{}", value, ty)).collect()
+    }
+
+    #[test]
+    fn merchant_filters_are_parameterized() {
+        let request = GetPaymentsRequest::Merchant(("m1".to_string(), Some(GetMerchantPayments {
+            id: None,
+            client_id: Some("c1".to_string()),
+            status: Some(vec![PaymentStatusesSlim::Processing]),
+            payment_side: Some(PaymentSides::Sell),
+            from: None,
+            to: None,
+            search: PaymentSearch::default(),
+            limit: Some(10),
+            page: Some(3),
+            cursor: None,
+        })));
+        let query = request.to_sql();
+        assert_eq!(query.sql, " WHERE merchant_id=$1 AND client_id=$2 AND payment_side=$3 AND status=ANY($4) \
+            ORDER BY created_at DESC, id DESC LIMIT 10 OFFSET 20");
+        assert_eq!(params(&query), vec![
+            "\"m1\":varchar", "\"c1\":varchar", "\"SELL\":varchar", "[\"PROCESSING\", \"PAID\"]:_text",
+        ]);
+    }
+
+    #[test]
+    fn admin_indexes_follow_params() {
+        let created_at = NaiveDate::from_ymd_opt(2025, 1, 2).unwrap().and_hms_opt(3, 4, 5).unwrap();
+        let request = GetPaymentsRequest::Admin(Some(GetPaymentRequestAdmin {
+            id: None,
+            client_id: Some("c1".to_string()),
+            external_id: None,
+            bank_id: None,
+            requisite_id: Some("r1".to_string()),
+            merchant_id: Some("m1".to_string()),
+            trader_id: None,
+            statuses: None,
+            from: None,
+            to: None,
+            search: PaymentSearch::default(),
+            limit: Some(20),
+            page: None,
+            cursor: Some(PaymentCursor { created_at, id: "p1".to_string(), direction: CursorDirection::Next }),
+        }));
+        let query = request.to_sql();
+        assert_eq!(query.sql, " WHERE merchant_id=$1 AND client_id=$2 AND requisite_id=$3 \
+            AND (created_at, id)<($4, $5) ORDER BY created_at DESC, id DESC LIMIT 21");
+        assert_eq!(query.params().len(), 5);
+        assert_eq!(params(&query)[3], "2025-01-02T03:04:05:timestamp");
+    }
+
+    #[test]
+    fn dispute_subquery_shares_param_numbering() {
+        let request = GetPaymentsRequest::AdminDisputes(Some(GetDisputedPaymentsAdmin {
+            dispute_statuses: Some(vec![DisputeStatus::Open]),
+            opened_by: Some(DisputeOpener::Trader),
+            trader_id: Some("t1".to_string()),
+            ..Default::default()
+        }));
+        let query = request.to_sql();
+        assert_eq!(query.sql, " WHERE id IN (SELECT payment_id FROM disputes WHERE status=ANY($1) AND opened_by=$2) \
+            AND trader_id=$3 ORDER BY created_at DESC, id DESC LIMIT 50 OFFSET 0");
+        assert_eq!(params(&query), vec!["[\"OPEN\"]:_text", "\"trader\":varchar", "\"t1\":varchar"]);
+
+        let request = GetPaymentsRequest::AdminDisputes(Some(GetDisputedPaymentsAdmin {
+            overdue: Some(true),
+            ..Default::default()
+        }));
+        assert_eq!(request.to_sql().sql, " WHERE id IN (SELECT payment_id FROM disputes WHERE status<>'RESOLVED' \
+            AND (resolve_by<NOW() OR (status='OPEN' AND respond_by<NOW()))) ORDER BY created_at DESC, id DESC LIMIT 50 OFFSET 0");
+    }
+
+    #[test]
+    fn search_filters_use_index_friendly_predicates() {
+        let request = GetPaymentsRequest::Trader(("t1".to_string(), Some(GetPaymentsTrader {
+            id: None,
+            status: None,
+            bank_id: None,
+            from: None,
+            to: None,
+            search: PaymentSearch {
+                min_amount: Some(rust_decimal::dec!(100)),
+                methods: Some(vec!["card".to_string()]),
+                holder_account_suffix: Some("1234".to_string()),
+                external_id_prefix: Some("ab_".to_string()),
+                ..Default::default()
+            },
+            limit: None,
+            page: None,
+            cursor: None,
+        })));
+        let query = request.to_sql();
+        assert_eq!(query.sql, " WHERE trader_id=$1 AND fiat_amount>=$2 AND method=ANY($3) \
+            AND reverse(holder_account) LIKE $4 AND external_id LIKE $5 ORDER BY created_at DESC, id DESC LIMIT 50 OFFSET 0");
+        assert_eq!(&params(&query)[3..], ["\"4321%\":text", "\"ab\\\\_%\":text"]);
+    }
+}
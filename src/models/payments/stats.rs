@@ -0,0 +1,184 @@
+use chrono::NaiveDateTime;
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use tokio_postgres::types::Type;
+use crate::models::payments::filter::SqlQuery;
+use crate::models::payments::payment::{PaymentStatuses, PaymentStatusesSlim};
+use crate::models::payments::requests::GetPaymentsRequest;
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum StatsGranularity {
+    Hour,
+    Day,
+}
+
+impl StatsGranularity {
+    fn trunc(&self) -> &'static str {
+        match self {
+            StatsGranularity::Hour => "hour",
+            StatsGranularity::Day => "day",
+        }
+    }
+}
+
+// разрезы статистики, без них считается одна итоговая строка
+#[derive(Deserialize, Serialize, Debug, Clone, Default)]
+pub struct PaymentStatsRequest {
+    pub granularity: Option<StatsGranularity>,
+    #[serde(default)]
+    pub by_status: bool,
+    #[serde(default)]
+    pub by_method: bool,
+    #[serde(default)]
+    pub by_currency: bool,
+}
+
+// статусы вне групп PaymentStatusesSlim (UNPAID, PENDING, QUEUED)
+pub const OTHER_STATUS_GROUP: &str = "OTHER";
+
+const SLIM_STATUSES: [PaymentStatusesSlim; 4] = [
+    PaymentStatusesSlim::Completed,
+    PaymentStatusesSlim::Processing,
+    PaymentStatusesSlim::Frozen,
+    PaymentStatusesSlim::Cancelled,
+];
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct PaymentStatsRow {
+    // поля разреза, None если по нему не группировали
+    pub period: Option<NaiveDateTime>,
+    pub status: Option<String>,
+    pub method: Option<String>,
+    pub currency: Option<String>,
+    pub count: i64,
+    pub completed_count: i64,
+    // обороты и комиссии только по завершенным платежам
+    pub fiat_turnover: Decimal,
+    pub crypto_turnover: Decimal,
+    pub trader_crypto_turnover: Decimal,
+    pub fiat_fee: Decimal,
+    pub crypto_fee: Decimal,
+    pub trader_crypto_fee: Decimal,
+    pub earnings: Decimal,
+    // completed_count / count
+    pub conversion_rate: Decimal,
+}
+
+impl From<&tokio_postgres::Row> for PaymentStatsRow {
+    fn from(row: &tokio_postgres::Row) -> Self {
+        let count: i64 = row.get("payments_count");
+        let completed_count: i64 = row.get("completed_count");
+        let conversion_rate = if count > 0 {
+            (Decimal::from(completed_count) / Decimal::from(count)).round_dp(4)
+        } else {
+            Decimal::ZERO
+        };
+        Self {
+            period: row.get("period"),
+            status: row.get("status_group"),
+            method: row.get("method"),
+            currency: row.get("currency"),
+            count,
+            completed_count,
+            fiat_turnover: row.get("fiat_turnover"),
+            crypto_turnover: row.get("crypto_turnover"),
+            trader_crypto_turnover: row.get("trader_crypto_turnover"),
+            fiat_fee: row.get("fiat_fee"),
+            crypto_fee: row.get("crypto_fee"),
+            trader_crypto_fee: row.get("trader_crypto_fee"),
+            earnings: row.get("earnings"),
+            conversion_rate,
+        }
+    }
+}
+
+impl PaymentStatsRequest {
+    // агрегирующий запрос с теми же условиями, что и выдача списка для request, по всем страницам
+    pub fn to_sql(&self, request: &GetPaymentsRequest) -> SqlQuery {
+        let mut filter = request.scope();
+        let period = match self.granularity {
+            Some(granularity) => format!("date_trunc('{}', created_at)", granularity.trunc()),
+            None => "NULL::timestamp".to_string(),
+        };
+        let status_group = if self.by_status {
+            let mut case = String::from("CASE");
+            for slim in SLIM_STATUSES.iter() {
+                let statuses = slim.statuses().iter().map(|status| status.to_string()).collect::<Vec<_>>();
+                let param = filter.bind(statuses, Type::TEXT_ARRAY);
+                case.push_str(format!(" WHEN status=ANY({}) THEN '{}'", param, slim).as_str());
+            }
+            case.push_str(format!(" ELSE '{}' END", OTHER_STATUS_GROUP).as_str());
+            case
+        } else {
+            "NULL::varchar".to_string()
+        };
+        let method = if self.by_method { "method" } else { "NULL::varchar" };
+        let currency = if self.by_currency { "currency" } else { "NULL::varchar" };
+
+        let mut group_by = Vec::with_capacity(4);
+        for (index, grouped) in [self.granularity.is_some(), self.by_status, self.by_method, self.by_currency].iter().enumerate() {
+            if *grouped {
+                group_by.push((index + 1).to_string());
+            }
+        }
+        let tail = if group_by.is_empty() {
+            String::new()
+        } else {
+            format!(" GROUP BY {0} ORDER BY {0}", group_by.join(", "))
+        };
+
+        let completed = PaymentStatuses::Completed;
+        let sum = |column: &str, alias: &str|
+            format!("COALESCE(SUM({}) FILTER (WHERE status='{}'), 0) AS {}", column, completed, alias);
+        let aggregates = [
+            format!("COUNT(*) AS payments_count, COUNT(*) FILTER (WHERE status='{}') AS completed_count", completed),
+            sum("fiat_amount", "fiat_turnover"),
+            sum("crypto_amount", "crypto_turnover"),
+            sum("trader_crypto_amount", "trader_crypto_turnover"),
+            sum("fiat_fee", "fiat_fee"),
+            sum("crypto_fee", "crypto_fee"),
+            sum("trader_crypto_fee", "trader_crypto_fee"),
+            sum("earnings", "earnings"),
+        ];
+        let mut query = filter.build(tail.as_str());
+        query.sql = format!("SELECT {} AS period, {} AS status_group, {} AS method, {} AS currency, {} FROM payments{}",
+                            period, status_group, method, currency, aggregates.join(", "), query.sql);
+        query
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::models::payments::cursor::{CursorDirection, PaymentCursor};
+    use crate::models::payments::payment::PaymentSearch;
+    use crate::models::payments::trader::GetPaymentsTrader;
+    use super::*;
+
+    #[test]
+    fn stats_reuse_request_scope() {
+        let request = GetPaymentsRequest::Trader(("t1".to_string(), None));
+        let stats = PaymentStatsRequest { granularity: Some(StatsGranularity::Day), by_status: true, ..Default::default() };
+        let query = stats.to_sql(&request);
+        assert!(query.sql.starts_with("SELECT date_trunc('day', created_at) AS period, CASE WHEN status=ANY($2) THEN 'COMPLETED'"));
+        assert!(query.sql.ends_with(" FROM payments WHERE trader_id=$1 GROUP BY 1, 2 ORDER BY 1, 2"));
+        assert_eq!(query.params.len(), 5);
+    }
+
+    #[test]
+    fn stats_ignore_page_cursor() {
+        let cursor = PaymentCursor {
+            created_at: chrono::NaiveDate::from_ymd_opt(2025, 3, 1).unwrap().and_hms_opt(0, 0, 0).unwrap(),
+            id: "p1".to_string(),
+            direction: CursorDirection::Next,
+        };
+        let request = GetPaymentsRequest::Trader(("t1".to_string(), Some(GetPaymentsTrader {
+            id: None, status: None, bank_id: None, from: None, to: None, search: PaymentSearch::default(),
+            limit: Some(10), page: None, cursor: Some(cursor),
+        })));
+        assert!(request.to_sql().sql.contains("(created_at, id)<"));
+        let query = PaymentStatsRequest::default().to_sql(&request);
+        assert!(query.sql.ends_with(" FROM payments WHERE trader_id=$1"), "{}", query.sql);
+        assert_eq!(query.params.len(), 1);
+    }
+}
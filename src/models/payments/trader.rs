@@ -2,7 +2,8 @@ use std::str::FromStr;
 use chrono::NaiveDateTime;
 use rust_decimal::Decimal;
 use serde::{Deserialize, Serialize};
-use crate::models::payments::payment::{FullPayment, PaymentSides, PaymentStatuses, PaymentStatusesSlim, ToSQL};
+use crate::models::payments::cursor::PaymentCursor;
+use crate::models::payments::payment::{FullPayment, PaymentSearch, PaymentSides, PaymentStatuses, PaymentStatusesSlim, ToSQL};
 
 #[derive(Deserialize, Serialize, Clone, Debug)]
 pub struct TraderPayment {
@@ -138,6 +139,11 @@ pub struct GetPaymentsTrader {
     pub id: Option<String>,
     pub status: Option<Vec<PaymentStatusesSlim>>,
     pub bank_id: Option<String>,
+    pub from: Option<NaiveDateTime>,
+    pub to: Option<NaiveDateTime>,
+    #[serde(flatten)]
+    pub search: PaymentSearch,
     pub limit: Option<u32>,
     pub page: Option<u32>,
+    pub cursor: Option<PaymentCursor>,
 }
\ No newline at end of file
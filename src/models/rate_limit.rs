@@ -0,0 +1,77 @@
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+use std::time::Duration;
+use serde::{Deserialize, Serialize};
+
+// лимит субъекта на все группы маршрутов, если для группы нет своего
+pub const ALL_ROUTE_GROUPS: &str = "*";
+
+// не больше limit запросов за period_ms, весь limit можно потратить сразу
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RateLimit {
+    pub limit: i32,
+    pub period_ms: i64,
+}
+
+impl RateLimit {
+    pub fn new(limit: i32, period: Duration) -> Self {
+        Self { limit, period_ms: period.as_millis() as i64 }
+    }
+
+    pub fn is_valid(&self) -> bool {
+        self.limit > 0 && self.period_ms > 0
+    }
+
+    // интервал между запросами при равномерной нагрузке, мкс
+    pub fn emission_us(&self) -> i64 {
+        self.period_ms * 1000 / self.limit as i64
+    }
+
+    pub fn period_us(&self) -> i64 {
+        self.period_ms * 1000
+    }
+}
+
+// формат значения в кэше
+impl Display for RateLimit {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}/{}", self.limit, self.period_ms)
+    }
+}
+
+impl FromStr for RateLimit {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (limit, period_ms) = s.split_once('/').ok_or(format!("invalid rate limit {}", s))?;
+        let limit = RateLimit {
+            limit: limit.parse().map_err(|_| format!("invalid rate limit {}", s))?,
+            period_ms: period_ms.parse().map_err(|_| format!("invalid rate limit {}", s))?,
+        };
+        if !limit.is_valid() {
+            return Err(format!("invalid rate limit {}", s));
+        }
+        Ok(limit)
+    }
+}
+
+// Retry-After в целых секундах, не меньше одной
+pub fn retry_after_secs(retry_after_us: i64) -> u64 {
+    ((retry_after_us + 999_999) / 1_000_000).max(1) as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rate_limit_encoding() {
+        let limit = RateLimit::new(50, Duration::from_secs(10));
+        assert_eq!(limit.to_string(), "50/10000");
+        assert_eq!(RateLimit::from_str("50/10000"), Ok(limit));
+        assert_eq!(limit.emission_us(), 200_000);
+        assert!(RateLimit::from_str("0/1000").is_err());
+        assert!(RateLimit::from_str("10").is_err());
+        assert_eq!(retry_after_secs(1), 1);
+        assert_eq!(retry_after_secs(1_000_001), 2);
+    }
+}
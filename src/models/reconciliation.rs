@@ -0,0 +1,274 @@
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+use chrono::NaiveDateTime;
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use crate::models::ledger::{BalanceAction, LedgerEntry, LedgerOwner, LedgerTransaction};
+use crate::models::payments::payment::{FullPayment, PaymentSides, PaymentStatuses};
+
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct ExpectedBalanceAction {
+    pub payment_id: String,
+    pub owner_type: LedgerOwner,
+    pub owner_id: String,
+    pub action: BalanceAction,
+    pub amount: Decimal,
+}
+
+impl ExpectedBalanceAction {
+    pub fn to_entry(&self, reference_id: &str, idempotent_key: String) -> LedgerEntry {
+        LedgerEntry::new(self.owner_type, self.owner_id.clone(), self.action, self.amount,
+                         reference_id.to_string(), idempotent_key)
+    }
+}
+
+// какие движения баланса должен был породить платеж
+pub fn expected_balance_actions(payment: &FullPayment) -> Vec<ExpectedBalanceAction> {
+    if payment.status != PaymentStatuses::Completed {
+        return Vec::new();
+    }
+    let action = |owner_type, owner_id: &str, action, amount| ExpectedBalanceAction {
+        payment_id: payment.id.clone(),
+        owner_type,
+        owner_id: owner_id.to_string(),
+        action,
+        amount,
+    };
+    // заморожена была исходная сумма, при частичном закрытии остаток размораживается,
+    // при переплате недостающее списывается с основного баланса
+    let settle_frozen = |owner_type, owner_id: &str, frozen: Decimal, amount: Decimal| {
+        let mut actions = vec![action(owner_type, owner_id, BalanceAction::WithdrawFrozen, amount.min(frozen))];
+        if frozen > amount {
+            actions.push(action(owner_type, owner_id, BalanceAction::Unfroze, frozen - amount));
+        } else if amount > frozen {
+            actions.push(action(owner_type, owner_id, BalanceAction::WithdrawMain, amount - frozen));
+        }
+        actions
+    };
+    match payment.payment_side {
+        PaymentSides::Buy => {
+            let frozen = payment.original_trader_crypto_amount.unwrap_or(payment.trader_crypto_amount);
+            let mut actions = settle_frozen(LedgerOwner::Trader, &payment.trader_id, frozen, payment.trader_crypto_amount);
+            actions.push(action(LedgerOwner::Merchant, &payment.merchant_id, BalanceAction::Deposit, payment.crypto_amount));
+            actions
+        }
+        // трейдер замораживает свою сумму на обеих сторонах, на SELL заморозка снимается при зачислении
+        PaymentSides::Sell => {
+            let frozen = payment.original_crypto_amount.unwrap_or(payment.crypto_amount);
+            let trader_frozen = payment.original_trader_crypto_amount.unwrap_or(payment.trader_crypto_amount);
+            let mut actions = settle_frozen(LedgerOwner::Merchant, &payment.merchant_id, frozen, payment.crypto_amount);
+            actions.push(action(LedgerOwner::Trader, &payment.trader_id, BalanceAction::Unfroze, trader_frozen));
+            actions.push(action(LedgerOwner::Trader, &payment.trader_id, BalanceAction::Deposit, payment.trader_crypto_amount));
+            actions
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum DiscrepancyKind {
+    Missing,
+    AmountMismatch,
+    // движение записано на другого трейдера или мерчанта
+    OwnerMismatch,
+    Duplicate,
+    Unexpected,
+}
+
+impl Display for DiscrepancyKind {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DiscrepancyKind::Missing => f.write_str("missing"),
+            DiscrepancyKind::AmountMismatch => f.write_str("amount_mismatch"),
+            DiscrepancyKind::OwnerMismatch => f.write_str("owner_mismatch"),
+            DiscrepancyKind::Duplicate => f.write_str("duplicate"),
+            DiscrepancyKind::Unexpected => f.write_str("unexpected"),
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct Discrepancy {
+    pub kind: DiscrepancyKind,
+    pub payment_id: String,
+    pub owner_type: LedgerOwner,
+    pub owner_id: String,
+    pub action: BalanceAction,
+    pub expected: Option<Decimal>,
+    pub recorded: Option<Decimal>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct ReconciliationReport {
+    pub from: NaiveDateTime,
+    pub to: NaiveDateTime,
+    pub payments_checked: usize,
+    pub discrepancies: Vec<Discrepancy>,
+}
+
+impl ReconciliationReport {
+    pub fn new(from: NaiveDateTime, to: NaiveDateTime, payments: &[FullPayment], recorded: &[LedgerTransaction]) -> Self {
+        Self {
+            from,
+            to,
+            payments_checked: payments.len(),
+            discrepancies: find_discrepancies(payments, recorded),
+        }
+    }
+
+    pub fn is_clean(&self) -> bool {
+        self.discrepancies.is_empty()
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).unwrap()
+    }
+
+    // текстовая таблица для админки
+    pub fn to_table(&self) -> String {
+        let fmt_amount = |amount: &Option<Decimal>| amount.map(|a| a.to_string()).unwrap_or_else(|| "-".to_string());
+        let mut rows = vec![[
+            "kind".to_string(), "payment_id".to_string(), "owner".to_string(), "owner_id".to_string(),
+            "action".to_string(), "expected".to_string(), "recorded".to_string(),
+        ]];
+        for d in self.discrepancies.iter() {
+            rows.push([
+                d.kind.to_string(), d.payment_id.clone(), d.owner_type.to_string(), d.owner_id.clone(),
+                d.action.to_string(), fmt_amount(&d.expected), fmt_amount(&d.recorded),
+            ]);
+        }
+        let mut widths = [0usize; 7];
+        for row in rows.iter() {
+            for (i, cell) in row.iter().enumerate() {
+                widths[i] = widths[i].max(cell.len());
+            }
+        }
+        let mut table = format!("reconciliation {} - {}: {} payments, {} discrepancies\n",
+                                self.from, self.to, self.payments_checked, self.discrepancies.len());
+        for row in rows.iter() {
+            let line = row.iter().enumerate()
+                .map(|(i, cell)| format!("{:<width$}", cell, width = widths[i]))
+                .collect::<Vec<_>>()
+                .join(" | ");
+            table.push_str(line.trim_end());
+            table.push('\n');
+        }
+        table
+    }
+}
+
+type ActionKey = (String, LedgerOwner, BalanceAction);
+
+pub fn find_discrepancies(payments: &[FullPayment], recorded: &[LedgerTransaction]) -> Vec<Discrepancy> {
+    let mut recorded_by_key: HashMap<ActionKey, Vec<&LedgerTransaction>> = HashMap::new();
+    for tx in recorded.iter() {
+        let Some(payment_id) = tx.reference_id.as_ref() else { continue };
+        recorded_by_key.entry((payment_id.clone(), tx.owner_type, tx.action)).or_default().push(tx);
+    }
+
+    let mut discrepancies = Vec::new();
+    for payment in payments.iter() {
+        for expected in expected_balance_actions(payment) {
+            let key = (expected.payment_id.clone(), expected.owner_type, expected.action);
+            let discrepancy = |kind, recorded| Discrepancy {
+                kind,
+                payment_id: expected.payment_id.clone(),
+                owner_type: expected.owner_type,
+                owner_id: expected.owner_id.clone(),
+                action: expected.action,
+                expected: Some(expected.amount),
+                recorded,
+            };
+            match recorded_by_key.remove(&key) {
+                None => discrepancies.push(discrepancy(DiscrepancyKind::Missing, None)),
+                Some(txs) => {
+                    if txs[0].owner_id != expected.owner_id {
+                        discrepancies.push(discrepancy(DiscrepancyKind::OwnerMismatch, Some(txs[0].amount)));
+                    }
+                    if txs[0].amount != expected.amount {
+                        discrepancies.push(discrepancy(DiscrepancyKind::AmountMismatch, Some(txs[0].amount)));
+                    }
+                    for tx in txs.iter().skip(1) {
+                        discrepancies.push(discrepancy(DiscrepancyKind::Duplicate, Some(tx.amount)));
+                    }
+                }
+            }
+        }
+    }
+
+    // все что осталось - движения по платежам, которых быть не должно
+    let payment_ids = payments.iter().map(|p| p.id.as_str()).collect::<std::collections::HashSet<_>>();
+    let mut unexpected = recorded_by_key.into_iter()
+        .filter(|((payment_id, _, _), _)| payment_ids.contains(payment_id.as_str()))
+        .flat_map(|(_, txs)| txs)
+        .filter(|tx| matches!(tx.action, BalanceAction::WithdrawFrozen | BalanceAction::WithdrawMain | BalanceAction::Deposit))
+        .map(|tx| Discrepancy {
+            kind: DiscrepancyKind::Unexpected,
+            payment_id: tx.reference_id.clone().unwrap_or_default(),
+            owner_type: tx.owner_type,
+            owner_id: tx.owner_id.clone(),
+            action: tx.action,
+            expected: None,
+            recorded: Some(tx.amount),
+        })
+        .collect::<Vec<_>>();
+    unexpected.sort_by(|a, b| a.payment_id.cmp(&b.payment_id));
+    discrepancies.extend(unexpected);
+    discrepancies
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_decimal::dec;
+    use super::*;
+
+    fn payment() -> FullPayment {
+        FullPayment {
+            id: "p1".to_string(),
+            merchant_id: "m1".to_string(),
+            trader_id: "t1".to_string(),
+            status: PaymentStatuses::Completed,
+            payment_side: PaymentSides::Buy,
+            crypto_amount: dec!(10),
+            trader_crypto_amount: dec!(10.2),
+            ..Default::default()
+        }
+    }
+
+    fn tx(owner_type: LedgerOwner, owner_id: &str, action: BalanceAction, amount: Decimal) -> LedgerTransaction {
+        LedgerTransaction {
+            id: "tx".to_string(),
+            owner_type,
+            owner_id: owner_id.to_string(),
+            action,
+            amount,
+            fee: Decimal::ZERO,
+            reference_id: Some("p1".to_string()),
+            idempotent_key: "k".to_string(),
+            created_at: NaiveDateTime::default(),
+        }
+    }
+
+    #[test]
+    fn detects_missing_and_mismatched_actions() {
+        let payments = vec![payment()];
+        let recorded = vec![tx(LedgerOwner::Trader, "t1", BalanceAction::WithdrawFrozen, dec!(10.2))];
+        assert!(find_discrepancies(&payments, &recorded).iter().all(|d| d.kind == DiscrepancyKind::Missing));
+
+        let recorded = vec![
+            tx(LedgerOwner::Trader, "t1", BalanceAction::WithdrawFrozen, dec!(10.2)),
+            tx(LedgerOwner::Merchant, "m1", BalanceAction::Deposit, dec!(9)),
+            tx(LedgerOwner::Merchant, "m1", BalanceAction::WithdrawMain, dec!(1)),
+        ];
+        let kinds = find_discrepancies(&payments, &recorded).into_iter().map(|d| d.kind).collect::<Vec<_>>();
+        assert_eq!(kinds, vec![DiscrepancyKind::AmountMismatch, DiscrepancyKind::Unexpected]);
+
+        let recorded = vec![
+            tx(LedgerOwner::Trader, "t2", BalanceAction::WithdrawFrozen, dec!(10.2)),
+            tx(LedgerOwner::Merchant, "m1", BalanceAction::Deposit, dec!(10)),
+        ];
+        let discrepancies = find_discrepancies(&payments, &recorded);
+        assert_eq!(discrepancies.len(), 1);
+        assert_eq!((discrepancies[0].kind, discrepancies[0].owner_id.as_str()), (DiscrepancyKind::OwnerMismatch, "t1"));
+    }
+}
@@ -0,0 +1,87 @@
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum SagaStatus {
+    Running,
+    Compensating,
+    Completed,
+    Compensated,
+    Failed,
+}
+
+impl SagaStatus {
+    pub fn is_final(&self) -> bool {
+        matches!(self, SagaStatus::Completed | SagaStatus::Compensated | SagaStatus::Failed)
+    }
+}
+
+impl Display for SagaStatus {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SagaStatus::Running => f.write_str("running"),
+            SagaStatus::Compensating => f.write_str("compensating"),
+            SagaStatus::Completed => f.write_str("completed"),
+            SagaStatus::Compensated => f.write_str("compensated"),
+            SagaStatus::Failed => f.write_str("failed"),
+        }
+    }
+}
+
+impl FromStr for SagaStatus {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "running" => Ok(SagaStatus::Running),
+            "compensating" => Ok(SagaStatus::Compensating),
+            "completed" => Ok(SagaStatus::Completed),
+            "compensated" => Ok(SagaStatus::Compensated),
+            "failed" => Ok(SagaStatus::Failed),
+            _ => Err(format!("unknown saga status {}", s)),
+        }
+    }
+}
+
+// что делать с сагой, которая прервалась на прямом проходе
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SagaRecovery {
+    Resume,
+    Rollback,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct SagaState {
+    pub id: String,
+    pub kind: String,
+    pub reference_id: String,
+    pub status: SagaStatus,
+    // количество успешно выполненных шагов
+    pub completed_steps: usize,
+    // шаг completed_steps был запущен, но результат не сохранен
+    pub in_flight: bool,
+    // контекст саги в JSON
+    pub context: String,
+    pub error: Option<String>,
+    pub created_at: NaiveDateTime,
+    pub updated_at: NaiveDateTime,
+}
+
+impl From<&tokio_postgres::Row> for SagaState {
+    fn from(row: &tokio_postgres::Row) -> Self {
+        Self {
+            id: row.get("id"),
+            kind: row.get("kind"),
+            reference_id: row.get("reference_id"),
+            status: SagaStatus::from_str(row.get("status")).unwrap(),
+            completed_steps: row.get::<_, i32>("completed_steps") as usize,
+            in_flight: row.get("in_flight"),
+            context: row.get("context"),
+            error: row.get("error"),
+            created_at: row.get("created_at"),
+            updated_at: row.get("updated_at"),
+        }
+    }
+}
@@ -0,0 +1,215 @@
+use std::collections::BTreeMap;
+use chrono::{NaiveDate, NaiveDateTime};
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use crate::models::ledger::BalanceAction;
+
+// меняется при изменении формата, старые отчеты остаются со своей версией
+pub const SETTLEMENT_REPORT_VERSION: i32 = 1;
+
+// агрегат движений мерчанта по платежам одной валюты за период
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct SettlementMovement {
+    pub currency: String,
+    pub action: BalanceAction,
+    pub count: i64,
+    pub amount: Decimal,
+    // комиссия платежей, уже вычтенная из amount
+    pub fee: Decimal,
+    pub fiat_amount: Decimal,
+}
+
+impl From<&tokio_postgres::Row> for SettlementMovement {
+    fn from(row: &tokio_postgres::Row) -> Self {
+        Self {
+            currency: row.get("currency"),
+            action: row.get::<_, &str>("action_type").parse().unwrap(),
+            count: row.get("movements_count"),
+            amount: row.get("amount"),
+            fee: row.get("fee"),
+            fiat_amount: row.get("fiat_amount"),
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Default, PartialEq)]
+pub struct SettlementSection {
+    pub currency: String,
+    // зачисления по завершенным BUY
+    pub deposits_count: i64,
+    pub deposits: Decimal,
+    pub deposits_fiat: Decimal,
+    // списания по SELL и возвратам
+    pub withdrawals_count: i64,
+    pub withdrawals: Decimal,
+    pub withdrawals_fiat: Decimal,
+    pub fees: Decimal,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct SettlementReport {
+    pub version: i32,
+    pub merchant_id: String,
+    pub report_date: NaiveDate,
+    pub period_from: NaiveDateTime,
+    pub period_to: NaiveDateTime,
+    // main + frozen мерчанта в крипте
+    pub opening_balance: Decimal,
+    pub deposits: Decimal,
+    pub withdrawals: Decimal,
+    pub fees: Decimal,
+    // движения без платежа (ручные корректировки, выводы)
+    pub other_movements: Decimal,
+    pub closing_balance: Decimal,
+    pub sections: Vec<SettlementSection>,
+}
+
+impl SettlementReport {
+    // отчет зависит только от неизменяемых проводок, поэтому пересборка дает тот же результат
+    pub fn build(merchant_id: &str, report_date: NaiveDate, opening_balance: Decimal, closing_balance: Decimal,
+                 movements: &[SettlementMovement]) -> Self {
+        let mut sections: BTreeMap<&str, SettlementSection> = BTreeMap::new();
+        // изменение main + frozen по платежам, комиссия в суммах проводок уже учтена
+        let mut payments_net = Decimal::ZERO;
+        for movement in movements.iter() {
+            let section = sections.entry(movement.currency.as_str()).or_insert_with(|| SettlementSection {
+                currency: movement.currency.clone(),
+                ..Default::default()
+            });
+            match movement.action {
+                BalanceAction::Deposit => {
+                    section.deposits_count += movement.count;
+                    section.deposits += movement.amount;
+                    section.deposits_fiat += movement.fiat_amount;
+                    payments_net += movement.amount;
+                }
+                BalanceAction::WithdrawFrozen | BalanceAction::WithdrawMain => {
+                    section.withdrawals_count += movement.count;
+                    section.withdrawals += movement.amount;
+                    section.withdrawals_fiat += movement.fiat_amount;
+                    payments_net -= movement.amount;
+                }
+                // заморозки не меняют main + frozen, начальный остаток не относится к платежам
+                BalanceAction::FrozeSoft | BalanceAction::FrozeHard | BalanceAction::Unfroze
+                | BalanceAction::Opening => continue,
+            }
+            section.fees += movement.fee;
+        }
+        let sections = sections.into_values().map(|mut section| {
+            for amount in [&mut section.deposits, &mut section.deposits_fiat, &mut section.withdrawals,
+                &mut section.withdrawals_fiat, &mut section.fees] {
+                *amount = amount.normalize();
+            }
+            section
+        }).collect::<Vec<_>>();
+        let deposits = sections.iter().map(|s| s.deposits).sum::<Decimal>();
+        let withdrawals = sections.iter().map(|s| s.withdrawals).sum::<Decimal>();
+        let fees = sections.iter().map(|s| s.fees).sum::<Decimal>();
+        let other_movements = closing_balance - opening_balance - payments_net;
+        let period_from = report_date.and_hms_opt(0, 0, 0).unwrap();
+        Self {
+            version: SETTLEMENT_REPORT_VERSION,
+            merchant_id: merchant_id.to_string(),
+            report_date,
+            period_from,
+            period_to: period_from + chrono::Duration::days(1),
+            opening_balance: opening_balance.normalize(),
+            deposits: deposits.normalize(),
+            withdrawals: withdrawals.normalize(),
+            fees: fees.normalize(),
+            other_movements: other_movements.normalize(),
+            closing_balance: closing_balance.normalize(),
+            sections,
+        }
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).unwrap()
+    }
+
+    // итоговая строка с пустой валютой, затем секции по валютам
+    pub fn to_csv(&self) -> String {
+        let mut writer = csv::Writer::from_writer(Vec::new());
+        let _ = writer.write_record(["merchant_id", "report_date", "currency", "opening_balance", "deposits_count",
+            "deposits", "deposits_fiat", "withdrawals_count", "withdrawals", "withdrawals_fiat", "fees",
+            "other_movements", "closing_balance"]);
+        let total = [
+            self.merchant_id.clone(), self.report_date.to_string(), String::new(), self.opening_balance.to_string(),
+            String::new(), self.deposits.to_string(), String::new(), String::new(), self.withdrawals.to_string(),
+            String::new(), self.fees.to_string(), self.other_movements.to_string(), self.closing_balance.to_string(),
+        ];
+        let _ = writer.write_record(&total);
+        for section in self.sections.iter() {
+            let _ = writer.write_record([
+                self.merchant_id.clone(), self.report_date.to_string(), section.currency.clone(), String::new(),
+                section.deposits_count.to_string(), section.deposits.to_string(), section.deposits_fiat.to_string(),
+                section.withdrawals_count.to_string(), section.withdrawals.to_string(),
+                section.withdrawals_fiat.to_string(), section.fees.to_string(), String::new(), String::new(),
+            ]);
+        }
+        String::from_utf8(writer.into_inner().unwrap_or_default()).unwrap_or_default()
+    }
+}
+
+// сохраненный отчет, в БД не изменяется
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct StoredSettlementReport {
+    pub id: String,
+    pub merchant_id: String,
+    pub report_date: NaiveDate,
+    pub version: i32,
+    pub report_json: String,
+    pub report_csv: String,
+    // sha256 от report_json в hex
+    pub sha256: String,
+    // RSA PKCS#1 v1.5 SHA-256 в base64
+    pub json_signature: String,
+    pub csv_signature: String,
+    pub key_id: String,
+    pub created_at: NaiveDateTime,
+}
+
+impl From<&tokio_postgres::Row> for StoredSettlementReport {
+    fn from(row: &tokio_postgres::Row) -> Self {
+        Self {
+            id: row.get("id"),
+            merchant_id: row.get("merchant_id"),
+            report_date: row.get("report_date"),
+            version: row.get("version"),
+            report_json: row.get("report_json"),
+            report_csv: row.get("report_csv"),
+            sha256: row.get("sha256"),
+            json_signature: row.get("json_signature"),
+            csv_signature: row.get("csv_signature"),
+            key_id: row.get("key_id"),
+            created_at: row.get("created_at"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_decimal::dec;
+    use super::*;
+
+    #[test]
+    fn sections_and_other_movements() {
+        let movement = |currency: &str, action, amount, fee| SettlementMovement {
+            currency: currency.to_string(), action, count: 1, amount, fee, fiat_amount: amount * dec!(90),
+        };
+        let movements = vec![
+            movement("RUB", BalanceAction::Deposit, dec!(100), dec!(2)),
+            movement("KZT", BalanceAction::WithdrawFrozen, dec!(30), dec!(0)),
+            movement("RUB", BalanceAction::Unfroze, dec!(5), dec!(0)),
+        ];
+        let date = NaiveDate::from_ymd_opt(2025, 3, 1).unwrap();
+        // 10 ручного вывода сверх платежей, комиссия на остаток не влияет
+        let report = SettlementReport::build("m1", date, dec!(500), dec!(560), &movements);
+        assert_eq!(report.sections.iter().map(|s| s.currency.as_str()).collect::<Vec<_>>(), ["KZT", "RUB"]);
+        assert_eq!((report.deposits, report.withdrawals, report.fees), (dec!(100), dec!(30), dec!(2)));
+        assert_eq!(report.other_movements, dec!(-10));
+        assert_eq!(report.sections[1].fees, dec!(2));
+        assert!(report.to_csv().contains(",2,"));
+        assert_eq!(report, SettlementReport::build("m1", date, dec!(500), dec!(560), &movements));
+    }
+}
@@ -0,0 +1,50 @@
+use tokio_postgres::types::Type;
+use tracing::error;
+use crate::errors::LibError;
+use crate::errors::LibError::InternalError;
+use crate::map_err_with_log;
+use crate::models::audit::AuditEntry;
+
+// записи добавляются по одной, иначе две получат один prev_hash
+pub async fn lock_audit_log(tx: &tokio_postgres::Transaction<'_>) -> Result<(), LibError> {
+    tx.query_typed("SELECT pg_advisory_xact_lock(hashtext('audit_log'))", &[]).await.map_err(|e| {
+        error!(err=e.to_string(), "Error lock audit log");
+        InternalError
+    })?;
+    Ok(())
+}
+
+pub async fn get_last_audit_hash(tx: &tokio_postgres::Transaction<'_>) -> Result<Option<String>, LibError> {
+    let row = tx.query_typed("SELECT hash FROM audit_log ORDER BY seq DESC LIMIT 1", &[]).await.map_err(|e| {
+        error!(err=e.to_string(), "Error get last audit hash");
+        InternalError
+    })?;
+    Ok(row.first().map(|row| row.get("hash")))
+}
+
+pub async fn insert_audit_entry(tx: &tokio_postgres::Transaction<'_>, entry: &AuditEntry) -> Result<(), LibError> {
+    let record = &entry.record;
+    let id = record.id.as_str();
+    let outcome = record.outcome.to_string();
+    map_err_with_log!(tx.query_typed(
+        "INSERT INTO audit_log (id, actor_id, actor_role, subject_id, subject_role, method, route, path, status,
+            outcome, request_hash, created_at, prev_hash, hash)
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14)",
+        &[(&record.id, Type::VARCHAR), (&record.actor_id, Type::VARCHAR), (&record.actor_role, Type::VARCHAR),
+            (&record.subject_id, Type::VARCHAR), (&record.subject_role, Type::VARCHAR),
+            (&record.method, Type::VARCHAR), (&record.route, Type::VARCHAR), (&record.path, Type::TEXT),
+            (&record.status, Type::INT4), (&outcome, Type::VARCHAR), (&record.request_hash, Type::VARCHAR),
+            (&record.created_at, Type::TIMESTAMP), (&entry.prev_hash, Type::VARCHAR), (&entry.hash, Type::VARCHAR)]).await,
+        "Error insert audit entry", InternalError, id)?;
+    Ok(())
+}
+
+pub async fn get_audit_entries(client: &tokio_postgres::Client, after_seq: i64, limit: i64)
+    -> Result<Vec<AuditEntry>, LibError>
+{
+    let rows = map_err_with_log!(client.query_typed(
+        "SELECT * FROM audit_log WHERE seq>$1 ORDER BY seq LIMIT $2",
+        &[(&after_seq, Type::INT8), (&limit, Type::INT8)]).await,
+        "Error get audit entries", InternalError, after_seq)?;
+    Ok(rows.iter().map(AuditEntry::from).collect())
+}
@@ -0,0 +1,70 @@
+use chrono::NaiveDateTime;
+use tokio_postgres::types::Type;
+use tracing::error;
+use crate::errors::LibError;
+use crate::errors::LibError::InternalError;
+use crate::map_err_with_log;
+use crate::models::block::{BlockEvent, BlockTarget};
+
+pub async fn insert_block_event(tx: &tokio_postgres::Transaction<'_>, event: &BlockEvent) -> Result<(), LibError> {
+    let target = event.target.to_string();
+    let target_id = event.target_id.as_str();
+    let action = event.action.to_string();
+    map_err_with_log!(tx.query_typed(
+        "INSERT INTO block_events (id, target, target_id, action, reason, actor_id, actor_role, expires_at, created_at)
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)",
+        &[(&event.id, Type::VARCHAR), (&target, Type::VARCHAR), (&event.target_id, Type::VARCHAR),
+            (&action, Type::VARCHAR), (&event.reason, Type::TEXT), (&event.actor_id, Type::VARCHAR),
+            (&event.actor_role, Type::VARCHAR), (&event.expires_at, Type::TIMESTAMP),
+            (&event.created_at, Type::TIMESTAMP)]).await,
+        "Error insert block event", InternalError, target_id, action)?;
+    Ok(())
+}
+
+// события одной цели пишутся по очереди, иначе снятие истекшей блокировки может
+// перекрыть новую блокировку, выданную после выборки истекших
+pub async fn lock_block_target(tx: &tokio_postgres::Transaction<'_>, target: BlockTarget, target_id: &str)
+    -> Result<(), LibError>
+{
+    let target = target.to_string();
+    map_err_with_log!(tx.query_typed("SELECT pg_advisory_xact_lock(hashtext('block:' || $1 || ':' || $2))",
+        &[(&target, Type::VARCHAR), (&target_id, Type::VARCHAR)]).await,
+        "Error lock block target", InternalError, target_id)?;
+    Ok(())
+}
+
+pub async fn get_latest_block_event(tx: &tokio_postgres::Transaction<'_>, target: BlockTarget, target_id: &str)
+    -> Result<Option<BlockEvent>, LibError>
+{
+    let target = target.to_string();
+    let rows = map_err_with_log!(tx.query_typed(
+        "SELECT * FROM block_events WHERE target=$1 AND target_id=$2 ORDER BY created_at DESC, id DESC LIMIT 1",
+        &[(&target, Type::VARCHAR), (&target_id, Type::VARCHAR)]).await,
+        "Error get latest block event", InternalError, target_id)?;
+    Ok(rows.first().map(BlockEvent::from))
+}
+
+pub async fn get_block_history(client: &tokio_postgres::Client, target: BlockTarget, target_id: &str)
+    -> Result<Vec<BlockEvent>, LibError>
+{
+    let target = target.to_string();
+    let rows = map_err_with_log!(client.query_typed(
+        "SELECT * FROM block_events WHERE target=$1 AND target_id=$2 ORDER BY created_at DESC, id DESC",
+        &[(&target, Type::VARCHAR), (&target_id, Type::VARCHAR)]).await,
+        "Error get block history", InternalError, target_id)?;
+    Ok(rows.iter().map(BlockEvent::from).collect())
+}
+
+// временные блокировки с истекшим сроком, после которых не было других событий.
+// история цели не перебирается: более позднее событие ищется по block_events_target_idx
+pub async fn get_expired_blocks(client: &tokio_postgres::Client, now: NaiveDateTime) -> Result<Vec<BlockEvent>, LibError> {
+    let rows = client.query_typed(
+        "SELECT * FROM block_events e WHERE e.action='BLOCK' AND e.expires_at<=$1
+        AND NOT EXISTS (SELECT 1 FROM block_events n WHERE n.target=e.target AND n.target_id=e.target_id
+            AND (n.created_at, n.id) > (e.created_at, e.id))",
+        &[(&now, Type::TIMESTAMP)]).await.map_err(|e| {
+        error!(err=e.to_string(), "Error get expired blocks");
+        InternalError
+    })?;
+    Ok(rows.iter().map(BlockEvent::from).collect())
+}
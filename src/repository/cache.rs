@@ -0,0 +1,374 @@
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use redis::{AsyncCommands, ExistenceCheck, SetExpiry, SetOptions};
+use serde::{Deserialize, Serialize};
+use tracing::{debug, error, warn};
+use crate::errors::LibError;
+
+// отметка об отсутствующей в БД записи лежит рядом с ключом, а не в нем: ключи общие
+// с другими сервисами, и любое значение в самом ключе они прочитали бы как данные
+const NEGATIVE_MARKER: &str = "1";
+
+fn negative_key(redis_key: &str) -> String {
+    format!("{}:none", redis_key)
+}
+
+pub const CACHE_INVALIDATION_CHANNEL: &str = "cache:invalidate";
+
+// сообщение в CACHE_INVALIDATION_CHANNEL: имя кэша и ключ без префикса Redis
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct CacheInvalidation {
+    pub cache: String,
+    pub key: String,
+    // Redis уже содержит новое значение, удалять его не нужно
+    #[serde(default)]
+    pub written: bool,
+}
+
+// удаляет ключ из Redis и оповещает процессы, чтобы они сбросили L1.
+// вызывается после записи в БД, поэтому ошибка только логируется - значение устареет не дольше TTL
+pub async fn publish_invalidation(rdb: &deadpool_redis::Pool, cache: &str, key: &str, redis_key: &str) {
+    let mut pipe = redis::pipe();
+    pipe.del(&[redis_key.to_string(), negative_key(redis_key)]).ignore();
+    publish(rdb, cache, key, false, pipe).await;
+}
+
+// то же, но новое значение сразу пишется в Redis (write-through), чтобы после рассылки
+// первые чтения не шли в БД
+pub async fn publish_value<V: CacheValue>(rdb: &deadpool_redis::Pool, cache: &str, key: &str, redis_key: &str,
+                                          value: &V, ttl: Duration) {
+    let mut pipe = redis::pipe();
+    pipe.set_ex(redis_key, value.encode(), ttl.as_secs().max(1)).ignore()
+        .del(negative_key(redis_key)).ignore();
+    publish(rdb, cache, key, true, pipe).await;
+}
+
+// чтение ключа кэша напрямую из Redis, без L1. отметка об отсутствии дает None, как и промах
+pub async fn read_value<V: CacheValue>(conn: &mut redis::aio::MultiplexedConnection, redis_key: &str)
+    -> redis::RedisResult<Option<V>>
+{
+    let (raw, _): (Option<String>, Option<String>) = conn.mget(&[redis_key, negative_key(redis_key).as_str()]).await?;
+    Ok(raw.and_then(|raw| V::decode(&raw)))
+}
+
+// запись значения напрямую в Redis без рассылки, L1 других процессов обновится по своему TTL
+pub async fn write_value<V: CacheValue>(conn: &mut redis::aio::MultiplexedConnection, redis_key: &str, value: &V,
+                                        ttl: Duration) -> redis::RedisResult<()> {
+    redis::pipe()
+        .atomic()
+        .set_ex(redis_key, value.encode(), ttl.as_secs().max(1)).ignore()
+        .del(negative_key(redis_key)).ignore()
+        .exec_async(conn)
+        .await
+}
+
+async fn publish(rdb: &deadpool_redis::Pool, cache: &str, key: &str, written: bool, mut pipe: redis::Pipeline) {
+    let invalidation = CacheInvalidation { cache: cache.to_string(), key: key.to_string(), written };
+    let payload = match serde_json::to_string(&invalidation) {
+        Ok(payload) => payload,
+        Err(e) => {
+            error!(cache=cache, err=e.to_string(), "Error encode cache invalidation");
+            return;
+        }
+    };
+    let mut conn = match rdb.get().await {
+        Ok(conn) => conn,
+        Err(e) => {
+            error!(cache=cache, key=key, err=e.to_string(), "Error get redis connection for invalidation");
+            return;
+        }
+    };
+    let result = pipe.atomic()
+        .publish(CACHE_INVALIDATION_CHANNEL, payload).ignore()
+        .exec_async(&mut conn)
+        .await;
+    if let Err(e) = result {
+        error!(cache=cache, key=key, err=e.to_string(), "Error publish cache invalidation");
+    }
+}
+
+// формат значения в Redis, совпадает с тем, что пишут другие сервисы
+pub trait CacheValue: Sized {
+    fn encode(&self) -> String;
+    fn decode(value: &str) -> Option<Self>;
+}
+
+impl CacheValue for bool {
+    fn encode(&self) -> String {
+        if *self { "1" } else { "0" }.to_string()
+    }
+
+    fn decode(value: &str) -> Option<Self> {
+        Some(value == "1")
+    }
+}
+
+impl CacheValue for String {
+    fn encode(&self) -> String {
+        self.clone()
+    }
+
+    fn decode(value: &str) -> Option<Self> {
+        Some(value.to_string())
+    }
+}
+
+struct LocalEntry<V> {
+    expires_at: Instant,
+    // None - записи нет в БД
+    value: Option<V>,
+}
+
+type LoadingMap<K> = Mutex<HashMap<K, Arc<tokio::sync::Mutex<()>>>>;
+
+// участник загрузки одного ключа. запись удаляет последний участник: если удалить ее раньше,
+// следующий промах заведет новый мьютекс и будет грузить параллельно с теми, кто ждет старый
+struct LoadingFlight<'a, K: Eq + Hash> {
+    loading: &'a LoadingMap<K>,
+    key: &'a K,
+    lock: Arc<tokio::sync::Mutex<()>>,
+}
+
+impl<'a, K: Eq + Hash + Clone> LoadingFlight<'a, K> {
+    fn join(loading: &'a LoadingMap<K>, key: &'a K) -> Self {
+        let lock = loading.lock().unwrap().entry(key.clone()).or_default().clone();
+        Self { loading, key, lock }
+    }
+}
+
+impl<K: Eq + Hash> Drop for LoadingFlight<'_, K> {
+    fn drop(&mut self) {
+        // клоны создаются только под блокировкой карты, поэтому счетчик здесь точный
+        let mut loading = self.loading.lock().unwrap();
+        let last = loading.get(self.key)
+            .is_some_and(|current| Arc::ptr_eq(current, &self.lock) && Arc::strong_count(&self.lock) == 2);
+        if last {
+            loading.remove(self.key);
+        }
+    }
+}
+
+// L1 в памяти процесса -> Redis -> загрузка из БД. одновременные промахи по одному ключу
+// ждут одну загрузку, остальные берут результат из L1
+pub struct ReadThroughCache<K, V> {
+    name: &'static str,
+    redis_key: fn(&K) -> String,
+    ttl: Duration,
+    negative_ttl: Duration,
+    local_ttl: Duration,
+    local_capacity: usize,
+    local: Mutex<HashMap<K, LocalEntry<V>>>,
+    loading: LoadingMap<K>,
+}
+
+impl<K, V> ReadThroughCache<K, V>
+where
+    K: Eq + Hash + Clone + Send + Sync + 'static,
+    V: CacheValue + Clone + Send + Sync + 'static,
+{
+    pub fn new(name: &'static str, redis_key: fn(&K) -> String, ttl: Duration) -> Self {
+        Self {
+            name,
+            redis_key,
+            ttl,
+            negative_ttl: Duration::from_secs(30),
+            local_ttl: Duration::from_secs(5),
+            local_capacity: 10_000,
+            local: Mutex::new(HashMap::new()),
+            loading: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn with_negative_ttl(mut self, negative_ttl: Duration) -> Self {
+        self.negative_ttl = negative_ttl;
+        self
+    }
+
+    pub fn with_local(mut self, local_ttl: Duration, local_capacity: usize) -> Self {
+        self.local_ttl = local_ttl;
+        self.local_capacity = local_capacity;
+        self
+    }
+
+    // load возвращает None, если записи нет - такой ответ тоже кэшируется на negative_ttl
+    pub async fn get<F, Fut>(&self, rdb: &deadpool_redis::Pool, key: &K, load: F) -> Result<Option<V>, LibError>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<Option<V>, LibError>>,
+    {
+        if let Some(value) = self.get_local(key) {
+            return Ok(value);
+        }
+        let flight = LoadingFlight::join(&self.loading, key);
+        let _guard = flight.lock.lock().await;
+        // пока ждали, значение мог загрузить другой запрос
+        if let Some(value) = self.get_local(key) {
+            return Ok(value);
+        }
+        self.load(rdb, key, load).await
+    }
+
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    // убирает ключ только из L1 этого процесса
+    pub fn evict_local(&self, key: &K) {
+        self.local.lock().unwrap().remove(key);
+    }
+
+    // L1 целиком, когда сообщения об инвалидации могли быть пропущены
+    pub fn clear_local(&self) {
+        self.local.lock().unwrap().clear();
+    }
+
+    // L1 и Redis. повторное удаление из Redis закрывает гонку с загрузкой,
+    // прочитавшей БД до изменения
+    pub async fn invalidate(&self, rdb: &deadpool_redis::Pool, key: &K) {
+        self.evict_local(key);
+        let redis_key = (self.redis_key)(key);
+        match rdb.get().await {
+            Ok(mut conn) => if let Err(e) = conn.del::<_, ()>(&[redis_key.clone(), negative_key(&redis_key)]).await {
+                warn!(cache=self.name, key=redis_key, err=e.to_string(), "Error delete cache key");
+            },
+            Err(e) => warn!(cache=self.name, err=e.to_string(), "Error get redis connection"),
+        }
+    }
+
+    async fn load<F, Fut>(&self, rdb: &deadpool_redis::Pool, key: &K, load: F) -> Result<Option<V>, LibError>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<Option<V>, LibError>>,
+    {
+        let redis_key = (self.redis_key)(key);
+        let mut conn = match rdb.get().await {
+            Ok(conn) => Some(conn),
+            Err(e) => {
+                warn!(cache=self.name, err=e.to_string(), "Error get redis connection");
+                None
+            }
+        };
+        let negative_key = negative_key(&redis_key);
+        if let Some(conn) = conn.as_mut() {
+            match conn.mget::<_, (Option<String>, Option<String>)>(&[redis_key.as_str(), negative_key.as_str()]).await {
+                // значение, записанное после отметки, новее ее
+                Ok((Some(raw), _)) => if let Some(value) = V::decode(&raw) {
+                    self.set_local(key, Some(value.clone()));
+                    return Ok(Some(value));
+                },
+                Ok((None, Some(_))) => {
+                    self.set_local(key, None);
+                    return Ok(None);
+                }
+                Ok((None, None)) => debug!(cache=self.name, key=redis_key, "cache miss"),
+                Err(e) => warn!(cache=self.name, key=redis_key, err=e.to_string(), "Error read cache"),
+            }
+        }
+
+        let value = load().await?;
+        self.set_local(key, value.clone());
+        if let Some(mut conn) = conn {
+            let (redis_key, raw, ttl) = match value.as_ref() {
+                Some(value) => (redis_key, value.encode(), self.ttl),
+                None => (negative_key, NEGATIVE_MARKER.to_string(), self.negative_ttl),
+            };
+            let name = self.name;
+            // NX: значение, записанное publish_value после нашего чтения БД, новее загруженного
+            let options = SetOptions::default()
+                .conditional_set(ExistenceCheck::NX)
+                .with_expiration(SetExpiry::EX(ttl.as_secs().max(1)));
+            tokio::spawn(async move {
+                if let Err(e) = conn.set_options::<_, _, ()>(redis_key.as_str(), raw, options).await {
+                    warn!(cache=name, key=redis_key, err=e.to_string(), "Error write cache");
+                }
+            });
+        }
+        Ok(value)
+    }
+
+    fn get_local(&self, key: &K) -> Option<Option<V>> {
+        let local = self.local.lock().unwrap();
+        local.get(key)
+            .filter(|entry| entry.expires_at > Instant::now())
+            .map(|entry| entry.value.clone())
+    }
+
+    fn set_local(&self, key: &K, value: Option<V>) {
+        let ttl = if value.is_some() { self.local_ttl } else { self.local_ttl.min(self.negative_ttl) };
+        let now = Instant::now();
+        let mut local = self.local.lock().unwrap();
+        if local.len() >= self.local_capacity {
+            local.retain(|_, entry| entry.expires_at > now);
+            if local.len() >= self.local_capacity {
+                local.clear();
+            }
+        }
+        local.insert(key.clone(), LocalEntry { expires_at: now + ttl, value });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use super::*;
+
+    #[tokio::test]
+    async fn concurrent_misses_load_once() {
+        // Redis недоступен, кэш работает только на L1
+        let rdb = deadpool_redis::Config::from_url("redis://127.0.0.1:1")
+            .create_pool(Some(deadpool_redis::Runtime::Tokio1)).unwrap();
+        let cache = Arc::new(ReadThroughCache::<String, bool>::new("test", |id| format!("test:{}", id),
+                                                                   Duration::from_secs(60)));
+        let loads = Arc::new(AtomicUsize::new(0));
+        let mut tasks = Vec::new();
+        for _ in 0..8 {
+            let (cache, loads, rdb) = (cache.clone(), loads.clone(), rdb.clone());
+            tasks.push(tokio::spawn(async move {
+                cache.get(&rdb, &"t1".to_string(), || async {
+                    loads.fetch_add(1, Ordering::SeqCst);
+                    tokio::time::sleep(Duration::from_millis(20)).await;
+                    Ok(None)
+                }).await
+            }));
+        }
+        for task in tasks {
+            assert_eq!(task.await.unwrap(), Ok(None));
+        }
+        assert_eq!(loads.load(Ordering::SeqCst), 1);
+        assert!(cache.loading.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn failed_loads_do_not_overlap() {
+        let rdb = deadpool_redis::Config::from_url("redis://127.0.0.1:1")
+            .create_pool(Some(deadpool_redis::Runtime::Tokio1)).unwrap();
+        let cache = Arc::new(ReadThroughCache::<String, bool>::new("test", |id| format!("test:{}", id),
+                                                                   Duration::from_secs(60)));
+        let (running, max_running) = (Arc::new(AtomicUsize::new(0)), Arc::new(AtomicUsize::new(0)));
+        let mut tasks = Vec::new();
+        for i in 0..6 {
+            let (cache, rdb) = (cache.clone(), rdb.clone());
+            let (running, max_running) = (running.clone(), max_running.clone());
+            // вторая половина приходит, когда первая загрузка уже завершилась ошибкой и ее ждут остальные
+            if i == 3 {
+                tokio::time::sleep(Duration::from_millis(15)).await;
+            }
+            tasks.push(tokio::spawn(async move {
+                cache.get(&rdb, &"t1".to_string(), || async {
+                    let now = running.fetch_add(1, Ordering::SeqCst) + 1;
+                    max_running.fetch_max(now, Ordering::SeqCst);
+                    tokio::time::sleep(Duration::from_millis(10)).await;
+                    running.fetch_sub(1, Ordering::SeqCst);
+                    Err(LibError::InternalError)
+                }).await
+            }));
+        }
+        for task in tasks {
+            assert!(task.await.unwrap().is_err());
+        }
+        assert_eq!(max_running.load(Ordering::SeqCst), 1);
+        assert!(cache.loading.lock().unwrap().is_empty());
+    }
+}
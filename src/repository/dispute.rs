@@ -0,0 +1,129 @@
+use chrono::NaiveDateTime;
+use tokio_postgres::types::Type;
+use tracing::error;
+use crate::errors::LibError;
+use crate::errors::LibError::{Conflict, InternalError, NotFound};
+use crate::map_err_with_log;
+use crate::models::payments::dispute::{Dispute, DisputeEvidence, DisputeResolution, DisputeStatus};
+use crate::models::payments::payment::{FullPayment, PaymentStatuses, ToSQL};
+
+pub async fn get_payment_for_update(tx: &tokio_postgres::Transaction<'_>, payment_id: &str) -> Result<FullPayment, LibError> {
+    let query = format!("{} WHERE id=$1 FOR UPDATE", FullPayment::sql());
+    let rows = map_err_with_log!(tx.query_typed(query.as_str(), &[(&payment_id, Type::VARCHAR)]).await,
+        "Error get payment for update", InternalError, payment_id)?;
+    Ok(FullPayment::from(rows.first().ok_or(NotFound)?))
+}
+
+pub async fn set_payment_status(tx: &tokio_postgres::Transaction<'_>, payment_id: &str, status: &PaymentStatuses,
+                                now: NaiveDateTime) -> Result<(), LibError> {
+    let status = status.to_string();
+    map_err_with_log!(tx.query_typed("UPDATE payments SET status=$1, updated_at=$2 WHERE id=$3",
+        &[(&status, Type::VARCHAR), (&now, Type::TIMESTAMP), (&payment_id, Type::VARCHAR)]).await,
+        "Error set payment status", InternalError, payment_id, status)?;
+    Ok(())
+}
+
+pub async fn insert_dispute(tx: &tokio_postgres::Transaction<'_>, dispute: &Dispute) -> Result<(), LibError> {
+    let dispute_id = dispute.id.as_str();
+    let opened_by = dispute.opened_by.to_string();
+    let status = dispute.status.to_string();
+    let payment_status_before = dispute.payment_status_before.to_string();
+    let rows = map_err_with_log!(tx.query_typed(
+        "INSERT INTO disputes (id, payment_id, merchant_id, trader_id, opened_by, opener_id, reason, status,
+        payment_status_before, respond_by, resolve_by, created_at)
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12) ON CONFLICT DO NOTHING RETURNING id",
+        &[(&dispute.id, Type::VARCHAR), (&dispute.payment_id, Type::VARCHAR), (&dispute.merchant_id, Type::VARCHAR),
+            (&dispute.trader_id, Type::VARCHAR), (&opened_by, Type::VARCHAR), (&dispute.opener_id, Type::VARCHAR),
+            (&dispute.reason, Type::TEXT), (&status, Type::VARCHAR), (&payment_status_before, Type::VARCHAR),
+            (&dispute.respond_by, Type::TIMESTAMP), (&dispute.resolve_by, Type::TIMESTAMP),
+            (&dispute.created_at, Type::TIMESTAMP)]).await,
+        "Error insert dispute", InternalError, dispute_id)?;
+    // на платеж может быть только один незакрытый спор (уникальный индекс)
+    if rows.is_empty() {
+        return Err(Conflict);
+    }
+    Ok(())
+}
+
+pub async fn get_dispute(client: &tokio_postgres::Client, dispute_id: &str) -> Result<Dispute, LibError> {
+    let rows = map_err_with_log!(client.query_typed("SELECT * FROM disputes WHERE id=$1",
+        &[(&dispute_id, Type::VARCHAR)]).await,
+        "Error get dispute", InternalError, dispute_id)?;
+    Ok(Dispute::from(rows.first().ok_or(NotFound)?))
+}
+
+pub async fn get_dispute_for_update(tx: &tokio_postgres::Transaction<'_>, dispute_id: &str) -> Result<Dispute, LibError> {
+    let rows = map_err_with_log!(tx.query_typed("SELECT * FROM disputes WHERE id=$1 FOR UPDATE",
+        &[(&dispute_id, Type::VARCHAR)]).await,
+        "Error get dispute for update", InternalError, dispute_id)?;
+    Ok(Dispute::from(rows.first().ok_or(NotFound)?))
+}
+
+pub async fn get_payment_disputes(client: &tokio_postgres::Client, payment_id: &str) -> Result<Vec<Dispute>, LibError> {
+    let rows = map_err_with_log!(client.query_typed("SELECT * FROM disputes WHERE payment_id=$1 ORDER BY created_at DESC",
+        &[(&payment_id, Type::VARCHAR)]).await,
+        "Error get payment disputes", InternalError, payment_id)?;
+    Ok(rows.iter().map(Dispute::from).collect())
+}
+
+pub async fn set_dispute_status(client: &tokio_postgres::Client, dispute_id: &str, status: DisputeStatus,
+                                now: NaiveDateTime) -> Result<(), LibError> {
+    let status = status.to_string();
+    let rows = map_err_with_log!(client.query_typed(
+        "UPDATE disputes SET status=$1, updated_at=$2 WHERE id=$3 AND status<>'RESOLVED' RETURNING id",
+        &[(&status, Type::VARCHAR), (&now, Type::TIMESTAMP), (&dispute_id, Type::VARCHAR)]).await,
+        "Error set dispute status", InternalError, dispute_id, status)?;
+    if rows.is_empty() {
+        return Err(Conflict);
+    }
+    Ok(())
+}
+
+pub async fn resolve_dispute(tx: &tokio_postgres::Transaction<'_>, dispute_id: &str, resolution: DisputeResolution,
+                             resolved_by: &str, comment: Option<&str>, now: NaiveDateTime) -> Result<(), LibError> {
+    let resolution = resolution.to_string();
+    map_err_with_log!(tx.query_typed(
+        "UPDATE disputes SET status='RESOLVED', resolution=$1, resolved_by=$2, resolution_comment=$3,
+        resolved_at=$4, updated_at=$4 WHERE id=$5",
+        &[(&resolution, Type::VARCHAR), (&resolved_by, Type::VARCHAR), (&comment, Type::TEXT),
+            (&now, Type::TIMESTAMP), (&dispute_id, Type::VARCHAR)]).await,
+        "Error resolve dispute", InternalError, dispute_id, resolution)?;
+    Ok(())
+}
+
+pub async fn insert_evidence(client: &tokio_postgres::Client, evidence: &DisputeEvidence) -> Result<(), LibError> {
+    let dispute_id = evidence.dispute_id.as_str();
+    let uploaded_by = evidence.uploaded_by.to_string();
+    map_err_with_log!(client.query_typed(
+        "INSERT INTO dispute_evidence (id, dispute_id, uploaded_by, uploader_id, file_name, content_type,
+        size_bytes, storage_key, sha256, created_at) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10)",
+        &[(&evidence.id, Type::VARCHAR), (&evidence.dispute_id, Type::VARCHAR), (&uploaded_by, Type::VARCHAR),
+            (&evidence.uploader_id, Type::VARCHAR), (&evidence.file_name, Type::VARCHAR),
+            (&evidence.content_type, Type::VARCHAR), (&evidence.size_bytes, Type::INT8),
+            (&evidence.storage_key, Type::VARCHAR), (&evidence.sha256, Type::VARCHAR),
+            (&evidence.created_at, Type::TIMESTAMP)]).await,
+        "Error insert dispute evidence", InternalError, dispute_id)?;
+    Ok(())
+}
+
+pub async fn get_evidence(client: &tokio_postgres::Client, dispute_id: &str) -> Result<Vec<DisputeEvidence>, LibError> {
+    let rows = map_err_with_log!(client.query_typed(
+        "SELECT * FROM dispute_evidence WHERE dispute_id=$1 ORDER BY created_at",
+        &[(&dispute_id, Type::VARCHAR)]).await,
+        "Error get dispute evidence", InternalError, dispute_id)?;
+    Ok(rows.iter().map(DisputeEvidence::from).collect())
+}
+
+// незакрытые споры с нарушенным SLA
+pub async fn get_overdue_disputes(client: &tokio_postgres::Client, now: NaiveDateTime, limit: i64)
+    -> Result<Vec<Dispute>, LibError>
+{
+    let rows = client.query_typed(
+        "SELECT * FROM disputes WHERE status<>'RESOLVED'
+        AND (resolve_by<$1 OR (status='OPEN' AND respond_by<$1)) ORDER BY resolve_by LIMIT $2",
+        &[(&now, Type::TIMESTAMP), (&limit, Type::INT8)]).await.map_err(|e| {
+        error!(err=e.to_string(), "Error get overdue disputes");
+        InternalError
+    })?;
+    Ok(rows.iter().map(Dispute::from).collect())
+}
@@ -0,0 +1,244 @@
+use std::str::FromStr;
+use rust_decimal::Decimal;
+use tokio_postgres::types::Type;
+use tracing::{debug, error};
+use uuid::Uuid;
+use crate::errors::LibError;
+use crate::errors::LibError::{InsufficientFunds, InternalError};
+use crate::map_err_with_log;
+use crate::models::ledger::{BalanceAction, BalanceMismatch, LedgerAccount, LedgerBalances, LedgerEntry, LedgerOpening, LedgerOwner,
+    LedgerTransaction};
+
+// применяет действие с балансом как набор проводок в одной транзакции.
+// возвращает false если запись с таким idempotent_key уже была применена
+pub async fn apply_balance_action(client: &mut tokio_postgres::Client, entry: &LedgerEntry) -> Result<bool, LibError> {
+    insert_balance_action(client, entry, true).await
+}
+
+// действие, которое уже применил сервис трейдеров или мерчантов. остаток проверен там,
+// а у владельца без посева (seed_opening_balance) в леджере нет прежнего остатка, поэтому без проверки
+pub async fn record_balance_action(client: &mut tokio_postgres::Client, entry: &LedgerEntry) -> Result<bool, LibError> {
+    insert_balance_action(client, entry, false).await
+}
+
+async fn insert_balance_action(client: &mut tokio_postgres::Client, entry: &LedgerEntry, check_funds: bool)
+    -> Result<bool, LibError>
+{
+    let postings = entry.postings()?;
+    let owner_type = entry.owner_type.to_string();
+    let owner_id = entry.owner_id.as_str();
+    let idempotent_key = entry.idempotent_key.as_str();
+    let action_type = entry.action.to_string();
+    let tx = map_err_with_log!(client.transaction().await,
+        "Error begin ledger transaction", InternalError, owner_id)?;
+
+    let transaction_id = Uuid::now_v7().to_string();
+    let inserted = map_err_with_log!(tx.query_typed(
+        "INSERT INTO ledger_transactions (id, owner_type, owner_id, action_type, amount, fee, reference_id, idempotent_key)
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8) ON CONFLICT (idempotent_key) DO NOTHING RETURNING id",
+        &[(&transaction_id, Type::VARCHAR), (&owner_type, Type::VARCHAR), (&owner_id, Type::VARCHAR),
+            (&action_type, Type::VARCHAR), (&entry.amount, Type::NUMERIC), (&entry.fee, Type::NUMERIC),
+            (&entry.reference_id, Type::VARCHAR), (&idempotent_key, Type::VARCHAR)]).await,
+        "Error insert ledger transaction", InternalError, owner_id, idempotent_key)?;
+    if inserted.is_empty() {
+        debug!(owner_id=owner_id, idempotent_key=idempotent_key, "ledger transaction already applied");
+        return Ok(false);
+    }
+
+    for posting in postings.iter() {
+        let account = posting.account.to_string();
+        map_err_with_log!(tx.query_typed(
+            "INSERT INTO ledger_postings (transaction_id, owner_type, owner_id, account, amount)
+            VALUES ($1, $2, $3, $4, $5)",
+            &[(&transaction_id, Type::VARCHAR), (&owner_type, Type::VARCHAR), (&owner_id, Type::VARCHAR),
+                (&account, Type::VARCHAR), (&posting.amount, Type::NUMERIC)]).await,
+            "Error insert ledger posting", InternalError, owner_id, account)?;
+
+        let rows = map_err_with_log!(tx.query_typed(
+            "INSERT INTO ledger_balances (owner_type, owner_id, account, balance) VALUES ($1, $2, $3, $4)
+            ON CONFLICT (owner_type, owner_id, account) DO UPDATE SET balance = ledger_balances.balance + EXCLUDED.balance,
+            updated_at = NOW() RETURNING balance",
+            &[(&owner_type, Type::VARCHAR), (&owner_id, Type::VARCHAR),
+                (&account, Type::VARCHAR), (&posting.amount, Type::NUMERIC)]).await,
+            "Error update ledger balance", InternalError, owner_id, account)?;
+        let balance: Decimal = rows.first().ok_or(InternalError)?.get(0);
+        if check_funds && balance < Decimal::ZERO && !posting.account.allows_negative() {
+            // транзакция откатится при drop
+            return Err(InsufficientFunds);
+        }
+    }
+
+    map_err_with_log!(tx.commit().await, "Error commit ledger transaction", InternalError, owner_id, idempotent_key)?;
+    Ok(true)
+}
+
+// начальный остаток владельца: разница между балансами сервиса и суммой уже записанных проводок.
+// датируется раньше первой транзакции владельца, чтобы попадать в остаток на любой момент.
+// false если остаток уже посеян
+pub async fn seed_opening_balance(client: &mut tokio_postgres::Client, owner_type: LedgerOwner, owner_id: &str,
+                                  service_balances: &LedgerBalances) -> Result<bool, LibError> {
+    let owner = owner_type.to_string();
+    let idempotent_key = LedgerOpening::idempotent_key(owner_type, owner_id);
+    let tx = map_err_with_log!(client.transaction().await,
+        "Error begin ledger transaction", InternalError, owner_id)?;
+    // параллельный посев того же владельца ждет здесь и потом видит запись с ключом
+    map_err_with_log!(tx.query_typed("SELECT pg_advisory_xact_lock(hashtext($1))",
+        &[(&idempotent_key, Type::TEXT)]).await,
+        "Error lock ledger opening", InternalError, owner_id)?;
+    let existing = map_err_with_log!(tx.query_typed(
+        "SELECT id FROM ledger_transactions WHERE idempotent_key=$1",
+        &[(&idempotent_key, Type::VARCHAR)]).await,
+        "Error get ledger opening", InternalError, owner_id)?;
+    if !existing.is_empty() {
+        debug!(owner_id=owner_id, "ledger opening balance already seeded");
+        return Ok(false);
+    }
+
+    let rows = map_err_with_log!(tx.query_typed(
+        "SELECT account, COALESCE(SUM(amount), 0) AS total FROM ledger_postings
+        WHERE owner_type=$1 AND owner_id=$2 AND account IN ('main', 'frozen') GROUP BY account",
+        &[(&owner, Type::VARCHAR), (&owner_id, Type::VARCHAR)]).await,
+        "Error sum ledger postings", InternalError, owner_id)?;
+    let mut opening = LedgerOpening {
+        owner_type,
+        owner_id: owner_id.to_string(),
+        main: service_balances.main,
+        frozen: service_balances.frozen,
+    };
+    for row in rows.iter() {
+        let total: Decimal = row.get("total");
+        match LedgerAccount::from_str(row.get("account")) {
+            Ok(LedgerAccount::Main) => opening.main -= total,
+            Ok(LedgerAccount::Frozen) => opening.frozen -= total,
+            _ => (),
+        }
+    }
+
+    let transaction_id = Uuid::now_v7().to_string();
+    let action_type = BalanceAction::Opening.to_string();
+    let amount = opening.amount();
+    map_err_with_log!(tx.query_typed(
+        "INSERT INTO ledger_transactions (id, owner_type, owner_id, action_type, amount, idempotent_key, created_at)
+        VALUES ($1, $2, $3, $4, $5, $6, COALESCE((SELECT MIN(created_at) FROM ledger_transactions
+            WHERE owner_type=$2 AND owner_id=$3) - INTERVAL '1 microsecond', NOW()))",
+        &[(&transaction_id, Type::VARCHAR), (&owner, Type::VARCHAR), (&owner_id, Type::VARCHAR),
+            (&action_type, Type::VARCHAR), (&amount, Type::NUMERIC), (&idempotent_key, Type::VARCHAR)]).await,
+        "Error insert ledger opening", InternalError, owner_id)?;
+    for posting in opening.postings().iter() {
+        let account = posting.account.to_string();
+        map_err_with_log!(tx.query_typed(
+            "INSERT INTO ledger_postings (transaction_id, owner_type, owner_id, account, amount)
+            VALUES ($1, $2, $3, $4, $5)",
+            &[(&transaction_id, Type::VARCHAR), (&owner, Type::VARCHAR), (&owner_id, Type::VARCHAR),
+                (&account, Type::VARCHAR), (&posting.amount, Type::NUMERIC)]).await,
+            "Error insert ledger posting", InternalError, owner_id, account)?;
+        map_err_with_log!(tx.query_typed(
+            "INSERT INTO ledger_balances (owner_type, owner_id, account, balance) VALUES ($1, $2, $3, $4)
+            ON CONFLICT (owner_type, owner_id, account) DO UPDATE SET balance = ledger_balances.balance + EXCLUDED.balance,
+            updated_at = NOW()",
+            &[(&owner, Type::VARCHAR), (&owner_id, Type::VARCHAR),
+                (&account, Type::VARCHAR), (&posting.amount, Type::NUMERIC)]).await,
+            "Error update ledger balance", InternalError, owner_id, account)?;
+    }
+
+    map_err_with_log!(tx.commit().await, "Error commit ledger opening", InternalError, owner_id)?;
+    Ok(true)
+}
+
+pub async fn has_opening_balance(client: &tokio_postgres::Client, owner_type: LedgerOwner, owner_id: &str)
+    -> Result<bool, LibError>
+{
+    let idempotent_key = LedgerOpening::idempotent_key(owner_type, owner_id);
+    let rows = map_err_with_log!(client.query_typed(
+        "SELECT 1 FROM ledger_transactions WHERE idempotent_key=$1",
+        &[(&idempotent_key, Type::VARCHAR)]).await,
+        "Error get ledger opening", InternalError, owner_id)?;
+    Ok(!rows.is_empty())
+}
+
+pub async fn get_ledger_balances(client: &tokio_postgres::Client, owner_type: LedgerOwner, owner_id: &str)
+    -> Result<LedgerBalances, LibError>
+{
+    let owner_type = owner_type.to_string();
+    let rows = map_err_with_log!(client.query_typed(
+        "SELECT account, balance FROM ledger_balances WHERE owner_type=$1 AND owner_id=$2",
+        &[(&owner_type, Type::VARCHAR), (&owner_id, Type::VARCHAR)]).await,
+        "Error get ledger balances", InternalError, owner_id)?;
+    let mut balances = LedgerBalances::default();
+    for row in rows.iter() {
+        let balance: Decimal = row.get("balance");
+        match LedgerAccount::from_str(row.get("account")) {
+            Ok(LedgerAccount::Main) => balances.main = balance,
+            Ok(LedgerAccount::Frozen) => balances.frozen = balance,
+            Ok(LedgerAccount::Fees) => balances.fees = balance,
+            _ => (),
+        }
+    }
+    Ok(balances)
+}
+
+pub async fn get_ledger_transactions(client: &tokio_postgres::Client, owner_type: LedgerOwner, owner_id: &str,
+                                     limit: u32, offset: u32)
+    -> Result<Vec<LedgerTransaction>, LibError>
+{
+    let owner_type = owner_type.to_string();
+    let limit = limit.min(100) as i64;
+    let offset = offset as i64;
+    let rows = map_err_with_log!(client.query_typed(
+        "SELECT * FROM ledger_transactions WHERE owner_type=$1 AND owner_id=$2
+        ORDER BY created_at DESC LIMIT $3 OFFSET $4",
+        &[(&owner_type, Type::VARCHAR), (&owner_id, Type::VARCHAR), (&limit, Type::INT8), (&offset, Type::INT8)]).await,
+        "Error get ledger transactions", InternalError, owner_id)?;
+    Ok(rows.iter().map(LedgerTransaction::from).collect())
+}
+
+// id транзакций, проводки которых в сумме не равны нулю
+pub async fn find_unbalanced_transactions(client: &tokio_postgres::Client) -> Result<Vec<String>, LibError> {
+    let rows = client.query_typed(
+        "SELECT transaction_id FROM ledger_postings GROUP BY transaction_id HAVING SUM(amount) <> 0",
+        &[]).await.map_err(|e| {
+        error!(err=e.to_string(), "Error find unbalanced ledger transactions");
+        InternalError
+    })?;
+    Ok(rows.iter().map(|row| row.get(0)).collect())
+}
+
+// сверка main и frozen из ledger_balances с суммой проводок, пустой результат - балансы сходятся.
+// с балансами сервисов это сходится только у владельцев с посеянным начальным остатком
+pub async fn reconcile_balances(client: &tokio_postgres::Client, owner_type: LedgerOwner)
+    -> Result<Vec<BalanceMismatch>, LibError>
+{
+    let owner = owner_type.to_string();
+    let rows = client.query_typed(
+        "SELECT COALESCE(b.owner_id, p.owner_id) AS owner_id, COALESCE(b.account, p.account) AS account,
+        COALESCE(b.balance, 0) AS recorded, COALESCE(p.total, 0) AS computed
+        FROM (SELECT owner_id, account, balance FROM ledger_balances
+            WHERE owner_type=$1 AND account IN ('main', 'frozen')) b
+        FULL OUTER JOIN (SELECT owner_id, account, SUM(amount) AS total FROM ledger_postings
+            WHERE owner_type=$1 AND account IN ('main', 'frozen') GROUP BY owner_id, account) p
+        ON b.owner_id=p.owner_id AND b.account=p.account
+        WHERE COALESCE(b.balance, 0) <> COALESCE(p.total, 0)",
+        &[(&owner, Type::VARCHAR)]).await.map_err(|e| {
+        error!(owner_type=owner, err=e.to_string(), "Error reconcile ledger balances");
+        InternalError
+    })?;
+    Ok(rows.iter().map(|row| BalanceMismatch {
+        owner_type,
+        owner_id: row.get("owner_id"),
+        account: LedgerAccount::from_str(row.get("account")).unwrap(),
+        recorded: row.get("recorded"),
+        computed: row.get("computed"),
+    }).collect())
+}
+
+pub async fn get_ledger_transactions_by_references(client: &tokio_postgres::Client, reference_ids: &[String])
+    -> Result<Vec<LedgerTransaction>, LibError>
+{
+    let rows = client.query_typed(
+        "SELECT * FROM ledger_transactions WHERE reference_id = ANY($1) ORDER BY created_at",
+        &[(&reference_ids, Type::VARCHAR_ARRAY)]).await.map_err(|e| {
+        error!(err=e.to_string(), "Error get ledger transactions by references");
+        InternalError
+    })?;
+    Ok(rows.iter().map(LedgerTransaction::from).collect())
+}
@@ -1,22 +1,62 @@
 
-use redis::aio::MultiplexedConnection;
-use redis::AsyncCommands;
+use std::str::FromStr;
+use std::time::Duration;
+use tokio_postgres::GenericClient;
 use tokio_postgres::types::Type;
 use tracing::error;
 use crate::errors::LibError;
 use crate::errors::LibError::{InternalError, MerchantNotFound};
 use crate::map_err_with_log;
+use crate::models::ip_allowlist::IpAllowlist;
+use crate::repository::cache::{publish_invalidation, publish_value, read_value, write_value, CacheValue};
 
-const TTL_HOUR: usize = 60 * 60;
-pub async fn check_merchant_is_blocked_from_redis(conn: &mut redis::aio::MultiplexedConnection, trader_id : &str) -> Result<Option<bool>, LibError> {
-    let key = format!("merchant:{}:is_blocked", trader_id);
-    match conn.get::<_, Option<String>>(key).await.map_err(|e| {
+pub const MERCHANT_IS_BLOCKED_CACHE: &str = "merchant_is_blocked";
+pub const MERCHANT_PUBLIC_KEY_CACHE: &str = "merchant_public_key";
+pub const MERCHANT_IP_ALLOWLIST_CACHE: &str = "merchant_ip_allowlist";
+pub const MERCHANT_IS_BLOCKED_TTL: Duration = Duration::from_secs(5 * 60);
+pub const MERCHANT_PUBLIC_KEY_TTL: Duration = Duration::from_secs(60 * 60);
+
+pub fn merchant_is_blocked_key(merchant_id: &str) -> String {
+    format!("merchant:{}:is_blocked", merchant_id)
+}
+
+pub fn merchant_public_key_key(merchant_id: &str) -> String {
+    format!("merchant:{}:public_key", merchant_id)
+}
+
+pub fn merchant_ip_allowlist_key(merchant_id: &str) -> String {
+    format!("merchant:{}:ip_allowlist", merchant_id)
+}
+
+impl CacheValue for IpAllowlist {
+    fn encode(&self) -> String {
+        self.to_string()
+    }
+
+    fn decode(value: &str) -> Option<Self> {
+        IpAllowlist::from_str(value).ok()
+    }
+}
+
+#[deprecated(note = "use use_case::merchant::check_merchant_is_blocked")]
+pub async fn check_merchant_is_blocked_from_redis(conn: &mut redis::aio::MultiplexedConnection, merchant_id : &str) -> Result<Option<bool>, LibError> {
+    read_value(conn, &merchant_is_blocked_key(merchant_id)).await.map_err(|e| {
         error!("Error getting merchant: {}", e);
         InternalError
-    })? {
-        Some(value) => Ok(Some(value == "1")),
-        None => Ok(None)
-    }
+    })
+}
+
+// имя осталось от старой версии, ключ мерчанта
+#[deprecated(note = "use set_merchant_is_blocked, it also notifies other services")]
+pub async fn set_trader_is_blocked_to_redis(
+    conn: &mut redis::aio::MultiplexedConnection,
+    merchant_id: String,
+    is_blocked: bool,
+) -> Result<(), LibError> {
+    write_value(conn, &merchant_is_blocked_key(&merchant_id), &is_blocked, MERCHANT_IS_BLOCKED_TTL).await.map_err(|e| {
+        error!("Error setting merchant is_blocked to redis: {}", e);
+        InternalError
+    })
 }
 
 pub async fn check_merchant_is_blocked_from_db(client : &tokio_postgres::Client, merchant_id : &str) -> Result<bool, LibError> {
@@ -30,31 +70,6 @@ pub async fn check_merchant_is_blocked_from_db(client : &tokio_postgres::Client,
     Ok(row.first().ok_or(MerchantNotFound)?.get(0))
 }
 
-pub async fn set_trader_is_blocked_to_redis(
-    conn: &mut MultiplexedConnection,
-    trader_id: String,
-    is_blocked: bool,
-) -> Result<(), LibError> {
-    let key = format!("merchant:{}:is_blocked", trader_id);
-
-    redis::pipe()
-        .atomic()
-        .cmd("SET")
-        .arg(&key)
-        .arg(if is_blocked { "1" } else { "0" })
-        .cmd("EXPIRE")
-        .arg(&key)
-        .arg(TTL_HOUR)
-        .exec_async(conn)
-        .await
-        .map_err(|e| {
-            error!("Error setting merchant is_blocked to redis: {}", e);
-            InternalError
-        })?;
-
-    Ok(())
-}
-
 pub async fn get_public_key_from_db(client: &tokio_postgres::Client, merchant_id: &str)
                                     -> Result<String, LibError>
 {
@@ -65,6 +80,27 @@ pub async fn get_public_key_from_db(client: &tokio_postgres::Client, merchant_id
     row.get::<_, Option<String>>(0).ok_or(LibError::NotFound)
 }
 
+#[deprecated(note = "use use_case::merchant cache of public keys")]
+pub async fn get_public_key_from_redis(conn: &mut redis::aio::MultiplexedConnection, merchant_id: &str)
+                                       -> Result<Option<String>, LibError>
+{
+    let public_key = map_err_with_log!(read_value(conn, &merchant_public_key_key(merchant_id)).await,
+        "Error getting merchant public key from Redis",
+        InternalError, merchant_id)?;
+    Ok(public_key)
+}
+
+#[deprecated(note = "use set_public_key, it also notifies other services")]
+pub async fn set_public_key_in_redis(conn: &mut redis::aio::MultiplexedConnection, merchant_id: &str, public_key: &str)
+                                     -> Result<(), LibError>
+{
+    map_err_with_log!(write_value(conn, &merchant_public_key_key(merchant_id), &public_key.to_string(),
+        MERCHANT_PUBLIC_KEY_TTL).await,
+        "Error setting merchant public key in Redis",
+        InternalError, merchant_id)?;
+    Ok(())
+}
+
 pub async fn set_public_key_in_db(client: &tokio_postgres::Client, merchant_id: &str, public_key: &str)
                                   -> Result<(), LibError>
 {
@@ -77,22 +113,62 @@ pub async fn set_public_key_in_db(client: &tokio_postgres::Client, merchant_id:
     Ok(())
 }
 
-pub async fn get_public_key_from_redis(conn: &mut MultiplexedConnection, merchant_id: &str)
-                                       -> Result<Option<String>, LibError>
+pub async fn update_merchant_is_blocked(client: &(impl GenericClient + Sync), merchant_id: &str, is_blocked: bool)
+    -> Result<(), LibError>
 {
-    let key = format!("merchant:{}:public_key", merchant_id);
-    let public_key: Option<String> = map_err_with_log!(conn.get(key).await,
-        "Error getting merchant public key from DB",
-        InternalError, merchant_id)?;
-    Ok(public_key)
+    let rows = map_err_with_log!(client.query_typed("UPDATE merchants SET is_blocked=$1 WHERE id=$2 RETURNING id",
+        &[(&is_blocked, Type::BOOL), (&merchant_id, Type::VARCHAR)]).await,
+        "Error set merchant is blocked", InternalError, merchant_id)?;
+    if rows.is_empty() {
+        return Err(MerchantNotFound);
+    }
+    Ok(())
 }
 
-pub async fn set_public_key_in_redis(conn: &mut MultiplexedConnection, merchant_id: &str, public_key: &str)
-                                     -> Result<(), LibError>
+// значение уже в БД: пишется в Redis и рассылается, чтобы остальные сервисы сбросили L1
+pub async fn publish_merchant_is_blocked(rdb: &deadpool_redis::Pool, merchant_id: &str, is_blocked: bool) {
+    publish_value(rdb, MERCHANT_IS_BLOCKED_CACHE, merchant_id, &merchant_is_blocked_key(merchant_id), &is_blocked,
+                  MERCHANT_IS_BLOCKED_TTL).await;
+}
+
+// флаг меняется в БД, кэши во всех сервисах обновляются через pub/sub
+pub async fn set_merchant_is_blocked(client: &tokio_postgres::Client, rdb: &deadpool_redis::Pool, merchant_id: &str,
+                                     is_blocked: bool) -> Result<(), LibError> {
+    update_merchant_is_blocked(client, merchant_id, is_blocked).await?;
+    publish_merchant_is_blocked(rdb, merchant_id, is_blocked).await;
+    Ok(())
+}
+
+pub async fn set_public_key(client: &tokio_postgres::Client, rdb: &deadpool_redis::Pool, merchant_id: &str,
+                            public_key: &str) -> Result<(), LibError> {
+    set_public_key_in_db(client, merchant_id, public_key).await?;
+    publish_invalidation(rdb, MERCHANT_PUBLIC_KEY_CACHE, merchant_id, &merchant_public_key_key(merchant_id)).await;
+    Ok(())
+}
+
+pub async fn get_ip_allowlist_from_db(client: &tokio_postgres::Client, merchant_id: &str)
+                                      -> Result<IpAllowlist, LibError>
 {
-    let key = format!("merchant:{}:public_key", merchant_id);
-    let _: () = map_err_with_log!(conn.set(key, public_key).await,
-        "Error setting merchant public key in Redis",
-        InternalError, merchant_id)?;
+    let rows = map_err_with_log!(client.query_typed("SELECT ip_allowlist FROM merchants WHERE id=$1",
+        &[(&merchant_id, Type::VARCHAR)]).await,
+        "Error getting merchant ip allowlist from DB", InternalError, merchant_id)?;
+    let row = rows.first().ok_or(MerchantNotFound)?;
+    // в БД пишутся только проверенные диапазоны
+    IpAllowlist::try_from(row.get::<_, Vec<String>>(0)).map_err(|e| {
+        error!(merchant_id=merchant_id, err=e, "Invalid merchant ip allowlist in DB");
+        InternalError
+    })
+}
+
+pub async fn set_ip_allowlist(client: &tokio_postgres::Client, rdb: &deadpool_redis::Pool, merchant_id: &str,
+                              allowlist: &IpAllowlist) -> Result<(), LibError> {
+    let nets = Vec::<String>::from(allowlist.clone());
+    let rows = map_err_with_log!(client.query_typed("UPDATE merchants SET ip_allowlist=$1 WHERE id=$2 RETURNING id",
+        &[(&nets, Type::TEXT_ARRAY), (&merchant_id, Type::VARCHAR)]).await,
+        "Error setting merchant ip allowlist", InternalError, merchant_id)?;
+    if rows.is_empty() {
+        return Err(MerchantNotFound);
+    }
+    publish_invalidation(rdb, MERCHANT_IP_ALLOWLIST_CACHE, merchant_id, &merchant_ip_allowlist_key(merchant_id)).await;
     Ok(())
-}
\ No newline at end of file
+}
@@ -0,0 +1,60 @@
+use tokio_postgres::types::Type;
+use tracing::error;
+use crate::errors::LibError;
+use crate::errors::LibError::InternalError;
+use crate::map_err_with_log;
+use crate::models::mfa::MfaSecret;
+
+pub async fn get_mfa_secret(client: &tokio_postgres::Client, user_id: &str) -> Result<Option<MfaSecret>, LibError> {
+    let rows = map_err_with_log!(client.query_typed("SELECT * FROM mfa_secrets WHERE user_id=$1",
+        &[(&user_id, Type::VARCHAR)]).await,
+        "Error get mfa secret", InternalError, user_id)?;
+    Ok(rows.first().map(MfaSecret::from))
+}
+
+// неподтвержденный секрет заменяется, подтвержденный - нет: false, если второй фактор уже подключен
+pub async fn upsert_mfa_secret(tx: &tokio_postgres::Transaction<'_>, user_id: &str, role: &str, secret: &str)
+    -> Result<bool, LibError>
+{
+    let rows = map_err_with_log!(tx.query_typed(
+        "INSERT INTO mfa_secrets (user_id, role, secret, last_step, confirmed_at, created_at)
+        VALUES ($1, $2, $3, NULL, NULL, now())
+        ON CONFLICT (user_id) DO UPDATE SET role=$2, secret=$3, last_step=NULL, created_at=now()
+            WHERE mfa_secrets.confirmed_at IS NULL
+        RETURNING user_id",
+        &[(&user_id, Type::VARCHAR), (&role, Type::VARCHAR), (&secret, Type::VARCHAR)]).await,
+        "Error upsert mfa secret", InternalError, user_id)?;
+    Ok(!rows.is_empty())
+}
+
+pub async fn replace_recovery_codes(tx: &tokio_postgres::Transaction<'_>, user_id: &str, code_hashes: &[String])
+    -> Result<(), LibError>
+{
+    map_err_with_log!(tx.query_typed("DELETE FROM mfa_recovery_codes WHERE user_id=$1",
+        &[(&user_id, Type::VARCHAR)]).await,
+        "Error delete recovery codes", InternalError, user_id)?;
+    map_err_with_log!(tx.query_typed(
+        "INSERT INTO mfa_recovery_codes (user_id, code_hash, used_at) SELECT $1, UNNEST($2::VARCHAR[]), NULL",
+        &[(&user_id, Type::VARCHAR), (&code_hashes, Type::VARCHAR_ARRAY)]).await,
+        "Error insert recovery codes", InternalError, user_id)?;
+    Ok(())
+}
+
+// шаг TOTP засчитывается один раз: false, если он или более поздний уже использован
+pub async fn use_totp_step(client: &tokio_postgres::Client, user_id: &str, step: i64) -> Result<bool, LibError> {
+    let rows = map_err_with_log!(client.query_typed(
+        "UPDATE mfa_secrets SET last_step=$2, confirmed_at=COALESCE(confirmed_at, now())
+        WHERE user_id=$1 AND (last_step IS NULL OR last_step<$2) RETURNING user_id",
+        &[(&user_id, Type::VARCHAR), (&step, Type::INT8)]).await,
+        "Error use totp step", InternalError, user_id)?;
+    Ok(!rows.is_empty())
+}
+
+pub async fn use_recovery_code(client: &tokio_postgres::Client, user_id: &str, code_hash: &str) -> Result<bool, LibError> {
+    let rows = map_err_with_log!(client.query_typed(
+        "UPDATE mfa_recovery_codes SET used_at=now() WHERE user_id=$1 AND code_hash=$2 AND used_at IS NULL
+        RETURNING user_id",
+        &[(&user_id, Type::VARCHAR), (&code_hash, Type::VARCHAR)]).await,
+        "Error use recovery code", InternalError, user_id)?;
+    Ok(!rows.is_empty())
+}
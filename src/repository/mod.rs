@@ -1,44 +1,52 @@
-use std::fmt::Error;
-
 pub mod trader;
-pub(crate) mod merchant;
+pub mod merchant;
+pub mod ledger;
+pub mod payment;
+pub mod saga;
+pub mod dispute;
+pub mod refund;
+pub mod settlement;
+pub mod block;
+pub mod payout;
+pub mod cache;
+pub mod audit;
+pub mod rate_limit;
+pub mod mfa;
+// $policy - crate::retry::RetryPolicy. $sql_func вычисляется заново на каждой попытке,
+// повторяются только ошибки, которые policy считает повторяемыми, поэтому ошибка должна реализовывать
+// crate::retry::Classify. число вместо policy - старая форма вызова, это число попыток
 #[macro_export]
 macro_rules! retry {
-    ($sql_func:expr, $max_retries:expr) => {{
-        let mut result;
+    ($sql_func:expr, $max_attempts:literal) => {
+        $crate::retry!($sql_func, $crate::retry::RetryPolicy::default().with_max_attempts($max_attempts))
+    };
+    ($sql_func:expr, $policy:expr) => {{
+        let policy: &$crate::retry::RetryPolicy = &$policy;
+        let started = ::std::time::Instant::now();
         let mut attempt = 0;
         loop {
             attempt += 1;
-            result = tokio::time::timeout(Duration::from_millis(300), $sql_func).await;
-            match result {
-                Ok(Ok(r)) => {
-                    break Ok(r)
-                },
-                Ok(Err(ref e)) if attempt < $max_retries && is_connection_err(e) => {
-                    warn!(err=e.to_string(), "Error do request. Retrying...");
-                    tokio::time::sleep(tokio::time::Duration::from_millis(100)).await;
-                    continue;
-                },
-                Ok(Err(e)) => break Err(e.to_string()),
-                Err(e)if attempt < $max_retries => {
-                    warn!(err=e.to_string(), "timeout do request. Retrying...");
-                    tokio::time::sleep(tokio::time::Duration::from_millis(100)).await;
-                    continue;
-                }
-                Err(e) => {
-                    break Err(e.to_string());
+            let (kind, err) = match policy.timed($sql_func).await {
+                Ok(Ok(r)) => break Ok(r),
+                Ok(Err(e)) => ($crate::retry::Classify::error_kind(&e), e.to_string()),
+                Err(e) => ($crate::retry::ErrorKind::Timeout, e.to_string()),
+            };
+            match policy.next_delay(attempt, kind, started) {
+                Some(delay) => {
+                    ::tracing::warn!(err=err, kind=?kind, attempt=attempt, "Error do request. Retrying...");
+                    ::tokio::time::sleep(delay).await;
                 }
+                None => break Err(err),
             }
         }
     }};
 }
+
+// прежняя проверка по тексту ошибки, retry! ее больше не использует
+#[deprecated(note = "use crate::retry::Classify::error_kind")]
 pub fn is_connection_err<T>(e: &T) -> bool
 where T: ToString
 {
-    e.to_string().contains("connection")
-        || e.to_string().contains("broken")
-        || e.to_string().contains("time")
-        || e.to_string().contains("timed")
-        || e.to_string().contains("conn")
-        || e.to_string().contains("io")
-}
\ No newline at end of file
+    let e = e.to_string();
+    ["connection", "broken", "time", "timed", "conn", "io"].iter().any(|pattern| e.contains(pattern))
+}
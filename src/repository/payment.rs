@@ -0,0 +1,163 @@
+use chrono::NaiveDateTime;
+use tokio_postgres::types::Type;
+use tracing::error;
+use crate::errors::LibError;
+use crate::errors::LibError::{InternalError, NotFound};
+use crate::models::payments::expiry::ExpiryJob;
+use crate::models::payments::payment::{FullPayment, PaymentStatuses, ToSQL};
+use crate::models::payments::requests::GetPaymentsRequest;
+use crate::models::payments::stats::{PaymentStatsRequest, PaymentStatsRow};
+
+pub async fn get_payments_by_status_in_window(client: &tokio_postgres::Client, status: PaymentStatuses,
+                                              from: NaiveDateTime, to: NaiveDateTime)
+    -> Result<Vec<FullPayment>, LibError>
+{
+    let status = status.to_string();
+    let query = format!("{} WHERE status=$1 AND COALESCE(updated_at, created_at)>=$2 \
+        AND COALESCE(updated_at, created_at)<$3 ORDER BY created_at", FullPayment::sql());
+    let rows = client.query_typed(query.as_str(),
+        &[(&status, Type::VARCHAR), (&from, Type::TIMESTAMP), (&to, Type::TIMESTAMP)]).await.map_err(|e| {
+        error!(status=status, err=e.to_string(), "Error get payments in window");
+        InternalError
+    })?;
+    Ok(rows.iter().map(FullPayment::from).collect())
+}
+
+pub async fn get_payment(client: &tokio_postgres::Client, payment_id: &str) -> Result<FullPayment, LibError> {
+    let query = format!("{} WHERE id=$1", FullPayment::sql());
+    let rows = client.query_typed(query.as_str(), &[(&payment_id, Type::VARCHAR)]).await.map_err(|e| {
+        error!(payment_id=payment_id, err=e.to_string(), "Error get payment");
+        InternalError
+    })?;
+    Ok(FullPayment::from(rows.first().ok_or(NotFound)?))
+}
+
+// блокирует просроченные UNPAID платежи, занятые другими репликами пропускаются
+pub async fn lock_overdue_payments(tx: &tokio_postgres::Transaction<'_>, now: NaiveDateTime, limit: i64)
+    -> Result<Vec<FullPayment>, LibError>
+{
+    let query = format!("{} WHERE status='UNPAID' AND deadline<$1 ORDER BY deadline LIMIT $2 FOR UPDATE SKIP LOCKED",
+                        FullPayment::sql());
+    let rows = tx.query_typed(query.as_str(), &[(&now, Type::TIMESTAMP), (&limit, Type::INT8)]).await.map_err(|e| {
+        error!(err=e.to_string(), "Error lock overdue payments");
+        InternalError
+    })?;
+    Ok(rows.iter().map(FullPayment::from).collect())
+}
+
+pub async fn cancel_payment_by_timeout(tx: &tokio_postgres::Transaction<'_>, payment_id: &str, now: NaiveDateTime)
+    -> Result<bool, LibError>
+{
+    let status = PaymentStatuses::CancelledByTimeout.to_string();
+    let rows = tx.query_typed(
+        "UPDATE payments SET status=$1, updated_at=$2 WHERE id=$3 AND status='UNPAID' RETURNING id",
+        &[(&status, Type::VARCHAR), (&now, Type::TIMESTAMP), (&payment_id, Type::VARCHAR)]).await.map_err(|e| {
+        error!(payment_id=payment_id, err=e.to_string(), "Error cancel payment by timeout");
+        InternalError
+    })?;
+    Ok(!rows.is_empty())
+}
+
+// задание на снятие заморозок пишется в одной транзакции с отменой платежа
+pub async fn insert_expiry_job(tx: &tokio_postgres::Transaction<'_>, payment_id: &str, now: NaiveDateTime)
+    -> Result<(), LibError>
+{
+    tx.query_typed("INSERT INTO payment_expiry_jobs (payment_id, next_attempt_at) VALUES ($1, $2) ON CONFLICT DO NOTHING",
+        &[(&payment_id, Type::VARCHAR), (&now, Type::TIMESTAMP)]).await.map_err(|e| {
+        error!(payment_id=payment_id, err=e.to_string(), "Error insert expiry job");
+        InternalError
+    })?;
+    Ok(())
+}
+
+// забирает готовые задания и сразу переносит их на lease_until: блокировки на время вызовов
+// сервисов не держатся, а задание реплики, упавшей посередине, вернется в очередь после lease_until
+pub async fn claim_expiry_jobs(client: &tokio_postgres::Client, now: NaiveDateTime, lease_until: NaiveDateTime, limit: i64)
+    -> Result<Vec<ExpiryJob>, LibError>
+{
+    let rows = client.query_typed(
+        "UPDATE payment_expiry_jobs SET attempts=attempts+1, next_attempt_at=$2
+        WHERE payment_id IN (SELECT payment_id FROM payment_expiry_jobs WHERE next_attempt_at<=$1
+            ORDER BY next_attempt_at LIMIT $3 FOR UPDATE SKIP LOCKED)
+        RETURNING payment_id, attempts, next_attempt_at, last_error",
+        &[(&now, Type::TIMESTAMP), (&lease_until, Type::TIMESTAMP), (&limit, Type::INT8)]).await.map_err(|e| {
+        error!(err=e.to_string(), "Error claim expiry jobs");
+        InternalError
+    })?;
+    Ok(rows.iter().map(ExpiryJob::from).collect())
+}
+
+pub async fn reschedule_expiry_job(client: &tokio_postgres::Client, job: &ExpiryJob) -> Result<(), LibError> {
+    client.query_typed("UPDATE payment_expiry_jobs SET next_attempt_at=$1, last_error=$2 WHERE payment_id=$3",
+        &[(&job.next_attempt_at, Type::TIMESTAMP), (&job.last_error, Type::TEXT), (&job.payment_id, Type::VARCHAR)])
+        .await.map_err(|e| {
+        error!(payment_id=job.payment_id, err=e.to_string(), "Error reschedule expiry job");
+        InternalError
+    })?;
+    Ok(())
+}
+
+pub async fn delete_expiry_job(client: &tokio_postgres::Client, payment_id: &str) -> Result<(), LibError> {
+    client.query_typed("DELETE FROM payment_expiry_jobs WHERE payment_id=$1", &[(&payment_id, Type::VARCHAR)])
+        .await.map_err(|e| {
+        error!(payment_id=payment_id, err=e.to_string(), "Error delete expiry job");
+        InternalError
+    })?;
+    Ok(())
+}
+
+pub async fn get_payments_by_ids(client: &tokio_postgres::Client, payment_ids: &[String]) -> Result<Vec<FullPayment>, LibError> {
+    let query = format!("{} WHERE id = ANY($1)", FullPayment::sql());
+    let rows = client.query_typed(query.as_str(), &[(&payment_ids, Type::VARCHAR_ARRAY)]).await.map_err(|e| {
+        error!(err=e.to_string(), "Error get payments by ids");
+        InternalError
+    })?;
+    Ok(rows.iter().map(FullPayment::from).collect())
+}
+
+pub async fn get_oldest_overdue_deadline(client: &tokio_postgres::Client, now: NaiveDateTime)
+    -> Result<Option<NaiveDateTime>, LibError>
+{
+    let rows = client.query_typed("SELECT MIN(deadline) FROM payments WHERE status='UNPAID' AND deadline<$1",
+        &[(&now, Type::TIMESTAMP)]).await.map_err(|e| {
+        error!(err=e.to_string(), "Error get oldest overdue deadline");
+        InternalError
+    })?;
+    Ok(rows.first().and_then(|row| row.get(0)))
+}
+
+// сохраняет пересчитанные при закрытии суммы, исходные пишутся только при первом пересчете
+pub async fn save_closed_amounts(tx: &tokio_postgres::Transaction<'_>, payment: &FullPayment, now: NaiveDateTime)
+    -> Result<bool, LibError>
+{
+    let status = payment.status.to_string();
+    let rows = tx.query_typed(
+        "UPDATE payments SET status=$1, fiat_amount=$2, crypto_amount=$3, trader_crypto_amount=$4, crypto_fee=$5,
+        fiat_fee=$6, trader_crypto_fee=$7, trader_fiat_fee=$8, earnings=$9,
+        original_fiat_amount=COALESCE(original_fiat_amount, $10),
+        original_crypto_amount=COALESCE(original_crypto_amount, $11),
+        original_trader_crypto_amount=COALESCE(original_trader_crypto_amount, $12),
+        updated_at=$13 WHERE id=$14 AND status<>'COMPLETED' AND status NOT LIKE 'CANCELLED%' RETURNING id",
+        &[(&status, Type::VARCHAR), (&payment.fiat_amount, Type::NUMERIC), (&payment.crypto_amount, Type::NUMERIC),
+            (&payment.trader_crypto_amount, Type::NUMERIC), (&payment.crypto_fee, Type::NUMERIC),
+            (&payment.fiat_fee, Type::NUMERIC), (&payment.trader_crypto_fee, Type::NUMERIC),
+            (&payment.trader_fiat_fee, Type::NUMERIC), (&payment.earnings, Type::NUMERIC),
+            (&payment.original_fiat_amount, Type::NUMERIC), (&payment.original_crypto_amount, Type::NUMERIC),
+            (&payment.original_trader_crypto_amount, Type::NUMERIC), (&now, Type::TIMESTAMP),
+            (&payment.id, Type::VARCHAR)]).await.map_err(|e| {
+        error!(payment_id=payment.id, err=e.to_string(), "Error save closed payment amounts");
+        InternalError
+    })?;
+    Ok(!rows.is_empty())
+}
+
+pub async fn get_payment_stats(client: &tokio_postgres::Client, request: &GetPaymentsRequest, stats: &PaymentStatsRequest)
+    -> Result<Vec<PaymentStatsRow>, LibError>
+{
+    let query = stats.to_sql(request);
+    let rows = client.query_typed(query.sql.as_str(), &query.params()).await.map_err(|e| {
+        error!(err=e.to_string(), "Error get payment stats");
+        InternalError
+    })?;
+    Ok(rows.iter().map(PaymentStatsRow::from).collect())
+}
@@ -0,0 +1,139 @@
+use chrono::NaiveDateTime;
+use tokio_postgres::types::Type;
+use tracing::error;
+use crate::errors::LibError;
+use crate::errors::LibError::InternalError;
+use crate::map_err_with_log;
+use crate::models::payments::earnings::{EarningsRequest, EarningsRow, HELD_CONDITION};
+use crate::models::payments::payout::{PayoutBatch, PayoutEvent, PayoutEventKind, PayoutItem, PayoutStatus};
+
+pub async fn get_trader_earnings(client: &tokio_postgres::Client, trader_id: &str, request: &EarningsRequest)
+    -> Result<Vec<EarningsRow>, LibError>
+{
+    let query = request.to_sql(trader_id);
+    let rows = map_err_with_log!(client.query_typed(query.sql.as_str(), &query.params()).await,
+        "Error get trader earnings", InternalError, trader_id)?;
+    Ok(rows.iter().map(EarningsRow::from).collect())
+}
+
+// пачки одного трейдера формируются по очереди, иначе платеж попадет в две пачки
+pub async fn lock_trader_payouts(tx: &tokio_postgres::Transaction<'_>, trader_id: &str) -> Result<(), LibError> {
+    map_err_with_log!(tx.query_typed("SELECT pg_advisory_xact_lock(hashtext('payout:' || $1))",
+        &[(&trader_id, Type::VARCHAR)]).await,
+        "Error lock trader payouts", InternalError, trader_id)?;
+    Ok(())
+}
+
+pub async fn get_payout_by_key(tx: &tokio_postgres::Transaction<'_>, trader_id: &str, idempotent_key: &str)
+    -> Result<Option<PayoutBatch>, LibError>
+{
+    let rows = map_err_with_log!(tx.query_typed(
+        "SELECT * FROM payout_batches WHERE trader_id=$1 AND idempotent_key=$2",
+        &[(&trader_id, Type::VARCHAR), (&idempotent_key, Type::VARCHAR)]).await,
+        "Error get payout by key", InternalError, trader_id, idempotent_key)?;
+    Ok(rows.first().map(PayoutBatch::from))
+}
+
+pub async fn get_payout_for_update(tx: &tokio_postgres::Transaction<'_>, batch_id: &str)
+    -> Result<Option<PayoutBatch>, LibError>
+{
+    let rows = map_err_with_log!(tx.query_typed("SELECT * FROM payout_batches WHERE id=$1 FOR UPDATE",
+        &[(&batch_id, Type::VARCHAR)]).await,
+        "Error get payout batch", InternalError, batch_id)?;
+    Ok(rows.first().map(PayoutBatch::from))
+}
+
+pub async fn get_payout_batch(client: &tokio_postgres::Client, batch_id: &str) -> Result<Option<PayoutBatch>, LibError> {
+    let rows = map_err_with_log!(client.query_typed("SELECT * FROM payout_batches WHERE id=$1",
+        &[(&batch_id, Type::VARCHAR)]).await,
+        "Error get payout batch", InternalError, batch_id)?;
+    Ok(rows.first().map(PayoutBatch::from))
+}
+
+pub async fn get_payout_items(client: &tokio_postgres::Client, batch_id: &str) -> Result<Vec<PayoutItem>, LibError> {
+    let rows = map_err_with_log!(client.query_typed(
+        "SELECT payment_id, amount FROM payout_items WHERE batch_id=$1 ORDER BY payment_id",
+        &[(&batch_id, Type::VARCHAR)]).await,
+        "Error get payout items", InternalError, batch_id)?;
+    Ok(rows.iter().map(PayoutItem::from).collect())
+}
+
+pub async fn get_payout_events(client: &tokio_postgres::Client, batch_id: &str) -> Result<Vec<PayoutEvent>, LibError> {
+    let rows = map_err_with_log!(client.query_typed(
+        "SELECT * FROM payout_events WHERE batch_id=$1 ORDER BY id",
+        &[(&batch_id, Type::VARCHAR)]).await,
+        "Error get payout events", InternalError, batch_id)?;
+    Ok(rows.iter().map(PayoutEvent::from).collect())
+}
+
+pub async fn has_payout_event(tx: &tokio_postgres::Transaction<'_>, batch_id: &str, kind: PayoutEventKind)
+    -> Result<bool, LibError>
+{
+    let kind = kind.to_string();
+    let rows = map_err_with_log!(tx.query_typed("SELECT 1 FROM payout_events WHERE batch_id=$1 AND kind=$2 LIMIT 1",
+        &[(&batch_id, Type::VARCHAR), (&kind, Type::VARCHAR)]).await,
+        "Error check payout event", InternalError, batch_id, kind)?;
+    Ok(!rows.is_empty())
+}
+
+// завершенные платежи без удержания, еще не попавшие в действующую пачку
+pub async fn get_payable_payments(tx: &tokio_postgres::Transaction<'_>, trader_id: &str, until: NaiveDateTime)
+    -> Result<Vec<PayoutItem>, LibError>
+{
+    let sql = format!(
+        "SELECT id AS payment_id, trader_crypto_fee AS amount FROM payments
+        WHERE trader_id=$1 AND status='COMPLETED' AND created_at<$2 AND trader_crypto_fee>0 AND NOT {}
+        AND NOT EXISTS (SELECT 1 FROM payout_items i JOIN payout_batches b ON b.id=i.batch_id
+            WHERE i.payment_id=payments.id AND b.status<>'FAILED')
+        ORDER BY created_at, id", HELD_CONDITION);
+    let rows = map_err_with_log!(tx.query_typed(sql.as_str(),
+        &[(&trader_id, Type::VARCHAR), (&until, Type::TIMESTAMP)]).await,
+        "Error get payable payments", InternalError, trader_id)?;
+    Ok(rows.iter().map(PayoutItem::from).collect())
+}
+
+pub async fn insert_payout_batch(tx: &tokio_postgres::Transaction<'_>, batch: &PayoutBatch, items: &[PayoutItem])
+    -> Result<(), LibError>
+{
+    let batch_id = batch.id.as_str();
+    let status = batch.status.to_string();
+    map_err_with_log!(tx.query_typed(
+        "INSERT INTO payout_batches (id, trader_id, status, amount, payments_count, until, idempotent_key, created_by,
+        created_at) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)",
+        &[(&batch.id, Type::VARCHAR), (&batch.trader_id, Type::VARCHAR), (&status, Type::VARCHAR),
+            (&batch.amount, Type::NUMERIC), (&batch.payments_count, Type::INT8), (&batch.until, Type::TIMESTAMP),
+            (&batch.idempotent_key, Type::VARCHAR), (&batch.created_by, Type::VARCHAR),
+            (&batch.created_at, Type::TIMESTAMP)]).await,
+        "Error insert payout batch", InternalError, batch_id)?;
+    let payment_ids = items.iter().map(|item| item.payment_id.clone()).collect::<Vec<_>>();
+    let amounts = items.iter().map(|item| item.amount).collect::<Vec<_>>();
+    map_err_with_log!(tx.query_typed(
+        "INSERT INTO payout_items (batch_id, payment_id, amount) SELECT $1, * FROM UNNEST($2::varchar[], $3::numeric[])",
+        &[(&batch.id, Type::VARCHAR), (&payment_ids, Type::VARCHAR_ARRAY), (&amounts, Type::NUMERIC_ARRAY)]).await,
+        "Error insert payout items", InternalError, batch_id)?;
+    Ok(())
+}
+
+// меняет только PENDING, false если пачку уже завершили или отменили
+pub async fn set_payout_status(tx: &tokio_postgres::Transaction<'_>, batch_id: &str, status: PayoutStatus,
+                               now: NaiveDateTime) -> Result<bool, LibError> {
+    let status = status.to_string();
+    let rows = tx.query_typed(
+        "UPDATE payout_batches SET status=$1, completed_at=$2 WHERE id=$3 AND status='PENDING' RETURNING id",
+        &[(&status, Type::VARCHAR), (&now, Type::TIMESTAMP), (&batch_id, Type::VARCHAR)]).await.map_err(|e| {
+        error!(batch_id=batch_id, status=status, err=e.to_string(), "Error set payout status");
+        InternalError
+    })?;
+    Ok(!rows.is_empty())
+}
+
+pub async fn insert_payout_event(tx: &tokio_postgres::Transaction<'_>, batch_id: &str, kind: PayoutEventKind,
+                                 actor_id: &str, details: Option<String>, now: NaiveDateTime) -> Result<(), LibError> {
+    let kind = kind.to_string();
+    map_err_with_log!(tx.query_typed(
+        "INSERT INTO payout_events (batch_id, kind, actor_id, details, created_at) VALUES ($1, $2, $3, $4, $5)",
+        &[(&batch_id, Type::VARCHAR), (&kind, Type::VARCHAR), (&actor_id, Type::VARCHAR), (&details, Type::TEXT),
+            (&now, Type::TIMESTAMP)]).await,
+        "Error insert payout event", InternalError, batch_id, kind)?;
+    Ok(())
+}
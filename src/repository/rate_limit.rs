@@ -0,0 +1,92 @@
+use std::str::FromStr;
+use std::time::Duration;
+use once_cell::sync::Lazy;
+use tokio_postgres::types::Type;
+use tracing::error;
+use crate::errors::LibError;
+use crate::errors::LibError::InternalError;
+use crate::map_err_with_log;
+use crate::models::rate_limit::RateLimit;
+use crate::repository::cache::{publish_invalidation, CacheValue};
+
+pub const RATE_LIMIT_CACHE: &str = "rate_limit";
+pub const RATE_LIMIT_TTL: Duration = Duration::from_secs(10 * 60);
+
+// ключ кэша лимита, без префикса Redis
+pub fn rate_limit_cache_key(subject_id: &str, route_group: &str) -> String {
+    format!("{}:{}", subject_id, route_group)
+}
+
+pub fn rate_limit_key(cache_key: &str) -> String {
+    format!("rate_limit:{}", cache_key)
+}
+
+fn rate_limit_state_key(subject_id: &str, route_group: &str) -> String {
+    format!("rate_limit:{}:{}:tat", subject_id, route_group)
+}
+
+impl CacheValue for RateLimit {
+    fn encode(&self) -> String {
+        self.to_string()
+    }
+
+    fn decode(value: &str) -> Option<Self> {
+        RateLimit::from_str(value).ok()
+    }
+}
+
+// route_group = ALL_ROUTE_GROUPS - общий лимит субъекта
+pub async fn get_rate_limit(client: &tokio_postgres::Client, subject_id: &str, route_group: &str)
+    -> Result<Option<RateLimit>, LibError>
+{
+    let rows = map_err_with_log!(client.query_typed(
+        "SELECT \"limit\", period_ms FROM rate_limits WHERE subject_id=$1 AND route_group=$2",
+        &[(&subject_id, Type::VARCHAR), (&route_group, Type::VARCHAR)]).await,
+        "Error get rate limit", InternalError, subject_id, route_group)?;
+    Ok(rows.first().map(|row| RateLimit { limit: row.get("limit"), period_ms: row.get("period_ms") }))
+}
+
+pub async fn set_rate_limit(client: &tokio_postgres::Client, rdb: &deadpool_redis::Pool, subject_id: &str,
+                            route_group: &str, limit: RateLimit) -> Result<(), LibError> {
+    map_err_with_log!(client.query_typed(
+        "INSERT INTO rate_limits (subject_id, route_group, \"limit\", period_ms, updated_at) VALUES ($1, $2, $3, $4, now())
+        ON CONFLICT (subject_id, route_group) DO UPDATE SET \"limit\"=$3, period_ms=$4, updated_at=now()",
+        &[(&subject_id, Type::VARCHAR), (&route_group, Type::VARCHAR), (&limit.limit, Type::INT4),
+            (&limit.period_ms, Type::INT8)]).await,
+        "Error set rate limit", InternalError, subject_id, route_group)?;
+    let cache_key = rate_limit_cache_key(subject_id, route_group);
+    publish_invalidation(rdb, RATE_LIMIT_CACHE, &cache_key, &rate_limit_key(&cache_key)).await;
+    Ok(())
+}
+
+// GCRA: в ключе хранится теоретическое время следующего запроса (TAT), мкс по часам Redis.
+// возвращает 0, если запрос разрешен, иначе через сколько мкс повторить
+static GCRA: Lazy<redis::Script> = Lazy::new(|| redis::Script::new(r"
+local emission = tonumber(ARGV[1])
+local period = tonumber(ARGV[2])
+local time = redis.call('TIME')
+local now = tonumber(time[1]) * 1000000 + tonumber(time[2])
+local tat = tonumber(redis.call('GET', KEYS[1]) or now)
+if tat < now then
+    tat = now
+end
+local new_tat = tat + emission
+if new_tat - now > period then
+    return new_tat - now - period
+end
+redis.call('SET', KEYS[1], new_tat, 'PX', math.ceil((new_tat - now) / 1000))
+return 0
+"));
+
+pub async fn acquire_rate_limit(rdb: &deadpool_redis::Pool, subject_id: &str, route_group: &str, limit: RateLimit)
+    -> Result<i64, LibError>
+{
+    let mut conn = map_err_with_log!(rdb.get().await, "Error get redis connection", InternalError, subject_id)?;
+    let key = rate_limit_state_key(subject_id, route_group);
+    let retry_after_us: i64 = map_err_with_log!(GCRA.key(key.as_str())
+        .arg(limit.emission_us())
+        .arg(limit.period_us())
+        .invoke_async(&mut conn).await,
+        "Error acquire rate limit", InternalError, key)?;
+    Ok(retry_after_us)
+}
@@ -0,0 +1,67 @@
+use chrono::NaiveDateTime;
+use rust_decimal::Decimal;
+use tokio_postgres::types::Type;
+use tracing::error;
+use crate::errors::LibError;
+use crate::errors::LibError::{Conflict, InternalError};
+use crate::map_err_with_log;
+use crate::models::payments::refund::{Refund, RefundStatus};
+
+pub async fn get_refund_by_key(tx: &tokio_postgres::Transaction<'_>, payment_id: &str, idempotent_key: &str)
+    -> Result<Option<Refund>, LibError>
+{
+    let rows = map_err_with_log!(tx.query_typed("SELECT * FROM refunds WHERE payment_id=$1 AND idempotent_key=$2",
+        &[(&payment_id, Type::VARCHAR), (&idempotent_key, Type::VARCHAR)]).await,
+        "Error get refund by key", InternalError, payment_id, idempotent_key)?;
+    Ok(rows.first().map(Refund::from))
+}
+
+pub async fn get_payment_refunds(client: &tokio_postgres::Client, payment_id: &str) -> Result<Vec<Refund>, LibError> {
+    let rows = map_err_with_log!(client.query_typed("SELECT * FROM refunds WHERE payment_id=$1 ORDER BY created_at",
+        &[(&payment_id, Type::VARCHAR)]).await,
+        "Error get payment refunds", InternalError, payment_id)?;
+    Ok(rows.iter().map(Refund::from).collect())
+}
+
+pub async fn insert_refund(tx: &tokio_postgres::Transaction<'_>, refund: &Refund) -> Result<(), LibError> {
+    let refund_id = refund.id.as_str();
+    let kind = refund.kind.to_string();
+    let status = refund.status.to_string();
+    let rows = map_err_with_log!(tx.query_typed(
+        "INSERT INTO refunds (id, payment_id, merchant_id, trader_id, kind, status, fiat_amount, crypto_amount,
+        trader_crypto_amount, reason, initiator_id, idempotent_key, created_at)
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13) ON CONFLICT DO NOTHING RETURNING id",
+        &[(&refund.id, Type::VARCHAR), (&refund.payment_id, Type::VARCHAR), (&refund.merchant_id, Type::VARCHAR),
+            (&refund.trader_id, Type::VARCHAR), (&kind, Type::VARCHAR), (&status, Type::VARCHAR),
+            (&refund.fiat_amount, Type::NUMERIC), (&refund.crypto_amount, Type::NUMERIC),
+            (&refund.trader_crypto_amount, Type::NUMERIC), (&refund.reason, Type::TEXT),
+            (&refund.initiator_id, Type::VARCHAR), (&refund.idempotent_key, Type::VARCHAR),
+            (&refund.created_at, Type::TIMESTAMP)]).await,
+        "Error insert refund", InternalError, refund_id)?;
+    if rows.is_empty() {
+        return Err(Conflict);
+    }
+    Ok(())
+}
+
+// резервирует сумму возврата на платеже, delta < 0 снимает резерв
+pub async fn add_refunded_amount(tx: &tokio_postgres::Transaction<'_>, payment_id: &str, delta: Decimal,
+                                 now: NaiveDateTime) -> Result<(), LibError> {
+    map_err_with_log!(tx.query_typed(
+        "UPDATE payments SET refunded_fiat_amount=COALESCE(refunded_fiat_amount, 0) + $1, updated_at=$2 WHERE id=$3",
+        &[(&delta, Type::NUMERIC), (&now, Type::TIMESTAMP), (&payment_id, Type::VARCHAR)]).await,
+        "Error update refunded amount", InternalError, payment_id)?;
+    Ok(())
+}
+
+pub async fn set_refund_status(tx: &tokio_postgres::Transaction<'_>, refund_id: &str, status: RefundStatus,
+                               now: NaiveDateTime) -> Result<bool, LibError> {
+    let status = status.to_string();
+    let rows = tx.query_typed(
+        "UPDATE refunds SET status=$1, completed_at=$2 WHERE id=$3 AND status='PENDING' RETURNING id",
+        &[(&status, Type::VARCHAR), (&now, Type::TIMESTAMP), (&refund_id, Type::VARCHAR)]).await.map_err(|e| {
+        error!(refund_id=refund_id, status=status, err=e.to_string(), "Error set refund status");
+        InternalError
+    })?;
+    Ok(!rows.is_empty())
+}
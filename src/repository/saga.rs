@@ -0,0 +1,66 @@
+use tokio_postgres::types::Type;
+use tracing::error;
+use crate::errors::LibError;
+use crate::errors::LibError::InternalError;
+use crate::map_err_with_log;
+use crate::models::saga::SagaState;
+
+pub async fn insert_saga(client: &tokio_postgres::Client, saga: &SagaState) -> Result<(), LibError> {
+    let saga_id = saga.id.as_str();
+    let status = saga.status.to_string();
+    let completed_steps = saga.completed_steps as i32;
+    map_err_with_log!(client.query_typed(
+        "INSERT INTO payment_sagas (id, kind, reference_id, status, completed_steps, in_flight, context, error, created_at, updated_at)
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10)",
+        &[(&saga.id, Type::VARCHAR), (&saga.kind, Type::VARCHAR), (&saga.reference_id, Type::VARCHAR),
+            (&status, Type::VARCHAR), (&completed_steps, Type::INT4), (&saga.in_flight, Type::BOOL),
+            (&saga.context, Type::TEXT), (&saga.error, Type::TEXT), (&saga.created_at, Type::TIMESTAMP),
+            (&saga.updated_at, Type::TIMESTAMP)]).await,
+        "Error insert saga", InternalError, saga_id)?;
+    Ok(())
+}
+
+pub async fn update_saga(client: &tokio_postgres::Client, saga: &SagaState) -> Result<(), LibError> {
+    let saga_id = saga.id.as_str();
+    let status = saga.status.to_string();
+    let completed_steps = saga.completed_steps as i32;
+    let rows = map_err_with_log!(client.query_typed(
+        "UPDATE payment_sagas SET status=$1, completed_steps=$2, in_flight=$3, context=$4, error=$5,
+        updated_at=$6, locked_until=NULL WHERE id=$7 RETURNING id",
+        &[(&status, Type::VARCHAR), (&completed_steps, Type::INT4), (&saga.in_flight, Type::BOOL),
+            (&saga.context, Type::TEXT), (&saga.error, Type::TEXT), (&saga.updated_at, Type::TIMESTAMP),
+            (&saga.id, Type::VARCHAR)]).await,
+        "Error update saga", InternalError, saga_id)?;
+    if rows.is_empty() {
+        return Err(LibError::NotFound);
+    }
+    Ok(())
+}
+
+pub async fn get_saga(client: &tokio_postgres::Client, saga_id: &str) -> Result<SagaState, LibError> {
+    let rows = map_err_with_log!(client.query_typed("SELECT * FROM payment_sagas WHERE id=$1",
+        &[(&saga_id, Type::VARCHAR)]).await,
+        "Error get saga", InternalError, saga_id)?;
+    Ok(SagaState::from(rows.first().ok_or(LibError::NotFound)?))
+}
+
+// забирает незавершенные саги, которые не обновлялись stale_secs секунд,
+// и блокирует их на lock_secs, чтобы другие реплики их не трогали
+pub async fn claim_stale_sagas(client: &tokio_postgres::Client, stale_secs: i64, lock_secs: i64, limit: i64)
+    -> Result<Vec<SagaState>, LibError>
+{
+    let rows = client.query_typed(
+        "UPDATE payment_sagas SET locked_until = NOW() + make_interval(secs => $2)
+        WHERE id IN (
+            SELECT id FROM payment_sagas
+            WHERE status IN ('running', 'compensating')
+            AND updated_at < NOW() - make_interval(secs => $1)
+            AND (locked_until IS NULL OR locked_until < NOW())
+            ORDER BY updated_at LIMIT $3 FOR UPDATE SKIP LOCKED
+        ) RETURNING *",
+        &[(&(stale_secs as f64), Type::FLOAT8), (&(lock_secs as f64), Type::FLOAT8), (&limit, Type::INT8)]).await.map_err(|e| {
+        error!(err=e.to_string(), "Error claim stale sagas");
+        InternalError
+    })?;
+    Ok(rows.iter().map(SagaState::from).collect())
+}
@@ -0,0 +1,96 @@
+use chrono::{NaiveDate, NaiveDateTime};
+use rust_decimal::Decimal;
+use tokio_postgres::types::Type;
+use tracing::error;
+use crate::errors::LibError;
+use crate::errors::LibError::InternalError;
+use crate::map_err_with_log;
+use crate::models::settlement::{SettlementMovement, StoredSettlementReport};
+
+// main + frozen мерчанта по проводкам до момента at, проводки только добавляются.
+// это баланс, только если начальный остаток посеян (repository::ledger::has_opening_balance)
+pub async fn get_merchant_balance_at(client: &tokio_postgres::Client, merchant_id: &str, at: NaiveDateTime)
+    -> Result<Decimal, LibError>
+{
+    let rows = map_err_with_log!(client.query_typed(
+        "SELECT COALESCE(SUM(lp.amount), 0) AS balance FROM ledger_postings lp
+        JOIN ledger_transactions lt ON lt.id=lp.transaction_id
+        WHERE lp.owner_type='merchant' AND lp.owner_id=$1 AND lp.account IN ('main', 'frozen') AND lt.created_at<$2",
+        &[(&merchant_id, Type::VARCHAR), (&at, Type::TIMESTAMP)]).await,
+        "Error get merchant balance", InternalError, merchant_id)?;
+    Ok(rows.first().map(|row| row.get("balance")).unwrap_or_default())
+}
+
+// движения мерчанта по платежам и возвратам за [from, to) в разрезе валюты платежа
+// reference_id проводки - id платежа или возврата (use_case::ledger), проводки выплат ссылаются
+// на батч, в join не попадают и учитываются в other_movements.
+// комиссия берется из платежа: в проводки мерчанта идет сумма после комиссии. платеж дает одно
+// зачисление (BUY) или одно списание из frozen (SELL), возвраты комиссию не несут
+pub async fn get_merchant_movements(client: &tokio_postgres::Client, merchant_id: &str, from: NaiveDateTime,
+                                    to: NaiveDateTime) -> Result<Vec<SettlementMovement>, LibError> {
+    let rows = map_err_with_log!(client.query_typed(
+        "SELECT p.currency, lt.action_type, COUNT(*) AS movements_count, SUM(lt.amount) AS amount,
+        SUM(CASE WHEN r.id IS NULL AND lt.action_type IN ('DEPOSIT', 'WITHDRAW_FROZEN') THEN p.crypto_fee ELSE 0 END) AS fee,
+        SUM(COALESCE(r.fiat_amount, p.fiat_amount)) AS fiat_amount
+        FROM ledger_transactions lt
+        LEFT JOIN refunds r ON r.id=lt.reference_id
+        JOIN payments p ON p.id=COALESCE(r.payment_id, lt.reference_id)
+        WHERE lt.owner_type='merchant' AND lt.owner_id=$1 AND lt.created_at>=$2 AND lt.created_at<$3
+        AND lt.action_type IN ('DEPOSIT', 'WITHDRAW_FROZEN', 'WITHDRAW_MAIN')
+        GROUP BY p.currency, lt.action_type ORDER BY p.currency, lt.action_type",
+        &[(&merchant_id, Type::VARCHAR), (&from, Type::TIMESTAMP), (&to, Type::TIMESTAMP)]).await,
+        "Error get merchant movements", InternalError, merchant_id)?;
+    Ok(rows.iter().map(SettlementMovement::from).collect())
+}
+
+// мерчанты с проводками за период, для ежедневной генерации
+pub async fn get_active_merchants(client: &tokio_postgres::Client, from: NaiveDateTime, to: NaiveDateTime)
+    -> Result<Vec<String>, LibError>
+{
+    let rows = client.query_typed(
+        "SELECT DISTINCT owner_id FROM ledger_transactions
+        WHERE owner_type='merchant' AND created_at>=$1 AND created_at<$2 ORDER BY owner_id",
+        &[(&from, Type::TIMESTAMP), (&to, Type::TIMESTAMP)]).await.map_err(|e| {
+        error!(err=e.to_string(), "Error get active merchants");
+        InternalError
+    })?;
+    Ok(rows.iter().map(|row| row.get(0)).collect())
+}
+
+pub async fn get_settlement_report(client: &tokio_postgres::Client, merchant_id: &str, report_date: NaiveDate,
+                                   version: i32) -> Result<Option<StoredSettlementReport>, LibError> {
+    let rows = map_err_with_log!(client.query_typed(
+        "SELECT * FROM settlement_reports WHERE merchant_id=$1 AND report_date=$2 AND version=$3",
+        &[(&merchant_id, Type::VARCHAR), (&report_date, Type::DATE), (&version, Type::INT4)]).await,
+        "Error get settlement report", InternalError, merchant_id)?;
+    Ok(rows.first().map(StoredSettlementReport::from))
+}
+
+pub async fn get_settlement_reports(client: &tokio_postgres::Client, merchant_id: &str, from: NaiveDate, to: NaiveDate)
+    -> Result<Vec<StoredSettlementReport>, LibError>
+{
+    let rows = map_err_with_log!(client.query_typed(
+        "SELECT * FROM settlement_reports WHERE merchant_id=$1 AND report_date>=$2 AND report_date<=$3
+        ORDER BY report_date, version",
+        &[(&merchant_id, Type::VARCHAR), (&from, Type::DATE), (&to, Type::DATE)]).await,
+        "Error get settlement reports", InternalError, merchant_id)?;
+    Ok(rows.iter().map(StoredSettlementReport::from).collect())
+}
+
+// false если отчет за этот день и версию уже сохранен
+pub async fn insert_settlement_report(client: &tokio_postgres::Client, report: &StoredSettlementReport)
+    -> Result<bool, LibError>
+{
+    let merchant_id = report.merchant_id.as_str();
+    let rows = map_err_with_log!(client.query_typed(
+        "INSERT INTO settlement_reports (id, merchant_id, report_date, version, report_json, report_csv, sha256,
+        json_signature, csv_signature, key_id, created_at)
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11) ON CONFLICT DO NOTHING RETURNING id",
+        &[(&report.id, Type::VARCHAR), (&report.merchant_id, Type::VARCHAR), (&report.report_date, Type::DATE),
+            (&report.version, Type::INT4), (&report.report_json, Type::TEXT), (&report.report_csv, Type::TEXT),
+            (&report.sha256, Type::VARCHAR), (&report.json_signature, Type::TEXT),
+            (&report.csv_signature, Type::TEXT), (&report.key_id, Type::VARCHAR),
+            (&report.created_at, Type::TIMESTAMP)]).await,
+        "Error insert settlement report", InternalError, merchant_id)?;
+    Ok(!rows.is_empty())
+}
@@ -1,20 +1,25 @@
 
-use redis::AsyncCommands;
+use std::time::Duration;
+use tokio_postgres::GenericClient;
 use tokio_postgres::types::Type;
 use tracing::error;
 use crate::errors::LibError;
 use crate::errors::LibError::{InternalError, TraderNotFound};
+use crate::repository::cache::{publish_value, read_value, write_value};
 
-const TTL_HOUR: usize = 60 * 60;
+pub const TRADER_IS_BLOCKED_CACHE: &str = "trader_is_blocked";
+pub const TRADER_IS_BLOCKED_TTL: Duration = Duration::from_secs(5 * 60);
+
+pub fn trader_is_blocked_key(trader_id: &str) -> String {
+    format!("trader:{}:is_blocked", trader_id)
+}
+
+#[deprecated(note = "use use_case::trader::check_trader_is_blocked")]
 pub async fn check_trader_is_blocked_from_redis(conn: &mut redis::aio::MultiplexedConnection, trader_id : &str) -> Result<Option<bool>, LibError> {
-    let key = format!("trader:{}:is_blocked", trader_id);
-    match conn.get::<_, Option<String>>(key).await.map_err(|e| {
+    read_value(conn, &trader_is_blocked_key(trader_id)).await.map_err(|e| {
         error!("Error getting trader: {}", e);
         InternalError
-    })? {
-        Some(value) => Ok(Some(value == "1")),
-        None => Ok(None)
-    }
+    })
 }
 
 pub async fn check_trader_is_blocked_from_db(client : &tokio_postgres::Client, trader_id : &str) -> Result<bool, LibError> {
@@ -28,30 +33,44 @@ pub async fn check_trader_is_blocked_from_db(client : &tokio_postgres::Client, t
     Ok(row.first().ok_or(TraderNotFound)?.get(0))
 }
 
-
+#[deprecated(note = "use set_trader_is_blocked, it also notifies other services")]
 pub async fn set_trader_is_blocked_to_redis(
     conn: &mut redis::aio::MultiplexedConnection,
     trader_id: String,
     is_blocked: bool,
 ) -> Result<(), LibError> {
-    let key = format!("trader:{}:is_blocked", trader_id);
-
-    redis::pipe()
-        .atomic()
-        .cmd("SET")
-        .arg(&key)
-        .arg(if is_blocked { "1" } else { "0" })
-        .cmd("EXPIRE")
-        .arg(&key)
-        .arg(TTL_HOUR)
-        .exec_async(conn)
-        .await
-        .map_err(|e| {
-            error!("Error setting trader is_blocked to redis: {}", e);
-            InternalError
-        })?;
+    write_value(conn, &trader_is_blocked_key(&trader_id), &is_blocked, TRADER_IS_BLOCKED_TTL).await.map_err(|e| {
+        error!("Error setting trader is_blocked to redis: {}", e);
+        InternalError
+    })
+}
 
+pub async fn update_trader_is_blocked(client: &(impl GenericClient + Sync), trader_id: &str, is_blocked: bool)
+    -> Result<(), LibError>
+{
+    let rows = client.query_typed(
+        "UPDATE traders SET is_blocked=$1 WHERE id=$2 RETURNING id",
+        &[(&is_blocked, Type::BOOL), (&trader_id, Type::VARCHAR)]
+    ).await.map_err(|e| {
+        error!(trader_id=trader_id, err=e.to_string(), "Error set trader is blocked");
+        InternalError
+    })?;
+    if rows.is_empty() {
+        return Err(TraderNotFound);
+    }
     Ok(())
 }
 
+// значение уже в БД: пишется в Redis и рассылается, чтобы остальные сервисы сбросили L1
+pub async fn publish_trader_is_blocked(rdb: &deadpool_redis::Pool, trader_id: &str, is_blocked: bool) {
+    publish_value(rdb, TRADER_IS_BLOCKED_CACHE, trader_id, &trader_is_blocked_key(trader_id), &is_blocked,
+                  TRADER_IS_BLOCKED_TTL).await;
+}
 
+// флаг меняется в БД, кэши во всех сервисах обновляются через pub/sub
+pub async fn set_trader_is_blocked(client: &tokio_postgres::Client, rdb: &deadpool_redis::Pool, trader_id: &str,
+                                   is_blocked: bool) -> Result<(), LibError> {
+    update_trader_is_blocked(client, trader_id, is_blocked).await?;
+    publish_trader_is_blocked(rdb, trader_id, is_blocked).await;
+    Ok(())
+}
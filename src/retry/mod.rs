@@ -0,0 +1,289 @@
+use std::fmt::{Display, Formatter};
+use std::time::{Duration, Instant};
+use rand::Rng;
+use tokio::time::error::Elapsed;
+use tonic::Code;
+
+// причина ошибки с точки зрения повтора
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorKind {
+    // соединение не установлено или оборвалось
+    Connection,
+    // не дождались ответа
+    Timeout,
+    // конфликт, который проходит при повторе: deadlock, serialization failure, Aborted
+    Transient,
+    // сервис перегружен
+    Overloaded,
+    // повтор даст тот же результат
+    Permanent,
+}
+
+impl ErrorKind {
+    pub fn is_retryable(self) -> bool {
+        self != ErrorKind::Permanent
+    }
+
+    // только ошибки, при которых запрос точно не был выполнен
+    pub fn is_connection(self) -> bool {
+        self == ErrorKind::Connection
+    }
+}
+
+pub trait Classify {
+    fn error_kind(&self) -> ErrorKind;
+}
+
+impl Classify for tokio_postgres::Error {
+    fn error_kind(&self) -> ErrorKind {
+        if self.is_closed() {
+            return ErrorKind::Connection;
+        }
+        if let Some(db) = self.as_db_error() {
+            let code = db.code().code();
+            return match code {
+                "40001" | "40P01" => ErrorKind::Transient,
+                "57014" => ErrorKind::Timeout,
+                "57P03" => ErrorKind::Overloaded,
+                _ if code.starts_with("08") => ErrorKind::Connection,
+                _ if code.starts_with("53") => ErrorKind::Overloaded,
+                _ => ErrorKind::Permanent,
+            };
+        }
+        match std::error::Error::source(self) {
+            Some(source) if source.is::<std::io::Error>() => ErrorKind::Connection,
+            _ => ErrorKind::Permanent,
+        }
+    }
+}
+
+impl Classify for deadpool_postgres::PoolError {
+    fn error_kind(&self) -> ErrorKind {
+        match self {
+            deadpool_postgres::PoolError::Timeout(_) => ErrorKind::Timeout,
+            deadpool_postgres::PoolError::Backend(e) => e.error_kind(),
+            _ => ErrorKind::Permanent,
+        }
+    }
+}
+
+impl Classify for tonic::Status {
+    fn error_kind(&self) -> ErrorKind {
+        // повтор до Retry-After снова упрется в лимит
+        if crate::errors::grpc::is_rate_limited(self) {
+            return ErrorKind::Permanent;
+        }
+        match self.code() {
+            Code::Unavailable => ErrorKind::Connection,
+            Code::DeadlineExceeded => ErrorKind::Timeout,
+            Code::ResourceExhausted => ErrorKind::Overloaded,
+            Code::Aborted => ErrorKind::Transient,
+            _ => ErrorKind::Permanent,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum RetryError<E> {
+    Failed(E),
+    // попытка не уложилась в attempt_timeout
+    TimedOut,
+}
+
+impl<E: Display> Display for RetryError<E> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RetryError::Failed(e) => e.fmt(f),
+            RetryError::TimedOut => f.write_str("attempt timed out"),
+        }
+    }
+}
+
+impl<E: std::error::Error + 'static> std::error::Error for RetryError<E> {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            RetryError::Failed(e) => Some(e),
+            RetryError::TimedOut => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct RetryPolicy {
+    // всего попыток, включая первую
+    pub max_attempts: u32,
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+    pub multiplier: f64,
+    // доля паузы, которая выбирается случайно: 0 - без jitter, 1 - от нуля до полной паузы
+    pub jitter: f64,
+    // таймаут одной попытки, по умолчанию 300ms как у прежнего retry!
+    pub attempt_timeout: Option<Duration>,
+    // общий срок вызова вместе с паузами, после него новых попыток нет
+    pub deadline: Option<Duration>,
+    pub retry_on: fn(ErrorKind) -> bool,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 3,
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(2),
+            multiplier: 2.0,
+            jitter: 0.5,
+            attempt_timeout: Some(Duration::from_millis(300)),
+            deadline: None,
+            retry_on: ErrorKind::is_retryable,
+        }
+    }
+}
+
+impl RetryPolicy {
+    pub fn with_max_attempts(mut self, max_attempts: u32) -> Self {
+        self.max_attempts = max_attempts.max(1);
+        self
+    }
+
+    pub fn with_backoff(mut self, initial_backoff: Duration, max_backoff: Duration, multiplier: f64) -> Self {
+        self.initial_backoff = initial_backoff;
+        self.max_backoff = max_backoff;
+        self.multiplier = multiplier;
+        self
+    }
+
+    pub fn with_jitter(mut self, jitter: f64) -> Self {
+        self.jitter = jitter.clamp(0.0, 1.0);
+        self
+    }
+
+    pub fn with_attempt_timeout(mut self, attempt_timeout: Duration) -> Self {
+        self.attempt_timeout = Some(attempt_timeout);
+        self
+    }
+
+    // для вызовов, время которых ограничено снаружи, например таймаутом gRPC-канала
+    pub fn without_attempt_timeout(mut self) -> Self {
+        self.attempt_timeout = None;
+        self
+    }
+
+    pub fn with_deadline(mut self, deadline: Duration) -> Self {
+        self.deadline = Some(deadline);
+        self
+    }
+
+    pub fn with_retry_on(mut self, retry_on: fn(ErrorKind) -> bool) -> Self {
+        self.retry_on = retry_on;
+        self
+    }
+
+    // пауза после попытки attempt (с 1) без jitter
+    pub fn backoff(&self, attempt: u32) -> Duration {
+        let factor = self.multiplier.max(1.0).powi(attempt.saturating_sub(1).min(32) as i32);
+        self.initial_backoff.mul_f64(factor).min(self.max_backoff)
+    }
+
+    // None - больше не повторять
+    pub fn next_delay(&self, attempt: u32, kind: ErrorKind, started: Instant) -> Option<Duration> {
+        if attempt >= self.max_attempts || !(self.retry_on)(kind) {
+            return None;
+        }
+        let backoff = self.backoff(attempt);
+        let delay = backoff.mul_f64(1.0 - self.jitter * rand::thread_rng().gen_range(0.0..1.0));
+        match self.deadline {
+            Some(deadline) if started.elapsed() + delay >= deadline => None,
+            _ => Some(delay),
+        }
+    }
+
+    pub async fn timed<F: Future>(&self, future: F) -> Result<F::Output, Elapsed> {
+        match self.attempt_timeout {
+            Some(timeout) => tokio::time::timeout(timeout, future).await,
+            None => Ok(future.await),
+        }
+    }
+
+    // для операций, которые сами берут ресурсы на каждую попытку. вызовы через &mut клиента
+    // повторяются макросами retry! и retry_grpc!
+    pub async fn run<T, E, F, Fut>(&self, mut op: F) -> Result<T, RetryError<E>>
+    where
+        E: Classify,
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<T, E>>,
+    {
+        let started = Instant::now();
+        let mut attempt = 0;
+        loop {
+            attempt += 1;
+            let (kind, err) = match self.timed(op()).await {
+                Ok(Ok(result)) => return Ok(result),
+                Ok(Err(e)) => (e.error_kind(), RetryError::Failed(e)),
+                Err(_) => (ErrorKind::Timeout, RetryError::TimedOut),
+            };
+            match self.next_delay(attempt, kind, started) {
+                Some(delay) => tokio::time::sleep(delay).await,
+                None => return Err(err),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicU32, Ordering};
+    use super::*;
+
+    #[tokio::test]
+    async fn retries_by_error_kind() {
+        let policy = RetryPolicy::default()
+            .with_backoff(Duration::from_millis(1), Duration::from_millis(4), 2.0)
+            .with_max_attempts(4);
+        assert_eq!(policy.backoff(1), Duration::from_millis(1));
+        assert_eq!(policy.backoff(3), Duration::from_millis(4));
+        assert_eq!(policy.backoff(10), Duration::from_millis(4));
+        assert_eq!(policy.attempt_timeout, Some(Duration::from_millis(300)));
+
+        let calls = AtomicU32::new(0);
+        let result = policy.run(|| async {
+            calls.fetch_add(1, Ordering::SeqCst);
+            Err::<(), _>(tonic::Status::unavailable("down"))
+        }).await;
+        assert!(matches!(result, Err(RetryError::Failed(ref s)) if s.code() == Code::Unavailable));
+        assert_eq!(calls.load(Ordering::SeqCst), 4);
+
+        calls.store(0, Ordering::SeqCst);
+        let result = policy.run(|| async {
+            calls.fetch_add(1, Ordering::SeqCst);
+            Err::<(), _>(tonic::Status::invalid_argument("insufficient funds"))
+        }).await;
+        assert!(result.is_err());
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+
+        calls.store(0, Ordering::SeqCst);
+        let result = policy.run(|| async {
+            calls.fetch_add(1, Ordering::SeqCst);
+            Err::<(), _>(tonic::Status::from(crate::errors::LibError::TooManyRequests(1)))
+        }).await;
+        assert!(result.is_err());
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+
+        let result = policy.with_attempt_timeout(Duration::from_millis(5)).with_max_attempts(2)
+            .run(|| async {
+                tokio::time::sleep(Duration::from_secs(1)).await;
+                Ok::<(), tonic::Status>(())
+            }).await;
+        assert!(matches!(result, Err(RetryError::TimedOut)));
+    }
+
+    #[tokio::test]
+    async fn retry_macro_accepts_attempt_count() {
+        let calls = AtomicU32::new(0);
+        let result: Result<(), String> = crate::retry!(async {
+            calls.fetch_add(1, Ordering::SeqCst);
+            Err::<(), _>(tonic::Status::unavailable("down"))
+        }, 2);
+        assert!(result.is_err());
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+    }
+}
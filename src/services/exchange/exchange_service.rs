@@ -1,30 +1,27 @@
-use std::str::FromStr;
-use std::time::Duration;
+use std::sync::Arc;
 use rust_decimal::Decimal;
 use rust_decimal::prelude::FromPrimitive;
-use tokio::time::sleep;
-use tonic::Request;
-use tonic::transport::Endpoint;
-use tracing::{error, warn};
 use crate::{exchange_proto, retry_grpc};
 use crate::errors::LibError;
 use crate::errors::LibError::InternalError;
-use crate::services::{connect_to_grpc_server, need_retry, status_to_err};
+use crate::services::resilience::{endpoint_guard, EndpointConfig, EndpointGuard};
+use crate::services::{connect_to_grpc_server, status_to_err};
 
 #[derive(Clone)]
 pub struct ExchangeService {
     client: exchange_proto::exchange_service_client::ExchangeServiceClient<tonic::transport::Channel>,
+    guard: Arc<EndpointGuard>,
 }
 
 impl ExchangeService {
     pub fn new(addr : String) -> Self {
         let channel = connect_to_grpc_server(addr.as_str());
         let client = exchange_proto::exchange_service_client::ExchangeServiceClient::new(channel);
-        Self { client }
+        Self { client, guard: endpoint_guard("exchange", &addr, EndpointConfig::default()) }
     }
 
     pub async fn get_exchange_rate(&mut self) -> Result<Decimal, LibError> {
-        match retry_grpc!(self.client.get_exchange_rate(()), 3) {
+        match retry_grpc!(self.guard, self.client.get_exchange_rate(())) {
             Ok(result) => Ok(Decimal::from_f64(result.get_ref().rate).ok_or(InternalError)?),
             Err(e) => Err(status_to_err(e))
         }
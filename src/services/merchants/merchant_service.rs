@@ -1,13 +1,13 @@
-use std::time::Duration;
+use std::sync::Arc;
 use tonic::Request;
-use tracing::{debug, error, warn};
+use tracing::debug;
 use uuid::Uuid;
 use crate::errors::LibError;
-use crate::errors::LibError::InternalError;
 use crate::{merchant_proto, retry_grpc};
-use crate::services::{connect_to_grpc_server, need_retry, status_to_err};
+use crate::services::resilience::{endpoint_guard, EndpointConfig, EndpointGuard};
+use crate::services::{connect_to_grpc_server, status_to_err};
 
-pub(crate) const RETRY_COUNT: usize = 5;
+pub(crate) const RETRY_COUNT: u32 = 5;
 
 pub const MERCHANT_CHANGE_BALANCE_TOPIC: &'static str = "merchant_change_balance";
 
@@ -15,24 +15,31 @@ pub const MERCHANT_CHANGE_BALANCE_TOPIC: &'static str = "merchant_change_balance
 #[derive(Clone)]
 pub struct MerchantService {
     client: merchant_proto::merchant_service_client::MerchantServiceClient<tonic::transport::Channel>,
+    guard: Arc<EndpointGuard>,
 }
 impl MerchantService {
     pub fn new(addr : String) -> Self {
         let channel = connect_to_grpc_server(addr.as_str());
         let client = merchant_proto::merchant_service_client::MerchantServiceClient::new(channel);
-        Self { client }
+        Self { client, guard: endpoint_guard("merchant", &addr, EndpointConfig::with_retries(RETRY_COUNT)) }
     }
 
     pub async fn change_balance(&mut self, merchant_id: String, amount: f64, action_type: merchant_proto::BalanceActionType) -> Result<(), LibError> {
-        debug!(mechant_id = %merchant_id, action_type = action_type.as_str_name(), "[GRPC] send merchant change_balance");
         let idempotent_key = Uuid::now_v7().to_string();
+        self.change_balance_with_key(merchant_id, amount, action_type, idempotent_key).await
+    }
+
+    // для повторяемых операций, ключ должен быть одинаковым при каждом повторе
+    pub async fn change_balance_with_key(&mut self, merchant_id: String, amount: f64, action_type: merchant_proto::BalanceActionType,
+                                         idempotent_key: String) -> Result<(), LibError> {
+        debug!(mechant_id = %merchant_id, action_type = action_type.as_str_name(), "[GRPC] send merchant change_balance");
         let request = merchant_proto::ChangeBalanceRequest {
             merchant_id: merchant_id.clone(),
             amount,
             action_type: action_type as i32,
             idempotent_key,
         };
-        match retry_grpc!(self.client.change_balance(Request::new(request.clone())), 3) {
+        match retry_grpc!(self.guard, self.client.change_balance(Request::new(request.clone()))) {
             Ok(result) => Ok(result.into_inner()),
             Err(e) => Err(status_to_err(e))
         }
@@ -45,7 +52,7 @@ impl MerchantService {
            payment_method_id: payment_method_id.clone(),
         };
 
-        match retry_grpc!(self.client.get_payment_method(Request::new(request.clone())), 3) {
+        match retry_grpc!(self.guard, self.client.get_payment_method(Request::new(request.clone()))) {
             Ok(result) => Ok(result.into_inner()),
             Err(e) => Err(status_to_err(e))
         }
@@ -56,7 +63,7 @@ impl MerchantService {
         let request = merchant_proto::GetPmListReq{
             merchant_id: merchant_id.clone(),
         };
-        match retry_grpc!(self.client.get_pm_list(Request::new(request.clone())), 3) {
+        match retry_grpc!(self.guard, self.client.get_pm_list(Request::new(request.clone()))) {
             Ok(result) => Ok(result.into_inner()),
             Err(e) => Err(status_to_err(e))
         }
@@ -68,7 +75,7 @@ impl MerchantService {
         let request = merchant_proto::GetWebhookUrlRequest{
             merchant_id: merchant_id.clone(),
         };
-        match retry_grpc!(self.client.get_webhook_url(Request::new(request.clone())), 3) {
+        match retry_grpc!(self.guard, self.client.get_webhook_url(Request::new(request.clone()))) {
             Ok(result) => Ok(result.into_inner().webhook_url),
             Err(e) => Err(status_to_err(e))
         }
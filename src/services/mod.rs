@@ -4,6 +4,7 @@ use tonic::{Code, Status};
 use tonic::transport::Endpoint;
 use tracing::error;
 use crate::errors::LibError;
+use crate::errors::grpc::decode_status;
 use crate::errors::LibError::{Conflict, InsufficientFunds, InternalError, InvalidAmount, NoAvailableRequisites, NotFound};
 
 
@@ -14,9 +15,14 @@ pub mod devices;
 pub mod banks;
 pub mod exchange;
 pub mod payments;
+pub mod resilience;
 
 fn status_to_err(status: Status) -> LibError {
     error!("GRPC client_err {}", status.to_string());
+    if let Some(err) = decode_status(&status) {
+        return err;
+    }
+    // сервер без ErrorInfo, разбираем по тексту
     match status.code() {
         Code::Internal =>  InternalError,
         Code::NotFound => match status.message().to_lowercase().as_str() {
@@ -49,38 +55,46 @@ fn connect_to_grpc_server(addr: &str) -> tonic::transport::Channel
         .connect_lazy()
 }
 
-fn need_retry(code: Code) -> bool {
-    match code {
-        Code::DeadlineExceeded => true,
-        Code::ResourceExhausted => true,
-        Code::Aborted => true,
-        Code::Unavailable => true,
-        _ => false,
-    }
-}
+// $guard - EndpointGuard сервиса: место в bulkhead берется на весь вызов,
+// circuit breaker проверяется перед каждой попыткой, повторы по RetryPolicy из настроек сервиса
 #[macro_export]
 macro_rules! retry_grpc {
-    ($request:expr, $max_retries:expr) => {{
+    ($guard:expr, $request:expr) => {{
         let mut response;
-        let mut attempt = 0;
-        loop {
-            attempt += 1;
-            response = $request.await;
-            match &response {
-                Ok(response) => {
-                    break;
-                },
-                &Err(ref e) if need_retry(e.code()) && attempt < $max_retries => {
-                    warn!(err=e.message(),"[GRPC] error get response. Retrying...");
-                    tokio::time::sleep(Duration::from_millis(100)).await;
-                    continue;
-                },
-                &Err(ref e) => {
-                    error!(err=e.message(),"[GRPC] error get response");
-                    break;
+        match $guard.enter().await {
+            Err(rejection) => response = Err($guard.rejected(rejection)),
+            Ok(_permit) => {
+                let policy = $guard.retry_policy();
+                let started = ::std::time::Instant::now();
+                let mut attempt = 0;
+                loop {
+                    attempt += 1;
+                    if let Err(rejection) = $guard.try_call() {
+                        response = Err($guard.rejected(rejection));
+                        break;
+                    }
+                    response = match policy.timed($request).await {
+                        Ok(response) => response,
+                        Err(_) => Err(::tonic::Status::deadline_exceeded("attempt timed out")),
+                    };
+                    $guard.record(&response);
+                    let Err(e) = &response else {
+                        break;
+                    };
+                    let kind = $crate::retry::Classify::error_kind(e);
+                    match policy.next_delay(attempt, kind, started) {
+                        Some(delay) => {
+                            ::tracing::warn!(err=e.message(), kind=?kind, attempt=attempt, "[GRPC] error get response. Retrying...");
+                            ::tokio::time::sleep(delay).await;
+                        }
+                        None => {
+                            ::tracing::error!(err=e.message(), "[GRPC] error get response");
+                            break;
+                        }
+                    }
                 }
             }
         }
         response
     }};
-}
\ No newline at end of file
+}
@@ -1,16 +1,16 @@
-use crate::services::need_retry;
-use tracing::warn;
+use std::sync::Arc;
+use crate::services::resilience::{endpoint_guard, EndpointConfig, EndpointGuard};
 use tonic::transport::Channel;
 use crate::errors::LibError;
-use crate::{models, retry_grpc};
-use std::time::Duration;
-use tracing::error;
+use crate::retry_grpc;
 use crate::models::payments::payment_proto;
 use crate::models::payments::payment_proto::{ByExternalId, ById};
 use crate::services::{connect_to_grpc_server, status_to_err};
 
+#[derive(Clone)]
 pub struct PaymentService {
-    client: payment_proto::payment_service_client::PaymentServiceClient<Channel>
+    client: payment_proto::payment_service_client::PaymentServiceClient<Channel>,
+    guard: Arc<EndpointGuard>,
 }
 
 
@@ -19,7 +19,7 @@ impl PaymentService {
         let channel = connect_to_grpc_server(&addr);
         let client =
             payment_proto::payment_service_client::PaymentServiceClient::new(channel);
-        PaymentService { client }
+        PaymentService { client, guard: endpoint_guard("payment", &addr, EndpointConfig::default()) }
     }
     pub async fn get_payment_by_id(&mut self, payment_id: String, merchant_id: String)
     -> Result<payment_proto::PaymentProto, LibError>
@@ -30,7 +30,7 @@ impl PaymentService {
                 merchant_id,
             }))
         };
-        match retry_grpc!(self.client.get_payment_by_id(request.clone()), 3) {
+        match retry_grpc!(self.guard, self.client.get_payment_by_id(request.clone())) {
             Ok(response) => Ok(response.into_inner()),
             Err(e) => Err(status_to_err(e))
         }
@@ -44,7 +44,7 @@ impl PaymentService {
                 merchant_id,
             }))
         };
-        match retry_grpc!(self.client.get_payment_by_id(req.clone()), 3) {
+        match retry_grpc!(self.guard, self.client.get_payment_by_id(req.clone())) {
             Ok(resp) => Ok(resp.into_inner()),
             Err(e) => Err(status_to_err(e))
         }
@@ -53,7 +53,7 @@ impl PaymentService {
     -> Result<(), LibError>
     {
         let request = payment_proto::ClosePaymentRequest{ payment_id, amount };
-        match retry_grpc!(self.client.close_payment(request.clone()), 3) {
+        match retry_grpc!(self.guard, self.client.close_payment(request.clone())) {
             Ok(response) => Ok(response.into_inner()),
             Err(e) => Err(status_to_err(e))
         }
@@ -1,25 +1,26 @@
+use std::sync::Arc;
+use crate::services::resilience::{endpoint_guard, EndpointConfig, EndpointGuard};
 use crate::services::{connect_to_grpc_server, LibError};
-use std::str::FromStr;
 use deadpool::managed::{Metrics, Object, Pool, RecycleResult};
 use tokio::time::{Instant};
 use tonic::Request;
-use tracing::{error, warn};
+use tracing::warn;
 use crate::{requisites_proto, retry_grpc};
-use crate::services::{need_retry, status_to_err};
-use std::time::Duration;
+use crate::services::{status_to_err};
 
-const RETRY_COUNT: usize = 3;
+const RETRY_COUNT: u32 = 3;
 
 #[derive(Clone)]
 pub struct RequisitesService {
     client: requisites_proto::requisite_service_client::RequisiteServiceClient<tonic::transport::Channel>,
+    guard: Arc<EndpointGuard>,
 }
 
 impl RequisitesService {
     pub fn new(addr: String) -> Self {
         let channel = connect_to_grpc_server(addr.as_str());
         let client = requisites_proto::requisite_service_client::RequisiteServiceClient::new(channel);
-        Self { client }
+        Self { client, guard: endpoint_guard("requisite", &addr, EndpointConfig::with_retries(RETRY_COUNT)) }
     }
 
     pub async fn get_requisites_for_payment(&mut self, method_type: Option<String>, amount: f64, currency: String, bank: Option<String>, cross_border: Option<bool>) -> Result<Vec<requisites_proto::Requisite>, LibError> {
@@ -33,7 +34,7 @@ impl RequisitesService {
         let start = Instant::now();
 
         let res =
-            retry_grpc!(self.client.get_requisites_for_payment(Request::new(request.clone())), 3);
+            retry_grpc!(self.guard, self.client.get_requisites_for_payment(Request::new(request.clone())));
         match res {
             Ok(result) => {
                 if start.elapsed().as_millis() > 50 {
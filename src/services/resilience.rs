@@ -0,0 +1,289 @@
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use once_cell::sync::Lazy;
+use serde::Serialize;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use tonic::{Code, Status};
+use tracing::{info, warn};
+use crate::errors::grpc::is_rate_limited;
+use crate::retry::RetryPolicy;
+
+#[derive(Debug, Clone, Copy)]
+pub struct EndpointConfig {
+    // подряд неудачных вызовов до размыкания
+    pub failure_threshold: u32,
+    // сколько цепь разомкнута до пробных вызовов
+    pub open_timeout: Duration,
+    // пробных вызовов одновременно в half-open и успешных подряд до замыкания
+    pub half_open_calls: u32,
+    // bulkhead: одновременных вызовов к сервису
+    pub max_concurrent: usize,
+    // сколько ждать свободного места в bulkhead
+    pub bulkhead_wait: Duration,
+    // повторы одного вызова, каждая попытка проходит через circuit breaker
+    pub retry: RetryPolicy,
+}
+
+impl Default for EndpointConfig {
+    fn default() -> Self {
+        Self {
+            failure_threshold: 5,
+            open_timeout: Duration::from_secs(10),
+            half_open_calls: 1,
+            max_concurrent: 200,
+            bulkhead_wait: Duration::from_millis(200),
+            // попытку ограничивает таймаут канала
+            retry: RetryPolicy::default().without_attempt_timeout(),
+        }
+    }
+}
+
+impl EndpointConfig {
+    pub fn with_retries(max_attempts: u32) -> Self {
+        Self { retry: RetryPolicy::default().without_attempt_timeout().with_max_attempts(max_attempts), ..Default::default() }
+    }
+}
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum BreakerState {
+    Closed,
+    Open,
+    HalfOpen,
+}
+
+impl Display for BreakerState {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BreakerState::Closed => f.write_str("closed"),
+            BreakerState::Open => f.write_str("open"),
+            BreakerState::HalfOpen => f.write_str("half_open"),
+        }
+    }
+}
+
+// почему вызов не ушел в сервис
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Rejection {
+    BulkheadFull,
+    CircuitOpen,
+    HalfOpenBusy,
+}
+
+// ошибки, говорящие о проблеме с сервисом, а не с запросом. отказ по лимиту клиента (ErrorInfo
+// TOO_MANY_REQUESTS) приходит с тем же ResourceExhausted, но сервис при этом работает
+pub fn is_endpoint_failure(status: &Status) -> bool {
+    matches!(status.code(), Code::Unavailable | Code::DeadlineExceeded | Code::ResourceExhausted | Code::Aborted | Code::Unknown)
+        && !is_rate_limited(status)
+}
+
+#[derive(Debug)]
+struct Breaker {
+    state: BreakerState,
+    consecutive_failures: u32,
+    opened_at: Instant,
+    half_open_in_flight: u32,
+    half_open_successes: u32,
+}
+
+// circuit breaker и bulkhead одного сервиса, общие для всех клиентов из пула
+#[derive(Debug)]
+pub struct EndpointGuard {
+    name: &'static str,
+    addr: String,
+    config: EndpointConfig,
+    breaker: Mutex<Breaker>,
+    bulkhead: Arc<Semaphore>,
+}
+
+impl EndpointGuard {
+    pub fn new(name: &'static str, addr: &str, config: EndpointConfig) -> Self {
+        Self {
+            name,
+            addr: addr.to_string(),
+            config,
+            breaker: Mutex::new(Breaker {
+                state: BreakerState::Closed,
+                consecutive_failures: 0,
+                opened_at: Instant::now(),
+                half_open_in_flight: 0,
+                half_open_successes: 0,
+            }),
+            bulkhead: Arc::new(Semaphore::new(config.max_concurrent)),
+        }
+    }
+
+    // место в bulkhead на весь вызов вместе с повторами
+    pub async fn enter(&self) -> Result<OwnedSemaphorePermit, Rejection> {
+        match tokio::time::timeout(self.config.bulkhead_wait, self.bulkhead.clone().acquire_owned()).await {
+            Ok(Ok(permit)) => Ok(permit),
+            _ => Err(Rejection::BulkheadFull),
+        }
+    }
+
+    pub fn retry_policy(&self) -> RetryPolicy {
+        self.config.retry
+    }
+
+    // ответ вместо вызова. повторять его retry_grpc! не будет
+    pub fn rejected(&self, rejection: Rejection) -> Status {
+        warn!(service=self.name, rejection=?rejection, "[GRPC] request rejected");
+        match rejection {
+            Rejection::BulkheadFull => Status::resource_exhausted(format!("{} bulkhead is full", self.name)),
+            Rejection::CircuitOpen => Status::unavailable(format!("{} circuit breaker is open", self.name)),
+            Rejection::HalfOpenBusy => Status::unavailable(format!("{} circuit breaker is half-open", self.name)),
+        }
+    }
+
+    // перед каждой попыткой. при разомкнутой цепи запрос в сервис не уходит
+    pub fn try_call(&self) -> Result<(), Rejection> {
+        let mut breaker = self.breaker.lock().unwrap();
+        if breaker.state == BreakerState::Open {
+            if breaker.opened_at.elapsed() < self.config.open_timeout {
+                return Err(Rejection::CircuitOpen);
+            }
+            info!(service=self.name, "[GRPC] circuit breaker half-open");
+            breaker.state = BreakerState::HalfOpen;
+            breaker.opened_at = Instant::now();
+            breaker.half_open_in_flight = 0;
+            breaker.half_open_successes = 0;
+        }
+        if breaker.state == BreakerState::HalfOpen {
+            // отмененная пробная попытка не вызывает record, ее место освобождается по таймауту
+            if breaker.opened_at.elapsed() >= self.config.open_timeout {
+                breaker.opened_at = Instant::now();
+                breaker.half_open_in_flight = 0;
+            }
+            if breaker.half_open_in_flight >= self.config.half_open_calls {
+                return Err(Rejection::HalfOpenBusy);
+            }
+            breaker.half_open_in_flight += 1;
+        }
+        Ok(())
+    }
+
+    // после каждой попытки, разрешенной try_call
+    pub fn record<T>(&self, result: &Result<T, Status>) {
+        let failed = matches!(result, Err(status) if is_endpoint_failure(status));
+        let mut breaker = self.breaker.lock().unwrap();
+        match breaker.state {
+            BreakerState::Closed if failed => {
+                breaker.consecutive_failures += 1;
+                if breaker.consecutive_failures >= self.config.failure_threshold {
+                    warn!(service=self.name, failures=breaker.consecutive_failures, "[GRPC] circuit breaker open");
+                    breaker.state = BreakerState::Open;
+                    breaker.opened_at = Instant::now();
+                }
+            }
+            BreakerState::Closed => breaker.consecutive_failures = 0,
+            BreakerState::HalfOpen => {
+                breaker.half_open_in_flight = breaker.half_open_in_flight.saturating_sub(1);
+                if failed {
+                    warn!(service=self.name, "[GRPC] circuit breaker open again");
+                    breaker.state = BreakerState::Open;
+                    breaker.opened_at = Instant::now();
+                } else {
+                    breaker.half_open_successes += 1;
+                    if breaker.half_open_successes >= self.config.half_open_calls {
+                        info!(service=self.name, "[GRPC] circuit breaker closed");
+                        breaker.state = BreakerState::Closed;
+                        breaker.consecutive_failures = 0;
+                    }
+                }
+            }
+            // попытка началась до размыкания другим вызовом
+            BreakerState::Open => (),
+        }
+    }
+
+    pub fn health(&self) -> EndpointHealth {
+        let breaker = self.breaker.lock().unwrap();
+        EndpointHealth {
+            name: self.name,
+            addr: self.addr.clone(),
+            state: breaker.state,
+            consecutive_failures: breaker.consecutive_failures,
+            in_flight: self.config.max_concurrent - self.bulkhead.available_permits(),
+            max_concurrent: self.config.max_concurrent,
+        }
+    }
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct EndpointHealth {
+    pub name: &'static str,
+    pub addr: String,
+    pub state: BreakerState,
+    pub consecutive_failures: u32,
+    pub in_flight: usize,
+    pub max_concurrent: usize,
+}
+
+static ENDPOINT_CONFIGS: Lazy<Mutex<HashMap<&'static str, EndpointConfig>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+// имя сервиса и адрес
+type EndpointKey = (&'static str, String);
+static ENDPOINT_GUARDS: Lazy<Mutex<HashMap<EndpointKey, Arc<EndpointGuard>>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+
+// настройки сервиса, вызывается до создания клиентов
+pub fn configure_endpoint(name: &'static str, config: EndpointConfig) {
+    ENDPOINT_CONFIGS.lock().unwrap().insert(name, config);
+}
+
+// default - настройки клиента, configure_endpoint их переопределяет
+pub fn endpoint_guard(name: &'static str, addr: &str, default: EndpointConfig) -> Arc<EndpointGuard> {
+    let config = ENDPOINT_CONFIGS.lock().unwrap().get(name).copied().unwrap_or(default);
+    ENDPOINT_GUARDS.lock().unwrap()
+        .entry((name, addr.to_string()))
+        .or_insert_with(|| Arc::new(EndpointGuard::new(name, addr, config)))
+        .clone()
+}
+
+// для health check: сервис с разомкнутой цепью недоступен
+pub fn endpoints_health() -> Vec<EndpointHealth> {
+    let mut health: Vec<EndpointHealth> = ENDPOINT_GUARDS.lock().unwrap().values().map(|guard| guard.health()).collect();
+    health.sort_by(|a, b| (a.name, &a.addr).cmp(&(b.name, &b.addr)));
+    health
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::errors::LibError;
+    use super::*;
+
+    #[test]
+    fn breaker_transitions() {
+        let config = EndpointConfig { failure_threshold: 2, open_timeout: Duration::from_millis(20), ..Default::default() };
+        let guard = EndpointGuard::new("test", "http://test", config);
+        let unavailable: Result<(), Status> = Err(Status::unavailable("down"));
+        for _ in 0..2 {
+            guard.try_call().unwrap();
+            guard.record(&unavailable);
+        }
+        assert_eq!(guard.health().state, BreakerState::Open);
+        assert_eq!(guard.try_call(), Err(Rejection::CircuitOpen));
+
+        std::thread::sleep(Duration::from_millis(25));
+        guard.try_call().unwrap();
+        assert_eq!(guard.health().state, BreakerState::HalfOpen);
+        // одна пробная попытка за раз
+        assert_eq!(guard.try_call(), Err(Rejection::HalfOpenBusy));
+        guard.record(&unavailable);
+        assert_eq!(guard.health().state, BreakerState::Open);
+
+        std::thread::sleep(Duration::from_millis(25));
+        guard.try_call().unwrap();
+        // ошибка запроса, а не сервиса
+        guard.record::<()>(&Err(Status::not_found("no available requisites")));
+        assert_eq!(guard.health().state, BreakerState::Closed);
+
+        // лимит клиента на стороне сервиса
+        let rate_limited: Result<(), Status> = Err(Status::from(LibError::TooManyRequests(1)));
+        for _ in 0..3 {
+            guard.try_call().unwrap();
+            guard.record(&rate_limited);
+        }
+        assert_eq!(guard.health().state, BreakerState::Closed);
+    }
+}
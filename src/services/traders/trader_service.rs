@@ -1,18 +1,19 @@
+use std::sync::Arc;
 use std::time::Duration;
 use deadpool::managed::{Metrics, Object, Pool, RecycleResult};
 use prost::Message;
 use rdkafka::producer::{FutureProducer, FutureRecord};
 use tonic::{Request};
-use tonic::transport::{Channel, Endpoint};
-use tracing::{debug, error, info, warn};
+use tonic::transport::Channel;
+use tracing::{debug, error, info};
 use uuid::Uuid;
 use crate::errors::LibError;
-use crate::errors::LibError::InternalError;
 
-use crate::services::{connect_to_grpc_server, need_retry, status_to_err};
+use crate::services::resilience::{endpoint_guard, EndpointConfig, EndpointGuard};
+use crate::services::{connect_to_grpc_server, status_to_err};
 use crate::{retry_grpc, trader_proto};
 
-const RETRY_COUNT: i32 = 3;
+const RETRY_COUNT: u32 = 3;
 pub const TRADER_CHANGE_BALANCE_TOPIC: &'static str = "trader_change_balance";
 
 
@@ -32,6 +33,7 @@ pub async fn send_trader_change_balance_event(producer: FutureProducer, balance_
 #[derive(Clone)]
 pub struct TraderService {
     client: trader_proto::trader_service_client::TraderServiceClient<Channel>,
+    guard: Arc<EndpointGuard>,
 }
 
 
@@ -42,11 +44,17 @@ impl TraderService {
         let channel = connect_to_grpc_server(addr.as_str());
         let client = trader_proto::trader_service_client::TraderServiceClient::new(channel);
         info!("trader service connected");
-        Self { client }
+        Self { client, guard: endpoint_guard("trader", &addr, EndpointConfig::with_retries(RETRY_COUNT)) }
     }
 
     pub async fn change_balance(&mut self, trader_id: String, amount: f64, action_type: trader_proto::BalanceActionType) -> Result<(), LibError> {
         let idempotent_key = Uuid::now_v7().to_string();
+        self.change_balance_with_key(trader_id, amount, action_type, idempotent_key).await
+    }
+
+    // для повторяемых операций, ключ должен быть одинаковым при каждом повторе
+    pub async fn change_balance_with_key(&mut self, trader_id: String, amount: f64, action_type: trader_proto::BalanceActionType,
+                                         idempotent_key: String) -> Result<(), LibError> {
         let req = trader_proto::ChangeBalanceRequest {
             trader_id,
             amount,
@@ -54,7 +62,7 @@ impl TraderService {
             idempotent_key,
         };
 
-        match retry_grpc!(self.client.change_balance(Request::new(req.clone())), 3) {
+        match retry_grpc!(self.guard, self.client.change_balance(Request::new(req.clone()))) {
             Ok(re) => Ok(re.into_inner()),
             Err(e) => Err(status_to_err(e))
         }
@@ -66,7 +74,7 @@ impl TraderService {
             trader_id,
         };
 
-        match retry_grpc!(self.client.get_trader_margin(Request::new(request.clone())), 3) {
+        match retry_grpc!(self.guard, self.client.get_trader_margin(Request::new(request.clone()))) {
             Ok(result) => Ok(result.into_inner()),
             Err(e) => Err(status_to_err(e))
         }
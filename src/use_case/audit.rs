@@ -0,0 +1,53 @@
+use tracing::{error, warn};
+use crate::errors::LibError;
+use crate::errors::LibError::InternalError;
+use crate::models::audit::{AuditEntry, AuditRecord, AUDIT_GENESIS_HASH};
+use crate::repository;
+
+const VERIFY_BATCH: i64 = 1000;
+
+// дописывает запись в конец цепочки
+pub async fn append_audit_record(pool: &deadpool_postgres::Pool, record: AuditRecord) -> Result<AuditEntry, LibError> {
+    let mut pg = pool.get().await.map_err(|e| {
+        error!(err=e.to_string(), "Error get PG connection");
+        InternalError
+    })?;
+    let tx = pg.transaction().await.map_err(|e| {
+        error!(err=e.to_string(), "Error begin audit transaction");
+        InternalError
+    })?;
+    repository::audit::lock_audit_log(&tx).await?;
+    let prev_hash = repository::audit::get_last_audit_hash(&tx).await?
+        .unwrap_or_else(|| AUDIT_GENESIS_HASH.to_string());
+    let entry = AuditEntry { seq: 0, hash: record.chain_hash(&prev_hash), prev_hash, record };
+    repository::audit::insert_audit_entry(&tx, &entry).await?;
+    tx.commit().await.map_err(|e| {
+        error!(err=e.to_string(), "Error commit audit transaction");
+        InternalError
+    })?;
+    Ok(entry)
+}
+
+// проходит журнал с начала и возвращает seq первой записи, на которой цепочка разорвана
+pub async fn verify_audit_chain(pool: &deadpool_postgres::Pool) -> Result<Option<i64>, LibError> {
+    let pg = pool.get().await.map_err(|e| {
+        error!(err=e.to_string(), "Error get PG connection");
+        InternalError
+    })?;
+    let mut prev_hash = AUDIT_GENESIS_HASH.to_string();
+    let mut after_seq = 0;
+    loop {
+        let entries = repository::audit::get_audit_entries(&pg, after_seq, VERIFY_BATCH).await?;
+        for entry in entries.iter() {
+            if !entry.is_valid(&prev_hash) {
+                warn!(seq=entry.seq, id=entry.record.id, "audit chain broken");
+                return Ok(Some(entry.seq));
+            }
+            prev_hash = entry.hash.clone();
+            after_seq = entry.seq;
+        }
+        if (entries.len() as i64) < VERIFY_BATCH {
+            return Ok(None);
+        }
+    }
+}
@@ -0,0 +1,119 @@
+use chrono::NaiveDateTime;
+use tracing::{error, info};
+use uuid::Uuid;
+use crate::errors::LibError;
+use crate::errors::LibError::{Conflict, Forbidden, InternalError};
+use crate::models::{AuthState, Claims};
+use crate::models::block::{BlockAction, BlockEvent, BlockRequest, BlockTarget, SYSTEM_ACTOR};
+use crate::repository;
+
+pub async fn block(state: &AuthState, claims: &Claims, target: BlockTarget, target_id: &str, request: BlockRequest)
+    -> Result<BlockEvent, LibError>
+{
+    check_admin(claims)?;
+    request.validate(chrono::Utc::now().naive_utc())?;
+    let event = new_event(&claims.sub, &claims.role, target, target_id, BlockAction::Block, request.reason,
+                          request.expires_at);
+    set_blocked(state, event, None).await
+}
+
+pub async fn unblock(state: &AuthState, claims: &Claims, target: BlockTarget, target_id: &str, reason: String)
+    -> Result<BlockEvent, LibError>
+{
+    check_admin(claims)?;
+    let event = new_event(&claims.sub, &claims.role, target, target_id, BlockAction::Unblock, reason, None);
+    set_blocked(state, event, None).await
+}
+
+pub async fn get_block_history(state: &AuthState, claims: &Claims, target: BlockTarget, target_id: &str)
+    -> Result<Vec<BlockEvent>, LibError>
+{
+    check_admin(claims)?;
+    let pg = state.pool.get().await.map_err(|e| {
+        error!(err=e.to_string(), "Error get PG connection");
+        InternalError
+    })?;
+    repository::block::get_block_history(&pg, target, target_id).await
+}
+
+// снимает временные блокировки с истекшим сроком, запускается периодически
+pub async fn expire_temporary_blocks(state: &AuthState) -> Result<usize, LibError> {
+    let expired = {
+        let pg = state.pool.get().await.map_err(|e| {
+            error!(err=e.to_string(), "Error get PG connection");
+            InternalError
+        })?;
+        repository::block::get_expired_blocks(&pg, chrono::Utc::now().naive_utc()).await?
+    };
+    let mut unblocked = 0;
+    for event in expired.iter() {
+        let reason = format!("temporary block {} expired", event.id);
+        let unblock = new_event(SYSTEM_ACTOR, SYSTEM_ACTOR, event.target, &event.target_id, BlockAction::Unblock,
+                                reason, None);
+        match set_blocked(state, unblock, Some(event)).await {
+            Ok(_) => unblocked += 1,
+            Err(Conflict) => info!(target_id=event.target_id, block_id=event.id, "temporary block changed before expiry"),
+            Err(e) => error!(target_id=event.target_id, err=format!("{:?}", e), "Error expire temporary block"),
+        }
+    }
+    Ok(unblocked)
+}
+
+fn check_admin(claims: &Claims) -> Result<(), LibError> {
+    if claims.role.to_lowercase() != "admin" {
+        return Err(Forbidden);
+    }
+    Ok(())
+}
+
+fn new_event(actor_id: &str, actor_role: &str, target: BlockTarget, target_id: &str, action: BlockAction,
+             reason: String, expires_at: Option<NaiveDateTime>) -> BlockEvent {
+    BlockEvent {
+        id: Uuid::now_v7().to_string(),
+        target,
+        target_id: target_id.to_string(),
+        action,
+        reason,
+        actor_id: actor_id.to_string(),
+        actor_role: actor_role.to_lowercase(),
+        expires_at: expires_at.filter(|_| action == BlockAction::Block),
+        created_at: chrono::Utc::now().naive_utc(),
+    }
+}
+
+// флаг и запись истории в одной транзакции, после коммита значение пишется в Redis и рассылается.
+// expired - снимаемая временная блокировка, Conflict если она уже не последнее событие цели
+async fn set_blocked(state: &AuthState, event: BlockEvent, expired: Option<&BlockEvent>) -> Result<BlockEvent, LibError> {
+    let (target, target_id) = (event.target, event.target_id.as_str());
+    let is_blocked = event.action == BlockAction::Block;
+    let mut pg = state.pool.get().await.map_err(|e| {
+        error!(err=e.to_string(), "Error get PG connection");
+        InternalError
+    })?;
+    let tx = pg.transaction().await.map_err(|e| {
+        error!(err=e.to_string(), "Error begin block transaction");
+        InternalError
+    })?;
+    repository::block::lock_block_target(&tx, target, target_id).await?;
+    if let Some(expired) = expired {
+        let latest = repository::block::get_latest_block_event(&tx, target, target_id).await?;
+        if !expired.is_expired(latest.as_ref(), event.created_at) {
+            return Err(Conflict);
+        }
+    }
+    match target {
+        BlockTarget::Trader => repository::trader::update_trader_is_blocked(&*tx, target_id, is_blocked).await?,
+        BlockTarget::Merchant => repository::merchant::update_merchant_is_blocked(&*tx, target_id, is_blocked).await?,
+    }
+    repository::block::insert_block_event(&tx, &event).await?;
+    tx.commit().await.map_err(|e| {
+        error!(err=e.to_string(), "Error commit block transaction");
+        InternalError
+    })?;
+    match target {
+        BlockTarget::Trader => repository::trader::publish_trader_is_blocked(&state.rdb, target_id, is_blocked).await,
+        BlockTarget::Merchant => repository::merchant::publish_merchant_is_blocked(&state.rdb, target_id, is_blocked).await,
+    }
+    info!(target_id=target_id, target=%target, action=%event.action, actor_id=event.actor_id, "block state changed");
+    Ok(event)
+}
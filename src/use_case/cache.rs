@@ -0,0 +1,63 @@
+use std::time::Duration;
+use futures_util::StreamExt;
+use tracing::{info, warn};
+use crate::repository::cache::{CacheInvalidation, CACHE_INVALIDATION_CHANNEL};
+use crate::repository;
+use crate::use_case::merchant::{MERCHANT_IP_ALLOWLIST, MERCHANT_IS_BLOCKED, MERCHANT_PUBLIC_KEY};
+use crate::use_case::rate_limit::RATE_LIMITS;
+use crate::use_case::trader::TRADER_IS_BLOCKED;
+
+// слушает CACHE_INVALIDATION_CHANNEL и сбрасывает кэши этого процесса, запускается один раз на сервис.
+// pub/sub не работает через пул, поэтому нужен отдельный redis::Client
+pub async fn run_cache_invalidation_listener(client: redis::Client, rdb: deadpool_redis::Pool) {
+    loop {
+        if let Err(e) = listen(&client, &rdb).await {
+            warn!(err=e.to_string(), "cache invalidation subscription lost");
+        }
+        tokio::time::sleep(Duration::from_secs(1)).await;
+    }
+}
+
+async fn listen(client: &redis::Client, rdb: &deadpool_redis::Pool) -> redis::RedisResult<()> {
+    let mut pubsub = client.get_async_pubsub().await?;
+    pubsub.subscribe(CACHE_INVALIDATION_CHANNEL).await?;
+    // пока подписки не было, сообщения могли потеряться
+    clear_local_caches();
+    info!("cache invalidation subscribed");
+    let mut messages = pubsub.into_on_message();
+    while let Some(message) = messages.next().await {
+        let payload: String = message.get_payload()?;
+        match serde_json::from_str::<CacheInvalidation>(&payload) {
+            Ok(invalidation) => invalidate(rdb, &invalidation).await,
+            Err(e) => warn!(payload=payload, err=e.to_string(), "Error decode cache invalidation"),
+        }
+    }
+    Ok(())
+}
+
+// при write-through в Redis уже новое значение, сбрасывается только L1
+async fn invalidate(rdb: &deadpool_redis::Pool, invalidation: &CacheInvalidation) {
+    let key = &invalidation.key;
+    match (invalidation.cache.as_str(), invalidation.written) {
+        (repository::trader::TRADER_IS_BLOCKED_CACHE, true) => TRADER_IS_BLOCKED.evict_local(key),
+        (repository::trader::TRADER_IS_BLOCKED_CACHE, false) => TRADER_IS_BLOCKED.invalidate(rdb, key).await,
+        (repository::merchant::MERCHANT_IS_BLOCKED_CACHE, true) => MERCHANT_IS_BLOCKED.evict_local(key),
+        (repository::merchant::MERCHANT_IS_BLOCKED_CACHE, false) => MERCHANT_IS_BLOCKED.invalidate(rdb, key).await,
+        (repository::merchant::MERCHANT_PUBLIC_KEY_CACHE, true) => MERCHANT_PUBLIC_KEY.evict_local(key),
+        (repository::merchant::MERCHANT_PUBLIC_KEY_CACHE, false) => MERCHANT_PUBLIC_KEY.invalidate(rdb, key).await,
+        (repository::merchant::MERCHANT_IP_ALLOWLIST_CACHE, true) => MERCHANT_IP_ALLOWLIST.evict_local(key),
+        (repository::merchant::MERCHANT_IP_ALLOWLIST_CACHE, false) => MERCHANT_IP_ALLOWLIST.invalidate(rdb, key).await,
+        (repository::rate_limit::RATE_LIMIT_CACHE, true) => RATE_LIMITS.evict_local(key),
+        (repository::rate_limit::RATE_LIMIT_CACHE, false) => RATE_LIMITS.invalidate(rdb, key).await,
+        // кэш другого сервиса
+        _ => (),
+    }
+}
+
+fn clear_local_caches() {
+    TRADER_IS_BLOCKED.clear_local();
+    MERCHANT_IS_BLOCKED.clear_local();
+    MERCHANT_PUBLIC_KEY.clear_local();
+    MERCHANT_IP_ALLOWLIST.clear_local();
+    RATE_LIMITS.clear_local();
+}
@@ -0,0 +1,59 @@
+use rust_decimal::Decimal;
+use tracing::{error, info};
+use crate::errors::LibError;
+use crate::errors::LibError::{Conflict, InternalError};
+use crate::models::payments::close::{plan_close, ClosePlan, ClosePolicy};
+use crate::models::ledger::LedgerOwner;
+use crate::models::payments::payment::FullPayment;
+use crate::models::reconciliation::ExpectedBalanceAction;
+use crate::services::merchants::merchant_service::MerchantService;
+use crate::services::traders::trader_service::TraderServicePool;
+use crate::use_case::ledger;
+use crate::use_case::payment_saga::settle_actions;
+use crate::repository;
+
+// закрытие платежа на фактически оплаченную сумму с пересчетом сумм и движений балансов
+pub async fn close_payment(pool: &deadpool_postgres::Pool, traders: &TraderServicePool, merchants: &MerchantService,
+                           payment_id: &str, actual_amount: Option<Decimal>, policy: &ClosePolicy)
+    -> Result<ClosePlan, LibError>
+{
+    let mut pg = pool.get().await.map_err(|e| {
+        error!(err=e.to_string(), "Error get PG connection");
+        InternalError
+    })?;
+    let tx = pg.transaction().await.map_err(|e| {
+        error!(err=e.to_string(), "Error begin close transaction");
+        InternalError
+    })?;
+    let payment = repository::dispute::get_payment_for_update(&tx, payment_id).await?;
+    let plan = plan_close(&payment, actual_amount, policy)?;
+
+    settle_payment(pool, traders, merchants, &plan.payment, LedgerOwner::Trader).await?;
+    settle_payment(pool, traders, merchants, &plan.payment, LedgerOwner::Merchant).await?;
+    let now = chrono::Utc::now().naive_utc();
+    if !repository::payment::save_closed_amounts(&tx, &plan.payment, now).await? {
+        return Err(Conflict);
+    }
+    tx.commit().await.map_err(|e| {
+        error!(err=e.to_string(), "Error commit close transaction");
+        InternalError
+    })?;
+    info!(payment_id=payment_id, kind=?plan.kind, fiat_amount=%plan.payment.fiat_amount,
+        original_fiat_amount=?plan.payment.original_fiat_amount, "payment closed");
+    Ok(plan)
+}
+
+// движения одной стороны по закрытому платежу, общие для close_payment и саги закрытия.
+// ключи зависят только от платежа, поэтому повтор или закрытие другим путем не задвоят расчет
+pub async fn settle_payment(pool: &deadpool_postgres::Pool, traders: &TraderServicePool, merchants: &MerchantService,
+                            payment: &FullPayment, owner_type: LedgerOwner) -> Result<(), LibError> {
+    for action in settle_actions(payment, owner_type) {
+        let entry = action.to_entry(&payment.id, settle_key(&action));
+        ledger::change_balance(pool, traders, merchants, &entry).await?;
+    }
+    Ok(())
+}
+
+pub fn settle_key(action: &ExpectedBalanceAction) -> String {
+    format!("settle:{}:{}:{}", action.payment_id, action.owner_type, action.action)
+}
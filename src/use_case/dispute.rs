@@ -0,0 +1,164 @@
+use tracing::{error, info};
+use uuid::Uuid;
+use crate::errors::LibError;
+use crate::errors::LibError::{Conflict, Forbidden, InternalError, InvalidAmount};
+use crate::models::Claims;
+use crate::models::ledger::{LedgerEntry, LedgerOwner};
+use crate::models::payments::dispute::{dispute_outcome, Dispute, DisputeEvidence, DisputeOpener, DisputeOutcome,
+                                       DisputeSla, DisputeStatus, NewDisputeEvidence, OpenDisputeRequest, ResolveDisputeRequest};
+use crate::models::payments::payment::{FullPayment, PaymentStatuses};
+use crate::models::reconciliation::ExpectedBalanceAction;
+use crate::services::merchants::merchant_service::MerchantService;
+use crate::services::traders::trader_service::TraderServicePool;
+use crate::use_case::ledger;
+use crate::repository;
+
+// сторона спора, от имени которой действует пользователь
+fn dispute_party(claims: &Claims, merchant_id: &str, trader_id: &str) -> Result<DisputeOpener, LibError> {
+    match claims.role.to_lowercase().as_str() {
+        "trader" if claims.sub == trader_id => Ok(DisputeOpener::Trader),
+        "merchant" if claims.sub == merchant_id => Ok(DisputeOpener::Merchant),
+        _ => Err(Forbidden),
+    }
+}
+
+async fn get_pg(pool: &deadpool_postgres::Pool) -> Result<deadpool_postgres::Object, LibError> {
+    pool.get().await.map_err(|e| {
+        error!(err=e.to_string(), "Error get PG connection");
+        InternalError
+    })
+}
+
+pub async fn open_dispute(pool: &deadpool_postgres::Pool, claims: &Claims, request: OpenDisputeRequest, sla: &DisputeSla)
+    -> Result<Dispute, LibError>
+{
+    let mut pg = get_pg(pool).await?;
+    let tx = pg.transaction().await.map_err(|e| {
+        error!(err=e.to_string(), "Error begin dispute transaction");
+        InternalError
+    })?;
+    let payment = repository::dispute::get_payment_for_update(&tx, &request.payment_id).await?;
+    let party = dispute_party(claims, &payment.merchant_id, &payment.trader_id)?;
+    // спор от имени клиента открывает мерчант
+    if party != request.opened_by && !(party == DisputeOpener::Merchant && request.opened_by == DisputeOpener::Customer) {
+        return Err(Forbidden);
+    }
+    if matches!(payment.status, PaymentStatuses::Frozen | PaymentStatuses::Completed) {
+        return Err(Conflict);
+    }
+
+    let now = chrono::Utc::now().naive_utc();
+    let dispute = Dispute {
+        id: Uuid::now_v7().to_string(),
+        payment_id: payment.id.clone(),
+        merchant_id: payment.merchant_id.clone(),
+        trader_id: payment.trader_id.clone(),
+        opened_by: request.opened_by,
+        opener_id: claims.sub.clone(),
+        reason: request.reason,
+        status: DisputeStatus::Open,
+        payment_status_before: payment.status.clone(),
+        resolution: None,
+        resolved_by: None,
+        resolution_comment: None,
+        respond_by: now + sla.respond_within,
+        resolve_by: now + sla.resolve_within,
+        created_at: now,
+        updated_at: None,
+        resolved_at: None,
+    };
+    repository::dispute::insert_dispute(&tx, &dispute).await?;
+    repository::dispute::set_payment_status(&tx, &payment.id, &PaymentStatuses::Frozen, now).await?;
+    tx.commit().await.map_err(|e| {
+        error!(err=e.to_string(), "Error commit dispute transaction");
+        InternalError
+    })?;
+    info!(dispute_id=dispute.id, payment_id=dispute.payment_id, opened_by=%dispute.opened_by, "dispute opened");
+    Ok(dispute)
+}
+
+pub async fn add_evidence(pool: &deadpool_postgres::Pool, claims: &Claims, dispute_id: &str, evidence: NewDisputeEvidence)
+    -> Result<DisputeEvidence, LibError>
+{
+    let pg = get_pg(pool).await?;
+    let dispute = repository::dispute::get_dispute(&pg, dispute_id).await?;
+    let party = dispute_party(claims, &dispute.merchant_id, &dispute.trader_id)?;
+    if dispute.status == DisputeStatus::Resolved {
+        return Err(Conflict);
+    }
+    if evidence.size_bytes <= 0 {
+        return Err(InvalidAmount);
+    }
+    let now = chrono::Utc::now().naive_utc();
+    let evidence = DisputeEvidence {
+        id: Uuid::now_v7().to_string(),
+        dispute_id: dispute.id.clone(),
+        uploaded_by: party,
+        uploader_id: claims.sub.clone(),
+        file_name: evidence.file_name,
+        content_type: evidence.content_type,
+        size_bytes: evidence.size_bytes,
+        storage_key: evidence.storage_key,
+        sha256: evidence.sha256,
+        created_at: now,
+    };
+    repository::dispute::insert_evidence(&pg, &evidence).await?;
+    // ответ второй стороны переводит спор на рассмотрение
+    let opener_side = match dispute.opened_by {
+        DisputeOpener::Customer => DisputeOpener::Merchant,
+        opener => opener,
+    };
+    if dispute.status == DisputeStatus::Open && party != opener_side {
+        repository::dispute::set_dispute_status(&pg, &dispute.id, DisputeStatus::UnderReview, now).await?;
+    }
+    Ok(evidence)
+}
+
+pub async fn resolve_dispute(pool: &deadpool_postgres::Pool, traders: &TraderServicePool, merchants: &MerchantService,
+                             claims: &Claims, dispute_id: &str, request: ResolveDisputeRequest)
+    -> Result<DisputeOutcome, LibError>
+{
+    if claims.role.to_lowercase() != "admin" {
+        return Err(Forbidden);
+    }
+    let mut pg = get_pg(pool).await?;
+    let tx = pg.transaction().await.map_err(|e| {
+        error!(err=e.to_string(), "Error begin dispute transaction");
+        InternalError
+    })?;
+    let dispute = repository::dispute::get_dispute_for_update(&tx, dispute_id).await?;
+    if dispute.status == DisputeStatus::Resolved {
+        return Err(Conflict);
+    }
+    let payment = repository::dispute::get_payment_for_update(&tx, &dispute.payment_id).await?;
+    let outcome = dispute_outcome(&dispute, &payment, request.resolution);
+
+    // балансы меняются до коммита, при ошибке спор остается открытым и решение можно повторить с теми же ключами
+    for action in outcome.balance_actions.iter() {
+        apply_balance_action(pool, traders, merchants, &dispute, &payment, action).await?;
+    }
+    let now = chrono::Utc::now().naive_utc();
+    repository::dispute::set_payment_status(&tx, &payment.id, &outcome.payment_status, now).await?;
+    repository::dispute::resolve_dispute(&tx, &dispute.id, request.resolution, &claims.sub,
+                                         request.comment.as_deref(), now).await?;
+    tx.commit().await.map_err(|e| {
+        error!(err=e.to_string(), "Error commit dispute transaction");
+        InternalError
+    })?;
+    info!(dispute_id=dispute.id, payment_id=payment.id, resolution=%request.resolution,
+        payment_status=%outcome.payment_status, "dispute resolved");
+    Ok(outcome)
+}
+
+async fn apply_balance_action(pool: &deadpool_postgres::Pool, traders: &TraderServicePool, merchants: &MerchantService,
+                              dispute: &Dispute, payment: &FullPayment, action: &ExpectedBalanceAction)
+    -> Result<(), LibError>
+{
+    let key = format!("dispute:{}:{}:{}", dispute.id, action.owner_type, action.action);
+    let owner_id = match action.owner_type {
+        LedgerOwner::Trader => payment.trader_id.clone(),
+        LedgerOwner::Merchant => payment.merchant_id.clone(),
+    };
+    let entry = LedgerEntry::new(action.owner_type, owner_id, action.action, action.amount, payment.id.clone(), key);
+    ledger::change_balance(pool, traders, merchants, &entry).await
+}
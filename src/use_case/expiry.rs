@@ -0,0 +1,244 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicI64, AtomicU64, Ordering};
+use std::time::Duration;
+use async_trait::async_trait;
+use rdkafka::producer::FutureProducer;
+use serde::Serialize;
+use tokio::task::JoinHandle;
+use tracing::{debug, error, info, warn};
+use crate::errors::LibError;
+use crate::errors::LibError::InternalError;
+use crate::models::ledger::{LedgerEntry, LedgerOwner};
+use crate::models::payments::merchant::MerchantPayment;
+use crate::models::payments::payment::{FullPayment, PaymentSides, PaymentStatuses};
+use crate::services::merchants::merchant_service::MerchantService;
+use crate::services::traders::trader_service::TraderServicePool;
+use crate::use_case::kafka::send_kafka_message;
+use crate::use_case::ledger;
+use crate::retry::RetryPolicy;
+use crate::{merchant_proto, repository, trader_proto};
+
+pub const MERCHANT_PAYMENT_STATUS_TOPIC: &str = "merchant_payment_status";
+
+// вызывается после коммита отмены, вне транзакции. при ошибке вызов повторяется
+// с растущей паузой, поэтому хуки должны быть идемпотентными
+#[async_trait]
+pub trait ExpiryHooks: Send + Sync {
+    async fn on_expired(&self, payment: &FullPayment) -> Result<(), LibError>;
+
+    // вызывается после коммита отмены, ошибки не влияют на отмену
+    async fn notify(&self, _payment: FullPayment) {}
+}
+
+pub struct DefaultExpiryHooks {
+    pub pool: deadpool_postgres::Pool,
+    pub traders: TraderServicePool,
+    pub merchants: MerchantService,
+    pub producer: Option<FutureProducer>,
+}
+
+#[async_trait]
+impl ExpiryHooks for DefaultExpiryHooks {
+    async fn on_expired(&self, payment: &FullPayment) -> Result<(), LibError> {
+        // ключ в леджере уникален для всех владельцев, поэтому у трейдера и мерчанта свои
+        let key = |owner| format!("expire:{}:{}", payment.id, owner);
+        let entry = LedgerEntry::trader(payment.trader_id.clone(), payment.trader_crypto_amount,
+            trader_proto::BalanceActionType::Unfroze, payment.id.clone(), key(LedgerOwner::Trader));
+        ledger::change_trader_balance(&self.pool, &self.traders, &entry).await?;
+        if payment.payment_side == PaymentSides::Sell {
+            let entry = LedgerEntry::merchant(payment.merchant_id.clone(), payment.crypto_amount,
+                merchant_proto::BalanceActionType::Unfroze, payment.id.clone(), key(LedgerOwner::Merchant));
+            ledger::change_merchant_balance(&self.pool, &self.merchants, &entry).await?;
+        }
+        Ok(())
+    }
+
+    async fn notify(&self, payment: FullPayment) {
+        let Some(producer) = self.producer.as_ref() else { return };
+        let merchant_id = payment.merchant_id.clone();
+        let payload = match serde_json::to_vec(&MerchantPayment::from(payment)) {
+            Ok(payload) => payload,
+            Err(e) => {
+                error!(err=e.to_string(), "Error encode expired payment");
+                return;
+            }
+        };
+        if let Err(e) = send_kafka_message(producer, MERCHANT_PAYMENT_STATUS_TOPIC, &merchant_id, &payload).await {
+            warn!(merchant_id=merchant_id, err=?e, "Error notify merchant about expired payment");
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct ExpiryMetrics {
+    expired_total: AtomicU64,
+    hook_failures_total: AtomicU64,
+    lag_ms: AtomicI64,
+    last_run_ts: AtomicI64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ExpiryMetricsSnapshot {
+    pub expired_total: u64,
+    pub hook_failures_total: u64,
+    // насколько самый старый просроченный UNPAID платеж отстает от дедлайна
+    pub lag_ms: i64,
+    pub last_run_ts: i64,
+}
+
+impl ExpiryMetrics {
+    pub fn snapshot(&self) -> ExpiryMetricsSnapshot {
+        ExpiryMetricsSnapshot {
+            expired_total: self.expired_total.load(Ordering::Relaxed),
+            hook_failures_total: self.hook_failures_total.load(Ordering::Relaxed),
+            lag_ms: self.lag_ms.load(Ordering::Relaxed),
+            last_run_ts: self.last_run_ts.load(Ordering::Relaxed),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ExpirySchedulerConfig {
+    pub interval: Duration,
+    pub batch_size: i64,
+    // сколько задание на снятие заморозок занято репликой, которая его взяла
+    pub hook_lease: Duration,
+    // пауза перед повтором упавшего хука
+    pub hook_backoff: RetryPolicy,
+}
+
+impl Default for ExpirySchedulerConfig {
+    fn default() -> Self {
+        Self {
+            interval: Duration::from_secs(1),
+            batch_size: 100,
+            hook_lease: Duration::from_secs(60),
+            hook_backoff: RetryPolicy::default().with_backoff(Duration::from_secs(1), Duration::from_secs(300), 2.0),
+        }
+    }
+}
+
+// отменяет просроченные UNPAID платежи. несколько реплик могут работать одновременно:
+// строки берутся через FOR UPDATE SKIP LOCKED, поэтому каждый платеж отменяется один раз.
+// заморозки снимаются отдельно по payment_expiry_jobs, без блокировок платежей
+pub struct ExpiryScheduler {
+    pool: deadpool_postgres::Pool,
+    hooks: Arc<dyn ExpiryHooks>,
+    config: ExpirySchedulerConfig,
+    metrics: Arc<ExpiryMetrics>,
+}
+
+impl ExpiryScheduler {
+    pub fn new<H: ExpiryHooks + 'static>(pool: deadpool_postgres::Pool, hooks: H, config: ExpirySchedulerConfig) -> Self {
+        Self { pool, hooks: Arc::new(hooks), config, metrics: Arc::new(ExpiryMetrics::default()) }
+    }
+
+    pub fn metrics(&self) -> Arc<ExpiryMetrics> {
+        self.metrics.clone()
+    }
+
+    pub fn spawn(self) -> JoinHandle<()> {
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(self.config.interval);
+            interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+            loop {
+                interval.tick().await;
+                loop {
+                    match self.run_once().await {
+                        // отменили полный батч, скорее всего есть еще
+                        Ok(processed) if processed as i64 >= self.config.batch_size => continue,
+                        Ok(_) => break,
+                        Err(e) => {
+                            error!(err=?e, "expiry scheduler tick failed");
+                            break;
+                        }
+                    }
+                }
+            }
+        })
+    }
+
+    // один проход, возвращает количество отмененных платежей
+    pub async fn run_once(&self) -> Result<usize, LibError> {
+        let mut pg = self.pool.get().await.map_err(|e| {
+            error!(err=e.to_string(), "Error get PG connection");
+            InternalError
+        })?;
+        let now = chrono::Utc::now().naive_utc();
+        let tx = pg.transaction().await.map_err(|e| {
+            error!(err=e.to_string(), "Error begin expiry transaction");
+            InternalError
+        })?;
+        let payments = repository::payment::lock_overdue_payments(&tx, now, self.config.batch_size).await?;
+        let mut expired = Vec::with_capacity(payments.len());
+        for payment in payments {
+            if repository::payment::cancel_payment_by_timeout(&tx, &payment.id, now).await? {
+                repository::payment::insert_expiry_job(&tx, &payment.id, now).await?;
+                expired.push(payment);
+            }
+        }
+        tx.commit().await.map_err(|e| {
+            error!(err=e.to_string(), "Error commit expiry transaction");
+            InternalError
+        })?;
+
+        self.metrics.expired_total.fetch_add(expired.len() as u64, Ordering::Relaxed);
+        self.metrics.last_run_ts.store(now.and_utc().timestamp(), Ordering::Relaxed);
+        let lag_ms = repository::payment::get_oldest_overdue_deadline(&pg, now).await?
+            .map(|deadline| (now - deadline).num_milliseconds())
+            .unwrap_or(0);
+        self.metrics.lag_ms.store(lag_ms, Ordering::Relaxed);
+        drop(pg);
+
+        if !expired.is_empty() {
+            info!(count=expired.len(), lag_ms=lag_ms, "payments cancelled by timeout");
+        } else {
+            debug!(lag_ms=lag_ms, "no overdue payments");
+        }
+        let count = expired.len();
+        for mut payment in expired {
+            payment.status = PaymentStatuses::CancelledByTimeout;
+            payment.updated_at = Some(now);
+            self.hooks.notify(payment).await;
+        }
+        self.run_jobs().await?;
+        Ok(count)
+    }
+
+    // снимает заморозки по отмененным платежам, возвращает количество успешных заданий
+    pub async fn run_jobs(&self) -> Result<usize, LibError> {
+        let pg = self.pool.get().await.map_err(|e| {
+            error!(err=e.to_string(), "Error get PG connection");
+            InternalError
+        })?;
+        let now = chrono::Utc::now().naive_utc();
+        let lease_until = now + chrono::Duration::from_std(self.config.hook_lease).unwrap_or(chrono::Duration::MAX);
+        let jobs = repository::payment::claim_expiry_jobs(&pg, now, lease_until, self.config.batch_size).await?;
+        if jobs.is_empty() {
+            return Ok(0);
+        }
+        let payment_ids = jobs.iter().map(|job| job.payment_id.clone()).collect::<Vec<_>>();
+        let payments = repository::payment::get_payments_by_ids(&pg, &payment_ids).await?;
+        let mut done = 0;
+        for mut job in jobs {
+            let result = match payments.iter().find(|payment| payment.id == job.payment_id) {
+                Some(payment) => self.hooks.on_expired(payment).await,
+                None => Err(LibError::NotFound),
+            };
+            match result {
+                Ok(()) => {
+                    repository::payment::delete_expiry_job(&pg, &job.payment_id).await?;
+                    done += 1;
+                }
+                Err(e) => {
+                    self.metrics.hook_failures_total.fetch_add(1, Ordering::Relaxed);
+                    job.reschedule(chrono::Utc::now().naive_utc(), format!("{:?}", e), &self.config.hook_backoff);
+                    warn!(payment_id=job.payment_id, attempts=job.attempts, next_attempt_at=%job.next_attempt_at, err=?e,
+                        "expiry hook failed, will retry");
+                    repository::payment::reschedule_expiry_job(&pg, &job).await?;
+                }
+            }
+        }
+        Ok(done)
+    }
+}
@@ -0,0 +1,241 @@
+use async_trait::async_trait;
+use tokio::sync::mpsc;
+use tokio_postgres::types::{ToSql, Type};
+use tracing::{error, info};
+use crate::errors::LibError;
+use crate::errors::LibError::InternalError;
+use crate::models::payments::export::{ExportCell, ExportRow};
+use crate::models::payments::filter::SqlQuery;
+use crate::models::payments::merchant::MerchantPayment;
+use crate::models::payments::payment::FullPayment;
+use crate::models::payments::requests::GetPaymentsRequest;
+use crate::models::payments::trader::TraderPayment;
+
+// сколько строк за раз читается из курсора
+pub const EXPORT_BATCH_SIZE: i32 = 1000;
+
+#[async_trait]
+pub trait ExportSink: Send {
+    async fn write_header(&mut self, headers: &[&str]) -> Result<(), LibError>;
+    async fn write_rows(&mut self, rows: Vec<Vec<ExportCell>>) -> Result<(), LibError>;
+}
+
+// выгрузка по условиям request без лимита, колонки зависят от роли в request
+pub async fn export_payments(client: &mut tokio_postgres::Client, request: &GetPaymentsRequest, sink: &mut dyn ExportSink)
+    -> Result<u64, LibError>
+{
+    let query = request.filter().build(" ORDER BY created_at DESC, id DESC");
+    match request {
+        GetPaymentsRequest::Merchant(_) => stream_rows::<MerchantPayment>(client, query, sink).await,
+        GetPaymentsRequest::Trader(_) => stream_rows::<TraderPayment>(client, query, sink).await,
+        GetPaymentsRequest::Admin(_) | GetPaymentsRequest::AdminDisputes(_) =>
+            stream_rows::<FullPayment>(client, query, sink).await,
+    }
+}
+
+// строки читаются через портал, в памяти держится только текущая пачка
+async fn stream_rows<T: ExportRow>(client: &mut tokio_postgres::Client, query: SqlQuery, sink: &mut dyn ExportSink)
+    -> Result<u64, LibError>
+{
+    let sql = format!("{}{}", T::sql(), query.sql);
+    let params = query.params();
+    let types = params.iter().map(|(_, ty)| ty.clone()).collect::<Vec<Type>>();
+    let values = params.iter().map(|(value, _)| *value).collect::<Vec<&(dyn ToSql + Sync)>>();
+
+    let tx = client.transaction().await.map_err(|e| {
+        error!(err=e.to_string(), "Error begin export transaction");
+        InternalError
+    })?;
+    let statement = tx.prepare_typed(sql.as_str(), &types).await.map_err(|e| {
+        error!(err=e.to_string(), "Error prepare export query");
+        InternalError
+    })?;
+    let portal = tx.bind(&statement, &values).await.map_err(|e| {
+        error!(err=e.to_string(), "Error open export cursor");
+        InternalError
+    })?;
+
+    sink.write_header(T::headers()).await?;
+    let mut total = 0u64;
+    loop {
+        let rows = tx.query_portal(&portal, EXPORT_BATCH_SIZE).await.map_err(|e| {
+            error!(err=e.to_string(), "Error read export cursor");
+            InternalError
+        })?;
+        let fetched = rows.len();
+        total += fetched as u64;
+        if fetched > 0 {
+            sink.write_rows(rows.into_iter().map(|row| T::from(row).cells()).collect()).await?;
+        }
+        if fetched < EXPORT_BATCH_SIZE as usize {
+            break;
+        }
+    }
+    // транзакция только читала
+    tx.rollback().await.map_err(|e| {
+        error!(err=e.to_string(), "Error close export transaction");
+        InternalError
+    })?;
+    info!(rows=total, "payments exported");
+    Ok(total)
+}
+
+// CSV кусками в канал, получатель отдает их клиенту телом ответа
+pub struct CsvSink {
+    sender: mpsc::Sender<Vec<u8>>,
+}
+
+impl CsvSink {
+    pub fn new(sender: mpsc::Sender<Vec<u8>>) -> Self {
+        Self { sender }
+    }
+
+    async fn send<I: IntoIterator<Item = Vec<String>>>(&mut self, records: I) -> Result<(), LibError> {
+        let mut writer = csv::Writer::from_writer(Vec::new());
+        for record in records {
+            writer.write_record(&record).map_err(|e| {
+                error!(err=e.to_string(), "Error write csv record");
+                InternalError
+            })?;
+        }
+        let chunk = writer.into_inner().map_err(|e| {
+            error!(err=e.to_string(), "Error flush csv chunk");
+            InternalError
+        })?;
+        // получатель закрыл канал - клиент отключился
+        self.sender.send(chunk).await.map_err(|_| {
+            error!("export receiver dropped");
+            InternalError
+        })
+    }
+}
+
+#[async_trait]
+impl ExportSink for CsvSink {
+    async fn write_header(&mut self, headers: &[&str]) -> Result<(), LibError> {
+        let header = headers.iter().map(|h| h.to_string()).collect::<Vec<_>>();
+        self.send([header]).await
+    }
+
+    async fn write_rows(&mut self, rows: Vec<Vec<ExportCell>>) -> Result<(), LibError> {
+        self.send(rows.into_iter().map(|row| row.iter().map(csv_field).collect())).await
+    }
+}
+
+// текст, который начинается с =, +, -, @, табуляции или возврата каретки, табличный редактор
+// выполнит как формулу
+fn csv_field(cell: &ExportCell) -> String {
+    match cell {
+        ExportCell::Text(text) if text.starts_with(['=', '+', '-', '@', '\t', '\r']) => format!("'{}", text),
+        _ => cell.to_text(),
+    }
+}
+
+// XLSX нельзя отдавать по мере чтения (zip пишется в конце), строки копятся во временных
+// файлах constant_memory и в конце сохраняются через save
+#[cfg(feature = "xlsx")]
+pub struct XlsxSink {
+    workbook: rust_xlsxwriter::Workbook,
+    row: u32,
+}
+
+#[cfg(feature = "xlsx")]
+impl XlsxSink {
+    // лимит строк листа Excel
+    const MAX_ROWS: u32 = 1_048_576;
+
+    pub fn new() -> Self {
+        let mut workbook = rust_xlsxwriter::Workbook::new();
+        workbook.add_worksheet_with_constant_memory();
+        Self { workbook, row: 0 }
+    }
+
+    pub fn save<W: std::io::Write + std::io::Seek + Send>(mut self, writer: W) -> Result<(), LibError> {
+        self.workbook.save_to_writer(writer).map_err(|e| {
+            error!(err=e.to_string(), "Error save xlsx export");
+            InternalError
+        })
+    }
+
+    fn write_cell(&mut self, col: u16, cell: &ExportCell) -> Result<(), LibError> {
+        use rust_decimal::prelude::ToPrimitive;
+        let row = self.row;
+        let sheet = self.workbook.worksheet_from_index(0).map_err(|e| {
+            error!(err=e.to_string(), "Error get xlsx worksheet");
+            InternalError
+        })?;
+        let result = match cell {
+            ExportCell::Text(text) => sheet.write_string(row, col, text.as_str()).map(|_| ()),
+            ExportCell::Number(number) => sheet.write_number(row, col, number.to_f64().unwrap_or_default()).map(|_| ()),
+            ExportCell::Empty => Ok(()),
+        };
+        result.map_err(|e| {
+            error!(err=e.to_string(), row=row, "Error write xlsx cell");
+            InternalError
+        })
+    }
+
+    fn next_row(&mut self) -> Result<(), LibError> {
+        self.row += 1;
+        if self.row >= Self::MAX_ROWS {
+            error!("xlsx export exceeds sheet row limit");
+            return Err(InternalError);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(feature = "xlsx")]
+impl Default for XlsxSink {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(feature = "xlsx")]
+#[async_trait]
+impl ExportSink for XlsxSink {
+    async fn write_header(&mut self, headers: &[&str]) -> Result<(), LibError> {
+        for (col, header) in headers.iter().enumerate() {
+            self.write_cell(col as u16, &ExportCell::Text(header.to_string()))?;
+        }
+        self.next_row()
+    }
+
+    async fn write_rows(&mut self, rows: Vec<Vec<ExportCell>>) -> Result<(), LibError> {
+        for row in rows.iter() {
+            for (col, cell) in row.iter().enumerate() {
+                self.write_cell(col as u16, cell)?;
+            }
+            self.next_row()?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_decimal::dec;
+    use super::*;
+
+    #[tokio::test]
+    async fn csv_escapes_formulas() {
+        let (sender, mut receiver) = mpsc::channel(4);
+        let mut sink = CsvSink::new(sender);
+        sink.write_header(&["holder_name", "fiat_amount"]).await.unwrap();
+        sink.write_rows(vec![
+            vec![ExportCell::Text("=HYPERLINK(\"http://x\")".to_string()), ExportCell::Number(dec!(-10.5))],
+            vec![ExportCell::Text("@SUM(A1)".to_string()), ExportCell::Empty],
+            vec![ExportCell::Text("\t=1+1".to_string()), ExportCell::Empty],
+            vec![ExportCell::Text("\r=1+1".to_string()), ExportCell::Empty],
+            vec![ExportCell::Text("Ivan, Petrov".to_string()), ExportCell::Number(dec!(100))],
+        ]).await.unwrap();
+        drop(sink);
+        let mut output = Vec::new();
+        while let Some(chunk) = receiver.recv().await {
+            output.extend(chunk);
+        }
+        assert_eq!(String::from_utf8(output).unwrap(), "holder_name,fiat_amount\n\
+            \"'=HYPERLINK(\"\"http://x\"\")\",-10.5\n'@SUM(A1),\n'\t=1+1,\n\"'\r=1+1\",\n\"Ivan, Petrov\",100\n");
+    }
+}
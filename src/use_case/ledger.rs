@@ -0,0 +1,97 @@
+use std::time::{Duration, Instant};
+use rust_decimal::prelude::ToPrimitive;
+use tracing::{error, info, warn};
+use crate::errors::LibError;
+use crate::errors::LibError::{InternalError, InvalidAmount};
+use crate::models::ledger::{LedgerBalances, LedgerEntry, LedgerOwner};
+use crate::services::merchants::merchant_service::MerchantService;
+use crate::services::traders::trader_service::TraderServicePool;
+use crate::repository;
+use crate::retry::{ErrorKind, RetryPolicy};
+
+// все изменения балансов идут через эти функции: сначала сервис трейдеров или мерчантов,
+// затем запись в леджер с тем же idempotent_key. повтор после ошибки не задваивает ни то, ни другое
+pub async fn change_balance(pool: &deadpool_postgres::Pool, traders: &TraderServicePool, merchants: &MerchantService,
+                            entry: &LedgerEntry) -> Result<(), LibError> {
+    match entry.owner_type {
+        LedgerOwner::Trader => change_trader_balance(pool, traders, entry).await,
+        LedgerOwner::Merchant => change_merchant_balance(pool, merchants, entry).await,
+    }
+}
+
+pub async fn change_trader_balance(pool: &deadpool_postgres::Pool, traders: &TraderServicePool, entry: &LedgerEntry)
+    -> Result<(), LibError>
+{
+    let amount = entry.amount.to_f64().ok_or(InvalidAmount)?;
+    traders.get().await.change_balance_with_key(entry.owner_id.clone(), amount, entry.action.try_into()?,
+                                                entry.idempotent_key.clone()).await?;
+    record(pool, entry).await
+}
+
+pub async fn change_merchant_balance(pool: &deadpool_postgres::Pool, merchants: &MerchantService, entry: &LedgerEntry)
+    -> Result<(), LibError>
+{
+    let amount = entry.amount.to_f64().ok_or(InvalidAmount)?;
+    merchants.clone().change_balance_with_key(entry.owner_id.clone(), amount, entry.action.try_into()?,
+                                              entry.idempotent_key.clone()).await?;
+    record(pool, entry).await
+}
+
+// посев начального остатка по балансам main и frozen, снятым в сервисе трейдеров или мерчантов.
+// в леджер пишется разница с уже записанными проводками, поэтому снимок берется, пока по владельцу
+// нет изменений в процессе. false - остаток уже посеян
+pub async fn seed_opening_balance(pool: &deadpool_postgres::Pool, owner_type: LedgerOwner, owner_id: &str,
+                                  service_balances: &LedgerBalances) -> Result<bool, LibError> {
+    let mut pg = pool.get().await.map_err(|e| {
+        error!(err=e.to_string(), "Error get PG connection");
+        InternalError
+    })?;
+    let seeded = repository::ledger::seed_opening_balance(&mut pg, owner_type, owner_id, service_balances).await?;
+    if seeded {
+        info!(owner_type=%owner_type, owner_id=owner_id, "ledger opening balance seeded");
+    }
+    Ok(seeded)
+}
+
+// баланс в сервисе уже изменен, поэтому ошибку записи не отдаем вызывающему коду: он принял бы ее
+// за неприменившееся действие. вставка идемпотентна по idempotent_key, повторяем ее здесь же,
+// а если леджер так и не записался - движение без записи покажет сверка платежей
+async fn record(pool: &deadpool_postgres::Pool, entry: &LedgerEntry) -> Result<(), LibError> {
+    let policy = RetryPolicy::default()
+        .with_max_attempts(6)
+        .with_backoff(Duration::from_millis(200), Duration::from_secs(5), 2.0)
+        .with_deadline(Duration::from_secs(15));
+    let started = Instant::now();
+    let mut attempt = 0;
+    loop {
+        attempt += 1;
+        let err = match try_record(pool, entry).await {
+            Ok(()) => return Ok(()),
+            Err(e) => e,
+        };
+        // InternalError - сбой базы, остальное (например, неверная сумма) повтор не исправит
+        let kind = if err == InternalError { ErrorKind::Transient } else { ErrorKind::Permanent };
+        match policy.next_delay(attempt, kind, started) {
+            Some(delay) => {
+                warn!(owner_id=entry.owner_id, idempotent_key=entry.idempotent_key, attempt=attempt, err=?err,
+                    "Error record ledger entry, retrying");
+                tokio::time::sleep(delay).await;
+            }
+            None => {
+                error!(owner_id=entry.owner_id, idempotent_key=entry.idempotent_key, action=%entry.action,
+                    amount=%entry.amount, reference_id=?entry.reference_id, attempts=attempt, err=?err,
+                    "Balance changed but ledger entry was not recorded");
+                return Ok(());
+            }
+        }
+    }
+}
+
+async fn try_record(pool: &deadpool_postgres::Pool, entry: &LedgerEntry) -> Result<(), LibError> {
+    let mut pg = pool.get().await.map_err(|e| {
+        error!(err=e.to_string(), "Error get PG connection");
+        InternalError
+    })?;
+    repository::ledger::record_balance_action(&mut pg, entry).await?;
+    Ok(())
+}
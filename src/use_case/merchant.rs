@@ -1,4 +1,6 @@
+use std::net::IpAddr;
 use std::sync::Arc;
+use std::time::Duration;
 use base64::Engine;
 use base64::engine::general_purpose;
 use rsa::pkcs1::DecodeRsaPublicKey;
@@ -6,45 +8,50 @@ use rsa::pkcs1::der::zeroize::Zeroizing;
 use rsa::{Pkcs1v15Sign, RsaPublicKey};
 use rsa::sha2::{Digest, Sha256};
 use rsa::traits::SignatureScheme;
-use tracing::{error, warn};
+use once_cell::sync::Lazy;
+use tracing::error;
 use crate::errors::LibError;
 use crate::{map_err_with_log, models, repository};
-use crate::errors::LibError::InternalError;
+use crate::errors::LibError::{Forbidden, InternalError, MerchantNotFound, NotFound};
+use crate::models::Claims;
+use crate::models::ip_allowlist::IpAllowlist;
+use crate::repository::cache::ReadThroughCache;
 
-pub(crate) async fn check_merchant_is_blocked(
-    state: Arc<models::AuthState>,
-    trader_id: &str
-) -> Result<bool, LibError> {
-    let conn_result = state.rdb.get().await;
-    let mut conn = match conn_result {
-        Ok(conn) => conn,
-        Err(e) => {
-            let pg = state.pool.get().await.map_err(|e|{
-                error!(err=e.to_string(), "Error get PG connection");
-                InternalError
-            })?;
-            error!("Error getting Redis connection: {}", e);
-            return repository::merchant::check_merchant_is_blocked_from_db(&pg, trader_id).await;
-        }
-    };
+pub(crate) static MERCHANT_IS_BLOCKED: Lazy<ReadThroughCache<String, bool>> = Lazy::new(|| {
+    ReadThroughCache::new(repository::merchant::MERCHANT_IS_BLOCKED_CACHE,
+                          |merchant_id: &String| repository::merchant::merchant_is_blocked_key(merchant_id),
+                          repository::merchant::MERCHANT_IS_BLOCKED_TTL)
+});
 
+pub(crate) static MERCHANT_PUBLIC_KEY: Lazy<ReadThroughCache<String, String>> = Lazy::new(|| {
+    ReadThroughCache::new(repository::merchant::MERCHANT_PUBLIC_KEY_CACHE,
+                          |merchant_id: &String| repository::merchant::merchant_public_key_key(merchant_id),
+                          repository::merchant::MERCHANT_PUBLIC_KEY_TTL)
+        .with_local(Duration::from_secs(60), 10_000)
+});
 
-    match repository::merchant::check_merchant_is_blocked_from_redis(&mut conn.clone(), trader_id).await {
-        Ok(Some(val)) => Ok(val),
-        Ok(None) | Err(_) => {
-            let pg = state.pool.get().await.map_err(|e|{
-                error!(err=e.to_string(), "Error get PG connection");
-                InternalError
-            })?;
-            let blocked = repository::merchant::check_merchant_is_blocked_from_db(&pg, trader_id).await?;
-            let trader_id = trader_id.to_string();
-            tokio::spawn(async move {
-                let _ = repository::merchant::set_trader_is_blocked_to_redis(&mut conn, trader_id, blocked).await;
+pub(crate) static MERCHANT_IP_ALLOWLIST: Lazy<ReadThroughCache<String, IpAllowlist>> = Lazy::new(|| {
+    ReadThroughCache::new(repository::merchant::MERCHANT_IP_ALLOWLIST_CACHE,
+                          |merchant_id: &String| repository::merchant::merchant_ip_allowlist_key(merchant_id), Duration::from_secs(60 * 60))
+        .with_local(Duration::from_secs(60), 10_000)
+});
 
-            });
-            Ok(blocked)
+pub(crate) async fn check_merchant_is_blocked(
+    state: Arc<models::AuthState>,
+    merchant_id: &str
+) -> Result<bool, LibError> {
+    let blocked = MERCHANT_IS_BLOCKED.get(&state.rdb, &merchant_id.to_string(), || async {
+        let pg = state.pool.get().await.map_err(|e|{
+            error!(err=e.to_string(), "Error get PG connection");
+            InternalError
+        })?;
+        match repository::merchant::check_merchant_is_blocked_from_db(&pg, merchant_id).await {
+            Ok(blocked) => Ok(Some(blocked)),
+            Err(MerchantNotFound) => Ok(None),
+            Err(e) => Err(e),
         }
-    }
+    }).await?;
+    blocked.ok_or(MerchantNotFound)
 }
 
 pub async fn verify_signature(state: Arc<models::AuthState>, merchant_id: &str, signature: &str, raw_line: &str)
@@ -68,30 +75,40 @@ pub async fn verify_signature(state: Arc<models::AuthState>, merchant_id: &str,
 pub async fn get_public_key(state: Arc<models::AuthState>, merchant_id: &str)
                             -> Result<Zeroizing<String>, LibError>
 {
-    let mut conn = match state.rdb.get().await {
-        Ok(c) => c,
-        Err(e) => {
-            error!(err=e.to_string(), "Error get redis connection");
-            let pg = map_err_with_log!(state.pool.get().await, "Error get DB connection", InternalError, merchant_id)?;
-            let key = repository::merchant::get_public_key_from_db(&pg, merchant_id).await?;
-            return Ok(Zeroizing::new(key));
+    // мерчант без ключа кэшируется как отсутствующая запись
+    let key = MERCHANT_PUBLIC_KEY.get(&state.rdb, &merchant_id.to_string(), || async {
+        let pg = map_err_with_log!(state.pool.get().await, "Error get DB connection", InternalError, merchant_id)?;
+        match repository::merchant::get_public_key_from_db(&pg, merchant_id).await {
+            Ok(key) => Ok(Some(key)),
+            Err(NotFound) => Ok(None),
+            Err(e) => Err(e),
         }
-    };
-    match repository::merchant::get_public_key_from_redis(&mut conn, merchant_id).await {
-        Ok(Some(public_key)) => Ok(Zeroizing::new(public_key)),
-        Ok(None) => {
-            warn!(merchant_id=merchant_id,"public not found in redis");
-            let pg = map_err_with_log!(state.pool.get().await,"Error get DB connection",
-                InternalError, merchant_id)?;
-            let key = repository::merchant::get_public_key_from_db(&pg, merchant_id).await?;
-            let _ = repository::merchant::set_public_key_in_redis(&mut conn, merchant_id, &key).await;
-            Ok(Zeroizing::new(key))
-        },
-        Err(_) => {
-            let pg = map_err_with_log!(state.pool.get().await,"Error get DB connection",
-                InternalError, merchant_id)?;
-            let key = repository::merchant::get_public_key_from_db(&pg, merchant_id).await?;
-            Ok(Zeroizing::new(key))
+    }).await?;
+    key.map(Zeroizing::new).ok_or(NotFound)
+}
+
+// ip None - адрес клиента неизвестен, пропускаются только мерчанты без allowlist
+pub(crate) async fn check_ip_allowed(state: Arc<models::AuthState>, merchant_id: &str, ip: Option<IpAddr>)
+    -> Result<bool, LibError>
+{
+    let allowlist = MERCHANT_IP_ALLOWLIST.get(&state.rdb, &merchant_id.to_string(), || async {
+        let pg = map_err_with_log!(state.pool.get().await, "Error get DB connection", InternalError, merchant_id)?;
+        match repository::merchant::get_ip_allowlist_from_db(&pg, merchant_id).await {
+            Ok(allowlist) => Ok(Some(allowlist)),
+            Err(MerchantNotFound) => Ok(None),
+            Err(e) => Err(e),
         }
+    }).await?;
+    let allowlist = allowlist.ok_or(MerchantNotFound)?;
+    Ok(allowlist.is_empty() || ip.is_some_and(|ip| allowlist.contains(ip)))
+}
+
+pub async fn set_ip_allowlist(state: &models::AuthState, claims: &Claims, merchant_id: &str, allowlist: IpAllowlist)
+    -> Result<(), LibError>
+{
+    if claims.role.to_lowercase() != "admin" {
+        return Err(Forbidden);
     }
-}
\ No newline at end of file
+    let pg = map_err_with_log!(state.pool.get().await, "Error get DB connection", InternalError, merchant_id)?;
+    repository::merchant::set_ip_allowlist(&pg, &state.rdb, merchant_id, &allowlist).await
+}
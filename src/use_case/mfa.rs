@@ -0,0 +1,97 @@
+use chrono::Utc;
+use jsonwebtoken::{encode, EncodingKey, Header};
+use tracing::{error, info, warn};
+use crate::errors::LibError;
+use crate::errors::LibError::{Conflict, Forbidden, InternalError, NotFound, Unauthorized};
+use crate::middlewares::SECRET;
+use crate::models::{AuthState, Claims};
+use crate::models::mfa::{base32_decode, generate_recovery_codes, generate_totp_secret, hash_recovery_code,
+                         otpauth_uri, verify_totp, MfaEnrollment, MfaToken, MFA_ROLES};
+use crate::models::rate_limit::RateLimit;
+use crate::repository;
+use crate::use_case::rate_limit::check_rate_limit_strict;
+
+const MFA_VERIFY_ROUTE_GROUP: &str = "mfa_verify";
+// перебор 6 цифр должен быть дольше жизни кода
+const MFA_VERIFY_LIMIT: RateLimit = RateLimit { limit: 5, period_ms: 5 * 60 * 1000 };
+
+// секрет действует после первого успешного verify
+pub async fn enroll(state: &AuthState, claims: &Claims) -> Result<MfaEnrollment, LibError> {
+    check_mfa_role(claims)?;
+    let secret = generate_totp_secret();
+    let recovery_codes = generate_recovery_codes();
+    let code_hashes: Vec<String> = recovery_codes.iter().map(|code| hash_recovery_code(code)).collect();
+    let mut pg = state.pool.get().await.map_err(|e| {
+        error!(err=e.to_string(), "Error get PG connection");
+        InternalError
+    })?;
+    let tx = pg.transaction().await.map_err(|e| {
+        error!(err=e.to_string(), "Error begin mfa transaction");
+        InternalError
+    })?;
+    if !repository::mfa::upsert_mfa_secret(&tx, &claims.sub, &claims.role.to_lowercase(), &secret).await? {
+        return Err(Conflict);
+    }
+    repository::mfa::replace_recovery_codes(&tx, &claims.sub, &code_hashes).await?;
+    tx.commit().await.map_err(|e| {
+        error!(err=e.to_string(), "Error commit mfa transaction");
+        InternalError
+    })?;
+    Ok(MfaEnrollment { otpauth_uri: otpauth_uri(&claims.sub, &secret), secret, recovery_codes })
+}
+
+// проверяет TOTP или код восстановления и выдает токен с mfa_at
+pub async fn verify(state: &AuthState, claims: &Claims, code: &str) -> Result<MfaToken, LibError> {
+    check_mfa_role(claims)?;
+    check_rate_limit_strict(state, &claims.sub, MFA_VERIFY_ROUTE_GROUP, MFA_VERIFY_LIMIT).await?;
+    let pg = state.pool.get().await.map_err(|e| {
+        error!(err=e.to_string(), "Error get PG connection");
+        InternalError
+    })?;
+    let secret = repository::mfa::get_mfa_secret(&pg, &claims.sub).await?.ok_or(NotFound)?;
+    let key = base32_decode(&secret.secret).ok_or_else(|| {
+        error!(user_id=claims.sub, "Invalid mfa secret in DB");
+        InternalError
+    })?;
+    let now = Utc::now().timestamp();
+    let verified = match verify_totp(&key, code.trim(), now, secret.last_step) {
+        Some(step) => repository::mfa::use_totp_step(&pg, &claims.sub, step).await?,
+        // коды восстановления действуют только для подтвержденного второго фактора
+        None if secret.confirmed_at.is_some() => {
+            let used = repository::mfa::use_recovery_code(&pg, &claims.sub, &hash_recovery_code(code)).await?;
+            if used {
+                info!(user_id=claims.sub, "mfa recovery code used");
+            }
+            used
+        }
+        None => false,
+    };
+    if !verified {
+        warn!(user_id=claims.sub, "mfa verification failed");
+        return Err(Unauthorized);
+    }
+    Ok(MfaToken { token: issue_step_up_token(claims, now as usize)? })
+}
+
+// тот же токен, но с отметкой о прохождении второго фактора
+fn issue_step_up_token(claims: &Claims, mfa_at: usize) -> Result<String, LibError> {
+    let step_up = Claims {
+        sub: claims.sub.clone(),
+        role: claims.role.clone(),
+        exp: claims.exp,
+        impersonated_by: None,
+        mfa_at: Some(mfa_at),
+    };
+    encode(&Header::default(), &step_up, &EncodingKey::from_secret(SECRET.as_bytes())).map_err(|e| {
+        error!(user_id=claims.sub, err=e.to_string(), "Error encode step-up token");
+        InternalError
+    })
+}
+
+// второй фактор подключает сам пользователь, не админ под его именем
+fn check_mfa_role(claims: &Claims) -> Result<(), LibError> {
+    if claims.impersonated_by.is_some() || !MFA_ROLES.contains(&claims.role.to_lowercase().as_str()) {
+        return Err(Forbidden);
+    }
+    Ok(())
+}
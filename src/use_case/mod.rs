@@ -1,3 +1,19 @@
 pub mod trader;
 pub mod merchant;
-pub mod kafka;
\ No newline at end of file
+pub mod kafka;
+pub mod reconciliation;
+pub mod ledger;
+pub mod saga;
+pub mod payment_saga;
+pub mod expiry;
+pub mod dispute;
+pub mod close;
+pub mod refund;
+pub mod export;
+pub mod settlement;
+pub mod payout;
+pub mod cache;
+pub mod block;
+pub mod audit;
+pub mod rate_limit;
+pub mod mfa;
\ No newline at end of file
@@ -0,0 +1,417 @@
+use std::sync::Arc;
+use async_trait::async_trait;
+use rust_decimal::Decimal;
+use rust_decimal::prelude::ToPrimitive;
+use serde::{Deserialize, Serialize};
+use tracing::{error, warn};
+use crate::errors::LibError;
+use crate::errors::LibError::{Conflict, InternalError, InvalidAmount, NoAvailableRequisites};
+use crate::models::ledger::{LedgerEntry, LedgerOwner};
+use crate::models::payments::payment::{FullPayment, PaymentSides, PaymentStatuses};
+use crate::models::reconciliation::{expected_balance_actions, ExpectedBalanceAction};
+use crate::models::saga::SagaRecovery;
+use crate::services::exchange::exchange_service::ExchangeService;
+use crate::services::merchants::merchant_service::MerchantService;
+use crate::services::payments::payment_service::PaymentService;
+use crate::services::requisites::requisite_service::RequisiteServicePool;
+use crate::services::traders::trader_service::TraderServicePool;
+use crate::use_case::{close, ledger};
+use crate::use_case::saga::{Saga, SagaStep};
+use crate::{merchant_proto, repository, requisites_proto, trader_proto};
+
+pub const CREATE_PAYMENT_SAGA: &str = "create_payment";
+pub const CLOSE_PAYMENT_SAGA: &str = "close_payment";
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct AssignedRequisite {
+    pub id: String,
+    pub trader_id: String,
+    pub bank_id: String,
+    pub bank_name: String,
+    pub holder_name: String,
+    pub holder_account: String,
+    pub method: String,
+    pub last_four: String,
+    pub card_last_four: String,
+}
+
+impl From<requisites_proto::Requisite> for AssignedRequisite {
+    fn from(value: requisites_proto::Requisite) -> Self {
+        Self {
+            id: value.id,
+            trader_id: value.trader_id,
+            bank_id: value.bank_id,
+            bank_name: value.bank_name,
+            holder_name: value.holder_name,
+            holder_account: value.holder_account,
+            method: value.method,
+            last_four: value.last_four,
+            card_last_four: value.card_last_four,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct PaymentSagaContext {
+    pub payment_id: String,
+    pub merchant_id: String,
+    pub payment_side: PaymentSides,
+    pub currency: String,
+    pub method_type: Option<String>,
+    pub bank: Option<String>,
+    pub cross_border: Option<bool>,
+    pub fiat_amount: Decimal,
+    // если не заданы, считаются по курсу на шаге exchange_rate
+    pub crypto_amount: Decimal,
+    pub trader_crypto_amount: Decimal,
+    pub exchange_rate: Option<Decimal>,
+    pub candidates: Vec<AssignedRequisite>,
+    pub requisite: Option<AssignedRequisite>,
+    // трейдер, заморозка которого запущена последней. ее результат может быть неизвестен,
+    // поэтому повтор шага начинает с него
+    pub freezing_trader_id: Option<String>,
+    // сумма для ClosePayment
+    pub close_amount: Option<f64>,
+}
+
+impl PaymentSagaContext {
+    fn trader_id(&self) -> Result<String, LibError> {
+        self.requisite.as_ref().map(|r| r.trader_id.clone()).ok_or_else(|| {
+            error!(payment_id=self.payment_id, "saga context without requisite");
+            InternalError
+        })
+    }
+}
+
+fn to_f64(amount: Decimal) -> Result<f64, LibError> {
+    amount.to_f64().ok_or(InvalidAmount)
+}
+
+fn step_key(saga_id: &str, step: &str) -> String {
+    format!("{}:{}", saga_id, step)
+}
+
+fn compensate_key(saga_id: &str, step: &str) -> String {
+    format!("{}:{}:compensate", saga_id, step)
+}
+
+pub struct FetchExchangeRate {
+    exchange: ExchangeService,
+}
+
+#[async_trait]
+impl SagaStep<PaymentSagaContext> for FetchExchangeRate {
+    fn name(&self) -> &'static str {
+        "exchange_rate"
+    }
+
+    async fn execute(&self, _saga_id: &str, ctx: &mut PaymentSagaContext) -> Result<(), LibError> {
+        let rate = match ctx.exchange_rate {
+            Some(rate) => rate,
+            None => self.exchange.clone().get_exchange_rate().await?,
+        };
+        if rate <= Decimal::ZERO {
+            return Err(InvalidAmount);
+        }
+        ctx.exchange_rate = Some(rate);
+        if ctx.crypto_amount.is_zero() {
+            ctx.crypto_amount = (ctx.fiat_amount / rate).round_dp(6);
+        }
+        if ctx.trader_crypto_amount.is_zero() {
+            ctx.trader_crypto_amount = ctx.crypto_amount;
+        }
+        Ok(())
+    }
+
+    async fn compensate(&self, _saga_id: &str, _ctx: &PaymentSagaContext) -> Result<(), LibError> {
+        Ok(())
+    }
+}
+
+pub struct FindRequisites {
+    requisites: RequisiteServicePool,
+}
+
+#[async_trait]
+impl SagaStep<PaymentSagaContext> for FindRequisites {
+    fn name(&self) -> &'static str {
+        "find_requisites"
+    }
+
+    async fn execute(&self, _saga_id: &str, ctx: &mut PaymentSagaContext) -> Result<(), LibError> {
+        if !ctx.candidates.is_empty() {
+            return Ok(());
+        }
+        let candidates = self.requisites.get().await
+            .get_requisites_for_payment(ctx.method_type.clone(), to_f64(ctx.fiat_amount)?, ctx.currency.clone(),
+                                        ctx.bank.clone(), ctx.cross_border).await?;
+        if candidates.is_empty() {
+            return Err(NoAvailableRequisites);
+        }
+        ctx.candidates = candidates.into_iter().map(AssignedRequisite::from).collect();
+        Ok(())
+    }
+
+    async fn compensate(&self, _saga_id: &str, _ctx: &PaymentSagaContext) -> Result<(), LibError> {
+        Ok(())
+    }
+}
+
+// замораживает баланс первого трейдера из кандидатов, у которого хватает средств
+pub struct FreezeTrader {
+    pool: deadpool_postgres::Pool,
+    traders: TraderServicePool,
+}
+
+#[async_trait]
+impl SagaStep<PaymentSagaContext> for FreezeTrader {
+    fn name(&self) -> &'static str {
+        "freeze_trader"
+    }
+
+    async fn execute(&self, saga_id: &str, ctx: &mut PaymentSagaContext) -> Result<(), LibError> {
+        let freezing = ctx.freezing_trader_id.clone();
+        let (first, rest): (Vec<_>, Vec<_>) = ctx.candidates.iter().cloned()
+            .partition(|candidate| freezing.as_ref() == Some(&candidate.trader_id));
+        for candidate in first.into_iter().chain(rest) {
+            ctx.freezing_trader_id = Some(candidate.trader_id.clone());
+            let key = step_key(saga_id, &format!("{}:{}", self.name(), candidate.trader_id));
+            let entry = LedgerEntry::trader(candidate.trader_id.clone(), ctx.trader_crypto_amount,
+                trader_proto::BalanceActionType::FrozeHard, ctx.payment_id.clone(), key);
+            match ledger::change_trader_balance(&self.pool, &self.traders, &entry).await {
+                Ok(()) => {
+                    ctx.requisite = Some(candidate);
+                    return Ok(());
+                }
+                Err(LibError::InsufficientFunds) => {
+                    warn!(trader_id=candidate.trader_id, payment_id=ctx.payment_id, "trader has insufficient funds");
+                    continue;
+                }
+                Err(e) => return Err(e),
+            }
+        }
+        ctx.freezing_trader_id = None;
+        Err(NoAvailableRequisites)
+    }
+
+    async fn compensate(&self, saga_id: &str, ctx: &PaymentSagaContext) -> Result<(), LibError> {
+        let entry = LedgerEntry::trader(ctx.trader_id()?, ctx.trader_crypto_amount,
+            trader_proto::BalanceActionType::Unfroze, ctx.payment_id.clone(), compensate_key(saga_id, self.name()));
+        ledger::change_trader_balance(&self.pool, &self.traders, &entry).await
+    }
+}
+
+// на SELL мерчант замораживает сумму выплаты
+pub struct FreezeMerchant {
+    pool: deadpool_postgres::Pool,
+    merchants: MerchantService,
+}
+
+#[async_trait]
+impl SagaStep<PaymentSagaContext> for FreezeMerchant {
+    fn name(&self) -> &'static str {
+        "freeze_merchant"
+    }
+
+    async fn execute(&self, saga_id: &str, ctx: &mut PaymentSagaContext) -> Result<(), LibError> {
+        if ctx.payment_side != PaymentSides::Sell {
+            return Ok(());
+        }
+        let entry = LedgerEntry::merchant(ctx.merchant_id.clone(), ctx.crypto_amount,
+            merchant_proto::BalanceActionType::FrozeHard, ctx.payment_id.clone(), step_key(saga_id, self.name()));
+        ledger::change_merchant_balance(&self.pool, &self.merchants, &entry).await
+    }
+
+    async fn compensate(&self, saga_id: &str, ctx: &PaymentSagaContext) -> Result<(), LibError> {
+        if ctx.payment_side != PaymentSides::Sell {
+            return Ok(());
+        }
+        let entry = LedgerEntry::merchant(ctx.merchant_id.clone(), ctx.crypto_amount,
+            merchant_proto::BalanceActionType::Unfroze, ctx.payment_id.clone(), compensate_key(saga_id, self.name()));
+        ledger::change_merchant_balance(&self.pool, &self.merchants, &entry).await
+    }
+}
+
+pub struct ClosePayment {
+    pool: deadpool_postgres::Pool,
+    payments: PaymentService,
+}
+
+#[async_trait]
+impl SagaStep<PaymentSagaContext> for ClosePayment {
+    fn name(&self) -> &'static str {
+        "close_payment"
+    }
+
+    async fn execute(&self, _saga_id: &str, ctx: &mut PaymentSagaContext) -> Result<(), LibError> {
+        match self.payments.clone().close_payment(ctx.payment_id.clone(), ctx.close_amount).await {
+            Ok(()) => Ok(()),
+            // Conflict и у закрытого при прошлой попытке, и у отмененного или просроченного платежа
+            Err(LibError::Conflict) => completed_payment(&self.pool, &ctx.payment_id).await.map(|_| ()),
+            Err(e) => Err(e),
+        }
+    }
+
+    async fn compensate(&self, saga_id: &str, ctx: &PaymentSagaContext) -> Result<(), LibError> {
+        // закрытый платеж нельзя переоткрыть, сага уходит в failed для ручного разбора
+        error!(saga_id=saga_id, payment_id=ctx.payment_id, "closed payment can not be compensated");
+        Err(LibError::Conflict)
+    }
+}
+
+async fn completed_payment(pool: &deadpool_postgres::Pool, payment_id: &str) -> Result<FullPayment, LibError> {
+    let pg = pool.get().await.map_err(|e| {
+        error!(err=e.to_string(), "Error get PG connection");
+        InternalError
+    })?;
+    let payment = repository::payment::get_payment(&pg, payment_id).await?;
+    if payment.status != PaymentStatuses::Completed {
+        warn!(payment_id=payment_id, status=%payment.status, "payment is not completed");
+        return Err(Conflict);
+    }
+    Ok(payment)
+}
+
+// движения одной стороны по закрытому платежу. суммы берутся из платежа после закрытия,
+// на SELL трейдеру снимается заморозка и зачисляется крипта
+pub fn settle_actions(payment: &FullPayment, owner_type: LedgerOwner) -> Vec<ExpectedBalanceAction> {
+    expected_balance_actions(payment).into_iter().filter(|action| action.owner_type == owner_type).collect()
+}
+
+// компенсация в обратном порядке
+pub fn reverse_actions(actions: &[ExpectedBalanceAction]) -> Vec<ExpectedBalanceAction> {
+    actions.iter().rev().map(|action| ExpectedBalanceAction { action: action.action.reversal(), ..action.clone() }).collect()
+}
+
+pub struct SettlePayment {
+    owner_type: LedgerOwner,
+    pool: deadpool_postgres::Pool,
+    traders: TraderServicePool,
+    merchants: MerchantService,
+}
+
+#[async_trait]
+impl SagaStep<PaymentSagaContext> for SettlePayment {
+    fn name(&self) -> &'static str {
+        match self.owner_type {
+            LedgerOwner::Trader => "settle_trader",
+            LedgerOwner::Merchant => "settle_merchant",
+        }
+    }
+
+    // ключи те же, что у close::close_payment, а не от saga_id
+    async fn execute(&self, _saga_id: &str, ctx: &mut PaymentSagaContext) -> Result<(), LibError> {
+        let payment = completed_payment(&self.pool, &ctx.payment_id).await?;
+        close::settle_payment(&self.pool, &self.traders, &self.merchants, &payment, self.owner_type).await
+    }
+
+    async fn compensate(&self, saga_id: &str, ctx: &PaymentSagaContext) -> Result<(), LibError> {
+        // расчет по закрытому платежу верен и не откатывается, сага закрытия только доводится до конца
+        error!(saga_id=saga_id, payment_id=ctx.payment_id, step=self.name(), "payment settlement can not be compensated");
+        Err(LibError::Conflict)
+    }
+}
+
+#[derive(Clone)]
+pub struct PaymentSagaServices {
+    pub pool: deadpool_postgres::Pool,
+    pub traders: TraderServicePool,
+    pub merchants: MerchantService,
+    pub requisites: RequisiteServicePool,
+    pub exchange: ExchangeService,
+    pub payments: PaymentService,
+    // сохранение платежа в приложении, последний шаг саги создания. задается вместе с сервисами,
+    // поэтому resume_interrupted собирает сагу с теми же шагами, что и при запуске
+    pub save_payment: Arc<dyn SagaStep<PaymentSagaContext>>,
+}
+
+impl PaymentSagaServices {
+    // создание платежа: при падении посередине все откатывается
+    pub fn create_payment_saga(&self) -> Saga<PaymentSagaContext> {
+        Saga::new(CREATE_PAYMENT_SAGA, SagaRecovery::Rollback, self.pool.clone())
+            .step(FetchExchangeRate { exchange: self.exchange.clone() })
+            .step(FindRequisites { requisites: self.requisites.clone() })
+            .step(FreezeTrader { pool: self.pool.clone(), traders: self.traders.clone() })
+            .step(FreezeMerchant { pool: self.pool.clone(), merchants: self.merchants.clone() })
+            .step(self.save_payment.clone())
+    }
+
+    // закрытие платежа: после падения доводится до конца
+    pub fn close_payment_saga(&self) -> Saga<PaymentSagaContext> {
+        Saga::new(CLOSE_PAYMENT_SAGA, SagaRecovery::Resume, self.pool.clone())
+            .step(ClosePayment { pool: self.pool.clone(), payments: self.payments.clone() })
+            .step(self.settle_step(LedgerOwner::Trader))
+            .step(self.settle_step(LedgerOwner::Merchant))
+    }
+
+    fn settle_step(&self, owner_type: LedgerOwner) -> SettlePayment {
+        SettlePayment {
+            owner_type,
+            pool: self.pool.clone(),
+            traders: self.traders.clone(),
+            merchants: self.merchants.clone(),
+        }
+    }
+
+    // подбирает зависшие саги и доводит их до конечного состояния, возвращает количество обработанных
+    pub async fn resume_interrupted(&self, stale_secs: i64, limit: i64) -> Result<usize, LibError> {
+        let pg = self.pool.get().await.map_err(|e| {
+            error!(err=e.to_string(), "Error get PG connection");
+            InternalError
+        })?;
+        let sagas = repository::saga::claim_stale_sagas(&pg, stale_secs, stale_secs, limit).await?;
+        drop(pg);
+        let count = sagas.len();
+        for state in sagas {
+            let saga = match state.kind.as_str() {
+                CREATE_PAYMENT_SAGA => self.create_payment_saga(),
+                CLOSE_PAYMENT_SAGA => self.close_payment_saga(),
+                _ => {
+                    warn!(saga_id=state.id, kind=state.kind, "unknown saga kind");
+                    continue;
+                }
+            };
+            let saga_id = state.id.clone();
+            if let Err(e) = saga.resume(state).await {
+                warn!(saga_id=saga_id, err=?e, "resumed saga finished with error");
+            }
+        }
+        Ok(count)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_decimal::dec;
+    use crate::models::ledger::BalanceAction;
+    use super::*;
+
+    #[test]
+    fn sell_settlement_releases_trader_freeze() {
+        let payment = FullPayment {
+            id: "p1".to_string(),
+            merchant_id: "m1".to_string(),
+            trader_id: "t1".to_string(),
+            status: PaymentStatuses::Completed,
+            payment_side: PaymentSides::Sell,
+            crypto_amount: dec!(10),
+            trader_crypto_amount: dec!(10.2),
+            ..Default::default()
+        };
+        let actions = |owner_type| settle_actions(&payment, owner_type).into_iter()
+            .map(|action| (action.action, action.amount))
+            .collect::<Vec<_>>();
+        assert_eq!(actions(LedgerOwner::Trader),
+                   vec![(BalanceAction::Unfroze, dec!(10.2)), (BalanceAction::Deposit, dec!(10.2))]);
+        assert_eq!(actions(LedgerOwner::Merchant), vec![(BalanceAction::WithdrawFrozen, dec!(10))]);
+
+        let reversed = reverse_actions(&settle_actions(&payment, LedgerOwner::Trader)).into_iter()
+            .map(|action| action.action)
+            .collect::<Vec<_>>();
+        assert_eq!(reversed, vec![BalanceAction::WithdrawMain, BalanceAction::FrozeHard]);
+
+        let buy = FullPayment { payment_side: PaymentSides::Buy, ..payment };
+        assert_eq!(settle_actions(&buy, LedgerOwner::Trader).into_iter().map(|a| a.action).collect::<Vec<_>>(),
+                   vec![BalanceAction::WithdrawFrozen]);
+    }
+}
@@ -0,0 +1,158 @@
+use tracing::{error, info, warn};
+use crate::errors::LibError;
+use crate::errors::LibError::{Conflict, InternalError, NotFound};
+use crate::models::ledger::LedgerEntry;
+use crate::models::payments::earnings::{EarningsRequest, EarningsRow};
+use crate::models::payments::payout::{NewPayoutRequest, PayoutBatch, PayoutEventKind, PayoutStatus, PayoutStep};
+use crate::services::traders::trader_service::TraderServicePool;
+use crate::trader_proto;
+use crate::use_case::ledger;
+use crate::repository;
+
+pub struct PayoutServices {
+    pub pool: deadpool_postgres::Pool,
+    pub traders: TraderServicePool,
+}
+
+impl PayoutServices {
+    async fn client(&self) -> Result<deadpool_postgres::Object, LibError> {
+        self.pool.get().await.map_err(|e| {
+            error!(err=e.to_string(), "Error get PG connection");
+            InternalError
+        })
+    }
+
+    pub async fn get_earnings(&self, trader_id: &str, request: &EarningsRequest) -> Result<Vec<EarningsRow>, LibError> {
+        let pg = self.client().await?;
+        repository::payout::get_trader_earnings(&pg, trader_id, request).await
+    }
+
+    // закрепляет выплачиваемые платежи за пачкой и начисляет их сумму трейдеру.
+    // повтор с тем же ключом доводит незавершенную пачку
+    pub async fn create_payout(&self, actor_id: &str, request: NewPayoutRequest) -> Result<PayoutBatch, LibError> {
+        let mut pg = self.client().await?;
+        let tx = pg.transaction().await.map_err(|e| {
+            error!(err=e.to_string(), "Error begin payout transaction");
+            InternalError
+        })?;
+        repository::payout::lock_trader_payouts(&tx, &request.trader_id).await?;
+        let batch = match repository::payout::get_payout_by_key(&tx, &request.trader_id, &request.idempotent_key).await? {
+            Some(batch) => match batch.next_step() {
+                PayoutStep::Done(batch) => return Ok(batch),
+                PayoutStep::Settle(batch) => batch,
+            },
+            None => {
+                let items = repository::payout::get_payable_payments(&tx, &request.trader_id, request.until).await?;
+                let now = chrono::Utc::now().naive_utc();
+                let batch = PayoutBatch::new(actor_id, request, &items, now)?;
+                repository::payout::insert_payout_batch(&tx, &batch, &items).await?;
+                repository::payout::insert_payout_event(&tx, &batch.id, PayoutEventKind::Created, actor_id,
+                    Some(format!("amount={} payments={}", batch.amount, batch.payments_count)), now).await?;
+                batch
+            }
+        };
+        tx.commit().await.map_err(|e| {
+            error!(err=e.to_string(), "Error commit payout transaction");
+            InternalError
+        })?;
+        self.settle(batch, actor_id).await
+    }
+
+    // повтор начисления по пачке, которая осталась PENDING после ошибки trader-сервиса
+    pub async fn resume_payout(&self, actor_id: &str, batch_id: &str) -> Result<PayoutBatch, LibError> {
+        let pg = self.client().await?;
+        let batch = repository::payout::get_payout_batch(&pg, batch_id).await?.ok_or(NotFound)?;
+        drop(pg);
+        match batch.next_step() {
+            PayoutStep::Done(batch) => Ok(batch),
+            PayoutStep::Settle(batch) => self.settle(batch, actor_id).await,
+        }
+    }
+
+    pub async fn cancel_payout(&self, actor_id: &str, batch_id: &str, reason: &str) -> Result<PayoutBatch, LibError> {
+        let mut pg = self.client().await?;
+        let tx = pg.transaction().await.map_err(|e| {
+            error!(err=e.to_string(), "Error begin payout transaction");
+            InternalError
+        })?;
+        let mut batch = repository::payout::get_payout_for_update(&tx, batch_id).await?.ok_or(NotFound)?;
+        let balance_requested = repository::payout::has_payout_event(&tx, batch_id, PayoutEventKind::BalanceRequested).await?;
+        let now = chrono::Utc::now().naive_utc();
+        batch.cancel(balance_requested, now)?;
+        repository::payout::set_payout_status(&tx, batch_id, PayoutStatus::Failed, now).await?;
+        repository::payout::insert_payout_event(&tx, batch_id, PayoutEventKind::Failed, actor_id,
+            Some(reason.to_string()), now).await?;
+        tx.commit().await.map_err(|e| {
+            error!(err=e.to_string(), "Error commit payout transaction");
+            InternalError
+        })?;
+        info!(batch_id=batch_id, trader_id=batch.trader_id, "payout cancelled");
+        Ok(batch)
+    }
+
+    async fn settle(&self, mut batch: PayoutBatch, actor_id: &str) -> Result<PayoutBatch, LibError> {
+        let entry = LedgerEntry::trader(batch.trader_id.clone(), batch.amount, trader_proto::BalanceActionType::Deposit,
+                                        batch.id.clone(), batch.balance_key());
+        self.request_balance(&batch.id, actor_id).await?;
+        let result = ledger::change_trader_balance(&self.pool, &self.traders, &entry).await;
+        if let Err(e) = result {
+            // пачка остается PENDING, повтор идет с тем же ключом начисления
+            warn!(batch_id=batch.id, trader_id=batch.trader_id, err=?e, "Error credit trader payout");
+            self.record(&batch.id, PayoutEventKind::BalanceError, actor_id, Some(format!("{:?}", e)), None).await?;
+            return Err(e);
+        }
+        let now = self.record(&batch.id, PayoutEventKind::Completed, actor_id, None, Some(PayoutStatus::Completed)).await?;
+        batch.complete(now);
+        info!(batch_id=batch.id, trader_id=batch.trader_id, amount=%batch.amount, "payout completed");
+        Ok(batch)
+    }
+
+    // BALANCE_REQUESTED пишется под той же блокировкой строки пачки, что и отмена: отмена после него
+    // видит запрос, а запрос после отмены видит FAILED
+    async fn request_balance(&self, batch_id: &str, actor_id: &str) -> Result<(), LibError> {
+        let mut pg = self.client().await?;
+        let tx = pg.transaction().await.map_err(|e| {
+            error!(err=e.to_string(), "Error begin payout transaction");
+            InternalError
+        })?;
+        let batch = repository::payout::get_payout_for_update(&tx, batch_id).await?.ok_or(NotFound)?;
+        if let Err(e) = batch.request_balance() {
+            warn!(batch_id=batch_id, status=%batch.status, "payout is not pending, balance not requested");
+            return Err(e);
+        }
+        let now = chrono::Utc::now().naive_utc();
+        repository::payout::insert_payout_event(&tx, batch_id, PayoutEventKind::BalanceRequested, actor_id, None, now).await?;
+        tx.commit().await.map_err(|e| {
+            error!(err=e.to_string(), "Error commit payout transaction");
+            InternalError
+        })?;
+        Ok(())
+    }
+
+    // событие журнала и, если задан, новый статус пачки в одной транзакции
+    async fn record(&self, batch_id: &str, kind: PayoutEventKind, actor_id: &str, details: Option<String>,
+                    status: Option<PayoutStatus>) -> Result<chrono::NaiveDateTime, LibError> {
+        let mut pg = self.client().await?;
+        let tx = pg.transaction().await.map_err(|e| {
+            error!(err=e.to_string(), "Error begin payout transaction");
+            InternalError
+        })?;
+        let now = chrono::Utc::now().naive_utc();
+        let changed = match status {
+            Some(status) => repository::payout::set_payout_status(&tx, batch_id, status, now).await?,
+            None => true,
+        };
+        if !changed {
+            // после BALANCE_REQUESTED пачку не должны были отменить, а трейдеру начисление уже могло пройти
+            error!(batch_id=batch_id, status=?status, kind=%kind,
+                "payout batch is no longer pending, status change conflicts with balance change");
+            return Err(Conflict);
+        }
+        repository::payout::insert_payout_event(&tx, batch_id, kind, actor_id, details, now).await?;
+        tx.commit().await.map_err(|e| {
+            error!(err=e.to_string(), "Error commit payout transaction");
+            InternalError
+        })?;
+        Ok(now)
+    }
+}
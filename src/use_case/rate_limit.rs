@@ -0,0 +1,106 @@
+use once_cell::sync::Lazy;
+use tracing::{error, warn};
+use crate::errors::LibError;
+use crate::errors::LibError::{Forbidden, InternalError, TooManyRequests};
+use crate::models::{AuthState, Claims};
+use crate::models::rate_limit::{retry_after_secs, RateLimit, ALL_ROUTE_GROUPS};
+use crate::repository;
+use crate::repository::cache::ReadThroughCache;
+
+pub(crate) static RATE_LIMITS: Lazy<ReadThroughCache<String, RateLimit>> = Lazy::new(|| {
+    ReadThroughCache::new(repository::rate_limit::RATE_LIMIT_CACHE,
+                          |cache_key: &String| repository::rate_limit::rate_limit_key(cache_key),
+                          repository::rate_limit::RATE_LIMIT_TTL)
+});
+
+// ошибки Redis и БД не блокируют запросы: лимитер пропускает запрос и пишет предупреждение
+pub async fn check_rate_limit(state: &AuthState, subject_id: &str, route_group: &str, default: RateLimit)
+    -> Result<(), LibError>
+{
+    let limit = configured_rate_limit(state, subject_id, route_group, default).await;
+    enforce_rate_limit(state, subject_id, route_group, limit, true).await
+}
+
+// лимит без настроек субъекта. для адресов клиентов: слой по IP отсекает поток запросов до БД,
+// а поиск настроек стоил бы каждому новому адресу двух запросов в нее
+pub async fn check_fixed_rate_limit(state: &AuthState, subject_id: &str, route_group: &str, limit: RateLimit)
+    -> Result<(), LibError>
+{
+    enforce_rate_limit(state, subject_id, route_group, limit, true).await
+}
+
+// для маршрутов, где лимит защищает от перебора (коды второго фактора): без Redis запрос отклоняется.
+// настройки субъекта не применяются - щедрый общий лимит трейдера ускорил бы перебор
+pub async fn check_rate_limit_strict(state: &AuthState, subject_id: &str, route_group: &str, limit: RateLimit)
+    -> Result<(), LibError>
+{
+    enforce_rate_limit(state, subject_id, route_group, limit, false).await
+}
+
+async fn enforce_rate_limit(state: &AuthState, subject_id: &str, route_group: &str, limit: RateLimit,
+                            fail_open: bool) -> Result<(), LibError> {
+    match repository::rate_limit::acquire_rate_limit(&state.rdb, subject_id, route_group, limit).await {
+        Ok(retry_after_us) if retry_after_us > 0 => {
+            warn!(subject_id=subject_id, route_group=route_group, "rate limit exceeded");
+            Err(TooManyRequests(retry_after_secs(retry_after_us)))
+        }
+        Ok(_) => Ok(()),
+        Err(_) if fail_open => Ok(()),
+        Err(e) => {
+            warn!(subject_id=subject_id, route_group=route_group, "rate limiter unavailable, request rejected");
+            Err(e)
+        }
+    }
+}
+
+async fn configured_rate_limit(state: &AuthState, subject_id: &str, route_group: &str, default: RateLimit) -> RateLimit {
+    match get_rate_limit(state, subject_id, route_group).await {
+        Ok(limit) => limit.unwrap_or(default),
+        Err(e) => {
+            warn!(subject_id=subject_id, route_group=route_group, err=format!("{:?}", e), "Error get rate limit, using default");
+            default
+        }
+    }
+}
+
+// лимит группы, иначе общий лимит субъекта; None - действует лимит по умолчанию
+pub async fn get_rate_limit(state: &AuthState, subject_id: &str, route_group: &str)
+    -> Result<Option<RateLimit>, LibError>
+{
+    if let Some(limit) = get_exact_rate_limit(state, subject_id, route_group).await? {
+        return Ok(Some(limit));
+    }
+    if route_group == ALL_ROUTE_GROUPS {
+        return Ok(None);
+    }
+    get_exact_rate_limit(state, subject_id, ALL_ROUTE_GROUPS).await
+}
+
+// route_group None - общий лимит субъекта
+pub async fn set_rate_limit(state: &AuthState, claims: &Claims, subject_id: &str, route_group: Option<&str>,
+                            limit: RateLimit) -> Result<(), LibError> {
+    if claims.role.to_lowercase() != "admin" {
+        return Err(Forbidden);
+    }
+    if !limit.is_valid() {
+        return Err(LibError::invalid_field("limit", "POSITIVE", "limit and period_ms must be positive"));
+    }
+    let pg = state.pool.get().await.map_err(|e| {
+        error!(err=e.to_string(), "Error get PG connection");
+        InternalError
+    })?;
+    repository::rate_limit::set_rate_limit(&pg, &state.rdb, subject_id, route_group.unwrap_or(ALL_ROUTE_GROUPS), limit).await
+}
+
+async fn get_exact_rate_limit(state: &AuthState, subject_id: &str, route_group: &str)
+    -> Result<Option<RateLimit>, LibError>
+{
+    let cache_key = repository::rate_limit::rate_limit_cache_key(subject_id, route_group);
+    RATE_LIMITS.get(&state.rdb, &cache_key, || async {
+        let pg = state.pool.get().await.map_err(|e| {
+            error!(err=e.to_string(), "Error get PG connection");
+            InternalError
+        })?;
+        repository::rate_limit::get_rate_limit(&pg, subject_id, route_group).await
+    }).await
+}
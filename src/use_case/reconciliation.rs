@@ -0,0 +1,29 @@
+use chrono::NaiveDateTime;
+use tracing::{error, info, warn};
+use crate::errors::LibError;
+use crate::errors::LibError::InternalError;
+use crate::models::payments::payment::PaymentStatuses;
+use crate::models::reconciliation::ReconciliationReport;
+use crate::repository;
+
+// сверяет завершенные платежи за окно [from, to) с движениями в леджере
+pub async fn reconcile_payments(pool: &deadpool_postgres::Pool, from: NaiveDateTime, to: NaiveDateTime)
+    -> Result<ReconciliationReport, LibError>
+{
+    let pg = pool.get().await.map_err(|e| {
+        error!(err=e.to_string(), "Error get PG connection");
+        InternalError
+    })?;
+    let payments = repository::payment::get_payments_by_status_in_window(&pg, PaymentStatuses::Completed, from, to).await?;
+    let payment_ids = payments.iter().map(|p| p.id.clone()).collect::<Vec<_>>();
+    // use_case::ledger пишет движения по платежам с reference_id платежа, по возвратам - с id возврата
+    let recorded = repository::ledger::get_ledger_transactions_by_references(&pg, &payment_ids).await?;
+    let report = ReconciliationReport::new(from, to, &payments, &recorded);
+    if report.is_clean() {
+        info!(from=%from, to=%to, payments=report.payments_checked, "reconciliation finished without discrepancies");
+    } else {
+        warn!(from=%from, to=%to, payments=report.payments_checked, discrepancies=report.discrepancies.len(),
+            "reconciliation found discrepancies");
+    }
+    Ok(report)
+}
@@ -0,0 +1,154 @@
+use rdkafka::producer::FutureProducer;
+use tracing::{error, info, warn};
+use uuid::Uuid;
+use crate::errors::LibError;
+use crate::errors::LibError::{Forbidden, InsufficientFunds, InternalError};
+use crate::models::Claims;
+use crate::models::payments::merchant::MerchantPayment;
+use crate::models::payments::payment::FullPayment;
+use crate::models::payments::refund::{prepare_refund, MerchantRefund, NewRefundRequest, Refund, RefundKind, RefundStatus};
+use crate::models::reconciliation::ExpectedBalanceAction;
+use crate::services::merchants::merchant_service::MerchantService;
+use crate::services::traders::trader_service::TraderServicePool;
+use crate::use_case::expiry::MERCHANT_PAYMENT_STATUS_TOPIC;
+use crate::use_case::kafka::send_kafka_message;
+use crate::use_case::ledger;
+use crate::repository;
+
+pub struct RefundServices {
+    pub pool: deadpool_postgres::Pool,
+    pub traders: TraderServicePool,
+    pub merchants: MerchantService,
+    pub producer: Option<FutureProducer>,
+}
+
+// возврат оформляет мерчант платежа, чарджбэк - админ
+fn check_initiator(claims: &Claims, kind: RefundKind, payment: &FullPayment) -> Result<(), LibError> {
+    let role = claims.role.to_lowercase();
+    match kind {
+        RefundKind::Refund if role == "merchant" && claims.sub == payment.merchant_id => Ok(()),
+        RefundKind::Chargeback if role == "admin" => Ok(()),
+        _ => Err(Forbidden),
+    }
+}
+
+impl RefundServices {
+    // сумма резервируется на платеже в одной транзакции с созданием возврата,
+    // движения балансов проводятся после коммита с ключами от idempotent_key
+    pub async fn create_refund(&self, claims: &Claims, request: NewRefundRequest) -> Result<Refund, LibError> {
+        let mut pg = self.pool.get().await.map_err(|e| {
+            error!(err=e.to_string(), "Error get PG connection");
+            InternalError
+        })?;
+        let tx = pg.transaction().await.map_err(|e| {
+            error!(err=e.to_string(), "Error begin refund transaction");
+            InternalError
+        })?;
+        let mut payment = repository::dispute::get_payment_for_update(&tx, &request.payment_id).await?;
+        check_initiator(claims, request.kind, &payment)?;
+        let refund = match repository::refund::get_refund_by_key(&tx, &payment.id, &request.idempotent_key).await? {
+            // повтор: незавершенный возврат доводим, завершенный или упавший отдаем как есть
+            Some(refund) if refund.status != RefundStatus::Pending => return Ok(refund),
+            Some(refund) => refund,
+            None => {
+                let amounts = prepare_refund(&payment, &request)?;
+                let now = chrono::Utc::now().naive_utc();
+                let refund = Refund {
+                    id: Uuid::now_v7().to_string(),
+                    payment_id: payment.id.clone(),
+                    merchant_id: payment.merchant_id.clone(),
+                    trader_id: payment.trader_id.clone(),
+                    kind: request.kind,
+                    status: RefundStatus::Pending,
+                    fiat_amount: amounts.fiat_amount,
+                    crypto_amount: amounts.crypto_amount,
+                    trader_crypto_amount: amounts.trader_crypto_amount,
+                    reason: request.reason,
+                    initiator_id: claims.sub.clone(),
+                    idempotent_key: request.idempotent_key,
+                    created_at: now,
+                    completed_at: None,
+                };
+                repository::refund::insert_refund(&tx, &refund).await?;
+                repository::refund::add_refunded_amount(&tx, &payment.id, refund.fiat_amount, now).await?;
+                payment.refunded_fiat_amount = Some(payment.refunded_fiat_amount.unwrap_or_default() + refund.fiat_amount);
+                refund
+            }
+        };
+        tx.commit().await.map_err(|e| {
+            error!(err=e.to_string(), "Error commit refund transaction");
+            InternalError
+        })?;
+        self.settle(refund, payment).await
+    }
+
+    async fn settle(&self, mut refund: Refund, mut payment: FullPayment) -> Result<Refund, LibError> {
+        let mut result = Ok(());
+        for action in refund.balance_actions(payment.payment_side).iter() {
+            result = self.apply_balance_action(&refund, action).await;
+            if result.is_err() {
+                break;
+            }
+        }
+        let status = match result {
+            Ok(()) => RefundStatus::Completed,
+            // у возвращающей стороны не хватило средств, списание не прошло - возврат отклоняется и резерв снимается
+            Err(InsufficientFunds) => RefundStatus::Failed,
+            // остальные ошибки временные, возврат остается PENDING до повтора с тем же ключом
+            Err(e) => return Err(e),
+        };
+
+        let mut pg = self.pool.get().await.map_err(|e| {
+            error!(err=e.to_string(), "Error get PG connection");
+            InternalError
+        })?;
+        let tx = pg.transaction().await.map_err(|e| {
+            error!(err=e.to_string(), "Error begin refund transaction");
+            InternalError
+        })?;
+        let now = chrono::Utc::now().naive_utc();
+        if repository::refund::set_refund_status(&tx, &refund.id, status, now).await? && status == RefundStatus::Failed {
+            repository::refund::add_refunded_amount(&tx, &refund.payment_id, -refund.fiat_amount, now).await?;
+        }
+        tx.commit().await.map_err(|e| {
+            error!(err=e.to_string(), "Error commit refund transaction");
+            InternalError
+        })?;
+        refund.status = status;
+        refund.completed_at = Some(now);
+        info!(refund_id=refund.id, payment_id=refund.payment_id, kind=%refund.kind, status=%refund.status,
+            fiat_amount=%refund.fiat_amount, "refund settled");
+
+        if status == RefundStatus::Failed {
+            payment.refunded_fiat_amount = payment.refunded_fiat_amount.map(|amount| amount - refund.fiat_amount);
+        }
+        self.notify(&refund, payment).await;
+        if status == RefundStatus::Failed {
+            return Err(InsufficientFunds);
+        }
+        Ok(refund)
+    }
+
+    async fn apply_balance_action(&self, refund: &Refund, action: &ExpectedBalanceAction) -> Result<(), LibError> {
+        let key = format!("refund:{}:{}:{}:{}", refund.payment_id, refund.idempotent_key, action.owner_type, action.action);
+        ledger::change_balance(&self.pool, &self.traders, &self.merchants, &action.to_entry(&refund.id, key)).await
+    }
+
+    // вебхук мерчанту уходит через тот же топик, что и смена статуса платежа
+    async fn notify(&self, refund: &Refund, payment: FullPayment) {
+        let Some(producer) = self.producer.as_ref() else { return };
+        let merchant_id = payment.merchant_id.clone();
+        let mut merchant_payment = MerchantPayment::from(payment);
+        merchant_payment.refund = Some(MerchantRefund::from(refund));
+        let payload = match serde_json::to_vec(&merchant_payment) {
+            Ok(payload) => payload,
+            Err(e) => {
+                error!(err=e.to_string(), "Error encode refunded payment");
+                return;
+            }
+        };
+        if let Err(e) = send_kafka_message(producer, MERCHANT_PAYMENT_STATUS_TOPIC, &merchant_id, &payload).await {
+            warn!(merchant_id=merchant_id, refund_id=refund.id, err=?e, "Error notify merchant about refund");
+        }
+    }
+}
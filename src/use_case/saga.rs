@@ -0,0 +1,431 @@
+use std::sync::Arc;
+use async_trait::async_trait;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use tracing::{error, info, warn};
+use uuid::Uuid;
+use crate::errors::LibError;
+use crate::errors::LibError::InternalError;
+use crate::models::saga::{SagaRecovery, SagaState, SagaStatus};
+use crate::repository;
+
+// шаг саги. execute и compensate должны быть идемпотентными:
+// после падения шаг, который был в процессе, может быть выполнен повторно
+#[async_trait]
+pub trait SagaStep<C>: Send + Sync {
+    fn name(&self) -> &'static str;
+    async fn execute(&self, saga_id: &str, ctx: &mut C) -> Result<(), LibError>;
+    async fn compensate(&self, saga_id: &str, ctx: &C) -> Result<(), LibError>;
+}
+
+// шаг, общий для нескольких саг, например заданный приложением
+#[async_trait]
+impl<C: Send + Sync, S: SagaStep<C> + ?Sized> SagaStep<C> for Arc<S> {
+    fn name(&self) -> &'static str {
+        (**self).name()
+    }
+
+    async fn execute(&self, saga_id: &str, ctx: &mut C) -> Result<(), LibError> {
+        (**self).execute(saga_id, ctx).await
+    }
+
+    async fn compensate(&self, saga_id: &str, ctx: &C) -> Result<(), LibError> {
+        (**self).compensate(saga_id, ctx).await
+    }
+}
+
+// где хранится состояние саги
+#[async_trait]
+pub trait SagaStore: Send + Sync {
+    async fn insert(&self, state: &SagaState) -> Result<(), LibError>;
+    async fn update(&self, state: &SagaState) -> Result<(), LibError>;
+}
+
+pub struct PgSagaStore {
+    pool: deadpool_postgres::Pool,
+}
+
+impl PgSagaStore {
+    async fn pg(&self) -> Result<deadpool_postgres::Object, LibError> {
+        self.pool.get().await.map_err(|e| {
+            error!(err=e.to_string(), "Error get PG connection");
+            InternalError
+        })
+    }
+}
+
+#[async_trait]
+impl SagaStore for PgSagaStore {
+    async fn insert(&self, state: &SagaState) -> Result<(), LibError> {
+        let pg = self.pg().await?;
+        repository::saga::insert_saga(&pg, state).await
+    }
+
+    async fn update(&self, state: &SagaState) -> Result<(), LibError> {
+        let pg = self.pg().await?;
+        repository::saga::update_saga(&pg, state).await
+    }
+}
+
+// шаги задаются только при построении: resume_interrupted собирает сагу заново по kind,
+// и шаг, добавленный после построения, при восстановлении потерялся бы
+pub struct Saga<C> {
+    pub kind: &'static str,
+    pub recovery: SagaRecovery,
+    steps: Vec<Box<dyn SagaStep<C>>>,
+    store: Arc<dyn SagaStore>,
+}
+
+impl<C> Saga<C>
+where
+    C: Serialize + DeserializeOwned + Send + Sync,
+{
+    pub fn new(kind: &'static str, recovery: SagaRecovery, pool: deadpool_postgres::Pool) -> Self {
+        Self::with_store(kind, recovery, Arc::new(PgSagaStore { pool }))
+    }
+
+    pub fn with_store(kind: &'static str, recovery: SagaRecovery, store: Arc<dyn SagaStore>) -> Self {
+        Self { kind, recovery, steps: Vec::new(), store }
+    }
+
+    pub fn step<S: SagaStep<C> + 'static>(mut self, step: S) -> Self {
+        self.steps.push(Box::new(step));
+        self
+    }
+
+    pub fn step_names(&self) -> Vec<&'static str> {
+        self.steps.iter().map(|step| step.name()).collect()
+    }
+
+    pub async fn start(&self, reference_id: &str, ctx: C) -> Result<C, LibError> {
+        let now = chrono::Utc::now().naive_utc();
+        let mut state = SagaState {
+            id: Uuid::now_v7().to_string(),
+            kind: self.kind.to_string(),
+            reference_id: reference_id.to_string(),
+            status: SagaStatus::Running,
+            completed_steps: 0,
+            in_flight: false,
+            context: encode(&ctx)?,
+            error: None,
+            created_at: now,
+            updated_at: now,
+        };
+        self.store.insert(&state).await?;
+        self.drive(&mut state, ctx).await
+    }
+
+    // продолжает сагу после падения процесса
+    pub async fn resume(&self, mut state: SagaState) -> Result<C, LibError> {
+        if state.kind != self.kind {
+            error!(saga_id=state.id, kind=state.kind, "saga kind mismatch");
+            return Err(InternalError);
+        }
+        let mut ctx: C = decode(&state.context)?;
+        if state.completed_steps > self.steps.len() || (state.in_flight && state.completed_steps == self.steps.len()) {
+            // сага была запущена с шагами, которых нет в этом определении
+            error!(saga_id=state.id, step=state.completed_steps, "saga has unknown steps, manual check required");
+            state.status = SagaStatus::Failed;
+            state.error = Some("unknown steps on resume".to_string());
+            self.save(&mut state, &ctx).await?;
+            return Err(InternalError);
+        }
+        if state.status == SagaStatus::Running && self.recovery == SagaRecovery::Rollback {
+            warn!(saga_id=state.id, step=state.completed_steps, "rolling back interrupted saga");
+            if state.in_flight {
+                self.repeat_in_flight(&mut state, &mut ctx).await;
+            }
+            state.status = SagaStatus::Compensating;
+            state.error = Some("interrupted".to_string());
+            self.save(&mut state, &ctx).await?;
+        }
+        self.drive(&mut state, ctx).await
+    }
+
+    async fn drive(&self, state: &mut SagaState, mut ctx: C) -> Result<C, LibError> {
+        let mut failure = None;
+        while state.status == SagaStatus::Running && state.completed_steps < self.steps.len() {
+            let step = &self.steps[state.completed_steps];
+            state.in_flight = true;
+            self.save(state, &ctx).await?;
+            match step.execute(&state.id, &mut ctx).await {
+                Ok(()) => {
+                    state.completed_steps += 1;
+                    state.in_flight = false;
+                    self.save(state, &ctx).await?;
+                }
+                Err(e) if self.recovery == SagaRecovery::Resume => return self.stop_forward(state, &ctx, e).await,
+                Err(e) => {
+                    warn!(saga_id=state.id, step=step.name(), err=?e, "saga step failed, compensating");
+                    // ошибка не значит, что шаг не применился: ответ мог не дойти после изменения
+                    self.repeat_in_flight(state, &mut ctx).await;
+                    state.status = SagaStatus::Compensating;
+                    state.error = Some(format!("{}: {:?}", step.name(), e));
+                    self.save(state, &ctx).await?;
+                    failure = Some(e);
+                }
+            }
+        }
+        if state.status == SagaStatus::Running {
+            state.status = SagaStatus::Completed;
+            self.save(state, &ctx).await?;
+            info!(saga_id=state.id, kind=self.kind, "saga completed");
+            return Ok(ctx);
+        }
+
+        while state.status == SagaStatus::Compensating && state.completed_steps > 0 {
+            let step = &self.steps[state.completed_steps - 1];
+            if let Err(e) = step.compensate(&state.id, &ctx).await {
+                error!(saga_id=state.id, step=step.name(), err=?e, "saga compensation failed");
+                state.status = SagaStatus::Failed;
+                state.error = Some(format!("compensate {}: {:?}", step.name(), e));
+                self.save(state, &ctx).await?;
+                return Err(e);
+            }
+            state.completed_steps -= 1;
+            self.save(state, &ctx).await?;
+        }
+        if state.status == SagaStatus::Compensating {
+            state.status = SagaStatus::Compensated;
+            self.save(state, &ctx).await?;
+            info!(saga_id=state.id, kind=self.kind, "saga compensated");
+        }
+        Err(failure.unwrap_or(LibError::Conflict))
+    }
+
+    // сагу, которую доводят до конца, не откатывают. после сбоя она остается running с шагом in_flight,
+    // и resume_interrupted повторит его с тем же ключом. отказ по существу повтором не исправить:
+    // такая сага уходит в failed для ручного разбора, а если ни один шаг не выполнен - в compensated
+    async fn stop_forward(&self, state: &mut SagaState, ctx: &C, e: LibError) -> Result<C, LibError> {
+        let step = self.steps[state.completed_steps].name();
+        state.error = Some(format!("{}: {:?}", step, e));
+        if is_transient(&e) {
+            warn!(saga_id=state.id, step=step, err=?e, "saga step failed, will be resumed");
+        } else if state.completed_steps == 0 {
+            warn!(saga_id=state.id, step=step, err=?e, "saga step rejected, nothing to resume");
+            state.in_flight = false;
+            state.status = SagaStatus::Compensated;
+        } else {
+            error!(saga_id=state.id, step=step, err=?e, "saga step rejected, manual check required");
+            state.in_flight = false;
+            state.status = SagaStatus::Failed;
+        }
+        self.save(state, ctx).await?;
+        Err(e)
+    }
+
+    // шаг мог выполниться, повторяем его с тем же ключом: примененный повтор ничего не меняет,
+    // а шаг после успешного повтора компенсируется вместе с остальными
+    async fn repeat_in_flight(&self, state: &mut SagaState, ctx: &mut C) {
+        let step = &self.steps[state.completed_steps];
+        match step.execute(&state.id, ctx).await {
+            Ok(()) => state.completed_steps += 1,
+            Err(e) => warn!(saga_id=state.id, step=step.name(), err=?e, "in-flight step not applied"),
+        }
+        state.in_flight = false;
+    }
+
+    async fn save(&self, state: &mut SagaState, ctx: &C) -> Result<(), LibError> {
+        state.context = encode(ctx)?;
+        state.updated_at = chrono::Utc::now().naive_utc();
+        self.store.update(state).await
+    }
+}
+
+// сбой сервиса или сети, а не отказ по существу: повтор может пройти
+fn is_transient(e: &LibError) -> bool {
+    matches!(e, LibError::InternalError | LibError::TooManyRequests(_))
+}
+
+fn encode<C: Serialize>(ctx: &C) -> Result<String, LibError> {
+    serde_json::to_string(ctx).map_err(|e| {
+        error!(err=e.to_string(), "Error encode saga context");
+        InternalError
+    })
+}
+
+fn decode<C: DeserializeOwned>(context: &str) -> Result<C, LibError> {
+    serde_json::from_str(context).map_err(|e| {
+        error!(err=e.to_string(), "Error decode saga context");
+        InternalError
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+    use super::*;
+
+    #[derive(Default)]
+    struct MemoryStore {
+        states: Mutex<Vec<SagaState>>,
+    }
+
+    impl MemoryStore {
+        fn last(&self) -> SagaState {
+            self.states.lock().unwrap().last().cloned().unwrap()
+        }
+    }
+
+    #[async_trait]
+    impl SagaStore for MemoryStore {
+        async fn insert(&self, state: &SagaState) -> Result<(), LibError> {
+            self.states.lock().unwrap().push(state.clone());
+            Ok(())
+        }
+
+        async fn update(&self, state: &SagaState) -> Result<(), LibError> {
+            self.states.lock().unwrap().push(state.clone());
+            Ok(())
+        }
+    }
+
+    #[derive(Clone, Copy, PartialEq)]
+    enum Outcome {
+        Ok,
+        Fail,
+        // шаг применился, но ответ потерялся
+        LostReply,
+    }
+
+    // контекст - журнал вызовов шагов
+    struct Step {
+        name: &'static str,
+        outcome: Outcome,
+        compensated: Arc<Mutex<Vec<&'static str>>>,
+    }
+
+    #[async_trait]
+    impl SagaStep<Vec<String>> for Step {
+        fn name(&self) -> &'static str {
+            self.name
+        }
+
+        async fn execute(&self, _saga_id: &str, ctx: &mut Vec<String>) -> Result<(), LibError> {
+            let applied = format!("+{}", self.name);
+            match self.outcome {
+                Outcome::Fail => Err(LibError::NoAvailableRequisites),
+                // повтор с тем же ключом видит примененный шаг
+                Outcome::LostReply if !ctx.contains(&applied) => {
+                    ctx.push(applied);
+                    Err(LibError::InternalError)
+                }
+                _ => {
+                    if !ctx.contains(&applied) {
+                        ctx.push(applied);
+                    }
+                    Ok(())
+                }
+            }
+        }
+
+        async fn compensate(&self, _saga_id: &str, ctx: &Vec<String>) -> Result<(), LibError> {
+            // компенсируются только выполненные шаги
+            assert!(ctx.contains(&format!("+{}", self.name)));
+            self.compensated.lock().unwrap().push(self.name);
+            Ok(())
+        }
+    }
+
+    fn steps(names: &[&'static str], recovery: SagaRecovery, store: &Arc<MemoryStore>,
+             outcome_at: Option<(usize, Outcome)>, compensated: &Arc<Mutex<Vec<&'static str>>>) -> Saga<Vec<String>> {
+        names.iter().copied().enumerate().fold(
+            Saga::with_store("test", recovery, store.clone()),
+            |saga, (i, name)| {
+                let outcome = match outcome_at {
+                    Some((at, outcome)) if at == i => outcome,
+                    _ => Outcome::Ok,
+                };
+                saga.step(Step { name, outcome, compensated: compensated.clone() })
+            })
+    }
+
+    fn saga_with(recovery: SagaRecovery, store: &Arc<MemoryStore>, outcome_at: Option<(usize, Outcome)>)
+        -> Saga<Vec<String>>
+    {
+        steps(&["a", "b", "c"], recovery, store, outcome_at, &Arc::default())
+    }
+
+    fn saga(recovery: SagaRecovery, store: &Arc<MemoryStore>, fail_at: Option<usize>) -> Saga<Vec<String>> {
+        saga_with(recovery, store, fail_at.map(|at| (at, Outcome::Fail)))
+    }
+
+    fn compensating_steps(store: &MemoryStore) -> Vec<usize> {
+        store.states.lock().unwrap().iter()
+            .filter(|state| state.status == SagaStatus::Compensating)
+            .map(|state| state.completed_steps)
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn compensates_and_resumes() {
+        let store = Arc::new(MemoryStore::default());
+        let ctx = saga(SagaRecovery::Rollback, &store, None).start("p1", Vec::new()).await.unwrap();
+        assert_eq!(ctx, vec!["+a", "+b", "+c"]);
+        assert_eq!(store.last().status, SagaStatus::Completed);
+
+        // шаг c падает: b и a компенсируются в обратном порядке
+        let store = Arc::new(MemoryStore::default());
+        let err = saga(SagaRecovery::Rollback, &store, Some(2)).start("p1", Vec::new()).await.unwrap_err();
+        assert_eq!(err, LibError::NoAvailableRequisites);
+        assert_eq!(compensating_steps(&store), vec![2, 1, 0]);
+        assert_eq!(store.last().status, SagaStatus::Compensated);
+
+        // шаг b применился, но вернул ошибку: он компенсируется вместе с a
+        let lost = Arc::new(MemoryStore::default());
+        let err = saga_with(SagaRecovery::Rollback, &lost, Some((1, Outcome::LostReply)))
+            .start("p1", Vec::new()).await.unwrap_err();
+        assert_eq!(err, LibError::InternalError);
+        assert_eq!(compensating_steps(&lost), vec![2, 1, 0]);
+        assert_eq!(lost.last().status, SagaStatus::Compensated);
+
+        // процесс упал во время шага b
+        let mut interrupted = store.states.lock().unwrap()[0].clone();
+        interrupted.completed_steps = 1;
+        interrupted.in_flight = true;
+        interrupted.context = r#"["+a"]"#.to_string();
+
+        let store = Arc::new(MemoryStore::default());
+        let ctx = saga(SagaRecovery::Resume, &store, None).resume(interrupted.clone()).await.unwrap();
+        assert_eq!(ctx, vec!["+a", "+b", "+c"]);
+        assert_eq!(store.last().status, SagaStatus::Completed);
+
+        // откат повторяет шаг b с тем же ключом и компенсирует его вместе с a
+        let store = Arc::new(MemoryStore::default());
+        assert!(saga(SagaRecovery::Rollback, &store, None).resume(interrupted.clone()).await.is_err());
+        assert_eq!((store.last().status, store.last().completed_steps), (SagaStatus::Compensated, 0));
+
+        // сагу с шагами, которых нет в определении, не трогаем
+        interrupted.completed_steps = 3;
+        let store = Arc::new(MemoryStore::default());
+        assert!(saga(SagaRecovery::Rollback, &store, None).resume(interrupted).await.is_err());
+        assert_eq!(store.last().status, SagaStatus::Failed);
+    }
+
+    #[tokio::test]
+    async fn resume_saga_is_not_compensated() {
+        let close = ["close_payment", "settle_trader", "settle_merchant"];
+        let compensated = Arc::default();
+        let store = Arc::new(MemoryStore::default());
+        // сбой settle_merchant: расчет трейдера не откатывается, сага ждет повтора
+        let err = steps(&close, SagaRecovery::Resume, &store, Some((2, Outcome::LostReply)), &compensated)
+            .start("p1", Vec::new()).await.unwrap_err();
+        assert_eq!(err, LibError::InternalError);
+        assert!(compensated.lock().unwrap().is_empty());
+        let interrupted = store.last();
+        assert_eq!((interrupted.status, interrupted.completed_steps, interrupted.in_flight),
+                   (SagaStatus::Running, 2, true));
+
+        let ctx = steps(&close, SagaRecovery::Resume, &store, None, &compensated).resume(interrupted).await.unwrap();
+        assert_eq!(ctx, vec!["+close_payment", "+settle_trader", "+settle_merchant"]);
+        assert_eq!(store.last().status, SagaStatus::Completed);
+        assert!(compensated.lock().unwrap().is_empty());
+
+        // отказ по существу после закрытия - ручной разбор без отката
+        let store = Arc::new(MemoryStore::default());
+        assert!(steps(&close, SagaRecovery::Resume, &store, Some((2, Outcome::Fail)), &compensated)
+            .start("p1", Vec::new()).await.is_err());
+        assert_eq!(store.last().status, SagaStatus::Failed);
+        assert!(compensated.lock().unwrap().is_empty());
+    }
+}
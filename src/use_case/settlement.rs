@@ -0,0 +1,123 @@
+use base64::Engine;
+use base64::engine::general_purpose;
+use chrono::NaiveDate;
+use rsa::pkcs1::DecodeRsaPrivateKey;
+use rsa::{Pkcs1v15Sign, RsaPrivateKey, RsaPublicKey};
+use rsa::sha2::{Digest, Sha256};
+use tracing::{error, info, warn};
+use uuid::Uuid;
+use crate::errors::LibError;
+use crate::errors::LibError::{Conflict, InternalError};
+use crate::models::ledger::LedgerOwner;
+use crate::models::settlement::{SettlementReport, StoredSettlementReport, SETTLEMENT_REPORT_VERSION};
+use crate::repository;
+
+// ключ подписи отчетов, key_id сохраняется в отчете для выбора публичного ключа при проверке
+pub struct ReportSigner {
+    pub key_id: String,
+    private_key: RsaPrivateKey,
+}
+
+impl ReportSigner {
+    pub fn from_pkcs1_pem(key_id: &str, pem: &str) -> Result<Self, LibError> {
+        let private_key = RsaPrivateKey::from_pkcs1_pem(pem).map_err(|e| {
+            error!(err=e.to_string(), key_id=key_id, "Error parse settlement signing key");
+            InternalError
+        })?;
+        Ok(Self { key_id: key_id.to_string(), private_key })
+    }
+
+    pub fn public_key(&self) -> RsaPublicKey {
+        self.private_key.to_public_key()
+    }
+
+    fn sign(&self, content: &str) -> Result<String, LibError> {
+        let signature = self.private_key.sign(Pkcs1v15Sign::new::<Sha256>(), &Sha256::digest(content.as_bytes()))
+            .map_err(|e| {
+                error!(err=e.to_string(), "Error sign settlement report");
+                InternalError
+            })?;
+        Ok(general_purpose::STANDARD.encode(signature))
+    }
+}
+
+// проверка подписи JSON или CSV отчета на стороне получателя
+pub fn verify_report_signature(public_key: &RsaPublicKey, content: &str, signature: &str) -> bool {
+    let Ok(signature) = general_purpose::STANDARD.decode(signature) else { return false };
+    public_key.verify(Pkcs1v15Sign::new::<Sha256>(), &Sha256::digest(content.as_bytes()), &signature).is_ok()
+}
+
+// отчет за закрытый день. если уже сохранен - отдается сохраненный, повторно не пересчитывается
+pub async fn generate_settlement_report(pool: &deadpool_postgres::Pool, signer: &ReportSigner, merchant_id: &str,
+                                        report_date: NaiveDate) -> Result<StoredSettlementReport, LibError> {
+    let pg = pool.get().await.map_err(|e| {
+        error!(err=e.to_string(), "Error get PG connection");
+        InternalError
+    })?;
+    if let Some(stored) = repository::settlement::get_settlement_report(&pg, merchant_id, report_date,
+                                                                        SETTLEMENT_REPORT_VERSION).await? {
+        return Ok(stored);
+    }
+    let now = chrono::Utc::now().naive_utc();
+    let from = report_date.and_hms_opt(0, 0, 0).ok_or(InternalError)?;
+    let to = from + chrono::Duration::days(1);
+    // за незакончившийся день проводки еще добавляются
+    if to > now {
+        warn!(merchant_id=merchant_id, report_date=report_date.to_string(), "settlement day is not closed");
+        return Err(Conflict);
+    }
+
+    // без посева сумма проводок не равна балансу, и разница ушла бы в other_movements
+    if !repository::ledger::has_opening_balance(&pg, LedgerOwner::Merchant, merchant_id).await? {
+        warn!(merchant_id=merchant_id, "ledger has no opening balance for merchant");
+        return Err(Conflict);
+    }
+    let opening_balance = repository::settlement::get_merchant_balance_at(&pg, merchant_id, from).await?;
+    let closing_balance = repository::settlement::get_merchant_balance_at(&pg, merchant_id, to).await?;
+    let movements = repository::settlement::get_merchant_movements(&pg, merchant_id, from, to).await?;
+    let report = SettlementReport::build(merchant_id, report_date, opening_balance, closing_balance, &movements);
+
+    let report_json = report.to_json();
+    let report_csv = report.to_csv();
+    let stored = StoredSettlementReport {
+        id: Uuid::now_v7().to_string(),
+        merchant_id: merchant_id.to_string(),
+        report_date,
+        version: report.version,
+        sha256: Sha256::digest(report_json.as_bytes()).iter().map(|b| format!("{:02x}", b)).collect(),
+        json_signature: signer.sign(&report_json)?,
+        csv_signature: signer.sign(&report_csv)?,
+        key_id: signer.key_id.clone(),
+        report_json,
+        report_csv,
+        created_at: now,
+    };
+    if !repository::settlement::insert_settlement_report(&pg, &stored).await? {
+        // параллельная генерация успела сохранить свой экземпляр, отдаем его
+        return repository::settlement::get_settlement_report(&pg, merchant_id, report_date, SETTLEMENT_REPORT_VERSION)
+            .await?.ok_or(InternalError);
+    }
+    info!(merchant_id=merchant_id, report_date=report_date.to_string(), "settlement report generated");
+    Ok(stored)
+}
+
+// ежедневный прогон по всем мерчантам с движениями за день, ошибки одного не останавливают остальных
+pub async fn generate_daily_settlement_reports(pool: &deadpool_postgres::Pool, signer: &ReportSigner,
+                                               report_date: NaiveDate) -> Result<usize, LibError> {
+    let from = report_date.and_hms_opt(0, 0, 0).ok_or(InternalError)?;
+    let merchants = {
+        let pg = pool.get().await.map_err(|e| {
+            error!(err=e.to_string(), "Error get PG connection");
+            InternalError
+        })?;
+        repository::settlement::get_active_merchants(&pg, from, from + chrono::Duration::days(1)).await?
+    };
+    let mut generated = 0;
+    for merchant_id in merchants.iter() {
+        match generate_settlement_report(pool, signer, merchant_id, report_date).await {
+            Ok(_) => generated += 1,
+            Err(e) => error!(merchant_id=merchant_id, err=format!("{:?}", e), "Error generate settlement report"),
+        }
+    }
+    Ok(generated)
+}